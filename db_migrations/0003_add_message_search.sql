@@ -0,0 +1,10 @@
+-- Local full-text index over decrypted MLS messages
+CREATE VIRTUAL TABLE message_search USING fts5(
+    content,
+    message_id UNINDEXED,
+    mls_group_id UNINDEXED,  -- Hex encoded MLS group ID
+    account_pubkey UNINDEXED,
+    author_pubkey UNINDEXED,
+    created_at UNINDEXED,
+    tokenize = 'unicode61 remove_diacritics 2'
+);
@@ -0,0 +1,25 @@
+-- Per-group disappearing message timers, as announced by group members
+CREATE TABLE group_message_expirations (
+    mls_group_id BLOB NOT NULL,
+    account_pubkey TEXT NOT NULL,
+    expiration_seconds INTEGER,  -- NULL when disappearing messages are turned off
+    updated_at INTEGER NOT NULL,
+    updated_by TEXT NOT NULL,
+    PRIMARY KEY (mls_group_id, account_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+-- Messages that need to be purged once they expire. Expired messages are hidden rather than
+-- deleted from MLS storage, so the rows are kept and only flagged once their search index
+-- entries and media have been purged
+CREATE TABLE expiring_messages (
+    message_id TEXT NOT NULL,
+    mls_group_id BLOB NOT NULL,
+    account_pubkey TEXT NOT NULL,
+    expires_at INTEGER NOT NULL,
+    purged INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (message_id, account_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_expiring_messages_expires_at ON expiring_messages(account_pubkey, expires_at);
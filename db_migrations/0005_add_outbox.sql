@@ -0,0 +1,20 @@
+-- Outgoing events waiting for (or done with) delivery to relays
+CREATE TABLE outbox_events (
+    event_id TEXT NOT NULL,  -- The published (outer) event ID
+    message_id TEXT,  -- The inner message ID, for MLS group messages
+    account_pubkey TEXT NOT NULL,
+    event JSONB NOT NULL,  -- JSON signed Nostr event
+    relays JSONB NOT NULL,  -- JSON array of target relay URLs
+    successful_relays JSONB NOT NULL,  -- JSON array of relay URLs that accepted the event
+    failed_relays JSONB NOT NULL,  -- JSON object of relay URL -> last error message
+    state TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (event_id, account_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_outbox_events_message ON outbox_events(message_id, account_pubkey);
+CREATE INDEX idx_outbox_events_pending ON outbox_events(account_pubkey, state, next_attempt_at);
@@ -0,0 +1,21 @@
+-- How far into each group an account has read
+CREATE TABLE group_read_markers (
+    mls_group_id BLOB NOT NULL,
+    account_pubkey TEXT NOT NULL,
+    last_read_message_id TEXT,
+    last_read_at INTEGER NOT NULL,  -- created_at of the last read message
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (mls_group_id, account_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+-- Read receipts sent to us by other group members
+CREATE TABLE group_read_receipts (
+    mls_group_id BLOB NOT NULL,
+    account_pubkey TEXT NOT NULL,
+    member_pubkey TEXT NOT NULL,
+    last_read_message_id TEXT NOT NULL,
+    read_at INTEGER NOT NULL,
+    PRIMARY KEY (mls_group_id, account_pubkey, member_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
@@ -0,0 +1,6 @@
+-- Cached NIP-11 relay information documents
+CREATE TABLE relay_information (
+    relay_url TEXT PRIMARY KEY,
+    document TEXT NOT NULL, -- JSON
+    fetched_at INTEGER NOT NULL
+);
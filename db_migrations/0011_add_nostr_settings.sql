@@ -0,0 +1,6 @@
+-- NostrManager settings, either global (scope 'global') or per account (scope is the account's pubkey)
+CREATE TABLE nostr_settings (
+    scope TEXT PRIMARY KEY,
+    settings TEXT NOT NULL, -- JSON
+    updated_at INTEGER NOT NULL
+);
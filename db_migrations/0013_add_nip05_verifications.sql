@@ -0,0 +1,9 @@
+-- Cached NIP-05 verification results
+CREATE TABLE nip05_verifications (
+    nip05 TEXT NOT NULL,  -- Lowercase name@domain
+    pubkey TEXT NOT NULL,
+    status TEXT NOT NULL,  -- 'verified' or 'failed'
+    relays TEXT NOT NULL DEFAULT '[]',  -- JSON array of relays listed for the pubkey
+    checked_at INTEGER NOT NULL,
+    PRIMARY KEY (nip05, pubkey)
+);
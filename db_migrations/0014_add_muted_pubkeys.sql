@@ -0,0 +1,8 @@
+-- People an account muted, from its NIP-51 mute list
+CREATE TABLE muted_pubkeys (
+    account_pubkey TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    private INTEGER NOT NULL DEFAULT 0,  -- Listed in the encrypted part of the mute list
+    PRIMARY KEY (account_pubkey, pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
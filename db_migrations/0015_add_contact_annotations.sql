@@ -0,0 +1,11 @@
+-- Private nicknames, notes and pins an account puts on people, never published in the clear
+CREATE TABLE contact_annotations (
+    account_pubkey TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    nickname TEXT,
+    note TEXT,
+    pinned INTEGER NOT NULL DEFAULT 0,
+    updated_at INTEGER NOT NULL,  -- Cleared annotations are kept so the clearing syncs too
+    PRIMARY KEY (account_pubkey, pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
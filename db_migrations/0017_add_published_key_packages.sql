@@ -0,0 +1,14 @@
+-- The key packages an account published, so that old ones can be replaced and deleted from relays
+CREATE TABLE published_key_packages (
+    event_id TEXT PRIMARY KEY,
+    account_pubkey TEXT NOT NULL,
+    ciphersuite TEXT,
+    relays TEXT NOT NULL,  -- JSON array of the relays that accepted the event
+    created_at INTEGER NOT NULL,
+    deleted_at INTEGER,  -- When we asked relays to delete it, NULL while it's live
+    hash_ref TEXT,  -- Hex encoded hash reference MLS storage keeps the private key material under
+    keys_deleted_at INTEGER,  -- When the private key material was removed from MLS storage
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_published_key_packages_account ON published_key_packages(account_pubkey, created_at);
@@ -0,0 +1,9 @@
+-- SOCKS5 proxy settings per account, applied to relay and Blossom connections
+CREATE TABLE proxy_settings (
+    account_pubkey TEXT PRIMARY KEY,
+    enabled BOOLEAN NOT NULL,
+    address TEXT NOT NULL,
+    allow_clearnet_fallback BOOLEAN NOT NULL,
+    updated_at INTEGER NOT NULL,
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
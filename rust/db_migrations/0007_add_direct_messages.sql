@@ -0,0 +1,15 @@
+-- NIP-17 private direct messages, stored unwrapped
+CREATE TABLE direct_messages (
+    message_id TEXT NOT NULL,           -- Hex encoded id of the kind 14 rumor
+    account_pubkey TEXT NOT NULL,
+    conversation_pubkey TEXT NOT NULL,  -- The other participant
+    author_pubkey TEXT NOT NULL,
+    content TEXT NOT NULL,
+    tags JSONB NOT NULL,
+    created_at INTEGER NOT NULL,
+    wrapper_event_id TEXT,              -- Hex encoded id of the gift wrap we received it in
+    PRIMARY KEY (message_id, account_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_direct_messages_conversation ON direct_messages(account_pubkey, conversation_pubkey, created_at);
@@ -0,0 +1,10 @@
+-- People we've invited to White Noise
+CREATE TABLE invites (
+    account_pubkey TEXT NOT NULL,
+    invitee_pubkey TEXT NOT NULL,
+    method TEXT NOT NULL,  -- 'nip17' or 'nip04'
+    message TEXT NOT NULL,
+    invited_at INTEGER NOT NULL,
+    PRIMARY KEY (account_pubkey, invitee_pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
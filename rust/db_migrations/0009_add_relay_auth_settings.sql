@@ -0,0 +1,9 @@
+-- Whether an account is willing to authenticate (NIP-42) to a relay
+CREATE TABLE relay_auth_settings (
+    account_pubkey TEXT NOT NULL,
+    relay_url TEXT NOT NULL,
+    allow_auth BOOLEAN NOT NULL,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (account_pubkey, relay_url),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
@@ -0,0 +1,27 @@
+-- Local directory of people an account knows about, searchable offline
+CREATE TABLE contacts (
+    account_pubkey TEXT NOT NULL,
+    pubkey TEXT NOT NULL,
+    npub TEXT NOT NULL,
+    name TEXT,
+    display_name TEXT,
+    nip05 TEXT,
+    picture TEXT,
+    following INTEGER NOT NULL DEFAULT 0,  -- In the account's contact list
+    metadata_at INTEGER,  -- created_at of the metadata event the profile fields came from
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (account_pubkey, pubkey),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
+
+CREATE INDEX idx_contacts_pubkey ON contacts(pubkey);
+
+-- The created_at of the latest replaceable list (contact list, mute list) applied for each
+-- account, so that an older version arriving later doesn't overwrite a newer one
+CREATE TABLE applied_lists (
+    account_pubkey TEXT NOT NULL,
+    kind INTEGER NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (account_pubkey, kind),
+    FOREIGN KEY (account_pubkey) REFERENCES accounts(pubkey) ON DELETE CASCADE
+);
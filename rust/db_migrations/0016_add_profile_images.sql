@@ -0,0 +1,10 @@
+-- Thumbnails of profile pictures and banners, so that showing a contact doesn't hit their image host
+CREATE TABLE profile_images (
+    url_hash TEXT NOT NULL,  -- SHA256 of the image URL
+    kind TEXT NOT NULL,  -- avatar or banner
+    url TEXT NOT NULL,
+    file_path TEXT,  -- NULL until a download succeeds
+    failed INTEGER NOT NULL DEFAULT 0,  -- Whether the last download failed
+    fetched_at INTEGER NOT NULL,
+    PRIMARY KEY (url_hash, kind)
+);
@@ -1,6 +1,7 @@
 use crate::database::DatabaseError;
 use crate::nostr_manager;
-use crate::relays::RelayType;
+use crate::nostr_manager::router::relay_list_kind;
+use crate::relays::{normalize_relay_url, normalize_relay_urls, RelayType};
 use crate::secrets_store;
 use crate::whitenoise::Whitenoise;
 use nostr_mls::prelude::*;
@@ -42,6 +43,12 @@ pub enum AccountError {
 
     #[error("Nostr MLS not initialized")]
     NostrMlsNotInitialized,
+
+    #[error("Invalid relay URL: {0}")]
+    InvalidRelayUrl(String),
+
+    #[error("Failed to publish relay list: {0}")]
+    RelayListNotPublished(String),
 }
 
 pub type Result<T> = std::result::Result<T, AccountError>;
@@ -56,6 +63,12 @@ pub struct AccountSettings {
     pub dark_theme: bool,
     pub dev_mode: bool,
     pub lockdown_mode: bool,
+    /// Whether to let other group members know which messages we've read
+    #[serde(default)]
+    pub send_read_receipts: bool,
+    /// Whether to sync contact nicknames and notes between our devices, encrypted
+    #[serde(default)]
+    pub sync_contact_annotations: bool,
 }
 
 impl Default for AccountSettings {
@@ -64,6 +77,8 @@ impl Default for AccountSettings {
             dark_theme: true,
             dev_mode: false,
             lockdown_mode: false,
+            send_read_receipts: false,
+            sync_contact_annotations: false,
         }
     }
 }
@@ -493,6 +508,10 @@ impl Account {
         .await?)
     }
 
+    /// Stores the relays of a type, replacing the ones we had.
+    ///
+    /// This only touches the database. An empty list is ignored since it usually means we
+    /// couldn't find the user's relay list, use [`Account::replace_relays`] to actually clear it.
     pub async fn update_relays(
         &self,
         relay_type: RelayType,
@@ -504,8 +523,109 @@ impl Account {
         }
 
         let mut txn = wn.database.pool.begin().await?;
+        self.write_relays(&mut txn, relay_type, relays).await?;
+        txn.commit().await?;
+
+        Ok(self.clone())
+    }
+
+    /// Adds relays of a type, then publishes the updated relay list.
+    ///
+    /// See [`Account::replace_relays`].
+    pub async fn add_relays(
+        &self,
+        relay_type: RelayType,
+        relays: &[String],
+        wn: Arc<Whitenoise>,
+    ) -> Result<Vec<String>> {
+        let mut updated: Vec<String> = self
+            .relays(relay_type, wn.clone())
+            .await?
+            .iter()
+            .filter_map(|url| normalize_relay_url(url))
+            .collect();
+        updated.extend(normalize_relay_urls(relays).map_err(AccountError::InvalidRelayUrl)?);
+        self.replace_relays(relay_type, &updated, wn).await
+    }
+
+    /// Removes relays of a type, then publishes the updated relay list.
+    ///
+    /// See [`Account::replace_relays`].
+    pub async fn remove_relays(
+        &self,
+        relay_type: RelayType,
+        relays: &[String],
+        wn: Arc<Whitenoise>,
+    ) -> Result<Vec<String>> {
+        let removed = normalize_relay_urls(relays).map_err(AccountError::InvalidRelayUrl)?;
+        let updated: Vec<String> = self
+            .relays(relay_type, wn.clone())
+            .await?
+            .iter()
+            .filter_map(|url| normalize_relay_url(url))
+            .filter(|url| !removed.contains(url))
+            .collect();
+        self.replace_relays(relay_type, &updated, wn).await
+    }
+
+    /// Replaces the relays of a type, publishes the new relay list (kind 10002, 10050 or 10051)
+    /// and updates the relay pool.
+    ///
+    /// URLs are normalized and deduplicated first. Nothing is saved unless the relay list was
+    /// accepted by at least one relay.
+    ///
+    /// # Returns
+    /// The relays now stored for the type
+    pub async fn replace_relays(
+        &self,
+        relay_type: RelayType,
+        relays: &[String],
+        wn: Arc<Whitenoise>,
+    ) -> Result<Vec<String>> {
+        let relays = normalize_relay_urls(relays).map_err(AccountError::InvalidRelayUrl)?;
+        let previous = self.relays(relay_type, wn.clone()).await?;
+        let write_relays = match relay_type {
+            RelayType::Nostr => relays.clone(),
+            _ => self.relays(RelayType::Nostr, wn.clone()).await?,
+        };
+
+        // Publish first so that nothing is stored if no relay accepts the list, and so that the
+        // database isn't locked while we wait for relays
+        self.publish_relay_list_event(relay_type, &relays, &write_relays, wn.clone())
+            .await?;
+        let mut txn = wn.database.pool.begin().await?;
+        self.write_relays(&mut txn, relay_type, &relays).await?;
+        txn.commit().await?;
+
+        self.update_relay_pool(&previous, &relays, wn).await?;
+        Ok(relays)
+    }
+
+    /// Publishes the relay list of a type again, e.g. after a relay lost it
+    pub async fn republish_relay_list(
+        &self,
+        relay_type: RelayType,
+        wn: Arc<Whitenoise>,
+    ) -> Result<()> {
+        let relays = self.relays(relay_type, wn.clone()).await?;
+        let write_relays = self.relays(RelayType::Nostr, wn.clone()).await?;
+        self.publish_relay_list_event(relay_type, &relays, &write_relays, wn)
+            .await
+    }
+
+    /// Replaces the stored relays of a type within a transaction
+    async fn write_relays(
+        &self,
+        txn: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        relay_type: RelayType,
+        relays: &[String],
+    ) -> Result<()> {
+        sqlx::query("DELETE FROM account_relays WHERE relay_type = ? AND account_pubkey = ?")
+            .bind(String::from(relay_type))
+            .bind(self.pubkey.to_hex())
+            .execute(&mut **txn)
+            .await?;
 
-        // Then insert the new relays
         for relay in relays {
             sqlx::query(
                 "INSERT OR REPLACE INTO account_relays (url, relay_type, account_pubkey)
@@ -514,13 +634,10 @@ impl Account {
             .bind(relay)
             .bind(String::from(relay_type))
             .bind(self.pubkey.to_hex())
-            .execute(&mut *txn)
+            .execute(&mut **txn)
             .await?;
         }
-
-        txn.commit().await?;
-
-        Ok(self.clone())
+        Ok(())
     }
 
     /// Saves the account to the database
@@ -583,6 +700,12 @@ impl Account {
             .execute(&mut *txn)
             .await?;
 
+        // The search index is a virtual table so it doesn't cascade
+        sqlx::query("DELETE FROM message_search WHERE account_pubkey = ?")
+            .bind(hex_pubkey.as_str())
+            .execute(&mut *txn)
+            .await?;
+
         // Get first remaining account's pubkey (if any)
         let remaining_account_pubkey =
             sqlx::query_scalar::<_, String>("SELECT pubkey FROM accounts")
@@ -727,30 +850,90 @@ impl Account {
             return Ok(());
         }
 
-        // Create a minimal relay list event
-        let tags: Vec<Tag> = relays
-            .into_iter()
-            .map(|url| Tag::custom(TagKind::Relay, [url]))
-            .collect();
+        let write_relays = self.relays(RelayType::Nostr, wn.clone()).await?;
+        self.publish_relay_list_event(relay_type, &relays, &write_relays, wn)
+            .await
+    }
 
-        // Determine the kind of relay list event to publish
-        let relay_event_kind = match relay_type {
-            RelayType::Nostr => Kind::RelayList,
-            RelayType::Inbox => Kind::InboxRelays,
-            RelayType::KeyPackage => Kind::MlsKeyPackageRelays,
+    /// Publishes a relay list event to our write relays and the indexers.
+    ///
+    /// Fails if no relay accepted the event.
+    async fn publish_relay_list_event(
+        &self,
+        relay_type: RelayType,
+        relays: &[String],
+        write_relays: &[String],
+        wn: Arc<Whitenoise>,
+    ) -> Result<()> {
+        let tags: Vec<Tag> = match relay_type {
+            // NIP-65 lists use `r` tags, without a marker the relay is used for reading and writing
+            RelayType::Nostr => relays
+                .iter()
+                .filter_map(|url| RelayUrl::parse(url).ok())
+                .map(|url| Tag::relay_metadata(url, None))
+                .collect(),
+            _ => relays
+                .iter()
+                .map(|url| Tag::custom(TagKind::Relay, [url.clone()]))
+                .collect(),
         };
+        let event = EventBuilder::new(relay_list_kind(relay_type), "").tags(tags);
 
-        let event = EventBuilder::new(relay_event_kind, "").tags(tags);
-        wn.nostr
-            .client
-            .send_event_builder(event.clone())
-            .await
-            .map_err(|e| AccountError::NostrManagerError(e.into()))?;
+        let output = wn
+            .nostr
+            .publish_to_outbox(event.clone(), write_relays)
+            .await?;
+        if output.success.is_empty() {
+            return Err(AccountError::RelayListNotPublished(
+                output
+                    .failed
+                    .values()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ));
+        }
 
         tracing::debug!(target: "whitenoise::accounts::publish_relay_list", "Published relay list event to Nostr: {:?}", event);
         Ok(())
     }
 
+    /// Connects to relays we just started using and drops the ones nothing uses anymore
+    async fn update_relay_pool(
+        &self,
+        previous: &[String],
+        current: &[String],
+        wn: Arc<Whitenoise>,
+    ) -> Result<()> {
+        for url in current.iter().filter(|url| !previous.contains(url)) {
+            if wn.nostr.add_relay(url).await? {
+                wn.nostr
+                    .client
+                    .connect_relay(url)
+                    .await
+                    .map_err(nostr_manager::NostrManagerError::from)?;
+            }
+        }
+
+        let mut in_use = wn.nostr.relays().await?;
+        for relay_type in [RelayType::Nostr, RelayType::Inbox, RelayType::KeyPackage] {
+            in_use.extend(self.relays(relay_type, wn.clone()).await?);
+        }
+        for url in previous.iter().filter(|url| !current.contains(url)) {
+            if !in_use.contains(url) {
+                if let Err(e) = wn.nostr.client.remove_relay(url).await {
+                    tracing::warn!(
+                        target: "whitenoise::accounts::update_relay_pool",
+                        "Failed to remove relay {}: {}",
+                        url,
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub async fn onboard_new_account(&mut self, wn: Arc<Whitenoise>) -> Result<Self> {
         tracing::debug!(target: "whitenoise::accounts::onboard_new_account", "Starting onboarding process");
 
@@ -774,10 +957,8 @@ impl Account {
         let metadata_json = serde_json::to_string(&self.metadata)?;
         let event = EventBuilder::new(Kind::Metadata, metadata_json);
         wn.nostr
-            .client
-            .send_event_builder(event.clone())
-            .await
-            .map_err(|e| AccountError::NostrManagerError(e.into()))?;
+            .publish_to_outbox(event.clone(), &default_relays)
+            .await?;
         tracing::debug!(target: "whitenoise::accounts::onboard_new_account", "Published metadata event to Nostr: {:?}", event);
 
         // Also publish relay lists to Nostr
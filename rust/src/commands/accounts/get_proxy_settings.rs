@@ -0,0 +1,23 @@
+use crate::accounts::Account;
+use crate::nostr_manager::proxy::{self, ProxySettings};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Gets the active account's proxy settings.
+///
+/// # Arguments
+///
+/// * `wn` - A reference to the Whitenoise state
+///
+/// # Returns
+///
+/// * `Ok(ProxySettings)` - The account's proxy settings, the defaults if it never changed them
+/// * `Err(String)` - An error message if there was an issue loading the settings
+pub async fn get_proxy_settings(wn: Arc<Whitenoise>) -> Result<ProxySettings, String> {
+    let pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| format!("Error fetching active account: {}", e))?;
+    proxy::load_proxy_settings(&pubkey, &wn.database)
+        .await
+        .map_err(|e| format!("Error loading proxy settings: {}", e))
+}
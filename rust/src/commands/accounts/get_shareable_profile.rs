@@ -0,0 +1,20 @@
+use crate::identifiers::{self, ShareableProfile};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Gets the identifiers to share the active account with
+///
+/// The nprofile carries up to three of our inbox relays (or nostr relays if we have no inbox
+/// relays), so whoever scans it can reach us without knowing our relays.
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(ShareableProfile)` - Our npub, nprofile and the `nostr:` URI to put in a QR code
+/// * `Err(String)` - Error message if there is no active account
+pub async fn get_shareable_profile(wn: Arc<Whitenoise>) -> Result<ShareableProfile, String> {
+    identifiers::own_shareable_profile(wn)
+        .await
+        .map_err(|e| e.to_string())
+}
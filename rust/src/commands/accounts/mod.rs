@@ -2,6 +2,8 @@ mod create_identity;
 mod fetch_relays_list;
 mod get_accounts;
 mod get_nostr_wallet_connect_balance;
+mod get_proxy_settings;
+mod get_shareable_profile;
 mod has_nostr_wallet_connect_uri;
 mod login;
 mod logout;
@@ -9,12 +11,17 @@ mod publish_metadata_event;
 mod remove_nostr_wallet_connect_uri;
 mod set_active_account;
 mod set_nostr_wallet_connect_uri;
+mod set_proxy_settings;
 mod update_account_onboarding;
+mod update_account_settings;
+mod verify_own_nip05;
 
 pub use create_identity::create_identity;
 pub use fetch_relays_list::fetch_relays_list;
 pub use get_accounts::get_accounts;
 pub use get_nostr_wallet_connect_balance::get_nostr_wallet_connect_balance;
+pub use get_proxy_settings::get_proxy_settings;
+pub use get_shareable_profile::get_shareable_profile;
 pub use has_nostr_wallet_connect_uri::has_nostr_wallet_connect_uri;
 pub use login::login;
 pub use logout::logout;
@@ -22,4 +29,7 @@ pub use publish_metadata_event::publish_metadata_event;
 pub use remove_nostr_wallet_connect_uri::remove_nostr_wallet_connect_uri;
 pub use set_active_account::set_active_account;
 pub use set_nostr_wallet_connect_uri::set_nostr_wallet_connect_uri;
+pub use set_proxy_settings::set_proxy_settings;
 pub use update_account_onboarding::update_account_onboarding;
+pub use update_account_settings::update_account_settings;
+pub use verify_own_nip05::verify_own_nip05;
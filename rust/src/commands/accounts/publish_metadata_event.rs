@@ -1,4 +1,5 @@
 use crate::accounts::Account;
+use crate::relays::RelayType;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
 use std::sync::Arc;
@@ -7,7 +8,7 @@ use std::sync::Arc;
 ///
 /// This function performs two main operations:
 /// 1. Updates the local account's metadata and saves it
-/// 2. Publishes the metadata event to all connected Nostr relays
+/// 2. Publishes the metadata event to the account's write relays and the indexer relays
 ///
 /// # Arguments
 /// * `new_metadata` - The new metadata to publish and save
@@ -31,9 +32,12 @@ pub async fn publish_metadata_event(
     let metadata_json = serde_json::to_string(&new_metadata).map_err(|e| e.to_string())?;
     let event = EventBuilder::new(Kind::Metadata, metadata_json);
 
+    let write_relays = account
+        .relays(RelayType::Nostr, wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
     wn.nostr
-        .client
-        .send_event_builder(event.clone())
+        .publish_to_outbox(event.clone(), &write_relays)
         .await
         .map_err(|e| e.to_string())?;
 
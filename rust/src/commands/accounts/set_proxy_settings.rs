@@ -0,0 +1,35 @@
+use crate::accounts::Account;
+use crate::nostr_manager::proxy::{self, ProxySettings, ProxyState};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Updates the active account's proxy settings and reconnects to its relays with them.
+///
+/// # Arguments
+///
+/// * `settings` - The new proxy settings
+/// * `wn` - A reference to the Whitenoise state
+///
+/// # Returns
+///
+/// * `Ok(ProxyState)` - How relays and Blossom are now being reached
+/// * `Err(String)` - An error message if the settings couldn't be saved, or if the proxy is
+///   unreachable and clearnet fallback is disabled
+pub async fn set_proxy_settings(
+    settings: ProxySettings,
+    wn: Arc<Whitenoise>,
+) -> Result<ProxyState, String> {
+    let account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| format!("Error fetching active account: {}", e))?;
+    proxy::save_proxy_settings(&account.pubkey, &settings, &wn.database)
+        .await
+        .map_err(|e| format!("Error saving proxy settings: {}", e))?;
+
+    // Rebuilds the relay pool with the new settings
+    wn.nostr
+        .set_nostr_identity(&account, wn.clone())
+        .await
+        .map_err(|e| format!("Error reconnecting: {}", e))?;
+    Ok(wn.nostr.proxy_state())
+}
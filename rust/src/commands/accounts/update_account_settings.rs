@@ -0,0 +1,35 @@
+use crate::accounts::{Account, AccountSettings};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Updates the settings of a specific account.
+///
+/// # Arguments
+///
+/// * `pubkey` - The public key of the account to update
+/// * `settings` - The new settings
+/// * `wn` - A reference to the Whitenoise state
+///
+/// # Returns
+///
+/// * `Ok(Account)` - The updated account if successful
+/// * `Err(String)` - An error message if there was an issue updating the account
+
+pub async fn update_account_settings(
+    pubkey: String,
+    settings: AccountSettings,
+    wn: Arc<Whitenoise>,
+) -> Result<Account, String> {
+    let pubkey =
+        PublicKey::parse(&pubkey).map_err(|e| format!("Error parsing public key: {}", e))?;
+    let mut account = Account::find_by_pubkey(&pubkey, wn.clone())
+        .await
+        .map_err(|e| format!("Error fetching account: {}", e))?;
+    account.settings = settings;
+    account
+        .save(wn.clone())
+        .await
+        .map_err(|e| format!("Error saving account: {}", e))?;
+    Ok(account)
+}
@@ -0,0 +1,23 @@
+use crate::nip05::{self, Nip05Verification};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Verifies the NIP-05 address in the active account's profile
+///
+/// # Arguments
+/// * `refresh` - Check again even if we have a recent result
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Some(Nip05Verification))` - Whether the address is verified
+/// * `Ok(None)` - If the profile has no NIP-05 address
+/// * `Err(String)` - Error message if there is no active account, the address is invalid or
+///   the domain couldn't be reached
+pub async fn verify_own_nip05(
+    refresh: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Option<Nip05Verification>, String> {
+    nip05::verify_own(refresh, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
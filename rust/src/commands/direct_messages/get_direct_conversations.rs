@@ -0,0 +1,25 @@
+use crate::accounts::Account;
+use crate::direct_messages::{self, DirectConversation};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Lists the direct message conversations of the active account
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<DirectConversation>)` - Conversations ordered by their latest message
+/// * `Err(String)` - Error message if retrieval fails
+
+pub async fn get_direct_conversations(
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<DirectConversation>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    direct_messages::conversations(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
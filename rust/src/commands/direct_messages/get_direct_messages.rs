@@ -0,0 +1,40 @@
+use crate::accounts::Account;
+use crate::direct_messages::{self, DirectMessage, DEFAULT_HISTORY_LIMIT};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Gets the history of a direct message conversation
+///
+/// # Arguments
+/// * `pubkey` - Hex or npub encoded public key of the other participant
+/// * `before` - Optional unix timestamp, only messages older than this are returned
+/// * `limit` - Optional maximum number of messages, defaults to 100
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<DirectMessage>)` - Messages in chronological order
+/// * `Err(String)` - Error message if retrieval fails
+
+pub async fn get_direct_messages(
+    pubkey: String,
+    before: Option<u64>,
+    limit: Option<u32>,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<DirectMessage>, String> {
+    let conversation_pubkey =
+        PublicKey::parse(&pubkey).map_err(|e| format!("Error parsing public key: {}", e))?;
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    direct_messages::conversation_messages(
+        &account_pubkey,
+        &conversation_pubkey,
+        before.map(Timestamp::from),
+        limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+        &wn.database,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
@@ -0,0 +1,7 @@
+mod get_direct_conversations;
+mod get_direct_messages;
+mod send_direct_message;
+
+pub use get_direct_conversations::get_direct_conversations;
+pub use get_direct_messages::get_direct_messages;
+pub use send_direct_message::send_direct_message;
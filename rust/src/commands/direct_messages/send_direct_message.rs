@@ -0,0 +1,34 @@
+use crate::direct_messages::{self, DirectMessage};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Sends a NIP-17 private direct message
+///
+/// # Arguments
+/// * `pubkey` - Hex or npub encoded public key of the recipient
+/// * `message` - The message content
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(DirectMessage)` - The message that was sent
+/// * `Err(String)` - Error message if sending fails
+///
+/// # Errors
+/// Returns error if:
+/// - The public key is invalid
+/// - The recipient doesn't publish any inbox relays
+/// - None of the recipient's inbox relays accepted the message
+
+pub async fn send_direct_message(
+    pubkey: String,
+    message: String,
+    wn: Arc<Whitenoise>,
+) -> Result<DirectMessage, String> {
+    let receiver =
+        PublicKey::parse(&pubkey).map_err(|e| format!("Error parsing public key: {}", e))?;
+
+    direct_messages::send_direct_message(&receiver, &message, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
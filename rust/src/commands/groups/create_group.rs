@@ -8,6 +8,7 @@ use nostr_sdk::NostrSigner;
 
 use crate::accounts::Account;
 use crate::commands::nostr::fetch_enriched_contact;
+use crate::identifiers;
 use crate::key_packages::fetch_key_packages_for_members;
 use crate::whitenoise::Whitenoise;
 
@@ -15,8 +16,8 @@ use crate::whitenoise::Whitenoise;
 ///
 /// # Arguments
 /// * `creator_pubkey` - Public key of the group creator (must be the active account)
-/// * `member_pubkeys` - List of public keys for group members
-/// * `admin_pubkeys` - List of public keys for group admins
+/// * `member_pubkeys` - List of public keys for group members, as hex, npub, nprofile or `nostr:` URIs
+/// * `admin_pubkeys` - List of public keys for group admins, in the same forms
 /// * `group_name` - Name of the group
 /// * `description` - Description of the group
 /// * `wn` - Whitenoise state
@@ -57,31 +58,33 @@ pub async fn create_group(
         .map_err(|e| e.to_string())?;
     let signer = wn.nostr.client.signer().await.map_err(|e| e.to_string())?;
 
+    let parse_pubkeys = |pubkeys: &[String]| {
+        pubkeys
+            .iter()
+            .map(|pk| identifiers::parse_pubkey(pk).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, String>>()
+    };
+    let creator_pubkey = identifiers::parse_pubkey(&creator_pubkey).map_err(|e| e.to_string())?;
+    let member_pubkeys = parse_pubkeys(&member_pubkeys)?;
+    let admin_pubkeys = parse_pubkeys(&admin_pubkeys)?;
+
     // Check that active account is the creator and signer
-    if active_account.pubkey.to_hex() != creator_pubkey
-        || active_account.pubkey.to_hex()
-            != signer
-                .get_public_key()
-                .await
-                .map_err(|e| e.to_string())?
-                .to_hex()
+    if active_account.pubkey != creator_pubkey
+        || active_account.pubkey != signer.get_public_key().await.map_err(|e| e.to_string())?
     {
         return Err("You cannot create a group for another account".to_string());
     }
 
     // Fetch key packages for all members
-    let member_key_packages = fetch_key_packages_for_members(&member_pubkeys, wn.clone())
-        .await
-        .map_err(|e| e.to_string())?;
-    let member_pubkeys = member_pubkeys
-        .iter()
-        .map(|pk| PublicKey::from_hex(pk).unwrap())
-        .collect::<Vec<_>>();
-    let admin_pubkeys = admin_pubkeys
-        .iter()
-        .map(|pk| PublicKey::from_hex(pk).unwrap())
-        .collect::<Vec<_>>();
-    let creator_pubkey = PublicKey::from_hex(&creator_pubkey).unwrap();
+    let member_key_packages = fetch_key_packages_for_members(
+        &member_pubkeys
+            .iter()
+            .map(|pk| pk.to_hex())
+            .collect::<Vec<_>>(),
+        wn.clone(),
+    )
+    .await
+    .map_err(|e| e.to_string())?;
 
     tracing::debug!(
         target: "whitenoise::groups::create_group",
@@ -211,7 +214,6 @@ pub async fn create_group(
         for url in relay_urls.clone() {
             let to_remove = wn
                 .nostr
-                .client
                 .add_relay(url.clone())
                 .await
                 .map_err(|e| e.to_string())?;
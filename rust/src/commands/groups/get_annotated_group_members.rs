@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use super::{get_group_members, GroupMember};
+use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::whitenoise::Whitenoise;
+
+/// Gets the members of an MLS group along with our nicknames and notes for them
+///
+/// # Arguments
+/// * `group_id` - Hex-encoded MLS group ID
+/// * `wn` - Whitenoise state handle
+///
+/// # Returns
+/// * `Ok(Vec<GroupMember>)` - The members, pinned ones first
+/// * `Err(String)` - Error message if operation fails
+///
+/// # Errors
+/// * If no active account is found
+/// * If the members can't be retrieved, see [`get_group_members`]
+/// * If the annotations can't be read from the database
+pub async fn get_annotated_group_members(
+    group_id: &str,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<GroupMember>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let members = get_group_members(group_id, wn.clone()).await?;
+    let mut annotations = contact_annotations::annotations(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut members: Vec<GroupMember> = members
+        .into_iter()
+        .map(|pubkey| {
+            let pubkey = pubkey.to_hex();
+            GroupMember {
+                annotation: annotations.remove(&pubkey),
+                pubkey,
+            }
+        })
+        .collect();
+    // Stable, so members keep their order otherwise
+    members.sort_by_key(|member| {
+        !member
+            .annotation
+            .as_ref()
+            .is_some_and(|annotation| annotation.pinned)
+    });
+    Ok(members)
+}
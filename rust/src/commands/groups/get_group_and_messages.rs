@@ -1,14 +1,22 @@
 use nostr_mls::prelude::*;
+use std::collections::HashSet;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::timeout;
 
 use super::{GroupAndMessages, MessageWithTokens};
+use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::disappearing_messages::{self, EXPIRATION_SETTING_KIND};
+use crate::mute_list;
 use crate::nostr_manager::parser::parse;
 use crate::whitenoise::Whitenoise;
 
 /// Gets a single MLS group and its messages by group ID
 ///
+/// Messages from muted people and expired messages are left out. Our nicknames and notes for the authors of the
+/// other messages come along.
+///
 /// # Arguments
 /// * `group_id` - Hex encoded MLS group ID
 /// * `wn` - Whitenoise state
@@ -39,6 +47,19 @@ pub async fn get_group_and_messages(
         mls_group_id
     );
 
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let muted = mute_list::muted_pubkey_set(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+    let mut annotations = contact_annotations::annotations(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+    let expired = disappearing_messages::expired_message_ids(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+
     tracing::debug!(target: "whitenoise::commands::groups::get_group_and_messages", "Attempting to acquire nostr_mls lock");
     let nostr_mls_guard = match timeout(Duration::from_secs(5), wn.nostr_mls.lock()).await {
         Ok(guard) => {
@@ -74,16 +95,28 @@ pub async fn get_group_and_messages(
             );
 
             tracing::debug!(target: "whitenoise::commands::groups::get_group_and_messages", "nostr_mls lock released");
+            // Read receipts and timer announcements aren't part of the conversation, and expired
+            // messages are hidden until they're gone from every device
             let messages_with_tokens = messages
                 .iter()
+                .filter(|message| !message.kind.is_ephemeral())
+                .filter(|message| message.kind != Kind::Custom(EXPIRATION_SETTING_KIND))
+                .filter(|message| !expired.contains(&message.id.to_hex()))
+                .filter(|message| !muted.contains(&message.pubkey))
                 .map(|message| MessageWithTokens {
                     message: message.clone(),
                     tokens: parse(&message.content),
                 })
                 .collect::<Vec<MessageWithTokens>>();
+            let authors: HashSet<String> = messages_with_tokens
+                .iter()
+                .map(|message| message.message.pubkey.to_hex())
+                .collect();
+            annotations.retain(|pubkey, _| authors.contains(pubkey));
             Ok(GroupAndMessages {
                 group,
                 messages: messages_with_tokens,
+                annotations,
             })
         } else {
             Err("Group not found".to_string())
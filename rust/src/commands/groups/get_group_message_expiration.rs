@@ -0,0 +1,32 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+use crate::accounts::Account;
+use crate::disappearing_messages;
+use crate::whitenoise::Whitenoise;
+
+/// Gets the disappearing message timer of a group
+///
+/// # Arguments
+/// * `group_id` - Hex encoded MLS group ID
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Option<u64>)` - The timer in seconds, or `None` if disappearing messages are off
+/// * `Err(String)` - Error message if the lookup fails
+
+pub async fn get_group_message_expiration(
+    group_id: &str,
+    wn: Arc<Whitenoise>,
+) -> Result<Option<u64>, String> {
+    let mls_group_id = GroupId::from_slice(
+        &hex::decode(group_id).map_err(|e| format!("Error decoding group id: {}", e))?,
+    );
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    disappearing_messages::group_expiration(&account_pubkey, &mls_group_id, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
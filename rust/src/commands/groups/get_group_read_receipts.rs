@@ -0,0 +1,32 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+use crate::accounts::Account;
+use crate::read_markers::{self, ReadReceipt};
+use crate::whitenoise::Whitenoise;
+
+/// Gets the read receipts other members have sent to a group
+///
+/// # Arguments
+/// * `group_id` - Hex encoded MLS group ID
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<ReadReceipt>)` - The latest receipt of every member that sends them
+/// * `Err(String)` - Error message if the lookup fails
+
+pub async fn get_group_read_receipts(
+    group_id: &str,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<ReadReceipt>, String> {
+    let mls_group_id = GroupId::from_slice(
+        &hex::decode(group_id).map_err(|e| format!("Error decoding group id: {}", e))?,
+    );
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    read_markers::read_receipts(&account_pubkey, &mls_group_id, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,90 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+use super::GroupSummary;
+use crate::accounts::Account;
+use crate::disappearing_messages;
+use crate::mute_list;
+use crate::read_markers;
+use crate::whitenoise::Whitenoise;
+
+/// Gets the active groups of the active account along with their unread counts
+///
+/// Unread counts don't include our own messages, reactions, control messages, expired messages
+/// or messages from muted people.
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<GroupSummary>)` - The active groups with their unread counts and read markers
+/// * `Err(String)` - Error message if retrieval fails
+///
+/// # Errors
+/// Returns error if:
+/// - No active account found
+/// - Error fetching groups or messages
+/// - Database error occurs
+
+pub async fn get_group_summaries(wn: Arc<Whitenoise>) -> Result<Vec<GroupSummary>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let groups_and_messages = {
+        let nostr_mls_guard = match timeout(Duration::from_secs(5), wn.nostr_mls.lock()).await {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!(target: "whitenoise::commands::groups::get_group_summaries", "Timeout waiting for nostr_mls lock");
+                return Err("Timeout waiting for nostr_mls lock".to_string());
+            }
+        };
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or_else(|| "Nostr MLS not initialized".to_string())?;
+
+        let mut groups_and_messages = Vec::new();
+        for group in nostr_mls
+            .get_groups()
+            .map_err(|e| format!("Error fetching groups for account: {}", e))?
+            .into_iter()
+            .filter(|group| group.state == group_types::GroupState::Active)
+        {
+            let messages = nostr_mls
+                .get_messages(&group.mls_group_id)
+                .map_err(|e| format!("Error fetching messages: {}", e))?;
+            groups_and_messages.push((group, messages));
+        }
+        groups_and_messages
+    };
+
+    let muted = mute_list::muted_pubkey_set(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+    let expired = disappearing_messages::expired_message_ids(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mut summaries = Vec::with_capacity(groups_and_messages.len());
+    for (group, mut messages) in groups_and_messages {
+        // Messages from muted people and expired messages don't count as unread
+        messages.retain(|message| {
+            !muted.contains(&message.pubkey) && !expired.contains(&message.id.to_hex())
+        });
+        let read_marker =
+            read_markers::read_marker(&account_pubkey, &group.mls_group_id, &wn.database)
+                .await
+                .map_err(|e| e.to_string())?;
+        let unread_count =
+            read_markers::unread_count(&account_pubkey, &messages, read_marker.as_ref());
+        summaries.push(GroupSummary {
+            group,
+            unread_count,
+            read_marker,
+        });
+    }
+
+    Ok(summaries)
+}
@@ -0,0 +1,35 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+use crate::accounts::Account;
+use crate::typing_indicators;
+use crate::whitenoise::Whitenoise;
+
+/// Gets the members that are currently typing in a group
+///
+/// # Arguments
+/// * `group_id` - Hex encoded MLS group ID
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - Hex encoded pubkeys of the members that are typing
+/// * `Err(String)` - Error message if the lookup fails
+
+pub async fn get_typing_members(
+    group_id: &str,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<String>, String> {
+    let mls_group_id = GroupId::from_slice(
+        &hex::decode(group_id).map_err(|e| format!("Error decoding group id: {}", e))?,
+    );
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(
+        typing_indicators::typing_members(&account_pubkey, &mls_group_id)
+            .iter()
+            .map(|pubkey| pubkey.to_hex())
+            .collect(),
+    )
+}
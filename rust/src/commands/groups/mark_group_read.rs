@@ -0,0 +1,92 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::timeout;
+
+use crate::accounts::Account;
+use crate::commands::groups::send_mls_message;
+use crate::read_markers::{self, READ_RECEIPT_KIND};
+use crate::whitenoise::Whitenoise;
+
+/// Marks every message in a group as read for the active account
+///
+/// If the account has read receipts turned on, the other members of the group are told which
+/// message we've read up to.
+///
+/// # Arguments
+/// * `group` - The MLS group to mark as read
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(())` - If the group was marked as read
+/// * `Err(String)` - Error message if the operation fails
+///
+/// # Errors
+/// Returns error if:
+/// - No active account found
+/// - Error fetching messages
+/// - Database error occurs
+
+pub async fn mark_group_read(group: group_types::Group, wn: Arc<Whitenoise>) -> Result<(), String> {
+    let account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let account_pubkey = account.pubkey;
+
+    let latest_message = {
+        let nostr_mls_guard = match timeout(Duration::from_secs(5), wn.nostr_mls.lock()).await {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!(target: "whitenoise::commands::groups::mark_group_read", "Timeout waiting for nostr_mls lock");
+                return Err("Timeout waiting for nostr_mls lock".to_string());
+            }
+        };
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or_else(|| "Nostr MLS not initialized".to_string())?;
+        nostr_mls
+            .get_messages(&group.mls_group_id)
+            .map_err(|e| format!("Error fetching messages: {}", e))?
+            .into_iter()
+            .filter(|message| !message.kind.is_ephemeral())
+            .max_by_key(|message| message.created_at)
+    };
+
+    let Some(latest_message) = latest_message else {
+        return Ok(());
+    };
+
+    let previous_marker =
+        read_markers::read_marker(&account_pubkey, &group.mls_group_id, &wn.database)
+            .await
+            .map_err(|e| e.to_string())?;
+    read_markers::mark_read(&account_pubkey, &latest_message, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let already_read = previous_marker
+        .and_then(|marker| marker.last_read_message_id)
+        .is_some_and(|id| id == latest_message.id.to_hex());
+
+    if account.settings.send_read_receipts
+        && !already_read
+        && read_markers::is_countable(&account_pubkey, &latest_message)
+    {
+        tracing::debug!(
+            target: "whitenoise::commands::groups::mark_group_read",
+            "Sending read receipt for message {}",
+            latest_message.id.to_hex()
+        );
+        send_mls_message(
+            group,
+            String::new(),
+            READ_RECEIPT_KIND,
+            Some(read_markers::read_receipt_tags(&latest_message.id)),
+            None,
+            wn,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
@@ -1,34 +1,62 @@
 use nostr_mls::prelude::*;
 use serde::Serialize;
+use std::collections::HashMap;
 
+use crate::contact_annotations::ContactAnnotation;
 use crate::nostr_manager::parser::SerializableToken;
+use crate::read_markers::ReadMarker;
 
 mod create_group;
 mod delete_message;
 mod get_active_groups;
+mod get_annotated_group_members;
 mod get_group;
 mod get_group_admins;
 mod get_group_and_messages;
 mod get_group_members;
+mod get_group_message_expiration;
+mod get_group_read_receipts;
 mod get_group_relays;
+mod get_group_summaries;
+mod get_typing_members;
+mod mark_group_read;
 mod rotate_key_in_group;
 mod send_mls_message;
+mod send_typing_indicator;
+mod set_group_message_expiration;
 
 pub use create_group::create_group;
 pub use delete_message::delete_message;
 pub use get_active_groups::get_active_groups;
+pub use get_annotated_group_members::get_annotated_group_members;
 pub use get_group::get_group;
 pub use get_group_admins::get_group_admins;
 pub use get_group_and_messages::get_group_and_messages;
 pub use get_group_members::get_group_members;
+pub use get_group_message_expiration::get_group_message_expiration;
+pub use get_group_read_receipts::get_group_read_receipts;
 pub use get_group_relays::get_group_relays;
+pub use get_group_summaries::get_group_summaries;
+pub use get_typing_members::get_typing_members;
+pub use mark_group_read::mark_group_read;
 pub use rotate_key_in_group::rotate_key_in_group;
 pub use send_mls_message::send_mls_message;
+pub use send_typing_indicator::send_typing_indicator;
+pub use set_group_message_expiration::set_group_message_expiration;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct GroupAndMessages {
     group: group_types::Group,
     messages: Vec<MessageWithTokens>,
+    /// Our annotations of the message authors, keyed by hex encoded pubkey
+    annotations: HashMap<String, ContactAnnotation>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupMember {
+    /// Hex encoded pubkey
+    pubkey: String,
+    annotation: Option<ContactAnnotation>,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -36,3 +64,10 @@ pub struct MessageWithTokens {
     message: message_types::Message,
     tokens: Vec<SerializableToken>,
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupSummary {
+    group: group_types::Group,
+    unread_count: u64,
+    read_marker: Option<ReadMarker>,
+}
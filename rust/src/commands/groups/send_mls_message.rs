@@ -9,8 +9,11 @@ use nostr_mls::prelude::*;
 use nostr_sdk::prelude::*;
 
 use super::MessageWithTokens;
+use crate::disappearing_messages::{self, EXPIRATION_SETTING_KIND};
 use crate::media::{add_media_file, FileUpload};
+use crate::message_search;
 use crate::nostr_manager::parser::parse;
+use crate::outbox;
 use crate::whitenoise::Whitenoise;
 
 pub async fn send_mls_message(
@@ -22,9 +25,27 @@ pub async fn send_mls_message(
     wn: Arc<Whitenoise>,
 ) -> Result<MessageWithTokens, String> {
     let nostr_keys = wn.nostr.client.signer().await.map_err(|e| e.to_string())?;
+    let account_pubkey = nostr_keys
+        .get_public_key()
+        .await
+        .map_err(|e| e.to_string())?;
     let mut final_tags = tags.unwrap_or_default();
     let mut final_content = message;
 
+    // Messages in groups with disappearing messages turned on get a NIP-40 expiration tag
+    if kind != EXPIRATION_SETTING_KIND {
+        if let Some(expiration_tag) = disappearing_messages::expiration_tag(
+            &account_pubkey,
+            &group.mls_group_id,
+            &wn.database,
+        )
+        .await
+        .map_err(|e| e.to_string())?
+        {
+            final_tags.push(expiration_tag);
+        }
+    }
+
     tracing::debug!(target: "whitenoise::commands::groups::send_mls_message", "Sending MLSMessage event to group: {:?}", group);
 
     // Process media files if present
@@ -133,18 +154,64 @@ pub async fn send_mls_message(
 
     if let Some(relays) = relays {
         if let Some(event_to_publish) = event_to_publish {
-            let result = wn
-                .nostr
-                .client
-                .send_event_to(relays, &event_to_publish)
-                .await
-                .map_err(|e| e.to_string())?;
-
-            tracing::debug!(target: "whitenoise::commands::groups::send_mls_message", "Event sent to relays: {:?}", result);
+            // Ephemeral signals (typing indicators, read receipts) are sent once and never retried
+            if Kind::from(kind).is_ephemeral() {
+                if let Err(e) = wn
+                    .nostr
+                    .client
+                    .send_event_to(relays, &event_to_publish)
+                    .await
+                {
+                    tracing::warn!(target: "whitenoise::commands::groups::send_mls_message", "Failed to send ephemeral event: {}", e);
+                }
+                return message
+                    .map(|message| MessageWithTokens {
+                        message,
+                        tokens: vec![],
+                    })
+                    .ok_or_else(|| "Message not found".to_string());
+            }
+
+            // Persist the event first so it gets retried if some relays are unreachable
+            let relays: Vec<RelayUrl> = relays.into_iter().collect();
+            outbox::enqueue(
+                &account_pubkey,
+                inner_event.id,
+                &event_to_publish,
+                &relays,
+                &wn.database,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+
+            match outbox::deliver(&account_pubkey, &event_to_publish.id, wn.clone()).await {
+                Ok(status) => {
+                    tracing::debug!(target: "whitenoise::commands::groups::send_mls_message", "Event sent to relays: {:?}", status);
+                }
+                Err(e) => {
+                    tracing::warn!(target: "whitenoise::commands::groups::send_mls_message", "Failed to deliver event, it will be retried: {}", e);
+                }
+            }
         }
     }
 
     if let Some(message) = message {
+        if let Err(e) = message_search::index_message(&account_pubkey, &message, &wn.database).await
+        {
+            tracing::warn!(
+                target: "whitenoise::commands::groups::send_mls_message",
+                "Failed to index message for search: {}",
+                e
+            );
+        }
+        disappearing_messages::handle_message(
+            &account_pubkey,
+            &message,
+            &group.admin_pubkeys,
+            &wn.database,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
         let tokens = parse(&message.content);
         // app_handle
         //     .emit("mls_message_sent", (&group, &message))
@@ -0,0 +1,29 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+use crate::typing_indicators;
+use crate::whitenoise::Whitenoise;
+
+/// Lets the other members of a group know that we're typing, or that we stopped
+///
+/// The indicator is an ephemeral event that's sent once, isn't retried and isn't stored.
+/// Clients should resend it every few seconds while the user keeps typing.
+///
+/// # Arguments
+/// * `group` - The MLS group we're typing in
+/// * `is_typing` - `false` to clear the indicator before it expires
+/// * `wn` - Whitenoise state handle
+///
+/// # Returns
+/// * `Ok(())` - If the indicator was sent
+/// * `Err(String)` - Error message if creating or sending the indicator fails
+
+pub async fn send_typing_indicator(
+    group: group_types::Group,
+    is_typing: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<(), String> {
+    typing_indicators::send_indicator(&group, is_typing, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
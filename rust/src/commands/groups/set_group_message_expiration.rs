@@ -0,0 +1,48 @@
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+use super::MessageWithTokens;
+use crate::accounts::Account;
+use crate::commands::groups::send_mls_message;
+use crate::disappearing_messages::EXPIRATION_SETTING_KIND;
+use crate::whitenoise::Whitenoise;
+
+/// Turns disappearing messages on or off for a group
+///
+/// Sends a timer announcement to the group so that every member applies the same setting.
+/// Messages sent after the announcement carry a NIP-40 expiration tag and are purged from
+/// every member's device once they expire. Only group admins can change the timer.
+///
+/// # Arguments
+/// * `group` - The MLS group to update
+/// * `expiration_seconds` - How long messages live for, `None` turns disappearing messages off
+/// * `wn` - Whitenoise state handle
+///
+/// # Returns
+/// * `Ok(MessageWithTokens)` - The timer announcement that was sent
+/// * `Err(String)` - Error message if we aren't a group admin or sending the announcement fails
+
+pub async fn set_group_message_expiration(
+    group: group_types::Group,
+    expiration_seconds: Option<u64>,
+    wn: Arc<Whitenoise>,
+) -> Result<MessageWithTokens, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    if !group.admin_pubkeys.contains(&account_pubkey) {
+        return Err("Only group admins can change the message expiration".to_string());
+    }
+
+    let content = expiration_seconds
+        .map(|seconds| seconds.to_string())
+        .unwrap_or_default();
+
+    tracing::debug!(
+        target: "whitenoise::commands::groups::set_group_message_expiration",
+        "Setting message expiration to {:?}",
+        expiration_seconds
+    );
+
+    send_mls_message(group, content, EXPIRATION_SETTING_KIND, None, None, wn).await
+}
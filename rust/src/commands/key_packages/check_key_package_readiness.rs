@@ -0,0 +1,28 @@
+use crate::key_packages::{self, KeyPackageReadiness};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Checks which of several users can be invited to a group right now
+///
+/// # Arguments
+/// * `pubkeys` - Users to check, as hex pubkeys, npubs, nprofiles or `nostr:` URIs
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<KeyPackageReadiness>)` - One entry per user, in the order given, with the newest
+///   compatible key package, the relays it was found on and its age
+/// * `Err(String)` - Error message if the check couldn't run at all
+///
+/// # Errors
+/// Returns error if:
+/// - NostrMls is not initialized or its lock times out
+///
+/// Invalid pubkeys and relay failures are reported in the entry of the user they concern.
+pub async fn check_key_package_readiness(
+    pubkeys: Vec<String>,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<KeyPackageReadiness>, String> {
+    key_packages::check_key_package_readiness(&pubkeys, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
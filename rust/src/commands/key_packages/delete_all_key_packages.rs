@@ -1,4 +1,5 @@
 use crate::accounts::Account;
+use crate::key_package_lifecycle;
 use crate::relays::RelayType;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::event::EventBuilder;
@@ -40,6 +41,10 @@ pub async fn delete_all_key_packages(wn: Arc<Whitenoise>) -> Result<(), String>
             .send_event_builder_to(key_package_relays, delete_event)
             .await
             .map_err(|e| e.to_string())?;
+        let event_ids: Vec<_> = key_package_events.iter().map(|e| e.id).collect();
+        key_package_lifecycle::mark_deleted(&active_account.pubkey, &event_ids, &wn.database)
+            .await
+            .map_err(|e| e.to_string())?;
     } else {
         tracing::debug!(target: "whitenoise::commands::key_packages::delete_all_key_packages", "No key packages to delete");
     }
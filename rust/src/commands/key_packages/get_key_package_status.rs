@@ -0,0 +1,28 @@
+use crate::key_package_lifecycle::{self, RelayKeyPackageStatus};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Gets which of our key packages each of our key package relays has
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<RelayKeyPackageStatus>)` - One entry per key package relay, with our current key
+///   package if the relay has it, the replaced ones it still has and the ones we didn't publish
+///   from this device
+/// * `Err(String)` - Error message if the status couldn't be checked
+///
+/// # Errors
+/// Returns error if:
+/// - No active account found
+/// - The published key packages can't be read from the database
+///
+/// A relay that can't be reached is reported in its own entry.
+pub async fn get_key_package_status(
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<RelayKeyPackageStatus>, String> {
+    key_package_lifecycle::key_package_status(wn)
+        .await
+        .map_err(|e| e.to_string())
+}
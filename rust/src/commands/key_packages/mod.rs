@@ -1,7 +1,11 @@
+mod check_key_package_readiness;
 mod delete_all_key_packages;
+mod get_key_package_status;
 mod publish_new_key_package;
 mod valid_key_package_exists_for_user;
 
+pub use check_key_package_readiness::check_key_package_readiness;
 pub use delete_all_key_packages::delete_all_key_packages;
+pub use get_key_package_status::get_key_package_status;
 pub use publish_new_key_package::publish_new_key_package;
 pub use valid_key_package_exists_for_user::valid_key_package_exists_for_user;
@@ -0,0 +1,35 @@
+use crate::identifiers;
+use crate::media::{self, ProfileImageKind};
+use crate::whitenoise::Whitenoise;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Gets local thumbnails of the profile pictures or banners of several people
+///
+/// Images are downloaded once, through the proxy if there is one, sanitized, shrunk and cached,
+/// so showing them doesn't contact their image host again. Use the returned paths instead of
+/// the URLs in the metadata.
+///
+/// # Arguments
+///
+/// * `pubkeys` - The people, as hex pubkeys, npubs, nprofiles or `nostr:` URIs
+/// * `kind` - Avatars or banners
+/// * `wn` - The Whitenoise application state
+///
+/// # Returns
+///
+/// * `Ok(HashMap<String, Option<String>>)` - The path of each person's thumbnail, keyed by hex
+///   encoded pubkey. `None` if they have no image or it couldn't be downloaded.
+/// * `Err(String)` - An error message if a pubkey is invalid
+pub async fn get_profile_image_paths(
+    pubkeys: Vec<String>,
+    kind: ProfileImageKind,
+    wn: Arc<Whitenoise>,
+) -> Result<HashMap<String, Option<String>>, String> {
+    let pubkeys = pubkeys
+        .iter()
+        .map(|pubkey| identifiers::parse_pubkey(pubkey))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(media::profile_image_paths(&pubkeys, kind, wn).await)
+}
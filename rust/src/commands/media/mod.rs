@@ -1,4 +1,6 @@
+mod get_profile_image_paths;
 mod upload_file;
 mod upload_media;
+pub use get_profile_image_paths::get_profile_image_paths;
 pub use upload_file::upload_file;
 pub use upload_media::upload_media;
@@ -35,7 +35,7 @@ pub async fn upload_media(file: FileUpload, wn: Arc<Whitenoise>) -> Result<Strin
     // Upload the file to Blossom
     let blob_descriptor = wn
         .nostr
-        .blossom
+        .blossom()
         .upload_media(file.data, &file.mime_type, &keys)
         .await
         .map_err(|e| format!("Failed to upload file to Blossom: {}", e))?;
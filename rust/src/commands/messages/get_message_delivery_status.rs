@@ -0,0 +1,35 @@
+use crate::accounts::Account;
+use crate::outbox::{self, DeliveryStatus};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Gets the delivery status of a message sent by the active account
+///
+/// # Arguments
+/// * `message_id` - Hex encoded event id of the message
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Some(DeliveryStatus))` - Per-relay delivery status of the message
+/// * `Ok(None)` - If the message wasn't sent from this device
+/// * `Err(String)` - Error message if the lookup fails
+///
+/// # Errors
+/// Returns error if:
+/// - No active account found
+/// - Message ID is not valid hex
+/// - Database error occurs
+pub async fn get_message_delivery_status(
+    message_id: String,
+    wn: Arc<Whitenoise>,
+) -> Result<Option<DeliveryStatus>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let message_id = EventId::from_hex(&message_id).map_err(|e| e.to_string())?;
+
+    outbox::delivery_status_for_message(&account_pubkey, &message_id, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
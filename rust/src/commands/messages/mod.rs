@@ -1,3 +1,7 @@
+mod get_message_delivery_status;
 mod query_message;
+mod search_messages;
 
+pub use get_message_delivery_status::get_message_delivery_status;
 pub use query_message::query_message;
+pub use search_messages::search_messages;
@@ -0,0 +1,57 @@
+use crate::accounts::Account;
+use crate::message_search::{self, MessageSearchResult, DEFAULT_SEARCH_LIMIT};
+use crate::whitenoise::Whitenoise;
+use nostr_mls::prelude::*;
+use std::sync::Arc;
+
+/// Searches the decrypted messages of the active account
+///
+/// # Arguments
+/// * `query` - Free-form search text
+/// * `group_id` - Optional hex encoded MLS group ID to limit the search to a single group
+/// * `limit` - Optional maximum number of results, defaults to 50
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<MessageSearchResult>)` - Matching messages ordered by relevance, with highlighted snippets
+/// * `Err(String)` - Error message if the search fails
+///
+/// # Errors
+/// Returns error if:
+/// - No active account found
+/// - Group ID is not valid hex
+/// - Database error occurs
+
+pub async fn search_messages(
+    query: String,
+    group_id: Option<String>,
+    limit: Option<u32>,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<MessageSearchResult>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let mls_group_id = match group_id {
+        Some(group_id) => Some(GroupId::from_slice(
+            &hex::decode(group_id).map_err(|e| format!("Error decoding group id: {}", e))?,
+        )),
+        None => None,
+    };
+
+    tracing::debug!(
+        target: "whitenoise::commands::messages::search_messages",
+        "Searching messages in group: {:?}",
+        mls_group_id
+    );
+
+    message_search::search(
+        &account_pubkey,
+        &query,
+        mls_group_id.as_ref(),
+        limit.unwrap_or(DEFAULT_SEARCH_LIMIT),
+        &wn.database,
+    )
+    .await
+    .map_err(|e| e.to_string())
+}
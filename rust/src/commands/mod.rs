@@ -2,6 +2,7 @@ use crate::whitenoise::Whitenoise;
 use std::sync::Arc;
 
 pub mod accounts;
+pub mod direct_messages;
 pub mod groups;
 pub mod key_packages;
 pub mod media;
@@ -0,0 +1,40 @@
+use crate::accounts::Account;
+use crate::nostr_manager::relay_info::RelayWarning;
+use crate::nostr_manager::router::relay_type_for_kind;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Checks whether relays are a good fit for one of the relay lists
+///
+/// Uses the relays' NIP-11 documents to warn about inbox and key package relays missing NIPs
+/// they need, requiring payment or restricting writes.
+///
+/// # Arguments
+/// * `relays` - The relays to check, or `None` for the relays already in the active account's list
+/// * `kind` - The relay list kind: `10002` (Nostr), `10050` (inbox) or `10051` (key package)
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<RelayWarning>)` - Everything that might go wrong, empty if the relays look fine
+/// * `Err(String)` - Error message if the kind is invalid or the stored relays couldn't be loaded
+pub async fn check_relays(
+    relays: Option<Vec<String>>,
+    kind: u64,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<RelayWarning>, String> {
+    let relay_type = relay_type_for_kind(Kind::from(kind as u16))
+        .ok_or_else(|| "Invalid relay list kind".to_string())?;
+
+    let relays = match relays {
+        Some(relays) => relays,
+        None => Account::get_active(wn.clone())
+            .await
+            .map_err(|e| e.to_string())?
+            .relays(relay_type, wn.clone())
+            .await
+            .map_err(|e| e.to_string())?,
+    };
+
+    Ok(wn.nostr.check_relays(&relays, relay_type).await)
+}
@@ -0,0 +1,36 @@
+use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::identifiers;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Removes the nickname, note and pin the active account has for someone
+///
+/// If the account syncs annotations between its devices, the removal is synced too.
+///
+/// # Arguments
+/// * `pubkey` - The contact, as a hex pubkey, npub, nprofile or `nostr:` URI
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(())` - If the annotation was removed or there was none
+/// * `Err(String)` - Error message if there is no active account, the pubkey is invalid or the
+///   database can't be written
+pub async fn clear_contact_annotation(pubkey: String, wn: Arc<Whitenoise>) -> Result<(), String> {
+    let pubkey = identifiers::parse_pubkey(&pubkey).map_err(|e| e.to_string())?;
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    contact_annotations::clear_annotation(&account_pubkey, &pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Err(e) = contact_annotations::publish_annotations(wn.clone()).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::clear_contact_annotation",
+            "Failed to sync annotations: {}",
+            e
+        );
+    }
+    Ok(())
+}
@@ -1,4 +1,6 @@
 use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::nip05;
 use crate::relays::RelayType;
 use crate::types::EnrichedContact;
 use crate::whitenoise::Whitenoise;
@@ -17,7 +19,7 @@ pub async fn fetch_enriched_contact(
         .fetch_user_metadata(pubkey)
         .await
         .map_err(|_| "Failed to get metadata".to_string())?;
-    let nostr_relays = wn
+    let mut nostr_relays = wn
         .nostr
         .fetch_user_relays(pubkey)
         .await
@@ -38,13 +40,26 @@ pub async fn fetch_enriched_contact(
         .await
         .map_err(|_| "Failed to get key packages".to_string())?;
 
+    let metadata = metadata.unwrap_or_default();
+    let verification = nip05::verify_metadata(&pubkey, &metadata, wn.clone()).await;
+    // Relays from the NIP-05 document help find people without a relay list
+    if nostr_relays.is_empty() {
+        if let Some(verification) = &verification {
+            nostr_relays = verification.relays.clone();
+        }
+    }
+
     let enriched_contact = EnrichedContact {
-        metadata: metadata.unwrap_or_default(),
+        metadata,
         nip17: !inbox_relays.is_empty(),
         nip104: !key_packages.is_empty(),
+        nip05_status: verification
+            .map(|verification| verification.status)
+            .unwrap_or_default(),
         nostr_relays,
         inbox_relays,
         key_package_relays,
+        annotation: contact_annotations::active_annotation(&pubkey, wn.clone()).await,
     };
 
     if update_account {
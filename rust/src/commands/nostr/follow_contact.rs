@@ -0,0 +1,27 @@
+use crate::contact_list::{self, ContactListUpdate};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Follows a contact by publishing an edited contact list (NIP-02)
+///
+/// The latest contact list is edited in place, so relay hints, petnames and tags added by
+/// other clients are kept. Following someone we already follow is a no-op and publishes nothing.
+///
+/// # Arguments
+/// * `pubkey` - Hex encoded public key of the contact
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(ContactListUpdate)` - Whether the list changed, and everyone we follow now
+/// * `Err(String)` - Error message if there is no active account, the pubkey is invalid or is our own,
+/// or no relay accepted the new list
+pub async fn follow_contact(
+    pubkey: String,
+    wn: Arc<Whitenoise>,
+) -> Result<ContactListUpdate, String> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| "Invalid pubkey".to_string())?;
+    contact_list::follow(&pubkey, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,24 @@
+use crate::accounts::Account;
+use crate::contact_annotations::{self, ContactAnnotation};
+use crate::whitenoise::Whitenoise;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Gets every nickname, note and pin the active account has for people
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(HashMap<String, ContactAnnotation>)` - Annotations keyed by hex encoded pubkey
+/// * `Err(String)` - Error message if there is no active account or the database can't be read
+pub async fn get_contact_annotations(
+    wn: Arc<Whitenoise>,
+) -> Result<HashMap<String, ContactAnnotation>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    contact_annotations::annotations(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
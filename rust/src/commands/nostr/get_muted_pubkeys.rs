@@ -0,0 +1,21 @@
+use crate::accounts::Account;
+use crate::mute_list::{self, MutedPubkey};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Gets everyone the active account muted
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<MutedPubkey>)` - Muted pubkeys, and whether each entry is private
+/// * `Err(String)` - Error message if there is no active account or the database can't be read
+pub async fn get_muted_pubkeys(wn: Arc<Whitenoise>) -> Result<Vec<MutedPubkey>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    mute_list::muted_pubkeys(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
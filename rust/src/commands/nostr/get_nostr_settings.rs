@@ -0,0 +1,15 @@
+use crate::nostr_manager::NostrManagerSettings;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Gets the Nostr settings currently in effect
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(NostrManagerSettings)` - The active account's settings, or the global ones if it has none
+/// * `Err(String)` - Error message if the settings couldn't be read
+pub async fn get_nostr_settings(wn: Arc<Whitenoise>) -> Result<NostrManagerSettings, String> {
+    Ok(wn.nostr.settings.lock().await.clone())
+}
@@ -0,0 +1,24 @@
+use crate::accounts::Account;
+use crate::nostr_manager::auth::{self, RelayAuthSetting};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Lists the relays the active account allowed or refused to authenticate to
+///
+/// Relays that aren't listed use the default, which is to authenticate.
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<RelayAuthSetting>)` - The per-relay settings, ordered by URL
+/// * `Err(String)` - Error message if retrieval fails
+pub async fn get_relay_auth_settings(wn: Arc<Whitenoise>) -> Result<Vec<RelayAuthSetting>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    auth::relay_auth_settings(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
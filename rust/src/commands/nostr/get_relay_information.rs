@@ -0,0 +1,28 @@
+use crate::nostr_manager::relay_info::RelayInformation;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Gets the NIP-11 information document of a relay
+///
+/// Documents are cached for a day.
+///
+/// # Arguments
+/// * `relay_url` - The relay to describe
+/// * `refresh` - Fetch the document again even if we have a fresh one cached
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(RelayInformation)` - The relay's name, software, supported NIPs and limitations
+/// * `Err(String)` - Error message if the URL is invalid or the relay didn't answer
+pub async fn get_relay_information(
+    relay_url: String,
+    refresh: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<RelayInformation, String> {
+    let relay_url = RelayUrl::parse(&relay_url).map_err(|e| e.to_string())?;
+    wn.nostr
+        .relay_information(&relay_url, refresh)
+        .await
+        .map_err(|e| e.to_string())
+}
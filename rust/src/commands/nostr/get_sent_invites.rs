@@ -0,0 +1,22 @@
+use crate::accounts::Account;
+use crate::invites::{self, Invite};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Lists the people the active account invited to White Noise
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<Invite>)` - Invites ordered by when they were sent, most recent first
+/// * `Err(String)` - Error message if retrieval fails
+pub async fn get_sent_invites(wn: Arc<Whitenoise>) -> Result<Vec<Invite>, String> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    invites::invites(&account_pubkey, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
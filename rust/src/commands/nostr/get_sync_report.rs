@@ -0,0 +1,16 @@
+use crate::nostr_manager::sync::SyncReport;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Returns the report of the last sync
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Some(SyncReport))` - The results of the last sync, per relay and per category
+/// * `Ok(None)` - If nothing was synced since the relay pool started
+/// * `Err(String)` - Error message if the report couldn't be retrieved
+pub async fn get_sync_report(wn: Arc<Whitenoise>) -> Result<Option<SyncReport>, String> {
+    Ok(wn.nostr.last_sync_report())
+}
@@ -1,30 +1,36 @@
-use crate::types::NostrEncryptionMethod;
+use crate::identifiers;
+use crate::invites::{self, Invite};
 use crate::whitenoise::Whitenoise;
-use nostr_sdk::prelude::*;
 use std::sync::Arc;
 
-pub async fn invite_to_white_noise(pubkey: String, wn: Arc<Whitenoise>) -> Result<(), String> {
-    let public_key = PublicKey::from_hex(&pubkey).map_err(|e| e.to_string())?;
-    let content = "Hi, I'm using White Noise to chat securely on Nostr. Join me! https://github.com/parres-hq/whitenoise/releases".to_string();
-    let encrypted_content = wn
-        .nostr
-        .encrypt_content(content, pubkey, NostrEncryptionMethod::Nip04)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    let event = EventBuilder::new(Kind::EncryptedDirectMessage, encrypted_content)
-        .tag(Tag::public_key(public_key));
+/// Invites someone to White Noise
+///
+/// The invite is sent as a NIP-17 direct message to the invitee's inbox relays, falling back to
+/// a NIP-04 direct message if they don't publish any.
+///
+/// # Arguments
+/// * `pubkey` - Public key of the invitee, as hex, npub, nprofile or `nostr:` URI
+/// * `message` - Optional custom message, defaults to a short introduction with a download link
+/// * `force` - Send the invite even if we've invited this person recently
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Invite)` - The record of the invite that was sent
+/// * `Err(String)` - Error message if the invite couldn't be sent
+pub async fn invite_to_white_noise(
+    pubkey: String,
+    message: Option<String>,
+    force: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Invite, String> {
+    let public_key = identifiers::parse_pubkey(&pubkey).map_err(|e| e.to_string())?;
 
     tracing::debug!(
         target: "whitenoise::commands::nostr::invite_to_white_noise",
-        "Sending event: {:?}",
-        event
+        "Inviting {}",
+        pubkey
     );
-    wn.nostr
-        .client
-        .send_event_builder(event)
+    invites::send_invite(&public_key, message, force, wn)
         .await
-        .map_err(|e| e.to_string())?;
-
-    Ok(())
+        .map_err(|e| e.to_string())
 }
@@ -0,0 +1,20 @@
+use crate::nip05::{self, Nip05Contact};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Finds a contact by their NIP-05 address
+///
+/// The address is resolved to a pubkey, then the contact's metadata and relay lists are
+/// fetched, using the relays listed in the NIP-05 document.
+///
+/// # Arguments
+/// * `nip05` - The `name@domain` address, or just a domain for `_@domain`
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Nip05Contact)` - The contact's pubkey and enriched profile
+/// * `Err(String)` - Error message if the address is invalid, unknown to the domain or the
+///   domain couldn't be reached
+pub async fn lookup_nip05(nip05: String, wn: Arc<Whitenoise>) -> Result<Nip05Contact, String> {
+    nip05::lookup(&nip05, wn).await.map_err(|e| e.to_string())
+}
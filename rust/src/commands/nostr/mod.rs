@@ -1,3 +1,6 @@
+mod add_relays;
+mod check_relays;
+mod clear_contact_annotation;
 mod decrypt_content;
 mod encrypt_content;
 mod export_nsec;
@@ -5,14 +8,40 @@ mod fetch_contacts_with_metadata;
 mod fetch_enriched_contact;
 mod fetch_enriched_contacts;
 mod fetch_relays;
+mod follow_contact;
+mod get_contact_annotations;
+mod get_muted_pubkeys;
+mod get_nostr_settings;
+mod get_relay_auth_settings;
+mod get_relay_information;
+mod get_sent_invites;
+mod get_sync_report;
 mod init_nostr_for_current_user;
 mod invite_to_white_noise;
+mod lookup_nip05;
+mod mute_pubkey;
 mod publish_relay_list;
 mod query_contacts_with_metadata;
 mod query_enriched_contact;
 mod query_enriched_contacts;
+mod relay_status;
+mod remove_relays;
+mod republish_relay_list;
+mod reset_nostr_settings;
+mod resolve_identifier;
+mod search_contacts;
 mod search_for_enriched_contacts;
+mod set_contact_annotation;
+mod set_relay_auth;
+mod sync_account;
+mod unfollow_contact;
+mod unmute_pubkey;
+mod update_nostr_settings;
+mod verify_nip05;
 
+pub use add_relays::add_relays;
+pub use check_relays::check_relays;
+pub use clear_contact_annotation::clear_contact_annotation;
 pub use decrypt_content::decrypt_content;
 pub use encrypt_content::encrypt_content;
 pub use export_nsec::export_nsec;
@@ -20,10 +49,33 @@ pub use fetch_contacts_with_metadata::fetch_contacts_with_metadata;
 pub use fetch_enriched_contact::fetch_enriched_contact;
 pub use fetch_enriched_contacts::fetch_enriched_contacts;
 pub use fetch_relays::fetch_relays;
+pub use follow_contact::follow_contact;
+pub use get_contact_annotations::get_contact_annotations;
+pub use get_muted_pubkeys::get_muted_pubkeys;
+pub use get_nostr_settings::get_nostr_settings;
+pub use get_relay_auth_settings::get_relay_auth_settings;
+pub use get_relay_information::get_relay_information;
+pub use get_sent_invites::get_sent_invites;
+pub use get_sync_report::get_sync_report;
 pub use init_nostr_for_current_user::init_nostr_for_current_user;
 pub use invite_to_white_noise::invite_to_white_noise;
+pub use lookup_nip05::lookup_nip05;
+pub use mute_pubkey::mute_pubkey;
 pub use publish_relay_list::publish_relay_list;
 pub use query_contacts_with_metadata::query_contacts_with_metadata;
 pub use query_enriched_contact::query_enriched_contact;
 pub use query_enriched_contacts::query_enriched_contacts;
+pub use relay_status::relay_status;
+pub use remove_relays::remove_relays;
+pub use republish_relay_list::republish_relay_list;
+pub use reset_nostr_settings::reset_nostr_settings;
+pub use resolve_identifier::resolve_identifier;
+pub use search_contacts::search_contacts;
 pub use search_for_enriched_contacts::search_for_enriched_contacts;
+pub use set_contact_annotation::set_contact_annotation;
+pub use set_relay_auth::set_relay_auth;
+pub use sync_account::sync_account;
+pub use unfollow_contact::unfollow_contact;
+pub use unmute_pubkey::unmute_pubkey;
+pub use update_nostr_settings::update_nostr_settings;
+pub use verify_nip05::verify_nip05;
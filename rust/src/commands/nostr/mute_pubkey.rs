@@ -0,0 +1,29 @@
+use crate::mute_list::{self, MutedPubkey};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Mutes someone by publishing an edited mute list (NIP-51)
+///
+/// Muted people's welcomes are declined, their group messages hidden and they're left out of
+/// search results. Private entries are encrypted to ourselves, so only we know about them.
+///
+/// # Arguments
+/// * `pubkey` - Hex encoded public key to mute
+/// * `private` - Whether to keep the entry in the encrypted part of the list
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<MutedPubkey>)` - Everyone muted now
+/// * `Err(String)` - Error message if there is no active account, the pubkey is invalid or is
+///   our own, the existing private entries can't be read or no relay accepted the new list
+pub async fn mute_pubkey(
+    pubkey: String,
+    private: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<MutedPubkey>, String> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| "Invalid pubkey".to_string())?;
+    mute_list::mute(&pubkey, private, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -1,75 +1,38 @@
 use crate::accounts::Account;
-use crate::relays::RelayType;
+use crate::nostr_manager::router::relay_type_for_kind;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
 use std::sync::Arc;
 
+/// Replaces one of the active account's relay lists and publishes it
+///
+/// The URLs are normalized and deduplicated, stored, published and the relay pool is updated.
+/// Nothing is stored if the relay list couldn't be published.
+///
+/// # Arguments
+/// * `relays` - The new relay URLs
+/// * `kind` - The relay list kind: `10002` (Nostr), `10050` (inbox) or `10051` (key package)
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The relays now in the list
+/// * `Err(String)` - Error message if the kind or a URL is invalid, or publishing failed
 pub async fn publish_relay_list(
     relays: Vec<String>,
     kind: u64,
     wn: Arc<Whitenoise>,
-) -> Result<(), String> {
-    let signer = wn.nostr.client.signer().await.map_err(|e| e.to_string())?;
-
-    let mut tags: Vec<Tag> = Vec::new();
-    for relay in relays.clone() {
-        tags.push(Tag::custom(TagKind::Relay, [relay]));
-    }
-
-    let event_kind = match kind {
-        10050 => Kind::InboxRelays,
-        10051 => Kind::MlsKeyPackageRelays,
-        _ => return Err("Invalid relay list kind".to_string()),
-    };
-
-    let event = EventBuilder::new(event_kind, "")
-        .tags(tags)
-        .sign(&signer)
-        .await
-        .map_err(|e| e.to_string())?;
-
-    tracing::debug!("Publishing relay list: {:?}", event);
-
-    wn.nostr
-        .client
-        .send_event(&event)
-        .await
-        .map_err(|e| e.to_string())?;
-
+) -> Result<Vec<String>, String> {
+    let relay_type = relay_type_for_kind(Kind::from(kind as u16))
+        .ok_or_else(|| "Invalid relay list kind".to_string())?;
     let active_account = Account::get_active(wn.clone())
         .await
         .map_err(|e| e.to_string())?;
 
-    match kind {
-        10050 => {
-            active_account
-                .update_relays(RelayType::Inbox, &relays, wn.clone())
-                .await
-                .map_err(|e| format!("Failed to update relays: {}", e))?;
-        }
-        10051 => {
-            active_account
-                .update_relays(RelayType::KeyPackage, &relays, wn.clone())
-                .await
-                .map_err(|e| format!("Failed to update relays: {}", e))?;
-        }
-        _ => return Err("Invalid relay list kind".to_string()),
-    }
+    let relays = active_account
+        .replace_relays(relay_type, &relays, wn.clone())
+        .await
+        .map_err(|e| format!("Failed to update relays: {}", e))?;
 
     tracing::debug!("Relay list published & relays updated");
-
-    for relay in relays.clone() {
-        wn.nostr
-            .client
-            .add_relay(&relay)
-            .await
-            .map_err(|e| e.to_string())?;
-        wn.nostr
-            .client
-            .connect_relay(&relay)
-            .await
-            .map_err(|e| e.to_string())?;
-    }
-
-    Ok(())
+    Ok(relays)
 }
@@ -1,4 +1,6 @@
 use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::nip05;
 use crate::relays::RelayType;
 use crate::types::EnrichedContact;
 use crate::whitenoise::Whitenoise;
@@ -38,13 +40,18 @@ pub async fn query_enriched_contact(
         .await
         .map_err(|_| "Failed to get key packages".to_string())?;
 
+    let metadata = metadata.unwrap_or_default();
+    let nip05_status = nip05::cached_status(&pubkey, &metadata, &wn.database).await;
+
     let enriched_contact = EnrichedContact {
-        metadata: metadata.unwrap_or_default(),
+        metadata,
         nip17: !inbox_relays.is_empty(),
         nip104: !key_packages.is_empty(),
+        nip05_status,
         nostr_relays,
         inbox_relays,
         key_package_relays,
+        annotation: contact_annotations::active_annotation(&pubkey, wn.clone()).await,
     };
 
     if update_account {
@@ -1,3 +1,6 @@
+use crate::contact_annotations;
+use crate::nip05::{self, Nip05Status};
+use crate::nostr_manager::router::relay_urls_from_event;
 use crate::types::EnrichedContact;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
@@ -34,9 +37,11 @@ pub async fn query_enriched_contacts(
                 metadata: Metadata::default(),
                 nip17: false,
                 nip104: false,
+                nip05_status: Nip05Status::Unchecked,
                 nostr_relays: Vec::new(),
                 inbox_relays: Vec::new(),
                 key_package_relays: Vec::new(),
+                annotation: None,
             },
         );
     }
@@ -71,12 +76,9 @@ pub async fn query_enriched_contacts(
                 }
                 Kind::RelayList => {
                     contact.nostr_relays.extend(
-                        event
-                            .tags
+                        relay_urls_from_event(&event)
                             .iter()
-                            .filter(|tag| tag.kind() == TagKind::Relay)
-                            .filter_map(|tag| tag.content())
-                            .map(|s| s.to_string()),
+                            .map(|url| url.to_string()),
                     );
                 }
                 Kind::InboxRelays => {
@@ -110,5 +112,14 @@ pub async fn query_enriched_contacts(
         }
     }
 
+    // Only what we already know, verifying every contact would mean a request per domain
+    for (pubkey, contact) in contacts_map.iter_mut() {
+        if let Ok(pubkey) = PublicKey::parse(pubkey) {
+            contact.nip05_status =
+                nip05::cached_status(&pubkey, &contact.metadata, &wn.database).await;
+        }
+    }
+    contact_annotations::annotate_contacts(&mut contacts_map, wn.clone()).await;
+
     Ok(contacts_map)
 }
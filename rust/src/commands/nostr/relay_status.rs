@@ -0,0 +1,18 @@
+use crate::nostr_manager::relay_health::RelayHealth;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Reports the health of every relay we're connected to
+///
+/// Includes the connection status and latency of each relay, along with the last notice,
+/// closed subscription, auth challenge and rejected event it sent us.
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<RelayHealth>)` - The health of every relay in the pool, ordered by URL
+/// * `Err(String)` - Error message if the status couldn't be retrieved
+pub async fn relay_status(wn: Arc<Whitenoise>) -> Result<Vec<RelayHealth>, String> {
+    Ok(wn.nostr.relay_health().await)
+}
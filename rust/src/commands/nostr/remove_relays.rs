@@ -0,0 +1,32 @@
+use crate::accounts::Account;
+use crate::nostr_manager::router::relay_type_for_kind;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Removes relays from one of the active account's relay lists and publishes the updated list
+///
+/// # Arguments
+/// * `relays` - The relay URLs to remove
+/// * `kind` - The relay list kind: `10002` (Nostr), `10050` (inbox) or `10051` (key package)
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - The relays left in the list
+/// * `Err(String)` - Error message if the kind or a URL is invalid, or publishing failed
+pub async fn remove_relays(
+    relays: Vec<String>,
+    kind: u64,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<String>, String> {
+    let relay_type = relay_type_for_kind(Kind::from(kind as u16))
+        .ok_or_else(|| "Invalid relay list kind".to_string())?;
+    let active_account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    active_account
+        .remove_relays(relay_type, &relays, wn.clone())
+        .await
+        .map_err(|e| format!("Failed to remove relays: {}", e))
+}
@@ -0,0 +1,27 @@
+use crate::accounts::Account;
+use crate::nostr_manager::router::relay_type_for_kind;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Publishes one of the active account's relay lists again, as stored locally
+///
+/// # Arguments
+/// * `kind` - The relay list kind: `10002` (Nostr), `10050` (inbox) or `10051` (key package)
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(())` - If at least one relay accepted the list
+/// * `Err(String)` - Error message if the kind is invalid or publishing failed
+pub async fn republish_relay_list(kind: u64, wn: Arc<Whitenoise>) -> Result<(), String> {
+    let relay_type = relay_type_for_kind(Kind::from(kind as u16))
+        .ok_or_else(|| "Invalid relay list kind".to_string())?;
+    let active_account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    active_account
+        .republish_relay_list(relay_type, wn.clone())
+        .await
+        .map_err(|e| format!("Failed to publish relay list: {}", e))
+}
@@ -0,0 +1,38 @@
+use crate::accounts::Account;
+use crate::nostr_manager::settings::{self, SettingsScope};
+use crate::nostr_manager::NostrManagerSettings;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Forgets saved Nostr settings and applies whatever is in effect without them
+///
+/// Resetting the account's settings falls back to the global ones, resetting the global
+/// settings falls back to the defaults.
+///
+/// # Arguments
+/// * `for_account` - Reset the active account's settings, instead of the global ones
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(NostrManagerSettings)` - The settings now in effect
+/// * `Err(String)` - Error message if the settings couldn't be reset
+pub async fn reset_nostr_settings(
+    for_account: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<NostrManagerSettings, String> {
+    let scope = if for_account {
+        let pubkey = Account::get_active_pubkey(wn.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        SettingsScope::Account(pubkey)
+    } else {
+        SettingsScope::Global
+    };
+
+    settings::delete_settings(scope, &wn.database)
+        .await
+        .map_err(|e| format!("Error resetting settings: {}", e))?;
+    settings::reload_settings(wn.clone())
+        .await
+        .map_err(|e| format!("Error applying settings: {}", e))
+}
@@ -0,0 +1,24 @@
+use crate::identifiers::{self, ResolvedProfile};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Resolves a shared identifier to a profile
+///
+/// Accepts hex pubkeys, npubs, nprofiles and `nostr:` URIs, e.g. scanned from a QR code. Relay
+/// hints in nprofiles are used to find the profile.
+///
+/// # Arguments
+/// * `identifier` - The identifier to resolve
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(ResolvedProfile)` - The pubkey, its metadata (empty if none was found) and relay hints
+/// * `Err(String)` - Error message if the identifier is invalid or is a secret key
+pub async fn resolve_identifier(
+    identifier: String,
+    wn: Arc<Whitenoise>,
+) -> Result<ResolvedProfile, String> {
+    identifiers::resolve_identifier(&identifier, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
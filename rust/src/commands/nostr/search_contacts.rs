@@ -0,0 +1,37 @@
+use crate::accounts::Account;
+use crate::contacts::{self, Contact, DEFAULT_SEARCH_LIMIT};
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Searches the active account's contact directory, and NIP-50 relays when online
+///
+/// Matches names, display names, NIP-05 addresses and npub prefixes, tolerating skipped
+/// letters. Works offline with whatever is in the directory; remote results are added to it.
+///
+/// # Arguments
+/// * `query` - What to search for
+/// * `limit` - Maximum number of results, defaults to 20
+/// * `include_remote` - Whether to also search relays
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<Contact>)` - Matching contacts, best matches first
+/// * `Err(String)` - Error message if there is no active account or the directory couldn't be read
+pub async fn search_contacts(
+    query: String,
+    limit: Option<u32>,
+    include_remote: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<Contact>, String> {
+    let account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let limit = limit.unwrap_or(DEFAULT_SEARCH_LIMIT);
+
+    if include_remote {
+        contacts::search_with_remote(&account.pubkey, &query, limit, wn.clone()).await
+    } else {
+        contacts::search(&account.pubkey, &query, limit, &wn.database).await
+    }
+    .map_err(|e| e.to_string())
+}
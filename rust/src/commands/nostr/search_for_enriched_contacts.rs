@@ -1,3 +1,4 @@
+use crate::contact_annotations;
 use crate::types::EnrichedContact;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
@@ -8,11 +9,12 @@ pub async fn search_for_enriched_contacts(
     query: String,
     wn: Arc<Whitenoise>,
 ) -> Result<HashMap<String, EnrichedContact>, String> {
-    let enriched_users = wn
+    let mut enriched_users = wn
         .nostr
         .search_users(query, wn.clone())
         .await
         .map_err(|e| e.to_string())?;
+    contact_annotations::annotate_contacts(&mut enriched_users, wn.clone()).await;
 
     Ok(enriched_users)
 }
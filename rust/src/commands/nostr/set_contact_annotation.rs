@@ -0,0 +1,54 @@
+use crate::accounts::Account;
+use crate::contact_annotations::{self, ContactAnnotation};
+use crate::identifiers;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Sets the private nickname, note and pin the active account has for someone
+///
+/// Nothing is published about the contact. If the account syncs annotations between its
+/// devices, they're published encrypted to ourselves; failing to do so doesn't fail the edit,
+/// the next edit or sync publishes them again.
+///
+/// # Arguments
+/// * `pubkey` - The contact, as a hex pubkey, npub, nprofile or `nostr:` URI
+/// * `nickname` - Nickname to show instead of their name, cleared if empty
+/// * `note` - Free text note, cleared if empty
+/// * `pinned` - Whether to show the contact first
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(ContactAnnotation)` - The annotation as stored
+/// * `Err(String)` - Error message if there is no active account, the pubkey is invalid or the
+///   database can't be written
+pub async fn set_contact_annotation(
+    pubkey: String,
+    nickname: Option<String>,
+    note: Option<String>,
+    pinned: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<ContactAnnotation, String> {
+    let pubkey = identifiers::parse_pubkey(&pubkey).map_err(|e| e.to_string())?;
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let annotation = contact_annotations::set_annotation(
+        &account_pubkey,
+        &pubkey,
+        nickname,
+        note,
+        pinned,
+        &wn.database,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    if let Err(e) = contact_annotations::publish_annotations(wn.clone()).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::set_contact_annotation",
+            "Failed to sync annotations: {}",
+            e
+        );
+    }
+    Ok(annotation)
+}
@@ -0,0 +1,33 @@
+use crate::accounts::Account;
+use crate::nostr_manager::auth;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Sets whether the active account is willing to authenticate (NIP-42) to a relay
+///
+/// Authenticating reveals our public key to the relay. Relays that require authentication won't
+/// serve or accept some events (e.g. gift wraps) when it's turned off.
+///
+/// # Arguments
+/// * `relay_url` - The relay URL
+/// * `allow_auth` - Whether to answer the relay's AUTH challenges
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(())` - If the setting was saved
+/// * `Err(String)` - Error message if the URL is invalid or saving fails
+pub async fn set_relay_auth(
+    relay_url: String,
+    allow_auth: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<(), String> {
+    let relay_url = RelayUrl::parse(&relay_url).map_err(|e| e.to_string())?;
+    let account_pubkey = Account::get_active_pubkey(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    auth::set_relay_auth_allowed(&account_pubkey, &relay_url, allow_auth, &wn.database)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,76 @@
+use crate::accounts::Account;
+use crate::contact_annotations;
+use crate::contacts;
+use crate::media;
+use crate::mute_list;
+use crate::nostr_manager::sync::SyncReport;
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Syncs the active account with every relay in the pool
+///
+/// Relays that support negentropy are synced with it, the others with `since` based REQs.
+/// A relay failing doesn't fail the sync, failures are listed in the report instead. The
+/// account's `last_synced` is only moved forward when every relay synced successfully.
+///
+/// # Arguments
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(SyncReport)` - What was synced with each relay, and how
+/// * `Err(String)` - Error message if there is no active account or the sync couldn't start
+pub async fn sync_account(wn: Arc<Whitenoise>) -> Result<SyncReport, String> {
+    let mut account = Account::get_active(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+    let group_ids = account
+        .nostr_group_ids(wn.clone())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let report = wn
+        .nostr
+        .sync_for_user(account.pubkey, account.last_synced, group_ids)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if let Err(e) = contacts::refresh_contacts(&account.pubkey, wn.clone()).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::sync_account",
+            "Failed to refresh contact directory: {}",
+            e
+        );
+    }
+
+    if let Err(e) = mute_list::refresh_mute_list(wn.clone()).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::sync_account",
+            "Failed to refresh mute list: {}",
+            e
+        );
+    }
+
+    if let Err(e) = contact_annotations::refresh_annotations(wn.clone()).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::sync_account",
+            "Failed to refresh contact annotations: {}",
+            e
+        );
+    }
+
+    if let Err(e) = media::prune_profile_images(&wn.database).await {
+        tracing::warn!(
+            target: "whitenoise::commands::nostr::sync_account",
+            "Failed to prune profile image cache: {}",
+            e
+        );
+    }
+
+    if report.is_complete() {
+        account.last_synced = Timestamp::from(report.started_at);
+        account.save(wn.clone()).await.map_err(|e| e.to_string())?;
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,24 @@
+use crate::mute_list::{self, MutedPubkey};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Unmutes someone by publishing an edited mute list (NIP-51)
+///
+/// # Arguments
+/// * `pubkey` - Hex encoded public key to unmute
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Vec<MutedPubkey>)` - Everyone still muted
+/// * `Err(String)` - Error message if there is no active account, the pubkey is invalid, the
+///   existing private entries can't be read or no relay accepted the new list
+pub async fn unmute_pubkey(
+    pubkey: String,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<MutedPubkey>, String> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| "Invalid pubkey".to_string())?;
+    mute_list::unmute(&pubkey, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
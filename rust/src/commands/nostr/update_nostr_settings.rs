@@ -0,0 +1,38 @@
+use crate::accounts::Account;
+use crate::nostr_manager::settings::{self, SettingsScope};
+use crate::nostr_manager::NostrManagerSettings;
+use crate::whitenoise::Whitenoise;
+use std::sync::Arc;
+
+/// Saves Nostr settings (relays, timeout, Blossom servers) and applies them right away
+///
+/// # Arguments
+/// * `settings` - The new settings
+/// * `for_account` - Save them for the active account only, instead of globally
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(NostrManagerSettings)` - The settings now in effect
+/// * `Err(String)` - Error message if the settings are invalid or couldn't be saved
+pub async fn update_nostr_settings(
+    settings: NostrManagerSettings,
+    for_account: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<NostrManagerSettings, String> {
+    let settings = settings.validated().map_err(|e| e.to_string())?;
+    let scope = if for_account {
+        let pubkey = Account::get_active_pubkey(wn.clone())
+            .await
+            .map_err(|e| e.to_string())?;
+        SettingsScope::Account(pubkey)
+    } else {
+        SettingsScope::Global
+    };
+
+    settings::save_settings(scope, &settings, &wn.database)
+        .await
+        .map_err(|e| format!("Error saving settings: {}", e))?;
+    settings::reload_settings(wn.clone())
+        .await
+        .map_err(|e| format!("Error applying settings: {}", e))
+}
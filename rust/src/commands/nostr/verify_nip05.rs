@@ -0,0 +1,29 @@
+use crate::nip05::{self, Nip05Verification};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+/// Verifies that a NIP-05 address belongs to a pubkey
+///
+/// Results are cached, a day for verified addresses and an hour for failed ones.
+///
+/// # Arguments
+/// * `pubkey` - Hex encoded public key claiming the address
+/// * `nip05` - The `name@domain` address
+/// * `refresh` - Check again even if we have a recent result
+/// * `wn` - Whitenoise state
+///
+/// # Returns
+/// * `Ok(Nip05Verification)` - Whether the address is verified, and the relays it lists
+/// * `Err(String)` - Error message if the address is invalid or the domain couldn't be reached
+pub async fn verify_nip05(
+    pubkey: String,
+    nip05: String,
+    refresh: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Nip05Verification, String> {
+    let pubkey = PublicKey::from_hex(&pubkey).map_err(|_| "Invalid pubkey".to_string())?;
+    nip05::verify(&pubkey, &nip05, refresh, wn)
+        .await
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,440 @@
+//! Private nicknames and notes for contacts
+//!
+//! An account can give people a nickname, a note and a pin ("Alice – work") without publishing
+//! anything about them. Annotations live in the local database and are shown wherever we render
+//! contacts, group members and message authors.
+//!
+//! If the account opts in, annotations are also synced between its devices as a NIP-78
+//! application data event whose content is NIP-44 encrypted to ourselves. Each annotation
+//! carries the time it was last changed and the newest change wins, so edits made on different
+//! devices merge instead of overwriting each other. Cleared annotations are kept, empty, so that
+//! clearing one syncs as well.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::database::Database;
+use crate::nostr_manager::NostrManagerError;
+use crate::relays::RelayType;
+use crate::replaceable_list::{latest_event, next_created_at};
+use crate::types::EnrichedContact;
+use crate::whitenoise::Whitenoise;
+
+/// The `d` tag of the application data event annotations are synced with
+pub(crate) const APP_DATA_IDENTIFIER: &str = "whitenoise/contact_annotations";
+
+/// Longest nickname we keep, in characters
+const MAX_NICKNAME_LEN: usize = 64;
+
+/// Longest note we keep, in characters
+const MAX_NOTE_LEN: usize = 2000;
+
+#[derive(Error, Debug)]
+pub enum ContactAnnotationsError {
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("Signer error: {0}")]
+    SignerError(#[from] SignerError),
+    #[error("Nostr database error: {0}")]
+    NostrDatabaseError(#[from] DatabaseError),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("The synced annotations can't be read: {0}")]
+    UnreadableAnnotations(String),
+    #[error("No relay accepted the annotations: {0:?}")]
+    NotPublished(Vec<String>),
+}
+
+pub type Result<T> = std::result::Result<T, ContactAnnotationsError>;
+
+/// What an account privately wrote about someone
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+pub struct ContactAnnotation {
+    /// Hex encoded pubkey
+    pub pubkey: String,
+    pub nickname: Option<String>,
+    pub note: Option<String>,
+    pub pinned: bool,
+    /// When the annotation was last changed, used to merge edits from other devices
+    pub updated_at: i64,
+}
+
+impl ContactAnnotation {
+    /// Whether there's nothing left, i.e. it was cleared
+    pub fn is_empty(&self) -> bool {
+        self.nickname.is_none() && self.note.is_none() && !self.pinned
+    }
+}
+
+/// Trims a field and caps its length, `None` if nothing is left
+fn normalize_field(value: Option<String>, max_len: usize) -> Option<String> {
+    let value = value?;
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.chars().take(max_len).collect())
+}
+
+/// Stores an annotation unless we already have a newer one for the same person
+async fn upsert(
+    account_pubkey: &PublicKey,
+    annotation: &ContactAnnotation,
+    db: &Database,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO contact_annotations (account_pubkey, pubkey, nickname, note, pinned, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (account_pubkey, pubkey) DO UPDATE SET
+            nickname = excluded.nickname,
+            note = excluded.note,
+            pinned = excluded.pinned,
+            updated_at = excluded.updated_at
+         WHERE excluded.updated_at >= contact_annotations.updated_at",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(&annotation.pubkey)
+    .bind(&annotation.nickname)
+    .bind(&annotation.note)
+    .bind(annotation.pinned)
+    .bind(annotation.updated_at)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Sets the nickname, note and pin an account has for someone. Empty fields are cleared.
+pub async fn set_annotation(
+    account_pubkey: &PublicKey,
+    pubkey: &PublicKey,
+    nickname: Option<String>,
+    note: Option<String>,
+    pinned: bool,
+    db: &Database,
+) -> Result<ContactAnnotation> {
+    let annotation = ContactAnnotation {
+        pubkey: pubkey.to_hex(),
+        nickname: normalize_field(nickname, MAX_NICKNAME_LEN),
+        note: normalize_field(note, MAX_NOTE_LEN),
+        pinned,
+        updated_at: Timestamp::now().as_u64() as i64,
+    };
+    upsert(account_pubkey, &annotation, db).await?;
+    Ok(annotation)
+}
+
+/// Removes everything an account wrote about someone
+pub async fn clear_annotation(
+    account_pubkey: &PublicKey,
+    pubkey: &PublicKey,
+    db: &Database,
+) -> Result<()> {
+    set_annotation(account_pubkey, pubkey, None, None, false, db).await?;
+    Ok(())
+}
+
+/// Every annotation of an account, cleared ones included
+async fn all_annotations(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<Vec<ContactAnnotation>> {
+    let annotations = sqlx::query_as::<_, ContactAnnotation>(
+        "SELECT pubkey, nickname, note, pinned, updated_at FROM contact_annotations
+         WHERE account_pubkey = ?
+         ORDER BY pubkey",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(annotations)
+}
+
+/// Returns an account's annotations, keyed by hex encoded pubkey
+pub async fn annotations(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<HashMap<String, ContactAnnotation>> {
+    Ok(all_annotations(account_pubkey, db)
+        .await?
+        .into_iter()
+        .filter(|annotation| !annotation.is_empty())
+        .map(|annotation| (annotation.pubkey.clone(), annotation))
+        .collect())
+}
+
+/// Returns what an account wrote about someone, if anything
+pub async fn annotation(
+    account_pubkey: &PublicKey,
+    pubkey: &PublicKey,
+    db: &Database,
+) -> Result<Option<ContactAnnotation>> {
+    let annotation = sqlx::query_as::<_, ContactAnnotation>(
+        "SELECT pubkey, nickname, note, pinned, updated_at FROM contact_annotations
+         WHERE account_pubkey = ? AND pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(annotation.filter(|annotation| !annotation.is_empty()))
+}
+
+/// What the active account wrote about someone, `None` if nothing or if it can't be read
+pub async fn active_annotation(
+    pubkey: &PublicKey,
+    wn: Arc<Whitenoise>,
+) -> Option<ContactAnnotation> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await.ok()?;
+    match annotation(&account_pubkey, pubkey, &wn.database).await {
+        Ok(annotation) => annotation,
+        Err(e) => {
+            tracing::warn!(
+                target: "whitenoise::contact_annotations::active_annotation",
+                "Failed to read annotation: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Adds the active account's annotations to enriched contacts keyed by hex encoded pubkey.
+/// Contacts are left without annotations if they can't be read.
+pub async fn annotate_contacts(
+    contacts: &mut HashMap<String, EnrichedContact>,
+    wn: Arc<Whitenoise>,
+) {
+    let Ok(account_pubkey) = Account::get_active_pubkey(wn.clone()).await else {
+        return;
+    };
+    let mut annotations = match annotations(&account_pubkey, &wn.database).await {
+        Ok(annotations) => annotations,
+        Err(e) => {
+            tracing::warn!(
+                target: "whitenoise::contact_annotations::annotate_contacts",
+                "Failed to read annotations: {}",
+                e
+            );
+            return;
+        }
+    };
+    for (pubkey, contact) in contacts.iter_mut() {
+        contact.annotation = annotations.remove(pubkey);
+    }
+}
+
+/// Merges annotations from another device, keeping whichever side changed last
+async fn merge_annotations(
+    account_pubkey: &PublicKey,
+    annotations: &[ContactAnnotation],
+    db: &Database,
+) -> Result<()> {
+    for annotation in annotations {
+        if PublicKey::from_hex(&annotation.pubkey).is_err() {
+            continue;
+        }
+        let annotation = ContactAnnotation {
+            pubkey: annotation.pubkey.clone(),
+            nickname: normalize_field(annotation.nickname.clone(), MAX_NICKNAME_LEN),
+            note: normalize_field(annotation.note.clone(), MAX_NOTE_LEN),
+            pinned: annotation.pinned,
+            updated_at: annotation.updated_at,
+        };
+        upsert(account_pubkey, &annotation, db).await?;
+    }
+    Ok(())
+}
+
+fn is_annotations_event(event: &Event) -> bool {
+    event.kind == Kind::ApplicationSpecificData
+        && event.tags.identifier() == Some(APP_DATA_IDENTIFIER)
+}
+
+/// Merges the annotations synced from another device of the active account
+pub async fn apply_annotations_event(event: &Event, wn: Arc<Whitenoise>) -> Result<()> {
+    if !is_annotations_event(event) {
+        return Ok(());
+    }
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+    if event.pubkey != account_pubkey {
+        return Ok(());
+    }
+
+    let decrypted = wn
+        .nostr
+        .client
+        .signer()
+        .await?
+        .nip44_decrypt(&event.pubkey, &event.content)
+        .await
+        .map_err(|e| ContactAnnotationsError::UnreadableAnnotations(e.to_string()))?;
+    let annotations: Vec<ContactAnnotation> = serde_json::from_str(&decrypted)
+        .map_err(|e| ContactAnnotationsError::UnreadableAnnotations(e.to_string()))?;
+    merge_annotations(&account_pubkey, &annotations, &wn.database).await
+}
+
+/// Merges the latest synced annotations of the active account that we have locally, e.g.
+/// after syncing
+pub async fn refresh_annotations(wn: Arc<Whitenoise>) -> Result<()> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+    let events = wn
+        .nostr
+        .client
+        .database()
+        .query(annotations_filter(&account_pubkey))
+        .await?;
+    if let Some(event) = latest_event(events) {
+        apply_annotations_event(&event, wn).await?;
+    }
+    Ok(())
+}
+
+fn annotations_filter(account_pubkey: &PublicKey) -> Filter {
+    Filter::new()
+        .author(*account_pubkey)
+        .kind(Kind::ApplicationSpecificData)
+        .identifier(APP_DATA_IDENTIFIER)
+        .limit(1)
+}
+
+/// Publishes the active account's annotations for its other devices, if it syncs them.
+/// Annotations from the latest published event are merged in first so that nothing another
+/// device wrote is lost.
+///
+/// Returns whether anything was published.
+pub async fn publish_annotations(wn: Arc<Whitenoise>) -> Result<bool> {
+    let account = Account::get_active(wn.clone()).await?;
+    if !account.settings.sync_contact_annotations {
+        return Ok(false);
+    }
+    let write_relays = account.relays(RelayType::Nostr, wn.clone()).await?;
+
+    let filter = annotations_filter(&account.pubkey);
+    let fetched = wn
+        .nostr
+        .fetch_own_events(filter.clone(), &write_relays)
+        .await?;
+    let stored = wn.nostr.client.database().query(filter).await?;
+    let base = latest_event(fetched.into_iter().chain(stored));
+    if let Some(event) = &base {
+        // Publishing without what the other devices wrote would drop it
+        apply_annotations_event(event, wn.clone()).await?;
+    }
+
+    let annotations = all_annotations(&account.pubkey, &wn.database).await?;
+    let json = serde_json::to_string(&annotations)
+        .map_err(|e| ContactAnnotationsError::UnreadableAnnotations(e.to_string()))?;
+    let content = wn
+        .nostr
+        .client
+        .signer()
+        .await?
+        .nip44_encrypt(&account.pubkey, &json)
+        .await?;
+    let created_at = next_created_at(base.as_ref().map(|e| e.created_at), Timestamp::now());
+    let builder = EventBuilder::new(Kind::ApplicationSpecificData, content)
+        .tag(Tag::identifier(APP_DATA_IDENTIFIER))
+        .custom_created_at(created_at);
+    let event = wn.nostr.client.sign_event_builder(builder).await?;
+
+    let output = wn
+        .nostr
+        .publish_event_to_outbox(&event, &write_relays)
+        .await?;
+    if output.success.is_empty() {
+        return Err(ContactAnnotationsError::NotPublished(
+            output.failed.values().cloned().collect(),
+        ));
+    }
+    wn.nostr.client.database().save_event(&event).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    #[tokio::test]
+    async fn test_set_and_clear_annotation() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate().public_key();
+        let alice = Keys::generate().public_key();
+
+        let saved = set_annotation(
+            &account,
+            &alice,
+            Some("  Alice – work ".to_string()),
+            Some("".to_string()),
+            true,
+            &db,
+        )
+        .await
+        .unwrap();
+        assert_eq!(saved.nickname.as_deref(), Some("Alice – work"));
+        assert_eq!(saved.note, None);
+        assert_eq!(
+            annotation(&account, &alice, &db).await.unwrap(),
+            Some(saved)
+        );
+
+        clear_annotation(&account, &alice, &db).await.unwrap();
+        assert_eq!(annotation(&account, &alice, &db).await.unwrap(), None);
+        assert!(annotations(&account, &db).await.unwrap().is_empty());
+        // Kept empty so that clearing it syncs to other devices
+        assert_eq!(all_annotations(&account, &db).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_merge_keeps_newest() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate().public_key();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+
+        let local = set_annotation(
+            &account,
+            &alice,
+            Some("Alice".to_string()),
+            None,
+            false,
+            &db,
+        )
+        .await
+        .unwrap();
+
+        let remote = vec![
+            ContactAnnotation {
+                pubkey: alice.to_hex(),
+                nickname: Some("Old Alice".to_string()),
+                updated_at: local.updated_at - 10,
+                ..Default::default()
+            },
+            ContactAnnotation {
+                pubkey: bob.to_hex(),
+                note: Some("Met at the conference".to_string()),
+                updated_at: local.updated_at - 10,
+                ..Default::default()
+            },
+            ContactAnnotation {
+                pubkey: "not a pubkey".to_string(),
+                nickname: Some("Nobody".to_string()),
+                ..Default::default()
+            },
+        ];
+        merge_annotations(&account, &remote, &db).await.unwrap();
+
+        let merged = annotations(&account, &db).await.unwrap();
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[&alice.to_hex()].nickname.as_deref(), Some("Alice"));
+        assert_eq!(
+            merged[&bob.to_hex()].note.as_deref(),
+            Some("Met at the conference")
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! Editing the contact list (NIP-02)
+//!
+//! Following and unfollowing edits the latest kind 3 contact list we can find (see
+//! `replaceable_list`). Everything we don't touch (relay hints, petnames, unknown tags and the
+//! content) is kept as is.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::contacts;
+use crate::nostr_manager::NostrManagerError;
+use crate::relays::RelayType;
+use crate::replaceable_list::{self, add_p_tag, remove_p_tag, Edited, ReplaceableList};
+use crate::whitenoise::Whitenoise;
+
+#[derive(Error, Debug)]
+pub enum ContactListError {
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] DatabaseError),
+    #[error("Can't follow yourself")]
+    CannotFollowSelf,
+    #[error("The contact list keeps changing, try again later")]
+    Conflict,
+    #[error("Couldn't reach our relays to find the current contact list")]
+    ListUnavailable,
+    #[error("No relay accepted the contact list: {0:?}")]
+    NotPublished(Vec<String>),
+}
+
+pub type Result<T> = std::result::Result<T, ContactListError>;
+
+/// The outcome of following or unfollowing someone
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ContactListUpdate {
+    /// `false` if we already (or no longer) followed them and nothing was published
+    pub changed: bool,
+    /// Hex encoded pubkeys of everyone we follow now
+    pub contacts: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    Follow(PublicKey),
+    Unfollow(PublicKey),
+}
+
+/// Follows someone
+pub async fn follow(pubkey: &PublicKey, wn: Arc<Whitenoise>) -> Result<ContactListUpdate> {
+    edit_contact_list(Edit::Follow(*pubkey), wn).await
+}
+
+/// Unfollows someone
+pub async fn unfollow(pubkey: &PublicKey, wn: Arc<Whitenoise>) -> Result<ContactListUpdate> {
+    edit_contact_list(Edit::Unfollow(*pubkey), wn).await
+}
+
+/// The kind 3 contact list, whose content we keep as is
+struct ContactList;
+
+#[async_trait::async_trait]
+impl ReplaceableList for ContactList {
+    const KIND: Kind = Kind::ContactList;
+
+    type Tags = Vec<Tag>;
+    type Error = ContactListError;
+
+    async fn read(event: &Event, _wn: Arc<Whitenoise>) -> Result<Vec<Tag>> {
+        Ok(event.tags.iter().cloned().collect())
+    }
+
+    async fn build(
+        tags: &Vec<Tag>,
+        base: Option<&Event>,
+        _account: &Account,
+        _wn: Arc<Whitenoise>,
+    ) -> Result<EventBuilder> {
+        let content = base.map(|event| event.content.clone()).unwrap_or_default();
+        Ok(EventBuilder::new(Kind::ContactList, content).tags(tags.clone()))
+    }
+
+    fn conflict() -> ContactListError {
+        ContactListError::Conflict
+    }
+
+    fn unavailable() -> ContactListError {
+        ContactListError::ListUnavailable
+    }
+
+    fn not_published(reasons: Vec<String>) -> ContactListError {
+        ContactListError::NotPublished(reasons)
+    }
+}
+
+async fn edit_contact_list(edit: Edit, wn: Arc<Whitenoise>) -> Result<ContactListUpdate> {
+    let account = Account::get_active(wn.clone()).await?;
+    if let Edit::Follow(pubkey) = edit {
+        if pubkey == account.pubkey {
+            return Err(ContactListError::CannotFollowSelf);
+        }
+    }
+    let write_relays = account.relays(RelayType::Nostr, wn.clone()).await?;
+
+    let edited = replaceable_list::edit_list::<ContactList>(
+        &account,
+        &write_relays,
+        |tags| match edit {
+            Edit::Follow(pubkey) => add_p_tag(tags, &pubkey),
+            Edit::Unfollow(pubkey) => remove_p_tag(tags, &pubkey),
+        },
+        wn.clone(),
+    )
+    .await?;
+
+    match edited {
+        Edited::Unchanged(tags, _) => Ok(ContactListUpdate {
+            changed: false,
+            contacts: contacts(&tags),
+        }),
+        Edited::Published(tags, event) => {
+            if let Err(e) = contacts::apply_contact_list(&event, &wn.database).await {
+                tracing::warn!(
+                    target: "whitenoise::contact_list::edit_contact_list",
+                    "Failed to update contact directory: {}",
+                    e
+                );
+            }
+            Ok(ContactListUpdate {
+                changed: true,
+                contacts: contacts(&tags),
+            })
+        }
+    }
+}
+
+/// Hex encoded pubkeys of the `p` tags
+fn contacts(tags: &[Tag]) -> Vec<String> {
+    tags.iter()
+        .filter(|tag| tag.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::P)))
+        .filter_map(|tag| tag.content().map(|c| c.to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_contacts() {
+        let alice = Keys::generate().public_key();
+        let tags = vec![
+            Tag::parse(["p", &alice.to_hex(), "wss://relay.example.com", "alice"]).unwrap(),
+            Tag::parse(["client", "another app"]).unwrap(),
+        ];
+        assert_eq!(contacts(&tags), vec![alice.to_hex()]);
+    }
+}
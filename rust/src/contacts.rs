@@ -0,0 +1,503 @@
+//! Local contact directory
+//!
+//! Every account keeps a directory of the people it knows about: everyone in its contact list,
+//! plus anyone we came across while searching. It's filled from the metadata and contact list
+//! events we receive and sync, so that finding someone by name, display name, NIP-05 address
+//! or npub works offline. Remote NIP-50 search results are added to the directory and merged
+//! with the local matches when we're online.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::database::Database;
+use crate::whitenoise::Whitenoise;
+
+/// The default number of results returned by a search
+pub const DEFAULT_SEARCH_LIMIT: u32 = 20;
+
+/// Queries shorter than this only match at the start of words, not as scattered letters
+const MIN_SUBSEQUENCE_QUERY_LEN: usize = 3;
+
+#[derive(Error, Debug)]
+pub enum ContactsError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Nostr database error: {0}")]
+    NostrDatabaseError(#[from] DatabaseError),
+}
+
+pub type Result<T> = std::result::Result<T, ContactsError>;
+
+/// Someone in an account's contact directory
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+pub struct Contact {
+    /// Hex encoded pubkey
+    pub pubkey: String,
+    pub npub: String,
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub nip05: Option<String>,
+    pub picture: Option<String>,
+    /// Whether the contact is in the account's contact list
+    pub following: bool,
+}
+
+impl Contact {
+    fn search_score(&self, query: &str) -> Option<u32> {
+        let mut best = [&self.name, &self.display_name, &self.nip05]
+            .into_iter()
+            .flatten()
+            .filter_map(|field| match_score(query, &field.to_lowercase()))
+            .max();
+
+        if query.starts_with("npub1") && self.npub.starts_with(query) {
+            best = best.max(Some(95));
+        }
+
+        // People we follow come first among equally good matches
+        best.map(|score| score + if self.following { 5 } else { 0 })
+    }
+}
+
+/// How well a (lowercase) field matches a (lowercase) query, `None` if it doesn't
+fn match_score(query: &str, field: &str) -> Option<u32> {
+    if query.is_empty() || field.is_empty() {
+        return None;
+    }
+    if field == query {
+        return Some(100);
+    }
+    if field.starts_with(query) {
+        return Some(90);
+    }
+    if field
+        .split(|c: char| !c.is_alphanumeric())
+        .any(|word| word.starts_with(query))
+    {
+        return Some(80);
+    }
+    if field.contains(query) {
+        return Some(70);
+    }
+    if query.chars().count() < MIN_SUBSEQUENCE_QUERY_LEN {
+        return None;
+    }
+
+    // The letters of the query in order, e.g. "jdoe" in "john doe". Fewer letters skipped in
+    // between is a better match.
+    let mut field_chars = field.chars();
+    let mut gaps = 0;
+    for query_char in query.chars() {
+        let mut skipped = 0;
+        loop {
+            match field_chars.next() {
+                Some(c) if c == query_char => break,
+                Some(_) => skipped += 1,
+                None => return None,
+            }
+        }
+        gaps += skipped;
+    }
+    Some(60u32.saturating_sub(gaps).max(10))
+}
+
+/// Normalizes a search query, `None` if there's nothing to search for
+fn normalize_query(query: &str) -> Option<String> {
+    let query = query.trim().trim_start_matches('@').to_lowercase();
+    (!query.is_empty()).then_some(query)
+}
+
+/// Adds someone to an account's directory, or updates their profile if the metadata event is
+/// newer than the one we have
+pub async fn save_metadata(account_pubkey: &PublicKey, event: &Event, db: &Database) -> Result<()> {
+    let metadata = Metadata::from_json(&event.content).unwrap_or_default();
+    sqlx::query(
+        "INSERT INTO contacts (account_pubkey, pubkey, npub, name, display_name, nip05, picture, metadata_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (account_pubkey, pubkey) DO UPDATE SET
+            name = excluded.name,
+            display_name = excluded.display_name,
+            nip05 = excluded.nip05,
+            picture = excluded.picture,
+            metadata_at = excluded.metadata_at,
+            updated_at = excluded.updated_at
+         WHERE contacts.metadata_at IS NULL OR contacts.metadata_at < excluded.metadata_at",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(event.pubkey.to_hex())
+    .bind(event.pubkey.to_bech32().unwrap_or_default())
+    .bind(metadata.name)
+    .bind(metadata.display_name)
+    .bind(metadata.nip05)
+    .bind(metadata.picture)
+    .bind(event.created_at.as_u64() as i64)
+    .bind(Timestamp::now().as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Updates the profile of someone in every directory they're in. Used for metadata events
+/// that arrive without an account context, e.g. from subscriptions.
+pub async fn update_metadata(event: &Event, db: &Database) -> Result<()> {
+    let account_pubkeys =
+        sqlx::query_scalar::<_, String>("SELECT account_pubkey FROM contacts WHERE pubkey = ?")
+            .bind(event.pubkey.to_hex())
+            .fetch_all(&db.pool)
+            .await?;
+    for account_pubkey in account_pubkeys {
+        if let Ok(account_pubkey) = PublicKey::from_hex(&account_pubkey) {
+            save_metadata(&account_pubkey, event, db).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Records that a version of a replaceable list is being applied for an account. Returns
+/// `false` if we already applied a version that isn't older, which should then be ignored.
+pub(crate) async fn claim_list_version(
+    account_pubkey: &str,
+    kind: Kind,
+    created_at: Timestamp,
+    txn: &mut sqlx::SqliteConnection,
+) -> std::result::Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO applied_lists (account_pubkey, kind, created_at) VALUES (?, ?, ?)
+         ON CONFLICT (account_pubkey, kind) DO UPDATE SET created_at = excluded.created_at
+         WHERE applied_lists.created_at < excluded.created_at",
+    )
+    .bind(account_pubkey)
+    .bind(kind.as_u16() as i64)
+    .bind(created_at.as_u64() as i64)
+    .execute(&mut *txn)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Marks the people in a contact list as followed, and everyone else in the directory as not
+/// followed. Contact lists of people who aren't one of our accounts, and lists older than the
+/// one we last applied, are ignored.
+pub async fn apply_contact_list(event: &Event, db: &Database) -> Result<()> {
+    if event.kind != Kind::ContactList {
+        return Ok(());
+    }
+    let account_pubkey = event.pubkey.to_hex();
+    let is_account = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM accounts WHERE pubkey = ?")
+        .bind(&account_pubkey)
+        .fetch_one(&db.pool)
+        .await?
+        > 0;
+    if !is_account {
+        return Ok(());
+    }
+
+    let followed: Vec<PublicKey> = event
+        .tags
+        .iter()
+        .filter(|tag| tag.kind() == TagKind::p())
+        .filter_map(|tag| tag.content().and_then(|c| PublicKey::from_hex(c).ok()))
+        .collect();
+    let now = Timestamp::now().as_u64() as i64;
+
+    let mut txn = db.pool.begin().await?;
+    if !claim_list_version(&account_pubkey, event.kind, event.created_at, &mut txn).await? {
+        return Ok(());
+    }
+    sqlx::query("UPDATE contacts SET following = 0 WHERE account_pubkey = ?")
+        .bind(&account_pubkey)
+        .execute(&mut *txn)
+        .await?;
+    for pubkey in followed {
+        sqlx::query(
+            "INSERT INTO contacts (account_pubkey, pubkey, npub, following, updated_at)
+             VALUES (?, ?, ?, 1, ?)
+             ON CONFLICT (account_pubkey, pubkey) DO UPDATE SET following = 1",
+        )
+        .bind(&account_pubkey)
+        .bind(pubkey.to_hex())
+        .bind(pubkey.to_bech32().unwrap_or_default())
+        .bind(now)
+        .execute(&mut *txn)
+        .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Rebuilds an account's directory from the contact list and metadata events we have locally,
+/// e.g. after syncing
+pub async fn refresh_contacts(account_pubkey: &PublicKey, wn: Arc<Whitenoise>) -> Result<()> {
+    let database = wn.nostr.client.database();
+    let contact_list = database
+        .query(
+            Filter::new()
+                .kind(Kind::ContactList)
+                .author(*account_pubkey)
+                .limit(1),
+        )
+        .await?;
+    if let Some(event) = contact_list.first() {
+        apply_contact_list(event, &wn.database).await?;
+    }
+
+    let pubkeys =
+        sqlx::query_scalar::<_, String>("SELECT pubkey FROM contacts WHERE account_pubkey = ?")
+            .bind(account_pubkey.to_hex())
+            .fetch_all(&wn.database.pool)
+            .await?
+            .into_iter()
+            .filter_map(|pubkey| PublicKey::from_hex(&pubkey).ok())
+            .collect::<Vec<_>>();
+    if pubkeys.is_empty() {
+        return Ok(());
+    }
+
+    let metadata_events = database
+        .query(Filter::new().kind(Kind::Metadata).authors(pubkeys))
+        .await?;
+    for event in metadata_events.iter() {
+        save_metadata(account_pubkey, event, &wn.database).await?;
+    }
+    Ok(())
+}
+
+/// Returns everyone in an account's directory
+pub async fn contacts(account_pubkey: &PublicKey, db: &Database) -> Result<Vec<Contact>> {
+    let contacts = sqlx::query_as::<_, Contact>(
+        "SELECT pubkey, npub, name, display_name, nip05, picture, following
+         FROM contacts WHERE account_pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(contacts)
+}
+
+/// Searches an account's directory by name, display name, NIP-05 address and npub prefix,
+/// tolerating typos in the form of skipped letters. Best matches come first, muted people are
+/// left out.
+pub async fn search(
+    account_pubkey: &PublicKey,
+    query: &str,
+    limit: u32,
+    db: &Database,
+) -> Result<Vec<Contact>> {
+    let Some(query) = normalize_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let muted = sqlx::query_scalar::<_, String>(
+        "SELECT pubkey FROM muted_pubkeys WHERE account_pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+
+    let mut matches: Vec<(u32, Contact)> = contacts(account_pubkey, db)
+        .await?
+        .into_iter()
+        .filter(|contact| !muted.contains(&contact.pubkey))
+        .filter_map(|contact| contact.search_score(&query).map(|score| (score, contact)))
+        .collect();
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.display_name.cmp(&b.display_name))
+            .then_with(|| a.pubkey.cmp(&b.pubkey))
+    });
+
+    Ok(matches
+        .into_iter()
+        .take(limit as usize)
+        .map(|(_, contact)| contact)
+        .collect())
+}
+
+/// Searches the directory and, when we're online, relays that support NIP-50.
+///
+/// Remote results are added to the directory, so they rank alongside the local ones and can
+/// be found offline later. Remote results that don't match locally (relays may also match e.g.
+/// the about text) come after the local matches. Failing to reach relays isn't an error.
+pub async fn search_with_remote(
+    account_pubkey: &PublicKey,
+    query: &str,
+    limit: u32,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<Contact>> {
+    let remote = match wn.nostr.search_metadata_events(query.to_string()).await {
+        Ok(events) => events,
+        Err(e) => {
+            tracing::debug!(
+                target: "whitenoise::contacts::search_with_remote",
+                "Remote search failed, using local results only: {}",
+                e
+            );
+            Vec::new()
+        }
+    };
+    for event in remote.iter() {
+        save_metadata(account_pubkey, event, &wn.database).await?;
+    }
+
+    let mut results = search(account_pubkey, query, limit, &wn.database).await?;
+    if results.len() < limit as usize && !remote.is_empty() {
+        let all = contacts(account_pubkey, &wn.database).await?;
+        for event in remote.iter() {
+            if results.len() >= limit as usize {
+                break;
+            }
+            let pubkey = event.pubkey.to_hex();
+            if results.iter().any(|contact| contact.pubkey == pubkey) {
+                continue;
+            }
+            if let Some(contact) = all.iter().find(|contact| contact.pubkey == pubkey) {
+                results.push(contact.clone());
+            }
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    fn metadata_event(keys: &Keys, metadata: &Metadata, created_at: u64) -> Event {
+        EventBuilder::metadata(metadata)
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    async fn insert_account(pubkey: &PublicKey, db: &Database) {
+        sqlx::query(
+            "INSERT INTO accounts (pubkey, metadata, settings, onboarding, last_used, last_synced)
+             VALUES (?, '{}', '{}', '{}', 0, 0)",
+        )
+        .bind(pubkey.to_hex())
+        .execute(&db.pool)
+        .await
+        .unwrap();
+    }
+
+    fn contact_list_event(keys: &Keys, contacts: &[PublicKey], created_at: u64) -> Event {
+        EventBuilder::new(Kind::ContactList, "")
+            .tags(contacts.iter().map(|pubkey| Tag::public_key(*pubkey)))
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_match_score() {
+        assert_eq!(match_score("alice", "alice"), Some(100));
+        assert_eq!(match_score("ali", "alice"), Some(90));
+        assert_eq!(match_score("doe", "john doe"), Some(80));
+        assert_eq!(match_score("ohn", "john doe"), Some(70));
+        assert!(match_score("jdoe", "john doe").unwrap() < 70);
+        assert_eq!(match_score("jd", "john doe"), None);
+        assert_eq!(match_score("xyz", "john doe"), None);
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        insert_account(&account.public_key(), &db).await;
+
+        let contact_list = EventBuilder::new(Kind::ContactList, "")
+            .tags([Tag::public_key(alice.public_key())])
+            .sign_with_keys(&account)
+            .unwrap();
+        apply_contact_list(&contact_list, &db).await.unwrap();
+
+        let alice_metadata = Metadata::new()
+            .name("alice")
+            .display_name("Alice Liddell")
+            .nip05("alice@wonderland.example");
+        update_metadata(&metadata_event(&alice, &alice_metadata, 100), &db)
+            .await
+            .unwrap();
+        save_metadata(
+            &account.public_key(),
+            &metadata_event(&bob, &Metadata::new().name("bob"), 100),
+            &db,
+        )
+        .await
+        .unwrap();
+
+        let results = search(&account.public_key(), "liddel", 10, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].pubkey, alice.public_key().to_hex());
+        assert!(results[0].following);
+
+        let results = search(&account.public_key(), "wonderland", 10, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+
+        let npub = bob.public_key().to_bech32().unwrap();
+        let results = search(&account.public_key(), &npub[..12], 10, &db)
+            .await
+            .unwrap();
+        assert_eq!(results[0].pubkey, bob.public_key().to_hex());
+        assert!(!results[0].following);
+
+        // Older metadata doesn't overwrite newer metadata
+        update_metadata(
+            &metadata_event(&alice, &Metadata::new().name("old alice"), 50),
+            &db,
+        )
+        .await
+        .unwrap();
+        let results = search(&account.public_key(), "alice", 10, &db)
+            .await
+            .unwrap();
+        assert_eq!(results[0].name.as_deref(), Some("alice"));
+
+        assert!(search(&account.public_key(), "  ", 10, &db)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply_contact_list_ignores_older_lists() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        insert_account(&account.public_key(), &db).await;
+
+        apply_contact_list(&contact_list_event(&account, &[alice, bob], 200), &db)
+            .await
+            .unwrap();
+        apply_contact_list(&contact_list_event(&account, &[alice], 100), &db)
+            .await
+            .unwrap();
+        let following = |pubkey: PublicKey| {
+            let db = db.clone();
+            let account = account.public_key();
+            async move {
+                search(&account, &pubkey.to_bech32().unwrap(), 10, &db)
+                    .await
+                    .unwrap()[0]
+                    .following
+            }
+        };
+        assert!(following(bob).await);
+
+        apply_contact_list(&contact_list_event(&account, &[alice], 300), &db)
+            .await
+            .unwrap();
+        assert!(following(alice).await);
+        assert!(!following(bob).await);
+    }
+}
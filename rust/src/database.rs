@@ -1,4 +1,6 @@
 use crate::runtime::wn;
+#[cfg(test)]
+use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::sqlite::SqlitePoolOptions;
 use sqlx::{migrate::MigrateDatabase, Sqlite, SqlitePool};
 use std::fs;
@@ -15,6 +17,70 @@ const MIGRATION_FILES: &[(&str, &[u8])] = &[
         "0002_add_media_files.sql",
         include_bytes!("../db_migrations/0002_add_media_files.sql"),
     ),
+    (
+        "0003_add_message_search.sql",
+        include_bytes!("../db_migrations/0003_add_message_search.sql"),
+    ),
+    (
+        "0004_add_disappearing_messages.sql",
+        include_bytes!("../db_migrations/0004_add_disappearing_messages.sql"),
+    ),
+    (
+        "0005_add_outbox.sql",
+        include_bytes!("../db_migrations/0005_add_outbox.sql"),
+    ),
+    (
+        "0006_add_read_markers.sql",
+        include_bytes!("../db_migrations/0006_add_read_markers.sql"),
+    ),
+    (
+        "0007_add_direct_messages.sql",
+        include_bytes!("../db_migrations/0007_add_direct_messages.sql"),
+    ),
+    (
+        "0008_add_invites.sql",
+        include_bytes!("../db_migrations/0008_add_invites.sql"),
+    ),
+    (
+        "0009_add_relay_auth_settings.sql",
+        include_bytes!("../db_migrations/0009_add_relay_auth_settings.sql"),
+    ),
+    (
+        "0010_add_relay_information.sql",
+        include_bytes!("../db_migrations/0010_add_relay_information.sql"),
+    ),
+    (
+        "0011_add_nostr_settings.sql",
+        include_bytes!("../db_migrations/0011_add_nostr_settings.sql"),
+    ),
+    (
+        "0012_add_contacts.sql",
+        include_bytes!("../db_migrations/0012_add_contacts.sql"),
+    ),
+    (
+        "0013_add_nip05_verifications.sql",
+        include_bytes!("../db_migrations/0013_add_nip05_verifications.sql"),
+    ),
+    (
+        "0014_add_muted_pubkeys.sql",
+        include_bytes!("../db_migrations/0014_add_muted_pubkeys.sql"),
+    ),
+    (
+        "0015_add_contact_annotations.sql",
+        include_bytes!("../db_migrations/0015_add_contact_annotations.sql"),
+    ),
+    (
+        "0016_add_profile_images.sql",
+        include_bytes!("../db_migrations/0016_add_profile_images.sql"),
+    ),
+    (
+        "0017_add_published_key_packages.sql",
+        include_bytes!("../db_migrations/0017_add_published_key_packages.sql"),
+    ),
+    (
+        "0018_add_proxy_settings.sql",
+        include_bytes!("../db_migrations/0018_add_proxy_settings.sql"),
+    ),
     // Add new migrations here in order, for example:
     // ("000X_something.sql", include_bytes!("../db_migrations/000X_something.sql")),
     // ("000Y_another.sql", include_bytes!("../db_migrations/000Y_another.sql")),
@@ -139,6 +205,63 @@ impl Database {
             .await?;
 
         // Delete data in reverse order of dependencies
+        sqlx::query("DELETE FROM proxy_settings")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM published_key_packages")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM profile_images")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM contact_annotations")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM muted_pubkeys")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM nip05_verifications")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM applied_lists")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM contacts")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM nostr_settings")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM relay_information")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM relay_auth_settings")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM invites")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM direct_messages")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM group_read_receipts")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM group_read_markers")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM outbox_events")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM expiring_messages")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM group_message_expirations")
+            .execute(&mut *txn)
+            .await?;
+        sqlx::query("DELETE FROM message_search")
+            .execute(&mut *txn)
+            .await?;
         sqlx::query("DELETE FROM media_files")
             .execute(&mut *txn)
             .await?;
@@ -158,3 +281,39 @@ impl Database {
         Ok(())
     }
 }
+
+/// Creates a database in a temporary directory with all migrations applied, for tests.
+///
+/// Foreign keys are off, so that tests don't have to create the accounts their rows belong to.
+#[cfg(test)]
+pub(crate) async fn setup_test_db() -> (Database, tempfile::TempDir) {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let db_path = temp_dir.path().join("test.db");
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect_with(
+            SqliteConnectOptions::new()
+                .filename(&db_path)
+                .create_if_missing(true)
+                .foreign_keys(false),
+        )
+        .await
+        .unwrap();
+
+    for (name, sql) in MIGRATION_FILES {
+        let sql = std::str::from_utf8(sql).unwrap();
+        if let Err(e) = sqlx::raw_sql(sql).execute(&pool).await {
+            panic!("Failed to run migration {}: {}", name, e);
+        }
+    }
+
+    (
+        Database {
+            pool,
+            path: db_path,
+            last_connected: std::time::SystemTime::now(),
+        },
+        temp_dir,
+    )
+}
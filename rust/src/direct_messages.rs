@@ -0,0 +1,347 @@
+//! NIP-17 private direct messages
+//!
+//! Direct messages are kind 14 rumors, sealed and gift wrapped to the recipient's inbox relays
+//! (kind 10050). A second gift wrap is sent to our own inbox relays so that the conversation
+//! history is available on our other devices. Received messages are unwrapped by the event
+//! processor and stored in the `direct_messages` table.
+//!
+//! Only one-to-one conversations are supported for now, group chats should use MLS groups.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::database::Database;
+use crate::nostr_manager::router::TemporaryRelayUse;
+use crate::nostr_manager::NostrManagerError;
+use crate::relays::RelayType;
+use crate::whitenoise::Whitenoise;
+
+/// The default number of messages returned when loading a conversation
+pub const DEFAULT_HISTORY_LIMIT: u32 = 100;
+
+#[derive(Error, Debug)]
+pub enum DirectMessagesError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("{0} doesn't publish any inbox relays")]
+    NoInboxRelays(String),
+    #[error("Only one-to-one conversations are supported")]
+    NotOneToOne,
+    #[error("No relay accepted the message: {0:?}")]
+    DeliveryFailed(Vec<String>),
+}
+
+pub type Result<T> = std::result::Result<T, DirectMessagesError>;
+
+/// A row in the direct_messages table
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct DirectMessageRow {
+    message_id: String,
+    conversation_pubkey: String,
+    author_pubkey: String,
+    content: String,
+    tags: String, // JSON string
+    created_at: i64,
+    wrapper_event_id: Option<String>,
+}
+
+/// A decrypted direct message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectMessage {
+    /// Hex encoded id of the kind 14 rumor
+    pub message_id: String,
+    /// Hex encoded pubkey of the other participant
+    pub conversation_pubkey: String,
+    /// Hex encoded pubkey of the author
+    pub author_pubkey: String,
+    pub content: String,
+    pub tags: Vec<Tag>,
+    pub created_at: i64,
+    /// Hex encoded id of the gift wrap the message arrived in, `None` for messages we sent
+    pub wrapper_event_id: Option<String>,
+}
+
+impl TryFrom<DirectMessageRow> for DirectMessage {
+    type Error = DirectMessagesError;
+
+    fn try_from(row: DirectMessageRow) -> Result<Self> {
+        Ok(Self {
+            message_id: row.message_id,
+            conversation_pubkey: row.conversation_pubkey,
+            author_pubkey: row.author_pubkey,
+            content: row.content,
+            tags: serde_json::from_str(&row.tags)?,
+            created_at: row.created_at,
+            wrapper_event_id: row.wrapper_event_id,
+        })
+    }
+}
+
+/// The latest state of a conversation, for the chat list
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DirectConversation {
+    /// Hex encoded pubkey of the other participant
+    pub conversation_pubkey: String,
+    pub last_message_id: String,
+    pub last_message_author: String,
+    pub last_message_content: String,
+    pub last_message_at: i64,
+    pub message_count: i64,
+}
+
+/// Sends a direct message to the receiver's inbox relays and a copy to our own
+pub async fn send_direct_message(
+    receiver: &PublicKey,
+    content: &str,
+    wn: Arc<Whitenoise>,
+) -> Result<DirectMessage> {
+    let receiver_relays = wn.nostr.relay_list_for(*receiver, RelayType::Inbox).await?;
+    if receiver_relays.is_empty() {
+        return Err(DirectMessagesError::NoInboxRelays(receiver.to_hex()));
+    }
+
+    let account = Account::get_active(wn.clone()).await?;
+    let rumor = EventBuilder::private_msg_rumor(*receiver, content).build(account.pubkey);
+
+    let temporary_relays = wn
+        .nostr
+        .acquire_temporary_relays(&receiver_relays, TemporaryRelayUse::Write)
+        .await?;
+    let output = wn
+        .nostr
+        .client
+        .gift_wrap_to(receiver_relays, receiver, rumor.clone(), [])
+        .await;
+    wn.nostr.release_temporary_relays(temporary_relays).await;
+    let output = output?;
+    tracing::debug!(
+        target: "whitenoise::direct_messages::send_direct_message",
+        "Gift wrap sent to relays: {:?}",
+        output
+    );
+    if output.success.is_empty() {
+        return Err(DirectMessagesError::DeliveryFailed(
+            output.failed.values().cloned().collect(),
+        ));
+    }
+
+    // Our own copy is best effort, the message is stored locally either way
+    let own_relays = account.relays(RelayType::Inbox, wn.clone()).await?;
+    if !own_relays.is_empty() {
+        if let Err(e) = wn
+            .nostr
+            .client
+            .gift_wrap_to(own_relays, &account.pubkey, rumor.clone(), [])
+            .await
+        {
+            tracing::warn!(
+                target: "whitenoise::direct_messages::send_direct_message",
+                "Failed to send a copy of the message to our inbox relays: {}",
+                e
+            );
+        }
+    }
+
+    save_message(&account.pubkey, &rumor, None, &wn.database)
+        .await?
+        .ok_or(DirectMessagesError::NotOneToOne)
+}
+
+/// Stores an unwrapped direct message.
+///
+/// Returns `None` if the rumor isn't part of a one-to-one conversation with the account.
+pub async fn save_message(
+    account_pubkey: &PublicKey,
+    rumor: &UnsignedEvent,
+    wrapper_event_id: Option<EventId>,
+    db: &Database,
+) -> Result<Option<DirectMessage>> {
+    let Some(conversation_pubkey) = conversation_pubkey(account_pubkey, rumor) else {
+        tracing::debug!(
+            target: "whitenoise::direct_messages::save_message",
+            "Ignoring direct message that isn't a one-to-one conversation"
+        );
+        return Ok(None);
+    };
+
+    let mut rumor = rumor.clone();
+    rumor.ensure_id();
+    let message_id = rumor.id.expect("Rumor ID was just computed");
+
+    // We receive our own messages back, keep whichever copy arrived first
+    sqlx::query(
+        "INSERT OR IGNORE INTO direct_messages
+            (message_id, account_pubkey, conversation_pubkey, author_pubkey, content, tags, created_at, wrapper_event_id)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(message_id.to_hex())
+    .bind(account_pubkey.to_hex())
+    .bind(conversation_pubkey.to_hex())
+    .bind(rumor.pubkey.to_hex())
+    .bind(&rumor.content)
+    .bind(serde_json::to_string(&rumor.tags)?)
+    .bind(rumor.created_at.as_u64() as i64)
+    .bind(wrapper_event_id.map(|id| id.to_hex()))
+    .execute(&db.pool)
+    .await?;
+
+    Ok(Some(DirectMessage {
+        message_id: message_id.to_hex(),
+        conversation_pubkey: conversation_pubkey.to_hex(),
+        author_pubkey: rumor.pubkey.to_hex(),
+        content: rumor.content.clone(),
+        tags: rumor.tags.to_vec(),
+        created_at: rumor.created_at.as_u64() as i64,
+        wrapper_event_id: wrapper_event_id.map(|id| id.to_hex()),
+    }))
+}
+
+/// Lists the conversations of an account, most recently active first
+pub async fn conversations(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<Vec<DirectConversation>> {
+    Ok(sqlx::query_as::<_, DirectConversation>(
+        "SELECT conversation_pubkey,
+                message_id AS last_message_id,
+                author_pubkey AS last_message_author,
+                content AS last_message_content,
+                MAX(created_at) AS last_message_at,
+                COUNT(*) AS message_count
+         FROM direct_messages
+         WHERE account_pubkey = ?
+         GROUP BY conversation_pubkey
+         ORDER BY last_message_at DESC",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?)
+}
+
+/// Loads the history of a conversation in chronological order.
+///
+/// Pass the `created_at` of the oldest loaded message as `before` to page further back.
+pub async fn conversation_messages(
+    account_pubkey: &PublicKey,
+    conversation_pubkey: &PublicKey,
+    before: Option<Timestamp>,
+    limit: u32,
+    db: &Database,
+) -> Result<Vec<DirectMessage>> {
+    let rows = sqlx::query_as::<_, DirectMessageRow>(
+        "SELECT message_id, conversation_pubkey, author_pubkey, content, tags, created_at, wrapper_event_id
+         FROM direct_messages
+         WHERE account_pubkey = ? AND conversation_pubkey = ? AND (? IS NULL OR created_at < ?)
+         ORDER BY created_at DESC
+         LIMIT ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(conversation_pubkey.to_hex())
+    .bind(before.map(|t| t.as_u64() as i64))
+    .bind(before.map(|t| t.as_u64() as i64))
+    .bind(limit as i64)
+    .fetch_all(&db.pool)
+    .await?;
+
+    rows.into_iter()
+        .rev()
+        .map(DirectMessage::try_from)
+        .collect()
+}
+
+/// Works out who the other participant of a one-to-one conversation is
+fn conversation_pubkey(account_pubkey: &PublicKey, rumor: &UnsignedEvent) -> Option<PublicKey> {
+    let recipients: Vec<PublicKey> = rumor.tags.public_keys().copied().collect();
+    match recipients.as_slice() {
+        [recipient] if rumor.pubkey == *account_pubkey => Some(*recipient),
+        [recipient] if recipient == account_pubkey => Some(rumor.pubkey),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    fn create_test_rumor(
+        author: PublicKey,
+        receiver: PublicKey,
+        content: &str,
+        created_at: u64,
+    ) -> UnsignedEvent {
+        EventBuilder::private_msg_rumor(receiver, content)
+            .custom_created_at(Timestamp::from(created_at))
+            .build(author)
+    }
+
+    #[test]
+    fn test_conversation_pubkey() {
+        let account = Keys::generate().public_key();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+
+        let incoming = create_test_rumor(alice, account, "hi", 1);
+        assert_eq!(conversation_pubkey(&account, &incoming), Some(alice));
+
+        let outgoing = create_test_rumor(account, alice, "hi", 1);
+        assert_eq!(conversation_pubkey(&account, &outgoing), Some(alice));
+
+        let not_for_us = create_test_rumor(alice, bob, "hi", 1);
+        assert_eq!(conversation_pubkey(&account, &not_for_us), None);
+    }
+
+    #[tokio::test]
+    async fn test_save_and_list_conversations() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate().public_key();
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+
+        let first = create_test_rumor(alice, account, "hi", 100);
+        let reply = create_test_rumor(account, alice, "hey alice", 200);
+        let other = create_test_rumor(bob, account, "yo", 150);
+        for rumor in [&first, &reply, &other] {
+            save_message(&account, rumor, None, &db).await.unwrap();
+        }
+        // The copy of our own message coming back doesn't create a duplicate
+        save_message(&account, &reply, None, &db).await.unwrap();
+
+        let conversations = conversations(&account, &db).await.unwrap();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].conversation_pubkey, alice.to_hex());
+        assert_eq!(conversations[0].last_message_content, "hey alice");
+        assert_eq!(conversations[0].message_count, 2);
+        assert_eq!(conversations[1].conversation_pubkey, bob.to_hex());
+
+        let history = conversation_messages(&account, &alice, None, DEFAULT_HISTORY_LIMIT, &db)
+            .await
+            .unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].content, "hi");
+        assert_eq!(history[1].content, "hey alice");
+
+        let older = conversation_messages(
+            &account,
+            &alice,
+            Some(Timestamp::from(200)),
+            DEFAULT_HISTORY_LIMIT,
+            &db,
+        )
+        .await
+        .unwrap();
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].content, "hi");
+    }
+}
@@ -0,0 +1,422 @@
+//! Disappearing messages
+//!
+//! Groups can opt into a timer after which messages are removed from every member's device.
+//! Group admins announce the timer with a control message (an inner event of
+//! [`EXPIRATION_SETTING_KIND`]) and every outgoing message in that group carries a NIP-40
+//! `expiration` tag. Received messages are tracked in the `expiring_messages` table. Once they
+//! expire they're hidden from the conversation and a background sweeper purges them from the
+//! search index and the media cache. `NostrMls` has no way to delete messages, so they stay in MLS
+//! storage.
+
+use nostr_mls::prelude::*;
+use std::collections::{BTreeSet, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::database::Database;
+use crate::media::{self, MediaError};
+use crate::message_search::{self, MessageSearchError};
+use crate::whitenoise::Whitenoise;
+
+/// Inner event kind used to announce a group's disappearing message timer.
+///
+/// The content is the timer in seconds, or empty (or `0`) to turn disappearing messages off.
+pub const EXPIRATION_SETTING_KIND: u16 = 4451;
+
+/// How often the sweeper looks for expired messages
+const SWEEP_INTERVAL: Duration = Duration::from_secs(30);
+
+static SWEEPER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+pub enum DisappearingMessagesError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Media error: {0}")]
+    MediaError(#[from] MediaError),
+    #[error("Message search error: {0}")]
+    MessageSearchError(#[from] MessageSearchError),
+    #[error("Nostr MLS error: {0}")]
+    NostrMlsError(#[from] nostr_mls::Error),
+    #[error("Nostr MLS not initialized")]
+    NostrMlsNotInitialized,
+    #[error("Timeout waiting for nostr_mls lock")]
+    NostrMlsLockTimeout,
+}
+
+pub type Result<T> = std::result::Result<T, DisappearingMessagesError>;
+
+/// Returns the disappearing message timer (in seconds) for a group, if one is set
+pub async fn group_expiration(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    db: &Database,
+) -> Result<Option<u64>> {
+    let expiration_seconds = sqlx::query_scalar::<_, Option<i64>>(
+        "SELECT expiration_seconds FROM group_message_expirations
+         WHERE mls_group_id = ? AND account_pubkey = ?",
+    )
+    .bind(mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(expiration_seconds.flatten().map(|s| s as u64))
+}
+
+/// Builds the NIP-40 `expiration` tag for a new outgoing message, if the group has a timer
+pub async fn expiration_tag(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    db: &Database,
+) -> Result<Option<Tag>> {
+    Ok(group_expiration(account_pubkey, mls_group_id, db)
+        .await?
+        .map(|seconds| Tag::expiration(Timestamp::now() + seconds)))
+}
+
+/// Handles a message that was just sent or received.
+///
+/// Timer announcements from group admins update the group's setting, every other message is
+/// scheduled for removal if it carries an `expiration` tag or the group has a timer.
+pub async fn handle_message(
+    account_pubkey: &PublicKey,
+    message: &message_types::Message,
+    group_admins: &BTreeSet<PublicKey>,
+    db: &Database,
+) -> Result<()> {
+    if message.kind == Kind::Custom(EXPIRATION_SETTING_KIND) {
+        if !group_admins.contains(&message.pubkey) {
+            tracing::warn!(
+                target: "whitenoise::disappearing_messages::handle_message",
+                "Ignoring expiration setting from non-admin {}",
+                message.pubkey.to_hex()
+            );
+            return Ok(());
+        }
+        return match parse_expiration_setting(&message.content) {
+            Some(expiration_seconds) => {
+                save_group_expiration(account_pubkey, message, expiration_seconds, db).await
+            }
+            None => {
+                tracing::warn!(
+                    target: "whitenoise::disappearing_messages::handle_message",
+                    "Ignoring malformed expiration setting: {}",
+                    message.content
+                );
+                Ok(())
+            }
+        };
+    }
+
+    let group_expiration = group_expiration(account_pubkey, &message.mls_group_id, db).await?;
+    if let Some(expires_at) = message_expires_at(message, group_expiration) {
+        sqlx::query(
+            "INSERT OR REPLACE INTO expiring_messages (message_id, mls_group_id, account_pubkey, expires_at)
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(message.id.to_hex())
+        .bind(message.mls_group_id.as_slice())
+        .bind(account_pubkey.to_hex())
+        .bind(expires_at.as_u64() as i64)
+        .execute(&db.pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Stores a group's timer, ignoring announcements older than the one we already have
+async fn save_group_expiration(
+    account_pubkey: &PublicKey,
+    message: &message_types::Message,
+    expiration_seconds: Option<u64>,
+    db: &Database,
+) -> Result<()> {
+    let result = sqlx::query(
+        "INSERT INTO group_message_expirations (mls_group_id, account_pubkey, expiration_seconds, updated_at, updated_by)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(mls_group_id, account_pubkey) DO UPDATE SET
+            expiration_seconds = excluded.expiration_seconds,
+            updated_at = excluded.updated_at,
+            updated_by = excluded.updated_by
+         WHERE excluded.updated_at >= group_message_expirations.updated_at",
+    )
+    .bind(message.mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .bind(expiration_seconds.map(|s| s as i64))
+    .bind(message.created_at.as_u64() as i64)
+    .bind(message.pubkey.to_hex())
+    .execute(&db.pool)
+    .await?;
+
+    tracing::debug!(
+        target: "whitenoise::disappearing_messages::save_group_expiration",
+        "Group expiration set to {:?} (rows affected: {})",
+        expiration_seconds,
+        result.rows_affected()
+    );
+    Ok(())
+}
+
+/// Starts the background task that purges expired messages of the active account.
+///
+/// Calling this more than once is a no-op.
+pub fn start_sweeper() {
+    if SWEEPER_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::debug!(
+            target: "whitenoise::disappearing_messages::sweeper",
+            "Starting expired message sweeper"
+        );
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let wn = crate::runtime::wn();
+            let Ok(account_pubkey) = Account::get_active_pubkey(wn.clone()).await else {
+                continue;
+            };
+            match sweep_expired_messages(&account_pubkey, wn).await {
+                Ok(0) => {}
+                Ok(count) => {
+                    tracing::debug!(
+                        target: "whitenoise::disappearing_messages::sweeper",
+                        "Purged {} expired messages",
+                        count
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        target: "whitenoise::disappearing_messages::sweeper",
+                        "Error purging expired messages: {}",
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Returns the hex encoded IDs of an account's messages that have expired and must be hidden
+pub async fn expired_message_ids(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<HashSet<String>> {
+    let ids = sqlx::query_scalar::<_, String>(
+        "SELECT message_id FROM expiring_messages WHERE account_pubkey = ? AND expires_at <= ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(Timestamp::now().as_u64() as i64)
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(ids.into_iter().collect())
+}
+
+/// Purges the expired messages of an account from the search index and the media cache.
+///
+/// A message that fails to purge is logged and retried on the next sweep without holding back
+/// the others. Returns the number of messages purged.
+pub async fn sweep_expired_messages(
+    account_pubkey: &PublicKey,
+    wn: Arc<Whitenoise>,
+) -> Result<usize> {
+    let expired = sqlx::query_scalar::<_, String>(
+        "SELECT message_id FROM expiring_messages
+         WHERE account_pubkey = ? AND expires_at <= ? AND purged = 0",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(Timestamp::now().as_u64() as i64)
+    .fetch_all(&wn.database.pool)
+    .await?;
+
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    // Collect the media referenced by the expired messages
+    let mut messages: Vec<(String, Vec<(GroupId, String)>)> = Vec::new();
+    {
+        let nostr_mls_guard = match tokio::time::timeout(
+            Duration::from_secs(5),
+            wn.nostr_mls.lock(),
+        )
+        .await
+        {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!(target: "whitenoise::disappearing_messages::sweep_expired_messages", "Timeout waiting for nostr_mls lock");
+                return Err(DisappearingMessagesError::NostrMlsLockTimeout);
+            }
+        };
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(DisappearingMessagesError::NostrMlsNotInitialized)?;
+        for message_id in expired {
+            let Ok(event_id) = EventId::from_hex(&message_id) else {
+                messages.push((message_id, Vec::new()));
+                continue;
+            };
+            match nostr_mls.get_message(&event_id) {
+                Ok(message) => {
+                    let media_files = message
+                        .map(|message| {
+                            media::imeta_file_hashes(&message.tags)
+                                .into_iter()
+                                .map(|file_hash| (message.mls_group_id.clone(), file_hash))
+                                .collect()
+                        })
+                        .unwrap_or_default();
+                    messages.push((message_id, media_files));
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: "whitenoise::disappearing_messages::sweep_expired_messages",
+                        "Failed to load expired message {}: {}",
+                        message_id,
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    let mut purged = 0;
+    for (message_id, media_files) in messages {
+        if let Err(e) = purge_message(account_pubkey, &message_id, &media_files, &wn.database).await
+        {
+            tracing::warn!(
+                target: "whitenoise::disappearing_messages::sweep_expired_messages",
+                "Failed to purge expired message {}: {}",
+                message_id,
+                e
+            );
+            continue;
+        }
+        purged += 1;
+    }
+    Ok(purged)
+}
+
+/// Removes an expired message from the search index and its media from the cache
+async fn purge_message(
+    account_pubkey: &PublicKey,
+    message_id: &str,
+    media_files: &[(GroupId, String)],
+    db: &Database,
+) -> Result<()> {
+    if let Ok(event_id) = EventId::from_hex(message_id) {
+        message_search::remove_messages(account_pubkey, &[event_id], db).await?;
+    }
+    for (mls_group_id, file_hash) in media_files {
+        media::purge_cached_media_file(mls_group_id, file_hash, db).await?;
+    }
+    sqlx::query(
+        "UPDATE expiring_messages SET purged = 1 WHERE message_id = ? AND account_pubkey = ?",
+    )
+    .bind(message_id)
+    .bind(account_pubkey.to_hex())
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Parses the content of a timer announcement.
+///
+/// Returns `Some(None)` when the timer is turned off and `None` if the content is malformed.
+fn parse_expiration_setting(content: &str) -> Option<Option<u64>> {
+    let content = content.trim();
+    if content.is_empty() {
+        return Some(None);
+    }
+    match content.parse::<u64>() {
+        Ok(0) => Some(None),
+        Ok(seconds) => Some(Some(seconds)),
+        Err(_) => None,
+    }
+}
+
+/// Works out when a message expires.
+///
+/// An explicit NIP-40 `expiration` tag on the message wins, otherwise the group's timer is applied
+/// from the time the message was created.
+fn message_expires_at(
+    message: &message_types::Message,
+    group_expiration: Option<u64>,
+) -> Option<Timestamp> {
+    let tagged = message
+        .tags
+        .iter()
+        .filter(|tag| tag.as_slice().first().map(|k| k.as_str()) == Some("expiration"))
+        .find_map(|tag| tag.content().and_then(|c| c.parse::<u64>().ok()))
+        .map(Timestamp::from);
+
+    tagged.or_else(|| group_expiration.map(|seconds| message.created_at + seconds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_message(created_at: Timestamp, tags: Tags) -> message_types::Message {
+        let pubkey = Keys::generate().public_key();
+        let mut event = UnsignedEvent::new(pubkey, created_at, Kind::Custom(9), tags.clone(), "hi");
+        event.ensure_id();
+        message_types::Message {
+            id: event.id.unwrap(),
+            pubkey,
+            kind: event.kind,
+            mls_group_id: GroupId::from_slice(&[1, 2, 3]),
+            created_at,
+            content: "hi".to_string(),
+            tags,
+            event,
+            wrapper_event_id: EventId::from_hex(
+                "1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef",
+            )
+            .unwrap(),
+            state: message_types::MessageState::Created,
+        }
+    }
+
+    #[test]
+    fn test_parse_expiration_setting() {
+        assert_eq!(parse_expiration_setting("3600"), Some(Some(3600)));
+        assert_eq!(parse_expiration_setting(" 60\n"), Some(Some(60)));
+        assert_eq!(parse_expiration_setting(""), Some(None));
+        assert_eq!(parse_expiration_setting("0"), Some(None));
+        assert_eq!(parse_expiration_setting("one hour"), None);
+        assert_eq!(parse_expiration_setting("-5"), None);
+    }
+
+    #[test]
+    fn test_message_expires_at() {
+        let created_at = Timestamp::from(1_000);
+
+        let message = create_test_message(created_at, Tags::new());
+        assert_eq!(message_expires_at(&message, None), None);
+        assert_eq!(
+            message_expires_at(&message, Some(60)),
+            Some(Timestamp::from(1_060))
+        );
+
+        // The sender's expiration tag wins over our local timer
+        let mut tags = Tags::new();
+        tags.push(Tag::expiration(Timestamp::from(5_000)));
+        let message = create_test_message(created_at, tags);
+        assert_eq!(
+            message_expires_at(&message, None),
+            Some(Timestamp::from(5_000))
+        );
+        assert_eq!(
+            message_expires_at(&message, Some(60)),
+            Some(Timestamp::from(5_000))
+        );
+    }
+}
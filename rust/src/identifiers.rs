@@ -0,0 +1,209 @@
+//! Shareable nostr identifiers
+//!
+//! People share themselves as hex pubkeys, `npub`s, `nprofile`s (NIP-19) or `nostr:` URIs of
+//! either (NIP-21), typed, pasted or scanned from a QR code. Everything that takes a pubkey from
+//! the user goes through [`parse_identifier`], and `nprofile` relay hints are used to find
+//! people whose relays we don't know yet.
+//!
+//! Our own account is shared as an `nprofile` with our inbox relays, so that whoever scans it
+//! can reach us right away.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::nostr_manager::NostrManagerError;
+use crate::relays::RelayType;
+use crate::whitenoise::Whitenoise;
+
+/// Relay hints put in our own `nprofile`, more make QR codes harder to scan
+const MAX_SHARED_RELAYS: usize = 3;
+/// Relay hints of someone else's identifier we look at, the rest are ignored
+const MAX_HINT_RELAYS: usize = 5;
+
+#[derive(Error, Debug)]
+pub enum IdentifierError {
+    #[error("Not a valid npub, nprofile or public key: {0}")]
+    InvalidIdentifier(String),
+    #[error("That's a secret key, never share it")]
+    SecretKey,
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+}
+
+pub type Result<T> = std::result::Result<T, IdentifierError>;
+
+/// A pubkey, with the relays the identifier suggested finding it on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identifier {
+    pub pubkey: PublicKey,
+    pub relay_hints: Vec<RelayUrl>,
+}
+
+/// Parses a hex pubkey, `npub`, `nprofile` or a `nostr:` URI of either
+pub fn parse_identifier(input: &str) -> Result<Identifier> {
+    let trimmed = input.trim();
+    let lowercase = trimmed.to_lowercase();
+    let identifier = lowercase.strip_prefix("nostr:").unwrap_or(&lowercase);
+
+    if identifier.starts_with("nsec1") || identifier.starts_with("ncryptsec1") {
+        return Err(IdentifierError::SecretKey);
+    }
+
+    let invalid = || IdentifierError::InvalidIdentifier(trimmed.to_string());
+    if identifier.starts_with("nprofile1") {
+        let profile = Nip19Profile::from_bech32(identifier).map_err(|_| invalid())?;
+        return Ok(Identifier {
+            pubkey: profile.public_key,
+            relay_hints: profile.relays,
+        });
+    }
+    let pubkey = if identifier.starts_with("npub1") {
+        PublicKey::from_bech32(identifier).map_err(|_| invalid())?
+    } else {
+        PublicKey::from_hex(identifier).map_err(|_| invalid())?
+    };
+    Ok(Identifier {
+        pubkey,
+        relay_hints: Vec::new(),
+    })
+}
+
+/// Parses any identifier [`parse_identifier`] accepts, when only the pubkey matters
+pub fn parse_pubkey(input: &str) -> Result<PublicKey> {
+    Ok(parse_identifier(input)?.pubkey)
+}
+
+/// Someone found through an identifier
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ResolvedProfile {
+    /// Hex encoded pubkey
+    pub pubkey: String,
+    pub npub: String,
+    pub metadata: Metadata,
+    /// The relays the identifier pointed to
+    pub relay_hints: Vec<String>,
+}
+
+/// Resolves an identifier to a profile, looking on the relays it hints at as well as ours.
+///
+/// The hints come from whoever made the identifier, so they're only connected to for this
+/// lookup and never join our relay pool.
+pub async fn resolve_identifier(input: &str, wn: Arc<Whitenoise>) -> Result<ResolvedProfile> {
+    let identifier = parse_identifier(input)?;
+    let hints: Vec<RelayUrl> = identifier
+        .relay_hints
+        .iter()
+        .take(MAX_HINT_RELAYS)
+        .cloned()
+        .collect();
+    let metadata = wn
+        .nostr
+        .fetch_user_metadata_from(identifier.pubkey, &hints)
+        .await?
+        .unwrap_or_default();
+
+    Ok(ResolvedProfile {
+        pubkey: identifier.pubkey.to_hex(),
+        npub: identifier.pubkey.to_bech32().unwrap_or_default(),
+        metadata,
+        relay_hints: identifier
+            .relay_hints
+            .iter()
+            .map(|url| url.to_string())
+            .collect(),
+    })
+}
+
+/// The ways to share an account
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ShareableProfile {
+    pub npub: String,
+    pub nprofile: String,
+    /// `nostr:nprofile1...`, what goes in the QR code
+    pub qr_payload: String,
+}
+
+/// Builds the shareable identifiers of a pubkey with some of its relays as hints
+fn shareable_profile(pubkey: PublicKey, relays: &[String]) -> Result<ShareableProfile> {
+    let relays: Vec<RelayUrl> = relays
+        .iter()
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .take(MAX_SHARED_RELAYS)
+        .collect();
+    let nprofile = Nip19Profile::new(pubkey, relays)
+        .to_bech32()
+        .map_err(|e| IdentifierError::InvalidIdentifier(e.to_string()))?;
+    Ok(ShareableProfile {
+        npub: pubkey
+            .to_bech32()
+            .map_err(|e| IdentifierError::InvalidIdentifier(e.to_string()))?,
+        qr_payload: format!("nostr:{}", nprofile),
+        nprofile,
+    })
+}
+
+/// Returns the shareable identifiers of the active account. The hints are our inbox relays, or
+/// our nostr relays if we have none.
+pub async fn own_shareable_profile(wn: Arc<Whitenoise>) -> Result<ShareableProfile> {
+    let account = Account::get_active(wn.clone()).await?;
+    let mut relays = account.relays(RelayType::Inbox, wn.clone()).await?;
+    if relays.is_empty() {
+        relays = account.relays(RelayType::Nostr, wn.clone()).await?;
+    }
+    shareable_profile(account.pubkey, &relays)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_identifier() {
+        let keys = Keys::generate();
+        let pubkey = keys.public_key();
+        let npub = pubkey.to_bech32().unwrap();
+
+        assert_eq!(parse_pubkey(&pubkey.to_hex()).unwrap(), pubkey);
+        assert_eq!(parse_pubkey(&npub).unwrap(), pubkey);
+        assert_eq!(
+            parse_pubkey(&format!(" nostr:{} ", npub.to_uppercase())).unwrap(),
+            pubkey
+        );
+
+        let relay = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let nprofile = Nip19Profile::new(pubkey, [relay.clone()])
+            .to_bech32()
+            .unwrap();
+        let identifier = parse_identifier(&format!("nostr:{}", nprofile)).unwrap();
+        assert_eq!(identifier.pubkey, pubkey);
+        assert_eq!(identifier.relay_hints, vec![relay]);
+
+        assert!(matches!(
+            parse_identifier(&keys.secret_key().to_bech32().unwrap()),
+            Err(IdentifierError::SecretKey)
+        ));
+        assert!(parse_identifier("npub1notreally").is_err());
+        assert!(parse_identifier("").is_err());
+    }
+
+    #[test]
+    fn test_shareable_profile() {
+        let pubkey = Keys::generate().public_key();
+        let relays: Vec<String> = (1..=5)
+            .map(|i| format!("wss://relay{}.example.com", i))
+            .chain(["not a relay".to_string()])
+            .collect();
+
+        let profile = shareable_profile(pubkey, &relays).unwrap();
+        assert_eq!(profile.qr_payload, format!("nostr:{}", profile.nprofile));
+
+        let identifier = parse_identifier(&profile.qr_payload).unwrap();
+        assert_eq!(identifier.pubkey, pubkey);
+        assert_eq!(identifier.relay_hints.len(), MAX_SHARED_RELAYS);
+    }
+}
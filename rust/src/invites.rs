@@ -0,0 +1,231 @@
+//! Invites to White Noise
+//!
+//! Invites are sent as NIP-17 direct messages to the invitee's inbox relays. Only when the
+//! invitee doesn't publish any inbox relays do we fall back to a legacy NIP-04 direct message.
+//! Every invite is recorded so that we don't invite the same person over and over again.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::database::Database;
+use crate::direct_messages::{self, DirectMessagesError};
+use crate::nostr_manager::NostrManagerError;
+use crate::types::NostrEncryptionMethod;
+use crate::whitenoise::Whitenoise;
+
+/// The message used when the user doesn't write their own
+pub const DEFAULT_INVITE_MESSAGE: &str = "Hi, I'm using White Noise to chat securely on Nostr. Join me! https://github.com/parres-hq/whitenoise/releases";
+
+/// How long to wait before the same person can be invited again
+pub const INVITE_COOLDOWN_SECS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(Error, Debug)]
+pub enum InviteError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Direct messages error: {0}")]
+    DirectMessagesError(#[from] DirectMessagesError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("Already invited {0} recently")]
+    AlreadyInvited(String),
+}
+
+pub type Result<T> = std::result::Result<T, InviteError>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InviteMethod {
+    Nip17,
+    Nip04,
+}
+
+impl From<String> for InviteMethod {
+    fn from(s: String) -> Self {
+        match s.to_lowercase().as_str() {
+            "nip17" => Self::Nip17,
+            "nip04" => Self::Nip04,
+            _ => panic!("Invalid invite method: {}", s),
+        }
+    }
+}
+
+impl From<InviteMethod> for String {
+    fn from(method: InviteMethod) -> Self {
+        match method {
+            InviteMethod::Nip17 => "nip17".to_string(),
+            InviteMethod::Nip04 => "nip04".to_string(),
+        }
+    }
+}
+
+/// A row in the invites table
+#[derive(Debug, Clone, sqlx::FromRow)]
+struct InviteRow {
+    invitee_pubkey: String,
+    method: String,
+    message: String,
+    invited_at: i64,
+}
+
+/// A record of an invite we sent
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Invite {
+    /// Hex encoded pubkey of the invitee
+    pub invitee_pubkey: String,
+    pub method: InviteMethod,
+    pub message: String,
+    pub invited_at: i64,
+}
+
+impl From<InviteRow> for Invite {
+    fn from(row: InviteRow) -> Self {
+        Self {
+            invitee_pubkey: row.invitee_pubkey,
+            method: InviteMethod::from(row.method),
+            message: row.message,
+            invited_at: row.invited_at,
+        }
+    }
+}
+
+/// Invites someone to White Noise with an optional custom message.
+///
+/// Fails with [`InviteError::AlreadyInvited`] if we invited them during the last
+/// [`INVITE_COOLDOWN_SECS`], unless `force` is set.
+pub async fn send_invite(
+    invitee: &PublicKey,
+    message: Option<String>,
+    force: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Invite> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+
+    if let Some(previous) = find_invite(&account_pubkey, invitee, &wn.database).await? {
+        if !force && !cooldown_elapsed(previous.invited_at, Timestamp::now()) {
+            return Err(InviteError::AlreadyInvited(invitee.to_hex()));
+        }
+    }
+
+    let message = message
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .unwrap_or_else(|| DEFAULT_INVITE_MESSAGE.to_string());
+
+    let method = match direct_messages::send_direct_message(invitee, &message, wn.clone()).await {
+        Ok(_) => InviteMethod::Nip17,
+        Err(DirectMessagesError::NoInboxRelays(_)) => {
+            tracing::debug!(
+                target: "whitenoise::invites::send_invite",
+                "{} has no inbox relays, falling back to NIP-04",
+                invitee.to_hex()
+            );
+            send_nip04_invite(invitee, &message, wn.clone()).await?;
+            InviteMethod::Nip04
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let invite = Invite {
+        invitee_pubkey: invitee.to_hex(),
+        method,
+        message,
+        invited_at: Timestamp::now().as_u64() as i64,
+    };
+    save_invite(&account_pubkey, &invite, &wn.database).await?;
+    Ok(invite)
+}
+
+/// Returns our latest invite to someone, if any
+pub async fn find_invite(
+    account_pubkey: &PublicKey,
+    invitee: &PublicKey,
+    db: &Database,
+) -> Result<Option<Invite>> {
+    let row = sqlx::query_as::<_, InviteRow>(
+        "SELECT invitee_pubkey, method, message, invited_at FROM invites
+         WHERE account_pubkey = ? AND invitee_pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(invitee.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(row.map(Invite::from))
+}
+
+/// Lists every invite an account sent, most recent first
+pub async fn invites(account_pubkey: &PublicKey, db: &Database) -> Result<Vec<Invite>> {
+    let rows = sqlx::query_as::<_, InviteRow>(
+        "SELECT invitee_pubkey, method, message, invited_at FROM invites
+         WHERE account_pubkey = ?
+         ORDER BY invited_at DESC",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(rows.into_iter().map(Invite::from).collect())
+}
+
+async fn save_invite(account_pubkey: &PublicKey, invite: &Invite, db: &Database) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO invites (account_pubkey, invitee_pubkey, method, message, invited_at)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(&invite.invitee_pubkey)
+    .bind(String::from(invite.method))
+    .bind(&invite.message)
+    .bind(invite.invited_at)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Sends the invite as a legacy NIP-04 direct message
+async fn send_nip04_invite(invitee: &PublicKey, message: &str, wn: Arc<Whitenoise>) -> Result<()> {
+    let encrypted_content = wn
+        .nostr
+        .encrypt_content(
+            message.to_string(),
+            invitee.to_hex(),
+            NostrEncryptionMethod::Nip04,
+        )
+        .await?;
+
+    let event = EventBuilder::new(Kind::EncryptedDirectMessage, encrypted_content)
+        .tag(Tag::public_key(*invitee));
+    wn.nostr.client.send_event_builder(event).await?;
+    Ok(())
+}
+
+fn cooldown_elapsed(invited_at: i64, now: Timestamp) -> bool {
+    (now.as_u64() as i64).saturating_sub(invited_at) >= INVITE_COOLDOWN_SECS as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cooldown_elapsed() {
+        let invited_at = 1_000_000;
+        assert!(!cooldown_elapsed(invited_at, Timestamp::from(1_000_060)));
+        assert!(cooldown_elapsed(
+            invited_at,
+            Timestamp::from(1_000_000 + INVITE_COOLDOWN_SECS)
+        ));
+    }
+
+    #[test]
+    fn test_invite_method_round_trip() {
+        for method in [InviteMethod::Nip17, InviteMethod::Nip04] {
+            assert_eq!(InviteMethod::from(String::from(method)), method);
+        }
+    }
+}
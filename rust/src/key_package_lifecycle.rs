@@ -0,0 +1,874 @@
+//! Keeping our published key packages in order
+//!
+//! Every key package we publish is recorded with the relays that accepted it. Publishing a new
+//! one supersedes the older ones, which are then deleted from relays (NIP-09). Their private
+//! key material stays in MLS storage for a while so that invites already on their way still
+//! work, then it's removed too. A background task publishes a fresh key package once the newest
+//! one gets old, and deletes packages other clients can't use anymore because they don't match
+//! our ciphersuite or extensions.
+//!
+//! When we start keeping track, the key packages already on our relays whose private key material
+//! is on this device are recorded as ours. The others come from our other devices or clients, so
+//! they're reported but never deleted unless they're incompatible.
+
+use nostr_mls::NostrMls;
+use nostr_mls_sqlite_storage::NostrMlsSqliteStorage;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::database::Database;
+use crate::key_packages::{self, KeyPackageError, KeyPackageRejection};
+use crate::nostr_manager::router::TemporaryRelayUse;
+use crate::nostr_manager::{NostrManager, NostrManagerError};
+use crate::relays::RelayType;
+use crate::whitenoise::Whitenoise;
+
+/// How often the background task checks our key packages
+const REFRESH_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Key packages older than this are replaced with a fresh one
+const MAX_KEY_PACKAGE_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How long the private key material of a key package is kept after it was deleted from relays
+const KEY_MATERIAL_GRACE_SECS: i64 = 7 * 24 * 60 * 60;
+
+static REFRESH_WORKER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+pub enum KeyPackageLifecycleError {
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Key package error: {0}")]
+    KeyPackageError(#[from] KeyPackageError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("JSON error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Nostr MLS Not Initialized")]
+    NostrMlsNotInitialized,
+    #[error("Timeout waiting for nostr_mls lock")]
+    NostrMlsLockTimeout,
+}
+
+pub type Result<T> = std::result::Result<T, KeyPackageLifecycleError>;
+
+/// A key package we published
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PublishedKeyPackage {
+    /// Hex encoded event id
+    pub event_id: String,
+    pub ciphersuite: Option<String>,
+    /// The relays that accepted it
+    pub relays: Vec<String>,
+    pub created_at: i64,
+    /// When we asked relays to delete it, `None` while it's live
+    pub deleted_at: Option<i64>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct PublishedKeyPackageRow {
+    event_id: String,
+    ciphersuite: Option<String>,
+    relays: String,
+    created_at: i64,
+    deleted_at: Option<i64>,
+}
+
+impl TryFrom<PublishedKeyPackageRow> for PublishedKeyPackage {
+    type Error = serde_json::Error;
+
+    fn try_from(row: PublishedKeyPackageRow) -> std::result::Result<Self, Self::Error> {
+        Ok(Self {
+            event_id: row.event_id,
+            ciphersuite: row.ciphersuite,
+            relays: serde_json::from_str(&row.relays)?,
+            created_at: row.created_at,
+            deleted_at: row.deleted_at,
+        })
+    }
+}
+
+/// What one of our key package relays has
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayKeyPackageStatus {
+    pub relay_url: String,
+    /// Why we couldn't ask the relay, `None` if we could
+    pub error: Option<String>,
+    /// Our newest key package, if the relay has it
+    pub current: Option<String>,
+    /// Key packages we replaced that the relay still has
+    pub superseded: Vec<String>,
+    /// Key packages we didn't publish from this device, e.g. from our other devices
+    pub untracked: Vec<String>,
+}
+
+/// Records a key package we just published, with the hash reference of its private key material
+pub async fn record_published(
+    account_pubkey: &PublicKey,
+    event: &Event,
+    hash_ref: Option<&str>,
+    relays: &[String],
+    db: &Database,
+) -> Result<()> {
+    let ciphersuite = event
+        .tags
+        .find(TagKind::MlsCiphersuite)
+        .and_then(|tag| tag.content())
+        .map(|ciphersuite| ciphersuite.to_string());
+    sqlx::query(
+        "INSERT INTO published_key_packages (event_id, account_pubkey, ciphersuite, relays, created_at, hash_ref)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (event_id) DO UPDATE SET relays = excluded.relays",
+    )
+    .bind(event.id.to_hex())
+    .bind(account_pubkey.to_hex())
+    .bind(ciphersuite)
+    .bind(serde_json::to_string(relays)?)
+    .bind(event.created_at.as_u64() as i64)
+    .bind(hash_ref)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Marks key packages as deleted from relays
+pub async fn mark_deleted(
+    account_pubkey: &PublicKey,
+    event_ids: &[EventId],
+    db: &Database,
+) -> Result<()> {
+    let now = Timestamp::now().as_u64() as i64;
+    for event_id in event_ids {
+        sqlx::query(
+            "UPDATE published_key_packages SET deleted_at = ?
+             WHERE account_pubkey = ? AND event_id = ? AND deleted_at IS NULL",
+        )
+        .bind(now)
+        .bind(account_pubkey.to_hex())
+        .bind(event_id.to_hex())
+        .execute(&db.pool)
+        .await?;
+    }
+    Ok(())
+}
+
+/// Returns every key package an account published, newest first
+pub async fn published_key_packages(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<Vec<PublishedKeyPackage>> {
+    let rows = sqlx::query_as::<_, PublishedKeyPackageRow>(
+        "SELECT event_id, ciphersuite, relays, created_at, deleted_at FROM published_key_packages
+         WHERE account_pubkey = ?
+         ORDER BY created_at DESC, event_id",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(rows
+        .into_iter()
+        .map(PublishedKeyPackage::try_from)
+        .collect::<std::result::Result<_, _>>()?)
+}
+
+/// The key packages among `events` whose private key material is on this device, with its hash
+/// reference
+fn local_key_packages<'a>(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    events: &'a [Event],
+) -> Vec<(&'a Event, String)> {
+    events
+        .iter()
+        .filter_map(|event| {
+            key_packages::local_key_package_ref(nostr_mls, event).map(|hash_ref| (event, hash_ref))
+        })
+        .collect()
+}
+
+/// Records the key packages this device already has on relays, for accounts that published them
+/// before we kept track. Key packages without key material on this device were published
+/// elsewhere and are skipped. Returns `false` if no relay confirmed what it has and we found
+/// nothing.
+async fn seed_from_relays(account: &Account, wn: Arc<Whitenoise>) -> Result<bool> {
+    let relays = account.relays(RelayType::KeyPackage, wn.clone()).await?;
+    let filter = Filter::new()
+        .author(account.pubkey)
+        .kind(Kind::MlsKeyPackage);
+    let (events, confirmed) = wn.nostr.fetch_own_events_confirmed(filter, &relays).await?;
+    let local = {
+        let nostr_mls_guard = tokio::time::timeout(Duration::from_secs(5), wn.nostr_mls.lock())
+            .await
+            .map_err(|_| KeyPackageLifecycleError::NostrMlsLockTimeout)?;
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(KeyPackageLifecycleError::NostrMlsNotInitialized)?;
+        local_key_packages(nostr_mls, &events)
+    };
+    for (event, hash_ref) in local {
+        record_published(
+            &account.pubkey,
+            event,
+            Some(&hash_ref),
+            &relays,
+            &wn.database,
+        )
+        .await?;
+    }
+    Ok(confirmed || !events.is_empty())
+}
+
+/// Key packages deleted from relays before `cutoff` whose private key material is still in MLS
+/// storage, with its hash reference if we know it
+async fn expired_key_material(
+    account_pubkey: &PublicKey,
+    cutoff: i64,
+    db: &Database,
+) -> Result<Vec<(String, Option<String>)>> {
+    Ok(sqlx::query_as::<_, (String, Option<String>)>(
+        "SELECT event_id, hash_ref FROM published_key_packages
+         WHERE account_pubkey = ? AND deleted_at IS NOT NULL AND deleted_at < ?
+            AND keys_deleted_at IS NULL",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(cutoff)
+    .fetch_all(&db.pool)
+    .await?)
+}
+
+async fn mark_keys_deleted(event_ids: &[String], db: &Database) -> Result<()> {
+    let now = Timestamp::now().as_u64() as i64;
+    for event_id in event_ids {
+        sqlx::query("UPDATE published_key_packages SET keys_deleted_at = ? WHERE event_id = ?")
+            .bind(now)
+            .bind(event_id)
+            .execute(&db.pool)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Removes the key material of key packages, given with their hash reference
+///
+/// Returns the key packages that are done with, and how many had key material removed.
+fn remove_key_material(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    key_packages: Vec<(String, Option<String>)>,
+) -> (Vec<String>, usize) {
+    let mut done = Vec::new();
+    let mut removed = 0;
+    for (event_id, hash_ref) in key_packages {
+        let Some(hash_ref) = hash_ref else {
+            tracing::warn!(
+                target: "whitenoise::key_package_lifecycle::remove_key_material",
+                "Key material of {} isn't on this device",
+                event_id
+            );
+            done.push(event_id);
+            continue;
+        };
+        match key_packages::delete_key_material(nostr_mls, &hash_ref) {
+            Ok(()) => {
+                removed += 1;
+                done.push(event_id);
+            }
+            Err(e) => {
+                tracing::warn!(
+                    target: "whitenoise::key_package_lifecycle::remove_key_material",
+                    "Failed to remove key material of {}: {}",
+                    event_id,
+                    e
+                );
+            }
+        }
+    }
+    (done, removed)
+}
+
+/// Removes the private key material of key packages that were deleted from relays more than
+/// [`KEY_MATERIAL_GRACE_SECS`] ago from MLS storage
+///
+/// Returns how many were removed.
+async fn delete_expired_key_material(wn: Arc<Whitenoise>) -> Result<usize> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+    let cutoff = Timestamp::now().as_u64() as i64 - KEY_MATERIAL_GRACE_SECS;
+    let expired = expired_key_material(&account_pubkey, cutoff, &wn.database).await?;
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    let (done, removed) = {
+        let nostr_mls_guard = tokio::time::timeout(Duration::from_secs(5), wn.nostr_mls.lock())
+            .await
+            .map_err(|_| KeyPackageLifecycleError::NostrMlsLockTimeout)?;
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(KeyPackageLifecycleError::NostrMlsNotInitialized)?;
+        remove_key_material(nostr_mls, expired)
+    };
+
+    mark_keys_deleted(&done, &wn.database).await?;
+    Ok(removed)
+}
+
+/// The live key packages replaced by the newest one, given packages newest first
+fn superseded(packages: &[PublishedKeyPackage]) -> Vec<&PublishedKeyPackage> {
+    packages
+        .iter()
+        .filter(|package| package.deleted_at.is_none())
+        .skip(1)
+        .collect()
+}
+
+/// Whether we should publish a fresh key package, given packages newest first
+fn needs_refresh(packages: &[PublishedKeyPackage], now: i64) -> bool {
+    match packages.iter().find(|package| package.deleted_at.is_none()) {
+        Some(newest) => now - newest.created_at > MAX_KEY_PACKAGE_AGE_SECS,
+        None => true,
+    }
+}
+
+/// Asks relays to delete some of our key packages and marks them as deleted. The private key
+/// material is kept.
+async fn delete_key_packages(
+    account_pubkey: &PublicKey,
+    event_ids: &[EventId],
+    relays: &[String],
+    reason: &str,
+    nostr: &NostrManager,
+    db: &Database,
+) -> Result<()> {
+    if event_ids.is_empty() {
+        return Ok(());
+    }
+    let relays: Vec<RelayUrl> = relays
+        .iter()
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .collect();
+    if !relays.is_empty() {
+        let builder = EventBuilder::delete(
+            EventDeletionRequest::new()
+                .ids(event_ids.iter().copied())
+                .reason(reason),
+        );
+        let event = nostr.client.sign_event_builder(builder).await?;
+        nostr.send_event_to_relays(&relays, &event).await?;
+    }
+    mark_deleted(account_pubkey, event_ids, db).await
+}
+
+/// Deletes the key packages of the active account that a newer one replaced
+///
+/// Returns how many were deleted.
+pub async fn delete_superseded(wn: Arc<Whitenoise>) -> Result<usize> {
+    let account = Account::get_active(wn.clone()).await?;
+    let packages = published_key_packages(&account.pubkey, &wn.database).await?;
+    let superseded = superseded(&packages);
+    if superseded.is_empty() {
+        return Ok(0);
+    }
+
+    let event_ids: Vec<EventId> = superseded
+        .iter()
+        .filter_map(|package| EventId::from_hex(&package.event_id).ok())
+        .collect();
+    let mut relays: Vec<String> = account.relays(RelayType::KeyPackage, wn.clone()).await?;
+    for package in &superseded {
+        relays.extend(package.relays.iter().cloned());
+    }
+    relays.sort();
+    relays.dedup();
+
+    delete_key_packages(
+        &account.pubkey,
+        &event_ids,
+        &relays,
+        "Superseded key package",
+        &wn.nostr,
+        &wn.database,
+    )
+    .await?;
+    Ok(event_ids.len())
+}
+
+/// The key packages among `events` that no client running our code could use, because their
+/// ciphersuite or extensions don't match ours or their lifetime is over
+fn incompatible_key_packages(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    events: &[Event],
+) -> Vec<EventId> {
+    events
+        .iter()
+        .filter(|event| {
+            matches!(
+                key_packages::check_key_package(nostr_mls, event),
+                Err(KeyPackageRejection::WrongCiphersuite { .. }
+                    | KeyPackageRejection::MissingExtensions { .. }
+                    | KeyPackageRejection::ExpiredLifetime)
+            )
+        })
+        .map(|event| event.id)
+        .collect()
+}
+
+/// Deletes our key packages that no client running our code could use
+///
+/// Returns how many were deleted.
+async fn delete_incompatible(wn: Arc<Whitenoise>) -> Result<usize> {
+    let account = Account::get_active(wn.clone()).await?;
+    let events: Vec<Event> = wn
+        .nostr
+        .fetch_user_key_packages(account.pubkey)
+        .await?
+        .into_iter()
+        .collect();
+
+    let incompatible = {
+        let nostr_mls_guard = tokio::time::timeout(Duration::from_secs(5), wn.nostr_mls.lock())
+            .await
+            .map_err(|_| KeyPackageLifecycleError::NostrMlsLockTimeout)?;
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(KeyPackageLifecycleError::NostrMlsNotInitialized)?;
+        incompatible_key_packages(nostr_mls, &events)
+    };
+
+    let relays = account.relays(RelayType::KeyPackage, wn.clone()).await?;
+    delete_key_packages(
+        &account.pubkey,
+        &incompatible,
+        &relays,
+        "Incompatible key package",
+        &wn.nostr,
+        &wn.database,
+    )
+    .await?;
+    Ok(incompatible.len())
+}
+
+/// Publishes a fresh key package if ours is getting old, and cleans up the ones that were
+/// replaced or that can't be used
+///
+/// Returns whether a new key package was published.
+pub async fn refresh_key_packages(wn: Arc<Whitenoise>) -> Result<bool> {
+    let account = Account::get_active(wn.clone()).await?;
+    let mut packages = published_key_packages(&account.pubkey, &wn.database).await?;
+    if packages.is_empty() {
+        // Key packages published before we kept track are only on our relays
+        if !seed_from_relays(&account, wn.clone()).await? {
+            tracing::debug!(
+                target: "whitenoise::key_package_lifecycle::refresh_key_packages",
+                "Couldn't check our key package relays, trying again later"
+            );
+            return Ok(false);
+        }
+        packages = published_key_packages(&account.pubkey, &wn.database).await?;
+    }
+
+    let published = if needs_refresh(&packages, Timestamp::now().as_u64() as i64) {
+        // Publishing deletes the superseded packages too
+        key_packages::publish_key_package(wn.clone()).await?;
+        true
+    } else {
+        delete_superseded(wn.clone()).await?;
+        false
+    };
+
+    let deleted = delete_incompatible(wn.clone()).await?;
+    if deleted > 0 {
+        tracing::debug!(
+            target: "whitenoise::key_package_lifecycle::refresh_key_packages",
+            "Deleted {} incompatible key packages",
+            deleted
+        );
+    }
+    let removed = delete_expired_key_material(wn).await?;
+    if removed > 0 {
+        tracing::debug!(
+            target: "whitenoise::key_package_lifecycle::refresh_key_packages",
+            "Removed the key material of {} deleted key packages",
+            removed
+        );
+    }
+    Ok(published)
+}
+
+/// Starts the background task that keeps the key packages of the active account fresh.
+///
+/// Calling this more than once is a no-op.
+pub fn start_refresh_worker() {
+    if REFRESH_WORKER_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::debug!(
+            target: "whitenoise::key_package_lifecycle::refresh_worker",
+            "Starting key package refresh worker"
+        );
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            let wn = crate::runtime::wn();
+            if Account::get_active_pubkey(wn.clone()).await.is_err() {
+                continue;
+            }
+            match refresh_key_packages(wn).await {
+                Ok(false) => {}
+                Ok(true) => {
+                    tracing::debug!(
+                        target: "whitenoise::key_package_lifecycle::refresh_worker",
+                        "Published a fresh key package"
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        target: "whitenoise::key_package_lifecycle::refresh_worker",
+                        "Error refreshing key packages: {}",
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// Sorts the key packages a relay has into current, superseded and untracked
+fn relay_status(
+    relay_url: String,
+    event_ids: &[EventId],
+    packages: &[PublishedKeyPackage],
+) -> RelayKeyPackageStatus {
+    let newest = packages
+        .iter()
+        .find(|package| package.deleted_at.is_none())
+        .map(|package| package.event_id.as_str());
+    let tracked: HashSet<&str> = packages
+        .iter()
+        .map(|package| package.event_id.as_str())
+        .collect();
+
+    let mut status = RelayKeyPackageStatus {
+        relay_url,
+        error: None,
+        current: None,
+        superseded: Vec::new(),
+        untracked: Vec::new(),
+    };
+    for event_id in event_ids {
+        let event_id = event_id.to_hex();
+        if Some(event_id.as_str()) == newest {
+            status.current = Some(event_id);
+        } else if tracked.contains(event_id.as_str()) {
+            status.superseded.push(event_id);
+        } else {
+            status.untracked.push(event_id);
+        }
+    }
+    status
+}
+
+/// Asks each of our key package relays which of our key packages it has
+pub async fn key_package_status(wn: Arc<Whitenoise>) -> Result<Vec<RelayKeyPackageStatus>> {
+    let account = Account::get_active(wn.clone()).await?;
+    let packages = published_key_packages(&account.pubkey, &wn.database).await?;
+    let relays: Vec<RelayUrl> = account
+        .relays(RelayType::KeyPackage, wn.clone())
+        .await?
+        .iter()
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .collect();
+    let temporary_relays = wn
+        .nostr
+        .acquire_temporary_relays(&relays, TemporaryRelayUse::Read)
+        .await?;
+
+    let filter = Filter::new()
+        .author(account.pubkey)
+        .kind(Kind::MlsKeyPackage);
+    let handles: Vec<_> = relays
+        .iter()
+        .map(|relay_url| {
+            let wn = wn.clone();
+            let relay_url = relay_url.clone();
+            let filter = filter.clone();
+            tokio::spawn(async move {
+                let timeout = wn.nostr.timeout().await.map_err(|e| e.to_string())?;
+                wn.nostr
+                    .client
+                    .fetch_events_from([relay_url], filter, timeout)
+                    .await
+                    .map(|events| events.into_iter().map(|event| event.id).collect::<Vec<_>>())
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+
+    let mut statuses = Vec::new();
+    for (relay_url, handle) in relays.iter().zip(handles) {
+        let relay_url = relay_url.to_string();
+        match handle.await.unwrap_or_else(|e| Err(e.to_string())) {
+            Ok(event_ids) => statuses.push(relay_status(relay_url, &event_ids, &packages)),
+            Err(e) => statuses.push(RelayKeyPackageStatus {
+                error: Some(e),
+                ..relay_status(relay_url, &[], &packages)
+            }),
+        }
+    }
+    wn.nostr.release_temporary_relays(temporary_relays).await;
+    Ok(statuses)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+    use crate::key_packages::test_utils::{
+        self, lifetime, single_use_key_package_event, test_nostr_mls,
+    };
+    use crate::nostr_manager::test_relay::MockRelay;
+    use crate::nostr_manager::NostrManagerSettings;
+
+    fn key_package_event(keys: &Keys, created_at: u64) -> Event {
+        EventBuilder::new(Kind::MlsKeyPackage, "key package")
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_record_and_supersede() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let keys = Keys::generate();
+        let old = key_package_event(&keys, 100);
+        let new = key_package_event(&keys, 200);
+        let relays = vec!["wss://relay.example.com".to_string()];
+
+        record_published(&keys.public_key(), &old, None, &relays, &db)
+            .await
+            .unwrap();
+        record_published(&keys.public_key(), &new, None, &relays, &db)
+            .await
+            .unwrap();
+
+        let packages = published_key_packages(&keys.public_key(), &db)
+            .await
+            .unwrap();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].event_id, new.id.to_hex());
+        assert_eq!(packages[0].relays, relays);
+
+        let superseded_ids: Vec<&str> = superseded(&packages)
+            .iter()
+            .map(|package| package.event_id.as_str())
+            .collect();
+        assert_eq!(superseded_ids, vec![old.id.to_hex()]);
+
+        mark_deleted(&keys.public_key(), &[old.id], &db)
+            .await
+            .unwrap();
+        let packages = published_key_packages(&keys.public_key(), &db)
+            .await
+            .unwrap();
+        assert!(packages[1].deleted_at.is_some());
+        assert!(superseded(&packages).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_key_material() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let (nostr_mls, _mls_dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let live = test_utils::key_package_event(&nostr_mls, &keys, 100);
+        let deleted = test_utils::key_package_event(&nostr_mls, &keys, 200);
+        for event in [&live, &deleted] {
+            let hash_ref = key_packages::local_key_package_ref(&nostr_mls, event);
+            assert!(hash_ref.is_some());
+            record_published(&keys.public_key(), event, hash_ref.as_deref(), &[], &db)
+                .await
+                .unwrap();
+        }
+        mark_deleted(&keys.public_key(), &[deleted.id], &db)
+            .await
+            .unwrap();
+
+        // Still within the grace period
+        let now = Timestamp::now().as_u64() as i64;
+        assert!(expired_key_material(&keys.public_key(), now - 60, &db)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let expired = expired_key_material(&keys.public_key(), now + 60, &db)
+            .await
+            .unwrap();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, deleted.id.to_hex());
+
+        // Removed by hash reference, without validating the key package again
+        let (done, removed) = remove_key_material(&nostr_mls, expired);
+        assert_eq!(done, vec![deleted.id.to_hex()]);
+        assert_eq!(removed, 1);
+        assert_eq!(
+            key_packages::local_key_package_ref(&nostr_mls, &deleted),
+            None
+        );
+        assert!(key_packages::local_key_package_ref(&nostr_mls, &live).is_some());
+
+        mark_keys_deleted(&done, &db).await.unwrap();
+        assert!(expired_key_material(&keys.public_key(), now + 60, &db)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_local_key_packages() {
+        let (nostr_mls, _mls_dir) = test_nostr_mls();
+        let (other_device, _other_dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let ours = test_utils::key_package_event(&nostr_mls, &keys, 100);
+        let theirs = test_utils::key_package_event(&other_device, &keys, 200);
+        let events = vec![ours.clone(), theirs];
+
+        let local = local_key_packages(&nostr_mls, &events);
+
+        assert_eq!(local.len(), 1);
+        assert_eq!(local[0].0.id, ours.id);
+        assert_eq!(
+            Some(local[0].1.clone()),
+            key_packages::local_key_package_ref(&nostr_mls, &ours)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_expired_key_package_deleted() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let (nostr_mls, _mls_dir) = test_nostr_mls();
+        let relay = MockRelay::run().await;
+        let nostr_dir = tempfile::TempDir::new().unwrap();
+        let nostr = NostrManager::new(
+            nostr_dir.path().to_path_buf(),
+            NostrManagerSettings::default(),
+        )
+        .await
+        .unwrap();
+        let keys = Keys::generate();
+        nostr.client.set_signer(keys.clone()).await;
+
+        let now = Timestamp::now().as_u64();
+        let live = single_use_key_package_event(
+            &nostr_mls,
+            &keys,
+            lifetime(now - 60 * 60, now + 60 * 60),
+            now - 60,
+        );
+        let expired = single_use_key_package_event(
+            &nostr_mls,
+            &keys,
+            lifetime(now - 2 * 60 * 60, now - 60 * 60),
+            now - 2 * 60 * 60,
+        );
+        let relays = vec![relay.url().to_string()];
+        for event in [&live, &expired] {
+            record_published(&keys.public_key(), event, None, &relays, &db)
+                .await
+                .unwrap();
+        }
+
+        let incompatible = incompatible_key_packages(&nostr_mls, &[live.clone(), expired.clone()]);
+        assert_eq!(incompatible, vec![expired.id]);
+        delete_key_packages(
+            &keys.public_key(),
+            &incompatible,
+            &relays,
+            "Incompatible key package",
+            &nostr,
+            &db,
+        )
+        .await
+        .unwrap();
+
+        let deletions: Vec<Event> = relay
+            .events()
+            .await
+            .into_iter()
+            .filter(|event| event.kind == Kind::EventDeletion)
+            .collect();
+        assert_eq!(deletions.len(), 1);
+        assert_eq!(
+            deletions[0].tags.event_ids().copied().collect::<Vec<_>>(),
+            vec![expired.id]
+        );
+        let packages = published_key_packages(&keys.public_key(), &db)
+            .await
+            .unwrap();
+        assert_eq!(packages[0].event_id, live.id.to_hex());
+        assert_eq!(packages[0].deleted_at, None);
+        assert_eq!(packages[1].event_id, expired.id.to_hex());
+        assert!(packages[1].deleted_at.is_some());
+    }
+
+    #[test]
+    fn test_needs_refresh() {
+        let package = |created_at, deleted_at| PublishedKeyPackage {
+            event_id: "id".to_string(),
+            ciphersuite: None,
+            relays: Vec::new(),
+            created_at,
+            deleted_at,
+        };
+        let now = 100 * 24 * 60 * 60;
+
+        assert!(needs_refresh(&[], now));
+        assert!(!needs_refresh(&[package(now - 60, None)], now));
+        assert!(needs_refresh(
+            &[package(now - MAX_KEY_PACKAGE_AGE_SECS - 1, None)],
+            now
+        ));
+        assert!(needs_refresh(&[package(now - 60, Some(now))], now));
+    }
+
+    #[test]
+    fn test_relay_status() {
+        let keys = Keys::generate();
+        let current = key_package_event(&keys, 300);
+        let replaced = key_package_event(&keys, 200);
+        let other_device = key_package_event(&keys, 100);
+        let packages = vec![
+            PublishedKeyPackage {
+                event_id: current.id.to_hex(),
+                ciphersuite: None,
+                relays: Vec::new(),
+                created_at: 300,
+                deleted_at: None,
+            },
+            PublishedKeyPackage {
+                event_id: replaced.id.to_hex(),
+                ciphersuite: None,
+                relays: Vec::new(),
+                created_at: 200,
+                deleted_at: Some(250),
+            },
+        ];
+
+        let status = relay_status(
+            "wss://relay.example.com".to_string(),
+            &[current.id, replaced.id, other_device.id],
+            &packages,
+        );
+        assert_eq!(status.current, Some(current.id.to_hex()));
+        assert_eq!(status.superseded, vec![replaced.id.to_hex()]);
+        assert_eq!(status.untracked, vec![other_device.id.to_hex()]);
+        assert_eq!(status.error, None);
+    }
+}
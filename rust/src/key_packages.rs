@@ -4,17 +4,28 @@
 //! establish secure communication channels between peers.
 //!
 //! It includes functions for fetching key packages from Nostr relays, publishing new key packages,
-//! and deleting key packages from relays.
+//! and deleting key packages from relays. When someone has several key packages, the newest valid
+//! one is used and the others are reported with the reason they were rejected.
 
 use nostr_mls::prelude::*;
+use nostr_mls::NostrMls;
+use nostr_mls_sqlite_storage::NostrMlsSqliteStorage;
+use nostr_sdk::prelude::Events;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use thiserror::Error;
+use tokio::sync::Semaphore;
 
 use crate::accounts::{Account, AccountError};
+use crate::identifiers;
+use crate::key_package_lifecycle;
 use crate::nostr_manager;
 use crate::relays::RelayType;
 use crate::whitenoise::Whitenoise;
 
+/// How many people's key packages `check_key_package_readiness` fetches at the same time
+const MAX_CONCURRENT_READINESS_FETCHES: usize = 8;
+
 #[derive(Error, Debug)]
 pub enum KeyPackageError {
     #[error("No valid key package found: {0}")]
@@ -35,6 +46,8 @@ pub enum KeyPackageError {
     NostrMlsNotInitialized,
     #[error("Join error: {0}")]
     JoinError(#[from] tokio::task::JoinError),
+    #[error("No relay accepted the key package: {0:?}")]
+    NotPublished(Vec<String>),
 }
 
 #[derive(Debug)]
@@ -46,6 +59,74 @@ pub struct KeyPackageResponse {
 
 pub type Result<T> = std::result::Result<T, KeyPackageError>;
 
+/// Why a key package can't be used to add someone to a group
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum KeyPackageRejection {
+    /// The event signature doesn't match its author
+    BadSignature,
+    /// The key package couldn't be decoded or its own signatures don't verify
+    InvalidKeyPackage { error: String },
+    /// Not the ciphersuite our groups use
+    WrongCiphersuite { expected: String, found: String },
+    /// Doesn't support every extension our groups need
+    MissingExtensions { missing: Vec<String> },
+    /// Outside of its lifetime, expired or not valid yet
+    ExpiredLifetime,
+}
+
+impl std::fmt::Display for KeyPackageRejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadSignature => write!(f, "bad event signature"),
+            Self::InvalidKeyPackage { error } => write!(f, "invalid key package: {}", error),
+            Self::WrongCiphersuite { expected, found } => {
+                write!(
+                    f,
+                    "wrong ciphersuite: expected {}, found {}",
+                    expected, found
+                )
+            }
+            Self::MissingExtensions { missing } => {
+                write!(f, "missing extensions: {}", missing.join(", "))
+            }
+            Self::ExpiredLifetime => write!(f, "expired lifetime"),
+        }
+    }
+}
+
+/// A key package we didn't use, and why
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RejectedKeyPackage {
+    /// Hex encoded event id
+    pub event_id: String,
+    #[serde(flatten)]
+    pub reason: KeyPackageRejection,
+}
+
+/// The key package picked among someone's key package events
+#[derive(Debug, Default)]
+pub struct KeyPackageSelection {
+    /// The newest valid key package
+    pub selected: Option<(Event, KeyPackage)>,
+    /// Every key package that isn't valid, newest first
+    pub rejected: Vec<RejectedKeyPackage>,
+}
+
+impl KeyPackageSelection {
+    /// Why we couldn't pick a key package, for error messages
+    fn rejection_summary(&self) -> String {
+        if self.rejected.is_empty() {
+            return "no key package events".to_string();
+        }
+        self.rejected
+            .iter()
+            .map(|rejected| format!("{}: {}", rejected.event_id, rejected.reason))
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
 /// Fetches key packages for a list of pubkeys
 pub async fn fetch_key_packages_for_members(
     member_pubkeys: &[String],
@@ -62,25 +143,26 @@ pub async fn fetch_key_packages_for_members(
     // Check that members are valid pubkeys & fetch key packages
     for pubkey in member_pubkeys.iter() {
         // Fetch prekeys from the members
-        match fetch_key_package_for_pubkey(pubkey.clone(), wn.clone()).await {
-            Ok(event_and_key_package) => match event_and_key_package {
-                Some((event_id, kp)) => member_key_packages.push(KeyPackageResponse {
+        match fetch_key_package_selection(pubkey.clone(), wn.clone()).await {
+            Ok(selection) => match selection.selected {
+                Some((event, kp)) => member_key_packages.push(KeyPackageResponse {
                     pubkey: pubkey.clone(),
-                    event_id,
+                    event_id: event.id,
                     key_package: kp,
                 }),
                 None => {
                     // TODO: Need to fix this when we get to adding more than one member to a group at once.
                     return Err(KeyPackageError::NoValidKeyPackage(format!(
-                        "No valid key package event found for member: {}",
-                        pubkey
+                        "No valid key package event found for member {} ({})",
+                        pubkey,
+                        selection.rejection_summary()
                     )));
                 }
             },
-            Err(_) => {
+            Err(e) => {
                 return Err(KeyPackageError::FetchingKeyPackage(format!(
-                    "Error fetching valid key package event for member: {}",
-                    pubkey
+                    "Error fetching valid key package event for member {}: {}",
+                    pubkey, e
                 )));
             }
         };
@@ -88,22 +170,29 @@ pub async fn fetch_key_packages_for_members(
     Ok(member_key_packages)
 }
 
-/// Fetches key packages for a single pubkey
+/// Fetches key packages for a single pubkey, given as hex, npub, nprofile or `nostr:` URI
 pub async fn fetch_key_package_for_pubkey(
     pubkey: String,
     wn: Arc<Whitenoise>,
 ) -> Result<Option<(EventId, KeyPackage)>> {
-    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_for_pubkey", "Fetching key package for pubkey: {:?}", pubkey);
-    let public_key = PublicKey::from_hex(&pubkey).expect("Invalid pubkey");
-    let key_package_filter = Filter::new().kind(Kind::MlsKeyPackage).author(public_key);
-    let key_package_events = wn
-        .nostr
-        .client
-        .fetch_events(key_package_filter, wn.nostr.timeout().await.unwrap())
-        .await
-        .expect("Error fetching key_package events");
+    Ok(fetch_key_package_selection(pubkey, wn)
+        .await?
+        .selected
+        .map(|(event, key_package)| (event.id, key_package)))
+}
+
+/// Fetches the key packages of a pubkey and picks the newest valid one, given as hex, npub,
+/// nprofile or `nostr:` URI
+pub async fn fetch_key_package_selection(
+    pubkey: String,
+    wn: Arc<Whitenoise>,
+) -> Result<KeyPackageSelection> {
+    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_selection", "Fetching key package for pubkey: {:?}", pubkey);
+    let public_key = identifiers::parse_pubkey(&pubkey)
+        .map_err(|e| KeyPackageError::FetchingKeyPackage(e.to_string()))?;
+    let key_package_events = wn.nostr.fetch_user_key_packages(public_key).await?;
 
-    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_for_pubkey", "Attempting to acquire nostr_mls lock");
+    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_selection", "Attempting to acquire nostr_mls lock");
     let nostr_mls_guard = match tokio::time::timeout(
         std::time::Duration::from_secs(5),
         wn.nostr_mls.lock(),
@@ -111,11 +200,11 @@ pub async fn fetch_key_package_for_pubkey(
     .await
     {
         Ok(guard) => {
-            tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_for_pubkey", "nostr_mls lock acquired");
+            tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_selection", "nostr_mls lock acquired");
             guard
         }
         Err(_) => {
-            tracing::error!(target: "whitenoise::key_packages::fetch_key_package_for_pubkey", "Timeout waiting for nostr_mls lock");
+            tracing::error!(target: "whitenoise::key_packages::fetch_key_package_selection", "Timeout waiting for nostr_mls lock");
             return Err(KeyPackageError::NostrMlsError(
                 nostr_mls::error::Error::KeyPackage(
                     "Timeout waiting for nostr_mls lock".to_string(),
@@ -123,69 +212,290 @@ pub async fn fetch_key_package_for_pubkey(
             ));
         }
     };
-    let result = if let Some(nostr_mls) = nostr_mls_guard.as_ref() {
-        let mut valid_key_packages: Vec<(EventId, KeyPackage)> = Vec::new();
-        for event in key_package_events.iter() {
-            let key_package = nostr_mls
-                .parse_key_package(event)
-                .map_err(KeyPackageError::NostrMlsError)?;
-            if key_package.ciphersuite() == nostr_mls.ciphersuite
-                && key_package.last_resort()
-                && key_package.leaf_node().capabilities().extensions().len()
-                    == nostr_mls.extensions.len()
-                && nostr_mls.extensions.iter().all(|&ext_type| {
-                    key_package
-                        .leaf_node()
-                        .capabilities()
-                        .extensions()
-                        .iter()
-                        .any(|ext| ext == &ext_type)
-                })
-            {
-                valid_key_packages.push((event.id, key_package));
+    let nostr_mls = nostr_mls_guard
+        .as_ref()
+        .ok_or(KeyPackageError::NostrMlsNotInitialized)?;
+    let selection = select_key_package(nostr_mls, key_package_events);
+    drop(nostr_mls_guard);
+    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_selection", "nostr_mls lock released");
+
+    for rejected in &selection.rejected {
+        tracing::debug!(
+            target: "whitenoise::key_packages::fetch_key_package_selection",
+            "Rejected key package {} of {}: {}",
+            rejected.event_id,
+            pubkey,
+            rejected.reason
+        );
+    }
+    match &selection.selected {
+        Some((event, _)) => tracing::debug!(
+            target: "whitenoise::key_packages::fetch_key_package_selection",
+            "Found valid key package {} for user {:?}",
+            event.id,
+            pubkey
+        ),
+        None => tracing::debug!(
+            target: "whitenoise::key_packages::fetch_key_package_selection",
+            "No valid key package found for user {:?}",
+            pubkey
+        ),
+    }
+    Ok(selection)
+}
+
+/// Orders key package events newest first. Equal timestamps are ordered by id so that every
+/// client picks the same one.
+fn sort_newest_first(events: &mut [Event]) {
+    events.sort_by(|a, b| b.created_at.cmp(&a.created_at).then(a.id.cmp(&b.id)));
+}
+
+/// Picks the newest valid key package among someone's key package events
+pub(crate) fn select_key_package(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    events: impl IntoIterator<Item = Event>,
+) -> KeyPackageSelection {
+    let mut events: Vec<Event> = events.into_iter().collect();
+    sort_newest_first(&mut events);
+
+    let mut selection = KeyPackageSelection::default();
+    for event in events {
+        match check_key_package(nostr_mls, &event) {
+            Ok(key_package) => {
+                // Older valid key packages aren't needed
+                if selection.selected.is_none() {
+                    selection.selected = Some((event, key_package));
+                }
             }
+            Err(reason) => selection.rejected.push(RejectedKeyPackage {
+                event_id: event.id.to_hex(),
+                reason,
+            }),
         }
+    }
+    selection
+}
 
-        match valid_key_packages.first() {
-            Some((event_id, kp)) => {
-                tracing::debug!(
-                    target: "whitenoise::key_packages::fetch_key_package_for_pubkey",
-                    "Found valid key package for user {:?}",
-                    pubkey.clone()
-                );
-                Ok(Some((*event_id, kp.clone())))
-            }
-            None => {
-                tracing::debug!(
-                    target: "whitenoise::key_packages::fetch_key_package_for_pubkey",
-                    "No valid key package found for user {:?}",
-                    pubkey
-                );
-                Ok(None)
-            }
+/// Checks that we can add someone to a group with a key package event: a valid signature, our
+/// ciphersuite, at least the extensions our groups need and a lifetime that covers now.
+/// Both last resort and single use key packages are accepted.
+pub(crate) fn check_key_package(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    event: &Event,
+) -> std::result::Result<KeyPackage, KeyPackageRejection> {
+    if event.verify().is_err() {
+        return Err(KeyPackageRejection::BadSignature);
+    }
+
+    // Parsing validates the key package, lifetime included, but its errors don't say which check
+    // failed, so the lifetime is checked on the decoded key package first
+    if !decode_key_package(event)?.life_time().is_valid() {
+        return Err(KeyPackageRejection::ExpiredLifetime);
+    }
+
+    let key_package =
+        nostr_mls
+            .parse_key_package(event)
+            .map_err(|e| KeyPackageRejection::InvalidKeyPackage {
+                error: e.to_string(),
+            })?;
+
+    if key_package.ciphersuite() != nostr_mls.ciphersuite {
+        return Err(KeyPackageRejection::WrongCiphersuite {
+            expected: format!("{:?}", nostr_mls.ciphersuite),
+            found: format!("{:?}", key_package.ciphersuite()),
+        });
+    }
+
+    let extensions = key_package.leaf_node().capabilities().extensions();
+    let missing: Vec<String> = nostr_mls
+        .extensions
+        .iter()
+        .filter(|ext_type| !extensions.contains(ext_type))
+        .map(|ext_type| format!("{:?}", ext_type))
+        .collect();
+    if !missing.is_empty() {
+        return Err(KeyPackageRejection::MissingExtensions { missing });
+    }
+
+    Ok(key_package)
+}
+
+/// Decodes the key package of an event without validating it
+fn decode_key_package(event: &Event) -> std::result::Result<KeyPackageIn, KeyPackageRejection> {
+    let invalid = |error: String| KeyPackageRejection::InvalidKeyPackage { error };
+    let bytes = hex::decode(&event.content).map_err(|e| invalid(e.to_string()))?;
+    KeyPackageIn::tls_deserialize_exact(bytes).map_err(|e| invalid(e.to_string()))
+}
+
+/// The hex encoded hash reference MLS storage keeps the private key material of a key package
+/// under, if that key material is on this device
+pub(crate) fn local_key_package_ref(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    event: &Event,
+) -> Option<String> {
+    let key_package = nostr_mls.parse_key_package(event).ok()?;
+    let hash_ref = key_package.hash_ref(nostr_mls.provider.crypto()).ok()?;
+    let stored: Option<KeyPackageBundle> =
+        nostr_mls.provider.storage().key_package(&hash_ref).ok()?;
+    stored.map(|_| hex::encode(hash_ref.as_slice()))
+}
+
+/// Removes the private key material stored under a hex encoded hash reference. Unlike
+/// `delete_key_package_from_storage`, this works for key packages that don't validate anymore.
+pub(crate) fn delete_key_material(
+    nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+    hash_ref: &str,
+) -> std::result::Result<(), String> {
+    let hash_ref = KeyPackageRef::from_slice(&hex::decode(hash_ref).map_err(|e| e.to_string())?);
+    nostr_mls
+        .provider
+        .storage()
+        .delete_key_package(&hash_ref)
+        .map_err(|e| e.to_string())
+}
+
+/// Whether someone can be invited right now
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyPackageReadiness {
+    /// The pubkey as it was given
+    pub pubkey: String,
+    /// `true` if they have a valid key package
+    pub ready: bool,
+    /// Hex encoded id of the newest valid key package
+    pub event_id: Option<String>,
+    /// The relays we found that key package on
+    pub relays: Vec<String>,
+    /// When that key package was published
+    pub created_at: Option<u64>,
+    /// How old that key package is, in seconds
+    pub age_secs: Option<u64>,
+    /// The key packages that can't be used, and why
+    pub rejected: Vec<RejectedKeyPackage>,
+    /// Why we couldn't check, `None` if we could
+    pub error: Option<String>,
+}
+
+impl KeyPackageReadiness {
+    fn not_ready(pubkey: String, rejected: Vec<RejectedKeyPackage>, error: Option<String>) -> Self {
+        Self {
+            pubkey,
+            ready: false,
+            event_id: None,
+            relays: Vec::new(),
+            created_at: None,
+            age_secs: None,
+            rejected,
+            error,
         }
-    } else {
-        Err(KeyPackageError::NostrMlsError(
-            nostr_mls::error::Error::KeyPackage("NostrMls instance is not initialized".to_string()),
-        ))
+    }
+}
+
+/// Checks whether each of several people has a valid key package. Everyone's key packages are
+/// fetched from their own key package relays, at most `MAX_CONCURRENT_READINESS_FETCHES` people
+/// at a time. A pubkey that's invalid or whose relays fail only marks its own entry as failed.
+pub async fn check_key_package_readiness(
+    pubkeys: &[String],
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<KeyPackageReadiness>> {
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_READINESS_FETCHES));
+    let handles: Vec<_> = pubkeys
+        .iter()
+        .map(|pubkey| {
+            let wn = wn.clone();
+            let pubkey = pubkey.clone();
+            let permits = permits.clone();
+            tokio::spawn(async move {
+                let public_key = identifiers::parse_pubkey(&pubkey).map_err(|e| e.to_string())?;
+                let _permit = permits.acquire().await.map_err(|e| e.to_string())?;
+                wn.nostr
+                    .fetch_user_key_packages(public_key)
+                    .await
+                    .map_err(|e| e.to_string())
+            })
+        })
+        .collect();
+
+    let mut fetched: Vec<(String, std::result::Result<Events, String>)> = Vec::new();
+    for (pubkey, handle) in pubkeys.iter().zip(handles) {
+        let events = handle.await.unwrap_or_else(|e| Err(e.to_string()));
+        fetched.push((pubkey.clone(), events));
+    }
+
+    let selections: Vec<(String, std::result::Result<KeyPackageSelection, String>)> = {
+        let nostr_mls_guard = match tokio::time::timeout(
+            std::time::Duration::from_secs(5),
+            wn.nostr_mls.lock(),
+        )
+        .await
+        {
+            Ok(guard) => guard,
+            Err(_) => {
+                tracing::error!(target: "whitenoise::key_packages::check_key_package_readiness", "Timeout waiting for nostr_mls lock");
+                return Err(KeyPackageError::NostrMlsError(
+                    nostr_mls::error::Error::KeyPackage(
+                        "Timeout waiting for nostr_mls lock".to_string(),
+                    ),
+                ));
+            }
+        };
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(KeyPackageError::NostrMlsNotInitialized)?;
+        fetched
+            .into_iter()
+            .map(|(pubkey, events)| {
+                (
+                    pubkey,
+                    events.map(|events| select_key_package(nostr_mls, events)),
+                )
+            })
+            .collect()
     };
-    tracing::debug!(target: "whitenoise::key_packages::fetch_key_package_for_pubkey", "nostr_mls lock released");
-    result
+
+    let now = Timestamp::now();
+    let mut readiness = Vec::with_capacity(selections.len());
+    for (pubkey, selection) in selections {
+        let selection = match selection {
+            Ok(selection) => selection,
+            Err(e) => {
+                readiness.push(KeyPackageReadiness::not_ready(pubkey, Vec::new(), Some(e)));
+                continue;
+            }
+        };
+
+        readiness.push(match selection.selected {
+            Some((event, _)) => KeyPackageReadiness {
+                ready: true,
+                event_id: Some(event.id.to_hex()),
+                relays: wn
+                    .nostr
+                    .query_event_seen_on_relays(&event.id)
+                    .await
+                    .unwrap_or_default(),
+                created_at: Some(event.created_at.as_u64()),
+                age_secs: Some(now.as_u64().saturating_sub(event.created_at.as_u64())),
+                rejected: selection.rejected,
+                error: None,
+                pubkey,
+            },
+            None => KeyPackageReadiness::not_ready(pubkey, selection.rejected, None),
+        });
+    }
+    Ok(readiness)
 }
 
-/// Publishes a new key package to relays
-pub async fn publish_key_package(wn: Arc<Whitenoise>) -> Result<()> {
+/// Publishes a new key package to relays, records it and deletes the ones it supersedes
+pub async fn publish_key_package(wn: Arc<Whitenoise>) -> Result<Event> {
     let active_account = Account::get_active(wn.clone()).await?;
 
     let key_package_relays: Vec<RelayUrl> = active_account
         .relays(RelayType::KeyPackage, wn.clone())
         .await?
         .into_iter()
-        .map(|r| RelayUrl::parse(&r).expect("Invalid relay URL"))
+        .filter_map(|r| RelayUrl::parse(&r).ok())
         .collect();
 
-    let mut encoded_key_package: Option<String> = None;
-    let mut tags: Option<[Tag; 5]> = None;
     tracing::debug!(target: "whitenoise::key_packages::publish_key_package", "Attempting to acquire nostr_mls lock");
     let nostr_mls_guard = match tokio::time::timeout(
         std::time::Duration::from_secs(5),
@@ -206,30 +516,66 @@ pub async fn publish_key_package(wn: Arc<Whitenoise>) -> Result<()> {
             ));
         }
     };
-    let _result = if let Some(nostr_mls) = nostr_mls_guard.as_ref() {
-        let (encoded_key_package_value, tags_value) = nostr_mls
+    let (encoded_key_package, tags) = match nostr_mls_guard.as_ref() {
+        Some(nostr_mls) => nostr_mls
             .create_key_package_for_event(&active_account.pubkey, key_package_relays.clone())
-            .map_err(KeyPackageError::NostrMlsError)?;
-        encoded_key_package = Some(encoded_key_package_value);
-        tags = Some(tags_value);
-        Ok(())
-    } else {
-        Err(KeyPackageError::NostrMlsNotInitialized)
+            .map_err(KeyPackageError::NostrMlsError)?,
+        None => return Err(KeyPackageError::NostrMlsNotInitialized),
     };
+    drop(nostr_mls_guard);
     tracing::debug!(target: "whitenoise::key_packages::publish_key_package", "nostr_mls lock released");
 
-    if encoded_key_package.is_some() && tags.is_some() {
-        let key_package_event_builder =
-            EventBuilder::new(Kind::MlsKeyPackage, encoded_key_package.unwrap())
-                .tags(tags.unwrap());
+    let key_package_event_builder =
+        EventBuilder::new(Kind::MlsKeyPackage, encoded_key_package).tags(tags);
+    let event = wn
+        .nostr
+        .client
+        .sign_event_builder(key_package_event_builder)
+        .await?;
+    let output = wn
+        .nostr
+        .client
+        .send_event_to(key_package_relays, &event)
+        .await?;
+    if output.success.is_empty() {
+        return Err(KeyPackageError::NotPublished(
+            output.failed.values().cloned().collect(),
+        ));
+    }
 
-        wn.nostr
-            .client
-            .send_event_builder_to(key_package_relays, key_package_event_builder)
-            .await?;
+    // Kept so that the key material can be removed once the key package is deleted
+    let hash_ref = match tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        wn.nostr_mls.lock(),
+    )
+    .await
+    {
+        Ok(nostr_mls_guard) => nostr_mls_guard
+            .as_ref()
+            .and_then(|nostr_mls| local_key_package_ref(nostr_mls, &event)),
+        Err(_) => {
+            tracing::warn!(target: "whitenoise::key_packages::publish_key_package", "Timeout waiting for nostr_mls lock, the key material of {} won't be removed", event.id);
+            None
+        }
+    };
+
+    let accepted: Vec<String> = output.success.iter().map(|url| url.to_string()).collect();
+    if let Err(e) = key_package_lifecycle::record_published(
+        &active_account.pubkey,
+        &event,
+        hash_ref.as_deref(),
+        &accepted,
+        &wn.database,
+    )
+    .await
+    {
+        tracing::warn!(target: "whitenoise::key_packages::publish_key_package", "Failed to record key package: {}", e);
+    }
+    if let Err(e) = key_package_lifecycle::delete_superseded(wn.clone()).await {
+        tracing::warn!(target: "whitenoise::key_packages::publish_key_package", "Failed to delete superseded key packages: {}", e);
     }
 
-    Ok(())
+    Ok(event)
 }
 
 /// Deletes a specific key package event from Nostr relays.
@@ -276,7 +622,7 @@ pub async fn delete_key_package_from_relays(
     let key_package_events = wn
         .nostr
         .client
-        .fetch_events(key_package_filter, wn.nostr.timeout().await.unwrap())
+        .fetch_events(key_package_filter, wn.nostr.timeout().await?)
         .await?;
 
     if let Some(event) = key_package_events.first() {
@@ -327,3 +673,308 @@ pub async fn delete_key_package_from_relays(
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test_utils {
+    //! Real key packages for tests, made with a throwaway MLS storage
+
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Timestamp};
+    use tempfile::TempDir;
+
+    pub(crate) fn test_nostr_mls() -> (NostrMls<NostrMlsSqliteStorage>, TempDir) {
+        let dir = TempDir::new().unwrap();
+        let storage = NostrMlsSqliteStorage::new(dir.path().join("mls")).unwrap();
+        (NostrMls::new(storage), dir)
+    }
+
+    fn relays() -> Vec<RelayUrl> {
+        vec![RelayUrl::parse("wss://relay.example.com").unwrap()]
+    }
+
+    pub(crate) fn sign_key_package(
+        keys: &Keys,
+        content: String,
+        tags: Vec<Tag>,
+        created_at: u64,
+    ) -> Event {
+        EventBuilder::new(Kind::MlsKeyPackage, content)
+            .tags(tags)
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    /// A key package event made the way we publish them
+    pub(crate) fn key_package_event(
+        nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+        keys: &Keys,
+        created_at: u64,
+    ) -> Event {
+        let (content, tags) = nostr_mls
+            .create_key_package_for_event(&keys.public_key(), relays())
+            .unwrap();
+        sign_key_package(keys, content, tags, created_at)
+    }
+
+    /// A lifetime from `not_before` to `not_after`, which `Lifetime::new` can't put in the past
+    pub(crate) fn lifetime(not_before: u64, not_after: u64) -> Lifetime {
+        let mut bytes = not_before.to_be_bytes().to_vec();
+        bytes.extend_from_slice(&not_after.to_be_bytes());
+        Lifetime::tls_deserialize_exact(bytes).unwrap()
+    }
+
+    /// A single use key package event with the given lifetime
+    pub(crate) fn single_use_key_package_event(
+        nostr_mls: &NostrMls<NostrMlsSqliteStorage>,
+        keys: &Keys,
+        lifetime: Lifetime,
+        created_at: u64,
+    ) -> Event {
+        let signer = SignatureKeyPair::new(nostr_mls.ciphersuite.signature_algorithm()).unwrap();
+        let credential_with_key = CredentialWithKey {
+            credential: BasicCredential::new(keys.public_key().to_bytes().to_vec()).into(),
+            signature_key: signer.public().into(),
+        };
+        let capabilities = Capabilities::new(
+            None,
+            Some(&[nostr_mls.ciphersuite]),
+            Some(&nostr_mls.extensions),
+            None,
+            None,
+        );
+        let bundle = KeyPackage::builder()
+            .leaf_node_capabilities(capabilities)
+            .key_package_lifetime(lifetime)
+            .build(
+                nostr_mls.ciphersuite,
+                &nostr_mls.provider,
+                &signer,
+                credential_with_key,
+            )
+            .unwrap();
+        let content = hex::encode(bundle.key_package().tls_serialize_detached().unwrap());
+
+        // Same tags as the key packages we publish
+        let (_, tags) = nostr_mls
+            .create_key_package_for_event(&keys.public_key(), relays())
+            .unwrap();
+        sign_key_package(keys, content, tags, created_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_utils::*;
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Timestamp};
+
+    fn event_at(keys: &Keys, created_at: u64) -> Event {
+        EventBuilder::text_note("key package")
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_select_newest_valid_key_package() {
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let old = key_package_event(&nostr_mls, &keys, 100);
+        let valid = key_package_event(&nostr_mls, &keys, 200);
+        let invalid = sign_key_package(&keys, "not a key package".to_string(), vec![], 300);
+
+        let selection = select_key_package(&nostr_mls, vec![old, invalid.clone(), valid.clone()]);
+
+        let (selected, _) = selection.selected.unwrap();
+        assert_eq!(selected.id, valid.id);
+        assert_eq!(selection.rejected.len(), 1);
+        assert_eq!(selection.rejected[0].event_id, invalid.id.to_hex());
+        assert!(matches!(
+            selection.rejected[0].reason,
+            KeyPackageRejection::InvalidKeyPackage { .. }
+        ));
+    }
+
+    #[test]
+    fn test_single_use_key_package_accepted() {
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let event = single_use_key_package_event(&nostr_mls, &keys, Lifetime::new(60 * 60), 100);
+
+        let key_package = check_key_package(&nostr_mls, &event).unwrap();
+
+        assert!(!key_package.last_resort());
+    }
+
+    #[test]
+    fn test_extension_superset_accepted() {
+        let (mut other, _other_dir) = test_nostr_mls();
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        other.extensions.push(ExtensionType::Unknown(0xff00));
+        let event = key_package_event(&other, &keys, 100);
+
+        assert!(check_key_package(&nostr_mls, &event).is_ok());
+    }
+
+    #[test]
+    fn test_extension_subset_rejected() {
+        let (mut other, _other_dir) = test_nostr_mls();
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let dropped = other.extensions.pop().unwrap();
+        let event = key_package_event(&other, &keys, 100);
+
+        assert_eq!(
+            check_key_package(&nostr_mls, &event).unwrap_err(),
+            KeyPackageRejection::MissingExtensions {
+                missing: vec![format!("{:?}", dropped)],
+            }
+        );
+    }
+
+    #[test]
+    fn test_wrong_ciphersuite_rejected() {
+        let (mut other, _other_dir) = test_nostr_mls();
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        other.ciphersuite = Ciphersuite::MLS_128_DHKEMX25519_CHACHA20POLY1305_SHA256_Ed25519;
+        let event = key_package_event(&other, &keys, 100);
+
+        assert_eq!(
+            check_key_package(&nostr_mls, &event).unwrap_err(),
+            KeyPackageRejection::WrongCiphersuite {
+                expected: format!("{:?}", nostr_mls.ciphersuite),
+                found: format!("{:?}", other.ciphersuite),
+            }
+        );
+    }
+
+    #[test]
+    fn test_bad_signature_rejected() {
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let mut event = key_package_event(&nostr_mls, &keys, 100);
+        event.pubkey = Keys::generate().public_key();
+
+        assert_eq!(
+            check_key_package(&nostr_mls, &event).unwrap_err(),
+            KeyPackageRejection::BadSignature
+        );
+    }
+
+    #[test]
+    fn test_undecodable_key_package_invalid() {
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let event = sign_key_package(&keys, "not a key package".to_string(), vec![], 100);
+
+        assert!(matches!(
+            check_key_package(&nostr_mls, &event),
+            Err(KeyPackageRejection::InvalidKeyPackage { .. })
+        ));
+    }
+
+    #[test]
+    fn test_expired_key_package_rejected() {
+        let (nostr_mls, _dir) = test_nostr_mls();
+        let keys = Keys::generate();
+        let now = Timestamp::now().as_u64();
+        let event = single_use_key_package_event(
+            &nostr_mls,
+            &keys,
+            lifetime(now - 2 * 60 * 60, now - 60 * 60),
+            100,
+        );
+
+        assert_eq!(
+            check_key_package(&nostr_mls, &event).unwrap_err(),
+            KeyPackageRejection::ExpiredLifetime
+        );
+    }
+
+    #[test]
+    fn test_sort_newest_first() {
+        let keys = Keys::generate();
+        let old = event_at(&keys, 100);
+        let new = event_at(&keys, 300);
+        let middle = event_at(&keys, 200);
+        let mut events = vec![old.clone(), new.clone(), middle.clone()];
+
+        sort_newest_first(&mut events);
+
+        assert_eq!(events, vec![new, middle, old]);
+    }
+
+    #[test]
+    fn test_sort_newest_first_breaks_ties_by_id() {
+        let keys = Keys::generate();
+        let a = EventBuilder::text_note("a")
+            .custom_created_at(Timestamp::from(100))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let b = EventBuilder::text_note("b")
+            .custom_created_at(Timestamp::from(100))
+            .sign_with_keys(&keys)
+            .unwrap();
+        let mut forward = vec![a.clone(), b.clone()];
+        let mut backward = vec![b, a];
+
+        sort_newest_first(&mut forward);
+        sort_newest_first(&mut backward);
+
+        assert_eq!(forward, backward);
+        assert!(forward[0].id < forward[1].id);
+    }
+
+    #[test]
+    fn test_rejection_serialization() {
+        let rejected = RejectedKeyPackage {
+            event_id: "abc".to_string(),
+            reason: KeyPackageRejection::MissingExtensions {
+                missing: vec!["RequiredCapabilities".to_string()],
+            },
+        };
+
+        let json = serde_json::to_value(&rejected).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "event_id": "abc",
+                "reason": "missing_extensions",
+                "missing": ["RequiredCapabilities"],
+            })
+        );
+        let parsed: RejectedKeyPackage = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, rejected);
+
+        let json = serde_json::to_value(KeyPackageRejection::ExpiredLifetime).unwrap();
+        assert_eq!(json, serde_json::json!({ "reason": "expired_lifetime" }));
+    }
+
+    #[test]
+    fn test_rejection_summary() {
+        let selection = KeyPackageSelection {
+            selected: None,
+            rejected: vec![
+                RejectedKeyPackage {
+                    event_id: "abc".to_string(),
+                    reason: KeyPackageRejection::BadSignature,
+                },
+                RejectedKeyPackage {
+                    event_id: "def".to_string(),
+                    reason: KeyPackageRejection::ExpiredLifetime,
+                },
+            ],
+        };
+        assert_eq!(
+            selection.rejection_summary(),
+            "abc: bad event signature; def: expired lifetime"
+        );
+        assert_eq!(
+            KeyPackageSelection::default().rejection_summary(),
+            "no key package events"
+        );
+    }
+}
@@ -1,15 +1,30 @@
 mod accounts;
 mod commands;
+mod contact_annotations;
+mod contact_list;
+mod contacts;
 mod database;
+mod direct_messages;
+mod disappearing_messages;
+mod identifiers;
+mod invites;
+mod key_package_lifecycle;
 mod key_packages;
 mod logging;
 mod media;
+mod message_search;
+mod mute_list;
+mod nip05;
 mod nostr_manager;
+mod outbox;
 mod payments;
+mod read_markers;
 mod relays;
+mod replaceable_list;
 pub mod runtime;
 mod secrets_store;
 mod types;
+mod typing_indicators;
 mod whitenoise;
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::nostr_manager::proxy::{self, ProxyState};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
@@ -64,6 +65,8 @@ pub struct BlobDescriptor {
 pub struct BlossomClient {
     /// Base URL of the Blossom server
     pub url: String,
+    /// Whether requests go through a proxy
+    proxy: ProxyState,
 }
 
 impl BlossomClient {
@@ -74,9 +77,21 @@ impl BlossomClient {
     pub fn new(url: &str) -> Self {
         BlossomClient {
             url: url.to_string(),
+            proxy: ProxyState::Direct,
         }
     }
 
+    /// Routes requests through a proxy
+    pub fn with_proxy(mut self, proxy: ProxyState) -> Self {
+        self.proxy = proxy;
+        self
+    }
+
+    /// Builds the HTTP client for a request
+    fn http_client(&self) -> Result<reqwest::Client, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(proxy::http_client(self.proxy)?)
+    }
+
     /// Creates a Nostr event for authorization
     ///
     /// # Arguments
@@ -124,7 +139,7 @@ impl BlossomClient {
         &self,
         file: Vec<u8>,
     ) -> Result<(BlobDescriptor, Keys), Box<dyn std::error::Error + Send + Sync>> {
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
         tracing::info!(
             target: "whitenoise::nostr_manager::blossom",
             "Uploading file to Blossom server: {}",
@@ -178,7 +193,7 @@ impl BlossomClient {
         sha256: &str,
         keys: &Keys,
     ) -> Result<BlobDescriptor, Box<dyn std::error::Error + Send + Sync>> {
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
         tracing::info!(
             target: "whitenoise::nostr_manager::blossom",
             "Deleting file from Blossom server: {}",
@@ -223,7 +238,7 @@ impl BlossomClient {
         content_type: &str,
         keys: &Keys,
     ) -> Result<BlobDescriptor, Box<dyn std::error::Error + Send + Sync>> {
-        let client = reqwest::Client::new();
+        let client = self.http_client()?;
         tracing::info!(
             target: "whitenoise::nostr_manager::blossom",
             "Uploading media to Blossom server: {}",
@@ -279,7 +294,10 @@ impl BlossomClient {
         content_length: u64,
         keys: &Keys,
     ) -> UploadRequirementsResult {
-        let client = reqwest::Client::new();
+        let client = self.http_client().map_err(|e| UploadError {
+            status: 0,
+            reason: e.to_string(),
+        })?;
         tracing::info!(
             target: "whitenoise::nostr_manager::blossom",
             "Checking upload requirements on Blossom server: {}",
@@ -345,7 +363,10 @@ impl BlossomClient {
         content_length: u64,
         keys: &Keys,
     ) -> UploadRequirementsResult {
-        let client = reqwest::Client::new();
+        let client = self.http_client().map_err(|e| UploadError {
+            status: 0,
+            reason: e.to_string(),
+        })?;
         tracing::info!(
             target: "whitenoise::nostr_manager::blossom",
             "Checking media upload requirements on Blossom server: {}",
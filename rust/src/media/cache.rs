@@ -140,20 +140,43 @@ pub async fn delete_cached_file(
     group: &group_types::Group,
     file_hash: &str,
     db: &Database,
+) -> Result<(), MediaError> {
+    delete_cached_file_for_group_id(&group.mls_group_id, file_hash, db).await
+}
+
+/// Deletes a cached file from both disk and database when only the MLS group ID is known.
+///
+/// # Arguments
+/// * `mls_group_id` - The MLS group ID
+/// * `file_hash` - The hash of the file
+/// * `db` - Database connection
+///
+/// # Returns
+/// * `Ok(())` - Success
+/// * `Err(MediaError)` - Error if deletion fails
+pub async fn delete_cached_file_for_group_id(
+    mls_group_id: &GroupId,
+    file_hash: &str,
+    db: &Database,
 ) -> Result<(), MediaError> {
     // First get the file path
-    let cached_media_file = fetch_cached_file(group, file_hash, db).await?;
+    let file_path = sqlx::query_scalar::<_, String>(
+        "SELECT file_path FROM media_files WHERE mls_group_id = ? AND file_hash = ?",
+    )
+    .bind(mls_group_id.as_slice())
+    .bind(file_hash)
+    .fetch_optional(&db.pool)
+    .await?;
 
-    if let Some(cached_media_file) = cached_media_file {
+    if let Some(file_path) = file_path {
         // Delete from disk
-        if Path::new(&cached_media_file.media_file.file_path).exists() {
-            fs::remove_file(&cached_media_file.media_file.file_path)
-                .map_err(|e| MediaError::Cache(e.to_string()))?;
+        if Path::new(&file_path).exists() {
+            fs::remove_file(&file_path).map_err(|e| MediaError::Cache(e.to_string()))?;
         }
 
         // Delete from database (cascade will handle this)
         sqlx::query("DELETE FROM media_files WHERE mls_group_id = ? AND file_hash = ?")
-            .bind(group.mls_group_id.as_slice())
+            .bind(mls_group_id.as_slice())
             .bind(file_hash)
             .execute(&db.pool)
             .await?;
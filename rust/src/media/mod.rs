@@ -7,6 +7,7 @@
 //! - Generation of IMETA tags for Nostr events
 //! - Image processing and metadata extraction
 //! - Media sanitization and security checks
+//! - Cached, sanitized thumbnails of profile pictures and banners
 //!
 //! The module is designed to work with the following workflow:
 //! 1. Files are sanitized to remove sensitive metadata
@@ -41,10 +42,14 @@ pub mod blossom;
 mod cache;
 mod encryption;
 mod errors;
+mod profile_images;
 mod sanitizer;
 mod types;
 
 pub use errors::MediaError;
+pub use profile_images::{
+    delete_profile_image_dir, profile_image_paths, prune_profile_images, ProfileImageKind,
+};
 pub use sanitizer::sanitize_media;
 pub use types::*;
 
@@ -104,7 +109,7 @@ pub async fn add_media_file(
     // Upload encrypted file to Blossom
     let (blob_descriptor, keys) = wn
         .nostr
-        .blossom
+        .blossom()
         .upload(encrypted_file_data)
         .await
         .map_err(|e| MediaError::Upload(e.to_string()))?;
@@ -189,6 +194,38 @@ pub async fn delete_media_file(
     Ok(())
 }
 
+/// Removes a media file from the local cache only, leaving the Blossom blob untouched.
+///
+/// Used when the message that referenced the file is purged locally (e.g. when it expires).
+///
+/// # Arguments
+///
+/// * `mls_group_id` - The MLS group ID that the media file belongs to
+/// * `file_hash` - The SHA256 hash of the original file
+/// * `db` - The database connection
+///
+/// # Returns
+///
+/// * `Ok(())` - Success, including when the file wasn't cached
+/// * `Err(MediaError)` - Error if deletion fails
+pub async fn purge_cached_media_file(
+    mls_group_id: &GroupId,
+    file_hash: &str,
+    db: &Database,
+) -> Result<(), MediaError> {
+    cache::delete_cached_file_for_group_id(mls_group_id, file_hash, db).await
+}
+
+/// Returns the SHA256 hashes of the original files referenced by the IMETA tags of a message.
+pub fn imeta_file_hashes(tags: &Tags) -> Vec<String> {
+    tags.iter()
+        .filter(|tag| tag.as_slice().first().map(|k| k.as_str()) == Some("imeta"))
+        .flat_map(|tag| tag.as_slice().iter().skip(1))
+        .filter_map(|value| value.strip_prefix("x "))
+        .map(|hash| hash.to_string())
+        .collect()
+}
+
 /// Generates an IMETA tag containing file metadata for Nostr events.
 ///
 /// Creates a tag containing:
@@ -315,6 +352,26 @@ mod tests {
         assert!(values.iter().any(|v| v.starts_with("blurhash ")));
     }
 
+    #[test]
+    fn test_imeta_file_hashes() {
+        let mut tags = Tags::new();
+        tags.push(Tag::custom(
+            TagKind::from("imeta"),
+            vec![
+                "url https://example.com/test.png".to_string(),
+                "m image/png".to_string(),
+                "x original_sha256".to_string(),
+            ],
+        ));
+        tags.push(Tag::reference("test_id"));
+
+        assert_eq!(
+            imeta_file_hashes(&tags),
+            vec!["original_sha256".to_string()]
+        );
+        assert!(imeta_file_hashes(&Tags::new()).is_empty());
+    }
+
     #[test]
     fn test_generate_imeta_tag_values_invalid_image() {
         let file = create_test_file("test.png", "image/png", b"not a real image");
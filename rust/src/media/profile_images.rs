@@ -0,0 +1,495 @@
+//! Cached profile pictures and banners.
+//!
+//! Profile images are linked from metadata events and hosted anywhere, so loading them straight
+//! from the UI would leak our IP to every image host and download full size images again and
+//! again. Instead they're downloaded once (through the proxy if there is one), size-limited,
+//! sanitized, shrunk to thumbnails and stored on disk, keyed by the hash of their URL. Images are
+//! downloaded again once they're older than [`CACHE_TTL_SECS`]; until then, and when downloading
+//! fails, the file we have is used.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+use image::{GenericImageView, ImageFormat, ImageOutputFormat};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::database::Database;
+use crate::media::errors::MediaError;
+use crate::media::sanitizer::sanitize_media;
+use crate::media::types::FileUpload;
+use crate::whitenoise::Whitenoise;
+
+const PROFILE_IMAGE_DIR: &str = "profile_images";
+
+/// Largest image we download, in bytes
+const MAX_DOWNLOAD_BYTES: usize = 5 * 1024 * 1024;
+
+/// How long a downloaded image is used before it's downloaded again
+const CACHE_TTL_SECS: i64 = 7 * 24 * 60 * 60;
+
+/// How long we wait before trying a failed download again
+const FAILURE_TTL_SECS: i64 = 60 * 60;
+
+/// Images not downloaded for this long are removed from the cache
+const MAX_CACHE_AGE_SECS: i64 = 30 * 24 * 60 * 60;
+
+/// How many images `profile_image_paths` downloads at the same time
+const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// Which profile image
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum ProfileImageKind {
+    /// `metadata.picture`
+    Avatar,
+    /// `metadata.banner`
+    Banner,
+}
+
+impl ProfileImageKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Avatar => "avatar",
+            Self::Banner => "banner",
+        }
+    }
+
+    /// The size thumbnails are shrunk to fit in
+    fn max_dimensions(self) -> (u32, u32) {
+        match self {
+            Self::Avatar => (256, 256),
+            Self::Banner => (1024, 512),
+        }
+    }
+
+    fn url(self, metadata: &Metadata) -> Option<&str> {
+        match self {
+            Self::Avatar => metadata.picture.as_deref(),
+            Self::Banner => metadata.banner.as_deref(),
+        }
+    }
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct ProfileImageRow {
+    file_path: Option<String>,
+    failed: bool,
+    fetched_at: i64,
+}
+
+fn url_hash(url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(url.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+fn now() -> i64 {
+    Timestamp::now().as_u64() as i64
+}
+
+/// Downloads an image, giving up as soon as it's bigger than [`MAX_DOWNLOAD_BYTES`]
+async fn download(url: &str, client: &reqwest::Client) -> Result<Vec<u8>, MediaError> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| MediaError::Download(e.to_string()))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(MediaError::Download(format!(
+            "Unsupported URL scheme: {}",
+            parsed.scheme()
+        )));
+    }
+
+    let mut response = client
+        .get(parsed)
+        .send()
+        .await
+        .map_err(|e| MediaError::Download(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(MediaError::Download(format!(
+            "Unexpected status: {}",
+            response.status()
+        )));
+    }
+    let too_large = || MediaError::Download("Image is too large".to_string());
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_DOWNLOAD_BYTES as u64)
+    {
+        return Err(too_large());
+    }
+
+    // The Content-Length header can lie or be missing
+    let mut data = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| MediaError::Download(e.to_string()))?
+    {
+        if data.len() + chunk.len() > MAX_DOWNLOAD_BYTES {
+            return Err(too_large());
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(data)
+}
+
+/// Sanitizes an image and shrinks it to a thumbnail. Returns the thumbnail and its extension.
+fn make_thumbnail(
+    data: &[u8],
+    kind: ProfileImageKind,
+) -> Result<(Vec<u8>, &'static str), MediaError> {
+    let mime_type = match image::guess_format(data) {
+        Ok(ImageFormat::Png) => "image/png",
+        Ok(ImageFormat::Jpeg) => "image/jpeg",
+        Ok(ImageFormat::Gif) => "image/gif",
+        Ok(ImageFormat::WebP) => "image/webp",
+        _ => return Err(MediaError::Sanitize("Not a supported image".to_string())),
+    };
+    let sanitized = sanitize_media(&FileUpload {
+        filename: kind.as_str().to_string(),
+        mime_type: mime_type.to_string(),
+        data: data.to_vec(),
+    })?;
+
+    let mut img = image::load_from_memory(&sanitized.data)
+        .map_err(|e| MediaError::Sanitize(e.to_string()))?;
+    let (max_width, max_height) = kind.max_dimensions();
+    let (width, height) = img.dimensions();
+    if width > max_width || height > max_height {
+        img = img.thumbnail(max_width, max_height);
+    }
+
+    let (format, extension) = if img.color().has_alpha() {
+        (ImageOutputFormat::Png, "png")
+    } else {
+        (ImageOutputFormat::Jpeg(85), "jpg")
+    };
+    let mut buffer = Cursor::new(Vec::new());
+    img.write_to(&mut buffer, format)
+        .map_err(|e| MediaError::Sanitize(e.to_string()))?;
+    Ok((buffer.into_inner(), extension))
+}
+
+/// Downloads an image and writes its thumbnail to the cache directory
+async fn fetch_thumbnail(
+    url: &str,
+    kind: ProfileImageKind,
+    hash: &str,
+    client: &reqwest::Client,
+    data_dir: &Path,
+) -> Result<PathBuf, MediaError> {
+    let data = download(url, client).await?;
+    let (thumbnail, extension) = tokio::task::spawn_blocking(move || make_thumbnail(&data, kind))
+        .await
+        .map_err(|e| MediaError::Sanitize(e.to_string()))??;
+
+    let dir = data_dir.join(PROFILE_IMAGE_DIR);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .map_err(|e| MediaError::Cache(e.to_string()))?;
+    let path = dir.join(format!("{}_{}.{}", hash, kind.as_str(), extension));
+    tokio::fs::write(&path, thumbnail)
+        .await
+        .map_err(|e| MediaError::Cache(e.to_string()))?;
+    Ok(path)
+}
+
+async fn cached_row(
+    hash: &str,
+    kind: ProfileImageKind,
+    db: &Database,
+) -> Result<Option<ProfileImageRow>, MediaError> {
+    Ok(sqlx::query_as::<_, ProfileImageRow>(
+        "SELECT file_path, failed, fetched_at FROM profile_images WHERE url_hash = ? AND kind = ?",
+    )
+    .bind(hash)
+    .bind(kind.as_str())
+    .fetch_optional(&db.pool)
+    .await?)
+}
+
+/// Returns the local path of a profile image thumbnail, downloading it if we don't have a fresh
+/// one. `None` if it couldn't be downloaded and we don't have an older copy.
+///
+/// # Arguments
+///
+/// * `url` - The URL from the metadata event
+/// * `kind` - Whether it's an avatar or a banner, which decides the thumbnail size
+/// * `wn` - The Whitenoise state
+///
+/// # Returns
+///
+/// * `Ok(Option<PathBuf>)` - The thumbnail, if we have one
+/// * `Err(MediaError)` - Error if the cache can't be read or written
+pub async fn profile_image_path(
+    url: &str,
+    kind: ProfileImageKind,
+    wn: Arc<Whitenoise>,
+) -> Result<Option<PathBuf>, MediaError> {
+    let hash = url_hash(url);
+    let cached = cached_row(&hash, kind, &wn.database).await?;
+    let cached_file = match cached.as_ref().and_then(|row| row.file_path.as_ref()) {
+        Some(path) if tokio::fs::try_exists(path).await.unwrap_or(false) => {
+            Some(PathBuf::from(path))
+        }
+        _ => None,
+    };
+
+    if let Some(row) = &cached {
+        let ttl = if row.failed {
+            FAILURE_TTL_SECS
+        } else {
+            CACHE_TTL_SECS
+        };
+        if now() - row.fetched_at < ttl && (row.failed || cached_file.is_some()) {
+            return Ok(cached_file);
+        }
+    }
+
+    let client = wn
+        .nostr
+        .http_client()
+        .map_err(|e| MediaError::Download(e.to_string()))?;
+    let (file_path, failed) = match fetch_thumbnail(url, kind, &hash, &client, &wn.data_dir).await {
+        Ok(path) => {
+            // The image may have changed format since the last download
+            if let Some(old) = cached_file.filter(|old| *old != path) {
+                let _ = tokio::fs::remove_file(old).await;
+            }
+            (Some(path), false)
+        }
+        Err(e) => {
+            tracing::debug!(
+                target: "whitenoise::media::profile_images::profile_image_path",
+                "Failed to cache {}: {}",
+                url,
+                e
+            );
+            // The copy we have is better than nothing
+            (cached_file, true)
+        }
+    };
+
+    sqlx::query(
+        "INSERT INTO profile_images (url_hash, kind, url, file_path, failed, fetched_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT (url_hash, kind) DO UPDATE SET
+            file_path = excluded.file_path,
+            failed = excluded.failed,
+            fetched_at = excluded.fetched_at",
+    )
+    .bind(&hash)
+    .bind(kind.as_str())
+    .bind(url)
+    .bind(
+        file_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().to_string()),
+    )
+    .bind(failed)
+    .bind(now())
+    .execute(&wn.database.pool)
+    .await?;
+
+    Ok(file_path)
+}
+
+/// Returns the local paths of the profile images of several people, keyed by hex encoded
+/// pubkey. Images are looked up in the metadata we have locally and downloaded in parallel, at
+/// most [`MAX_CONCURRENT_DOWNLOADS`] at a time; people without an image, or whose image couldn't
+/// be downloaded, map to `None`.
+///
+/// # Arguments
+///
+/// * `pubkeys` - The people whose images we want
+/// * `kind` - Avatars or banners
+/// * `wn` - The Whitenoise state
+///
+/// # Returns
+///
+/// * `HashMap<String, Option<String>>` - The thumbnail of each person, if we have one
+pub async fn profile_image_paths(
+    pubkeys: &[PublicKey],
+    kind: ProfileImageKind,
+    wn: Arc<Whitenoise>,
+) -> HashMap<String, Option<String>> {
+    let mut urls: HashMap<String, Option<String>> = HashMap::new();
+    for pubkey in pubkeys {
+        let metadata = wn.nostr.query_user_metadata(*pubkey).await.ok().flatten();
+        let url = metadata
+            .as_ref()
+            .and_then(|metadata| kind.url(metadata))
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        urls.insert(pubkey.to_hex(), url);
+    }
+
+    let unique_urls: HashSet<String> = urls.values().flatten().cloned().collect();
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DOWNLOADS));
+    let handles: Vec<_> = unique_urls
+        .into_iter()
+        .map(|url| {
+            let wn = wn.clone();
+            let permits = permits.clone();
+            tokio::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let path = profile_image_path(&url, kind, wn).await;
+                (url, path)
+            })
+        })
+        .collect();
+
+    let mut paths: HashMap<String, String> = HashMap::new();
+    for handle in handles {
+        match handle.await {
+            Ok((url, Ok(Some(path)))) => {
+                paths.insert(url, path.to_string_lossy().to_string());
+            }
+            Ok((_, Ok(None))) => {}
+            Ok((url, Err(e))) => tracing::warn!(
+                target: "whitenoise::media::profile_images::profile_image_paths",
+                "Failed to get profile image {}: {}",
+                url,
+                e
+            ),
+            Err(e) => tracing::error!(
+                target: "whitenoise::media::profile_images::profile_image_paths",
+                "Profile image task failed: {}",
+                e
+            ),
+        }
+    }
+
+    urls.into_iter()
+        .map(|(pubkey, url)| (pubkey, url.and_then(|url| paths.get(&url).cloned())))
+        .collect()
+}
+
+/// Removes images that haven't been downloaded for [`MAX_CACHE_AGE_SECS`], e.g. because
+/// nobody uses them as their profile image anymore.
+///
+/// # Returns
+///
+/// * `Ok(u64)` - How many images were removed
+/// * `Err(MediaError)` - Error if the cache can't be read or written
+pub async fn prune_profile_images(db: &Database) -> Result<u64, MediaError> {
+    let cutoff = now() - MAX_CACHE_AGE_SECS;
+    let expired = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT file_path FROM profile_images WHERE fetched_at < ?",
+    )
+    .bind(cutoff)
+    .fetch_all(&db.pool)
+    .await?;
+    for path in expired.into_iter().flatten() {
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                return Err(MediaError::Cache(e.to_string()));
+            }
+        }
+    }
+
+    let result = sqlx::query("DELETE FROM profile_images WHERE fetched_at < ?")
+        .bind(cutoff)
+        .execute(&db.pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+/// Removes every cached profile image from disk
+pub fn delete_profile_image_dir(data_dir: &Path) -> std::io::Result<()> {
+    let dir = data_dir.join(PROFILE_IMAGE_DIR);
+    if dir.exists() {
+        fs::remove_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Server;
+
+    fn create_test_image(width: u32, height: u32) -> Vec<u8> {
+        let img = image::RgbImage::new(width, height);
+        let mut buffer = Cursor::new(Vec::new());
+        img.write_to(&mut buffer, ImageOutputFormat::Png).unwrap();
+        buffer.into_inner()
+    }
+
+    #[test]
+    fn test_make_thumbnail() {
+        let (thumbnail, extension) =
+            make_thumbnail(&create_test_image(1000, 500), ProfileImageKind::Avatar).unwrap();
+        assert_eq!(extension, "jpg");
+        assert_eq!(
+            image::load_from_memory(&thumbnail).unwrap().dimensions(),
+            (256, 128)
+        );
+
+        // Small images aren't scaled up
+        let (thumbnail, _) =
+            make_thumbnail(&create_test_image(100, 50), ProfileImageKind::Banner).unwrap();
+        assert_eq!(
+            image::load_from_memory(&thumbnail).unwrap().dimensions(),
+            (100, 50)
+        );
+
+        assert!(make_thumbnail(b"<html>not an image</html>", ProfileImageKind::Avatar).is_err());
+    }
+
+    #[test]
+    fn test_url_hash() {
+        assert_eq!(url_hash("https://example.com/a.png").len(), 64);
+        assert_eq!(
+            url_hash("https://example.com/a.png"),
+            url_hash("https://example.com/a.png")
+        );
+        assert_ne!(
+            url_hash("https://example.com/a.png"),
+            url_hash("https://example.com/b.png")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download() {
+        let mut server = Server::new_async().await;
+        let image = create_test_image(10, 10);
+        let _small = server
+            .mock("GET", "/small.png")
+            .with_status(200)
+            .with_body(&image)
+            .create_async()
+            .await;
+        let _large = server
+            .mock("GET", "/large.png")
+            .with_status(200)
+            .with_body(vec![0u8; MAX_DOWNLOAD_BYTES + 1])
+            .create_async()
+            .await;
+        let _missing = server
+            .mock("GET", "/missing.png")
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let client = reqwest::Client::new();
+        let url = server.url();
+        assert_eq!(
+            download(&format!("{}/small.png", url), &client)
+                .await
+                .unwrap(),
+            image
+        );
+        assert!(download(&format!("{}/large.png", url), &client)
+            .await
+            .is_err());
+        assert!(download(&format!("{}/missing.png", url), &client)
+            .await
+            .is_err());
+        assert!(download("file:///etc/passwd", &client).await.is_err());
+    }
+}
@@ -0,0 +1,352 @@
+//! Local message search
+//!
+//! This module maintains a SQLite FTS5 index over the decrypted content of MLS group messages.
+//! Messages are indexed as they are created (`send_mls_message`) or received (`process_mls_message`)
+//! and removed again when they are deleted (NIP-09) or when we're no longer part of the group.
+//!
+//! Nothing in this index ever leaves the device.
+
+use nostr_mls::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::database::Database;
+
+/// The default number of results returned by a search
+pub const DEFAULT_SEARCH_LIMIT: u32 = 50;
+
+#[derive(Error, Debug)]
+pub enum MessageSearchError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+pub type Result<T> = std::result::Result<T, MessageSearchError>;
+
+/// A single search hit
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct MessageSearchResult {
+    /// Hex encoded event id of the message
+    pub message_id: String,
+    /// Hex encoded MLS group ID of the group the message belongs to
+    pub mls_group_id: String,
+    /// Hex encoded pubkey of the message author
+    pub author_pubkey: String,
+    /// Unix timestamp of the message
+    pub created_at: i64,
+    /// A fragment of the message with matched terms wrapped in `<mark>` tags
+    pub snippet: String,
+}
+
+/// Adds a message to the search index for the given account.
+///
+/// Deletion events (kind 5) aren't indexed, instead the messages they reference are removed
+/// from the index if the deletion comes from their author. Reactions and messages without any
+/// content are skipped.
+pub async fn index_message(
+    account_pubkey: &PublicKey,
+    message: &message_types::Message,
+    db: &Database,
+) -> Result<()> {
+    if message.kind == Kind::EventDeletion {
+        let deleted_ids: Vec<EventId> = message
+            .tags
+            .iter()
+            .filter(|tag| tag.kind() == TagKind::e())
+            .filter_map(|tag| tag.content().and_then(|c| EventId::from_hex(c).ok()))
+            .collect();
+        return delete_messages(account_pubkey, Some(&message.pubkey), &deleted_ids, db).await;
+    }
+
+    if message.kind == Kind::Reaction || message.content.trim().is_empty() {
+        return Ok(());
+    }
+
+    let mut txn = db.pool.begin().await?;
+
+    // Messages can be processed more than once (e.g. our own messages coming back from relays)
+    sqlx::query("DELETE FROM message_search WHERE message_id = ? AND account_pubkey = ?")
+        .bind(message.id.to_hex())
+        .bind(account_pubkey.to_hex())
+        .execute(&mut *txn)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO message_search (content, message_id, mls_group_id, account_pubkey, author_pubkey, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&message.content)
+    .bind(message.id.to_hex())
+    .bind(hex::encode(message.mls_group_id.as_slice()))
+    .bind(account_pubkey.to_hex())
+    .bind(message.pubkey.to_hex())
+    .bind(message.created_at.as_u64() as i64)
+    .execute(&mut *txn)
+    .await?;
+
+    txn.commit().await?;
+
+    tracing::debug!(
+        target: "whitenoise::message_search::index_message",
+        "Indexed message {}",
+        message.id.to_hex()
+    );
+    Ok(())
+}
+
+/// Removes messages from the search index
+pub async fn remove_messages(
+    account_pubkey: &PublicKey,
+    message_ids: &[EventId],
+    db: &Database,
+) -> Result<()> {
+    delete_messages(account_pubkey, None, message_ids, db).await
+}
+
+/// Removes messages from the search index, only those written by `author` if given
+async fn delete_messages(
+    account_pubkey: &PublicKey,
+    author: Option<&PublicKey>,
+    message_ids: &[EventId],
+    db: &Database,
+) -> Result<()> {
+    if message_ids.is_empty() {
+        return Ok(());
+    }
+
+    let mut removed = 0;
+    let mut txn = db.pool.begin().await?;
+    for message_id in message_ids {
+        let result = sqlx::query(
+            "DELETE FROM message_search
+             WHERE message_id = ? AND account_pubkey = ? AND (? IS NULL OR author_pubkey = ?)",
+        )
+        .bind(message_id.to_hex())
+        .bind(account_pubkey.to_hex())
+        .bind(author.map(|author| author.to_hex()))
+        .bind(author.map(|author| author.to_hex()))
+        .execute(&mut *txn)
+        .await?;
+        removed += result.rows_affected();
+    }
+    txn.commit().await?;
+
+    tracing::debug!(
+        target: "whitenoise::message_search::remove_messages",
+        "Removed {} messages from the search index",
+        removed
+    );
+    Ok(())
+}
+
+/// Removes every indexed message of a group, e.g. after leaving or being removed from it
+pub async fn purge_group(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    db: &Database,
+) -> Result<()> {
+    let result =
+        sqlx::query("DELETE FROM message_search WHERE mls_group_id = ? AND account_pubkey = ?")
+            .bind(hex::encode(mls_group_id.as_slice()))
+            .bind(account_pubkey.to_hex())
+            .execute(&db.pool)
+            .await?;
+
+    if result.rows_affected() > 0 {
+        tracing::debug!(
+            target: "whitenoise::message_search::purge_group",
+            "Purged {} messages from the search index",
+            result.rows_affected()
+        );
+    }
+    Ok(())
+}
+
+/// Searches the indexed messages of an account, optionally scoped to a single group.
+///
+/// Results are ordered by relevance. Returns an empty list if the query has no searchable terms.
+pub async fn search(
+    account_pubkey: &PublicKey,
+    query: &str,
+    mls_group_id: Option<&GroupId>,
+    limit: u32,
+    db: &Database,
+) -> Result<Vec<MessageSearchResult>> {
+    let Some(match_expression) = match_expression(query) else {
+        return Ok(vec![]);
+    };
+    let group_id = mls_group_id.map(|id| hex::encode(id.as_slice()));
+
+    let results = sqlx::query_as::<_, MessageSearchResult>(
+        "SELECT message_id, mls_group_id, author_pubkey, created_at,
+                snippet(message_search, 0, '<mark>', '</mark>', '…', 16) AS snippet
+         FROM message_search
+         WHERE message_search MATCH ?
+           AND account_pubkey = ?
+           AND (? IS NULL OR mls_group_id = ?)
+         ORDER BY rank
+         LIMIT ?",
+    )
+    .bind(match_expression)
+    .bind(account_pubkey.to_hex())
+    .bind(group_id.clone())
+    .bind(group_id)
+    .bind(limit as i64)
+    .fetch_all(&db.pool)
+    .await?;
+
+    Ok(results)
+}
+
+/// Turns free-form user input into an FTS5 match expression.
+///
+/// Every term is quoted so that FTS5 operators and punctuation in the input can't produce syntax
+/// errors, and the last term is treated as a prefix so results show up while the user is typing.
+fn match_expression(query: &str) -> Option<String> {
+    let terms: Vec<String> = query
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect();
+
+    if terms.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}*", terms.join(" ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    fn create_test_message(
+        content: &str,
+        kind: Kind,
+        tags: Tags,
+        group_id: &GroupId,
+        author: PublicKey,
+    ) -> message_types::Message {
+        let mut event = UnsignedEvent::new(author, Timestamp::now(), kind, tags.clone(), content);
+        event.ensure_id();
+        let id = event.id.unwrap();
+        message_types::Message {
+            id,
+            pubkey: author,
+            kind,
+            mls_group_id: group_id.clone(),
+            created_at: event.created_at,
+            content: content.to_string(),
+            tags,
+            event,
+            wrapper_event_id: EventId::from_hex(
+                "1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef",
+            )
+            .unwrap(),
+            state: message_types::MessageState::Created,
+        }
+    }
+
+    #[test]
+    fn test_match_expression() {
+        assert_eq!(match_expression("   "), None);
+        assert_eq!(match_expression("hello"), Some("\"hello\"*".to_string()));
+        assert_eq!(
+            match_expression("hello wor"),
+            Some("\"hello\" \"wor\"*".to_string())
+        );
+        assert_eq!(
+            match_expression("say \"hi\" OR"),
+            Some("\"say\" \"\"\"hi\"\"\" \"OR\"*".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_search_and_delete() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate().public_key();
+        let author = Keys::generate().public_key();
+        let group_a = GroupId::from_slice(&[1, 2, 3]);
+        let group_b = GroupId::from_slice(&[4, 5, 6]);
+
+        let message_a = create_test_message(
+            "Meet me at the café tomorrow",
+            Kind::Custom(9),
+            Tags::new(),
+            &group_a,
+            author,
+        );
+        let message_b = create_test_message(
+            "The cafe was closed",
+            Kind::Custom(9),
+            Tags::new(),
+            &group_b,
+            author,
+        );
+        index_message(&account, &message_a, &db).await.unwrap();
+        index_message(&account, &message_b, &db).await.unwrap();
+        // Indexing twice doesn't duplicate results
+        index_message(&account, &message_a, &db).await.unwrap();
+
+        let results = search(&account, "cafe", None, DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+
+        let results = search(&account, "caf", Some(&group_a), DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].message_id, message_a.id.to_hex());
+        assert!(results[0].snippet.contains("<mark>café</mark>"));
+
+        // Other accounts can't see our index
+        let other_account = Keys::generate().public_key();
+        let results = search(&other_account, "cafe", None, DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert!(results.is_empty());
+
+        // Only the author can delete a message
+        let mut deletion_tags = Tags::new();
+        deletion_tags.push(Tag::event(message_a.id));
+        let forged_deletion = create_test_message(
+            "Message deleted by someone else",
+            Kind::EventDeletion,
+            deletion_tags,
+            &group_a,
+            Keys::generate().public_key(),
+        );
+        index_message(&account, &forged_deletion, &db)
+            .await
+            .unwrap();
+        let results = search(&account, "cafe", None, DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 2);
+
+        // A deletion event removes the referenced message
+        let mut deletion_tags = Tags::new();
+        deletion_tags.push(Tag::event(message_a.id));
+        let deletion = create_test_message(
+            "Message deleted by user",
+            Kind::EventDeletion,
+            deletion_tags,
+            &group_a,
+            author,
+        );
+        index_message(&account, &deletion, &db).await.unwrap();
+        let results = search(&account, "cafe", None, DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].message_id, message_b.id.to_hex());
+
+        // Leaving a group purges everything from it
+        purge_group(&account, &group_b, &db).await.unwrap();
+        let results = search(&account, "cafe", None, DEFAULT_SEARCH_LIMIT, &db)
+            .await
+            .unwrap();
+        assert!(results.is_empty());
+    }
+}
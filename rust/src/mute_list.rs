@@ -0,0 +1,495 @@
+//! Muting people (NIP-51 mute lists)
+//!
+//! The mute list is a kind 10000 event. Public entries are `p` tags, private entries are the
+//! same tags NIP-44 encrypted to ourselves in the content. Like the contact list, edits are made
+//! on top of the latest list we can find (see `replaceable_list`) and everything we don't
+//! understand (muted words, hashtags, threads) is kept.
+//!
+//! The muted pubkeys of each account are kept in the database, so that welcomes from muted
+//! people can be declined, their group messages hidden and them left out of search results
+//! without decrypting the list every time.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::contacts;
+use crate::database::Database;
+use crate::nostr_manager::NostrManagerError;
+use crate::relays::RelayType;
+use crate::replaceable_list::{
+    self, add_p_tag, latest_event, remove_p_tag, Edited, ReplaceableList,
+};
+use crate::whitenoise::Whitenoise;
+
+#[derive(Error, Debug)]
+pub enum MuteListError {
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Client error: {0}")]
+    ClientError(#[from] nostr_sdk::client::Error),
+    #[error("Signer error: {0}")]
+    SignerError(#[from] SignerError),
+    #[error("Nostr database error: {0}")]
+    NostrDatabaseError(#[from] DatabaseError),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Can't mute yourself")]
+    CannotMuteSelf,
+    #[error("The private part of the mute list can't be read: {0}")]
+    UnreadablePrivateEntries(String),
+    #[error("The mute list keeps changing, try again later")]
+    Conflict,
+    #[error("Couldn't reach our relays to find the current mute list")]
+    ListUnavailable,
+    #[error("No relay accepted the mute list: {0:?}")]
+    NotPublished(Vec<String>),
+}
+
+pub type Result<T> = std::result::Result<T, MuteListError>;
+
+/// Someone on the mute list
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, sqlx::FromRow)]
+pub struct MutedPubkey {
+    /// Hex encoded pubkey
+    pub pubkey: String,
+    /// Whether they're in the encrypted part of the list, which only we can read
+    pub private: bool,
+}
+
+/// The public and private tags of a mute list
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct MuteListTags {
+    public: Vec<Tag>,
+    private: Vec<Tag>,
+}
+
+impl MuteListTags {
+    fn muted(&self) -> Vec<MutedPubkey> {
+        let mut muted: Vec<MutedPubkey> = Vec::new();
+        for (tags, private) in [(&self.public, false), (&self.private, true)] {
+            for pubkey in pubkeys(tags) {
+                if !muted.iter().any(|m| m.pubkey == pubkey) {
+                    muted.push(MutedPubkey { pubkey, private });
+                }
+            }
+        }
+        muted
+    }
+}
+
+/// Hex encoded pubkeys of the valid `p` tags
+fn pubkeys(tags: &[Tag]) -> Vec<String> {
+    tags.iter()
+        .filter(|tag| tag.kind() == TagKind::p())
+        .filter_map(|tag| tag.content().and_then(|c| PublicKey::from_hex(c).ok()))
+        .map(|pubkey| pubkey.to_hex())
+        .collect()
+}
+
+/// Parses the decrypted content of a mute list, a JSON array of tags
+fn parse_private_tags(json: &str) -> Option<Vec<Tag>> {
+    if json.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    let tags: Vec<Vec<String>> = serde_json::from_str(json).ok()?;
+    Some(
+        tags.into_iter()
+            .filter_map(|tag| Tag::parse(tag).ok())
+            .collect(),
+    )
+}
+
+fn private_tags_json(tags: &[Tag]) -> String {
+    let tags: Vec<Vec<String>> = tags.iter().map(|tag| tag.clone().to_vec()).collect();
+    serde_json::to_string(&tags).unwrap_or_default()
+}
+
+/// Reads a mute list, decrypting the private entries with our signer. Older clients encrypted
+/// them with NIP-04.
+async fn read_mute_list(event: &Event, wn: Arc<Whitenoise>) -> Result<MuteListTags> {
+    let public = event.tags.iter().cloned().collect();
+    if event.content.is_empty() {
+        return Ok(MuteListTags {
+            public,
+            private: Vec::new(),
+        });
+    }
+
+    let signer = wn.nostr.client.signer().await?;
+    let decrypted = if event.content.contains("?iv=") {
+        signer.nip04_decrypt(&event.pubkey, &event.content).await
+    } else {
+        signer.nip44_decrypt(&event.pubkey, &event.content).await
+    }
+    .map_err(|e| MuteListError::UnreadablePrivateEntries(e.to_string()))?;
+    let private = parse_private_tags(&decrypted).ok_or_else(|| {
+        MuteListError::UnreadablePrivateEntries("Invalid private entries".to_string())
+    })?;
+    Ok(MuteListTags { public, private })
+}
+
+/// Replaces the muted pubkeys stored for an account with those of a mute list, unless we
+/// already stored a list that isn't older
+async fn save_muted(
+    account_pubkey: &PublicKey,
+    muted: &[MutedPubkey],
+    list_created_at: Timestamp,
+    db: &Database,
+) -> Result<()> {
+    let mut txn = db.pool.begin().await?;
+    if !contacts::claim_list_version(
+        &account_pubkey.to_hex(),
+        Kind::MuteList,
+        list_created_at,
+        &mut txn,
+    )
+    .await?
+    {
+        return Ok(());
+    }
+    sqlx::query("DELETE FROM muted_pubkeys WHERE account_pubkey = ?")
+        .bind(account_pubkey.to_hex())
+        .execute(&mut *txn)
+        .await?;
+    for muted in muted {
+        sqlx::query("INSERT INTO muted_pubkeys (account_pubkey, pubkey, private) VALUES (?, ?, ?)")
+            .bind(account_pubkey.to_hex())
+            .bind(&muted.pubkey)
+            .bind(muted.private)
+            .execute(&mut *txn)
+            .await?;
+    }
+    txn.commit().await?;
+    Ok(())
+}
+
+/// Returns everyone an account muted
+pub async fn muted_pubkeys(account_pubkey: &PublicKey, db: &Database) -> Result<Vec<MutedPubkey>> {
+    let muted = sqlx::query_as::<_, MutedPubkey>(
+        "SELECT pubkey, private FROM muted_pubkeys WHERE account_pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?;
+    Ok(muted)
+}
+
+/// Returns everyone an account muted, for filtering
+pub async fn muted_pubkey_set(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> Result<HashSet<PublicKey>> {
+    Ok(muted_pubkeys(account_pubkey, db)
+        .await?
+        .into_iter()
+        .filter_map(|muted| PublicKey::from_hex(&muted.pubkey).ok())
+        .collect())
+}
+
+/// Whether an account muted someone
+pub async fn is_muted(
+    account_pubkey: &PublicKey,
+    pubkey: &PublicKey,
+    db: &Database,
+) -> Result<bool> {
+    let count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM muted_pubkeys WHERE account_pubkey = ? AND pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(pubkey.to_hex())
+    .fetch_one(&db.pool)
+    .await?;
+    Ok(count > 0)
+}
+
+/// Stores the entries of a mute list published by the active account. If the private entries
+/// can't be decrypted only the public ones are used. Lists older than the one we stored are
+/// ignored.
+pub async fn apply_mute_list(event: &Event, wn: Arc<Whitenoise>) -> Result<()> {
+    if event.kind != Kind::MuteList {
+        return Ok(());
+    }
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+    if event.pubkey != account_pubkey {
+        return Ok(());
+    }
+
+    let tags = match read_mute_list(event, wn.clone()).await {
+        Ok(tags) => tags,
+        Err(e) => {
+            tracing::warn!(
+                target: "whitenoise::mute_list::apply_mute_list",
+                "Using public mute list entries only: {}",
+                e
+            );
+            MuteListTags {
+                public: event.tags.iter().cloned().collect(),
+                private: Vec::new(),
+            }
+        }
+    };
+    save_muted(
+        &account_pubkey,
+        &tags.muted(),
+        event.created_at,
+        &wn.database,
+    )
+    .await
+}
+
+/// Applies the latest mute list of the active account that we have locally, e.g. after syncing
+pub async fn refresh_mute_list(wn: Arc<Whitenoise>) -> Result<()> {
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+    let events = wn
+        .nostr
+        .client
+        .database()
+        .query(
+            Filter::new()
+                .kind(Kind::MuteList)
+                .author(account_pubkey)
+                .limit(1),
+        )
+        .await?;
+    if let Some(event) = latest_event(events) {
+        apply_mute_list(&event, wn).await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Edit {
+    Mute { pubkey: PublicKey, private: bool },
+    Unmute(PublicKey),
+}
+
+/// Mutes someone, in the encrypted part of the list if `private` is set
+pub async fn mute(
+    pubkey: &PublicKey,
+    private: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Vec<MutedPubkey>> {
+    edit_mute_list(
+        Edit::Mute {
+            pubkey: *pubkey,
+            private,
+        },
+        wn,
+    )
+    .await
+}
+
+/// Unmutes someone, wherever they are on the list
+pub async fn unmute(pubkey: &PublicKey, wn: Arc<Whitenoise>) -> Result<Vec<MutedPubkey>> {
+    edit_mute_list(Edit::Unmute(*pubkey), wn).await
+}
+
+/// Applies an edit to the tags, `None` if there's nothing to change
+fn edit_tags(tags: &MuteListTags, edit: Edit) -> Option<MuteListTags> {
+    match edit {
+        Edit::Mute { pubkey, private } => {
+            if tags.muted().iter().any(|m| m.pubkey == pubkey.to_hex()) {
+                return None;
+            }
+            let mut edited = tags.clone();
+            if private {
+                edited.private = add_p_tag(&tags.private, &pubkey)?;
+            } else {
+                edited.public = add_p_tag(&tags.public, &pubkey)?;
+            }
+            Some(edited)
+        }
+        Edit::Unmute(pubkey) => {
+            let public = remove_p_tag(&tags.public, &pubkey);
+            let private = remove_p_tag(&tags.private, &pubkey);
+            if public.is_none() && private.is_none() {
+                return None;
+            }
+            Some(MuteListTags {
+                public: public.unwrap_or_else(|| tags.public.clone()),
+                private: private.unwrap_or_else(|| tags.private.clone()),
+            })
+        }
+    }
+}
+
+/// The kind 10000 mute list
+struct MuteList;
+
+#[async_trait::async_trait]
+impl ReplaceableList for MuteList {
+    const KIND: Kind = Kind::MuteList;
+
+    type Tags = MuteListTags;
+    type Error = MuteListError;
+
+    /// Unlike when applying a list, we can't go on without the private entries: publishing
+    /// would drop them
+    async fn read(event: &Event, wn: Arc<Whitenoise>) -> Result<MuteListTags> {
+        read_mute_list(event, wn).await
+    }
+
+    async fn build(
+        tags: &MuteListTags,
+        _base: Option<&Event>,
+        account: &Account,
+        wn: Arc<Whitenoise>,
+    ) -> Result<EventBuilder> {
+        let content = if tags.private.is_empty() {
+            String::new()
+        } else {
+            wn.nostr
+                .client
+                .signer()
+                .await?
+                .nip44_encrypt(&account.pubkey, &private_tags_json(&tags.private))
+                .await?
+        };
+        Ok(EventBuilder::new(Kind::MuteList, content).tags(tags.public.clone()))
+    }
+
+    fn conflict() -> MuteListError {
+        MuteListError::Conflict
+    }
+
+    fn unavailable() -> MuteListError {
+        MuteListError::ListUnavailable
+    }
+
+    fn not_published(reasons: Vec<String>) -> MuteListError {
+        MuteListError::NotPublished(reasons)
+    }
+}
+
+async fn edit_mute_list(edit: Edit, wn: Arc<Whitenoise>) -> Result<Vec<MutedPubkey>> {
+    let account = Account::get_active(wn.clone()).await?;
+    if let Edit::Mute { pubkey, .. } = edit {
+        if pubkey == account.pubkey {
+            return Err(MuteListError::CannotMuteSelf);
+        }
+    }
+    let write_relays = account.relays(RelayType::Nostr, wn.clone()).await?;
+
+    let (tags, event) = match replaceable_list::edit_list::<MuteList>(
+        &account,
+        &write_relays,
+        |tags| edit_tags(tags, edit),
+        wn.clone(),
+    )
+    .await?
+    {
+        Edited::Unchanged(tags, base) => (tags, base),
+        Edited::Published(tags, event) => (tags, Some(event)),
+    };
+
+    let muted = tags.muted();
+    if let Some(event) = event {
+        save_muted(&account.pubkey, &muted, event.created_at, &wn.database).await?;
+    }
+    Ok(muted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    #[test]
+    fn test_private_tags_round_trip() {
+        let pubkey = Keys::generate().public_key();
+        let tags = vec![
+            Tag::public_key(pubkey),
+            Tag::parse(["word", "spoilers"]).unwrap(),
+        ];
+        let json = private_tags_json(&tags);
+        assert_eq!(parse_private_tags(&json).unwrap(), tags);
+        assert_eq!(parse_private_tags("").unwrap(), Vec::new());
+        assert!(parse_private_tags("not json").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_save_muted_ignores_older_lists() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let account = Keys::generate().public_key();
+        let alice = MutedPubkey {
+            pubkey: Keys::generate().public_key().to_hex(),
+            private: false,
+        };
+        let bob = MutedPubkey {
+            pubkey: Keys::generate().public_key().to_hex(),
+            private: true,
+        };
+
+        save_muted(
+            &account,
+            &[alice.clone(), bob.clone()],
+            Timestamp::from(200),
+            &db,
+        )
+        .await
+        .unwrap();
+        save_muted(&account, &[alice.clone()], Timestamp::from(100), &db)
+            .await
+            .unwrap();
+        assert_eq!(muted_pubkeys(&account, &db).await.unwrap().len(), 2);
+
+        save_muted(&account, &[alice.clone()], Timestamp::from(300), &db)
+            .await
+            .unwrap();
+        assert_eq!(muted_pubkeys(&account, &db).await.unwrap(), vec![alice]);
+    }
+
+    #[test]
+    fn test_edit_tags() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let hashtag = Tag::hashtag("politics");
+        let tags = MuteListTags {
+            public: vec![Tag::public_key(alice), hashtag.clone()],
+            private: Vec::new(),
+        };
+
+        assert!(edit_tags(
+            &tags,
+            Edit::Mute {
+                pubkey: alice,
+                private: true
+            }
+        )
+        .is_none());
+
+        let muted = edit_tags(
+            &tags,
+            Edit::Mute {
+                pubkey: bob,
+                private: true,
+            },
+        )
+        .unwrap();
+        assert_eq!(muted.public, tags.public);
+        assert_eq!(
+            muted.muted(),
+            vec![
+                MutedPubkey {
+                    pubkey: alice.to_hex(),
+                    private: false
+                },
+                MutedPubkey {
+                    pubkey: bob.to_hex(),
+                    private: true
+                },
+            ]
+        );
+
+        let unmuted = edit_tags(&muted, Edit::Unmute(alice)).unwrap();
+        assert_eq!(unmuted.public, vec![hashtag]);
+        assert_eq!(unmuted.private, muted.private);
+        assert!(edit_tags(&unmuted, Edit::Unmute(alice)).is_none());
+    }
+}
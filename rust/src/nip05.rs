@@ -0,0 +1,580 @@
+//! NIP-05 verification
+//!
+//! A NIP-05 address (`name@domain`) is verified by fetching `https://domain/.well-known/nostr.json?name=name`
+//! and checking that it maps the name to the pubkey claiming it. The document can also list
+//! relays for the pubkey, which we use to find people that haven't published a relay list.
+//!
+//! Results are cached: successful verifications for a day, failures for an hour. Network errors
+//! aren't cached, the address just stays unchecked.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::contact_annotations;
+use crate::database::Database;
+use crate::nostr_manager::NostrManagerError;
+use crate::types::EnrichedContact;
+use crate::whitenoise::Whitenoise;
+
+/// How long a successful verification is trusted
+const VERIFIED_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long until a failed verification is retried
+const FAILED_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Documents are always fetched over HTTPS, tests run against a plain HTTP mock server
+#[cfg(not(test))]
+const DOCUMENT_SCHEME: &str = "https";
+#[cfg(test)]
+const DOCUMENT_SCHEME: &str = "http";
+
+#[derive(Error, Debug)]
+pub enum Nip05Error {
+    #[error("Invalid NIP-05 address: {0}")]
+    InvalidAddress(String),
+    #[error("HTTP error: {0}")]
+    HttpError(#[from] reqwest::Error),
+    #[error("{0} redirected, redirects aren't allowed for NIP-05")]
+    Redirected(String),
+    #[error("{0} doesn't exist")]
+    NotFound(String),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Nostr manager error: {0}")]
+    NostrManagerError(#[from] NostrManagerError),
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+}
+
+pub type Result<T> = std::result::Result<T, Nip05Error>;
+
+/// Whether a contact's NIP-05 address is legitimate
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Nip05Status {
+    /// No address, or we haven't been able to check it yet
+    #[default]
+    Unchecked,
+    Verified,
+    /// The domain doesn't vouch for the pubkey
+    Failed,
+}
+
+impl From<String> for Nip05Status {
+    fn from(s: String) -> Self {
+        match s.as_str() {
+            "verified" => Self::Verified,
+            "failed" => Self::Failed,
+            _ => Self::Unchecked,
+        }
+    }
+}
+
+impl From<Nip05Status> for String {
+    fn from(status: Nip05Status) -> Self {
+        match status {
+            Nip05Status::Unchecked => "unchecked".to_string(),
+            Nip05Status::Verified => "verified".to_string(),
+            Nip05Status::Failed => "failed".to_string(),
+        }
+    }
+}
+
+/// A parsed `name@domain` address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Nip05Address {
+    pub name: String,
+    pub domain: String,
+}
+
+impl Nip05Address {
+    /// Parses an address, a bare domain is the same as `_@domain`
+    pub fn parse(address: &str) -> Result<Self> {
+        let address = address.trim().to_lowercase();
+        let (name, domain) = match address.split_once('@') {
+            Some((name, domain)) => (name.to_string(), domain.to_string()),
+            None => ("_".to_string(), address.clone()),
+        };
+
+        let valid_name = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        let valid_domain = !domain.is_empty()
+            && !domain.contains(['/', '@', '?', '#'])
+            && !domain.chars().any(char::is_whitespace);
+        if !valid_name || !valid_domain {
+            return Err(Nip05Error::InvalidAddress(address));
+        }
+        Ok(Self { name, domain })
+    }
+
+    /// The address of the `nostr.json` document
+    pub fn document_url(&self) -> String {
+        format!(
+            "{}://{}/.well-known/nostr.json?name={}",
+            DOCUMENT_SCHEME, self.domain, self.name
+        )
+    }
+}
+
+impl fmt::Display for Nip05Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.name, self.domain)
+    }
+}
+
+/// A `nostr.json` document
+#[derive(Debug, Clone, Default, Deserialize)]
+struct Nip05Document {
+    #[serde(default)]
+    names: HashMap<String, String>,
+    #[serde(default)]
+    relays: HashMap<String, Vec<String>>,
+}
+
+impl Nip05Document {
+    /// The pubkey a name maps to, and the relays listed for it
+    fn resolve(&self, name: &str) -> Option<(PublicKey, Vec<String>)> {
+        let pubkey = self
+            .names
+            .iter()
+            .find(|(n, _)| n.to_lowercase() == name)
+            .and_then(|(_, pubkey)| PublicKey::from_hex(pubkey).ok())?;
+        let relays = self
+            .relays
+            .get(&pubkey.to_hex())
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|url| RelayUrl::parse(url).is_ok())
+            .collect();
+        Some((pubkey, relays))
+    }
+}
+
+/// The outcome of checking an address
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Nip05Verification {
+    pub nip05: String,
+    /// Hex encoded pubkey claiming the address
+    pub pubkey: String,
+    pub status: Nip05Status,
+    /// Relays the document lists for the pubkey
+    pub relays: Vec<String>,
+    pub checked_at: u64,
+}
+
+impl Nip05Verification {
+    fn is_fresh(&self, now: Timestamp) -> bool {
+        let ttl = match self.status {
+            Nip05Status::Verified => VERIFIED_TTL,
+            _ => FAILED_TTL,
+        };
+        self.checked_at + ttl.as_secs() > now.as_u64()
+    }
+}
+
+/// An HTTP client for fetching documents, which doesn't follow redirects
+fn document_client(builder: reqwest::ClientBuilder) -> Result<reqwest::Client> {
+    Ok(builder
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?)
+}
+
+/// Fetches the document for an address and returns what the name maps to
+async fn resolve_address(
+    address: &Nip05Address,
+    client: &reqwest::Client,
+    timeout: Duration,
+) -> Result<Option<(PublicKey, Vec<String>)>> {
+    let url = address.document_url();
+    let response = client
+        .get(&url)
+        .header("Accept", "application/json")
+        .timeout(timeout)
+        .send()
+        .await?;
+    // NIP-05 documents must be served directly from the domain
+    if response.status().is_redirection() {
+        return Err(Nip05Error::Redirected(address.to_string()));
+    }
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    let document = response.error_for_status()?.json::<Nip05Document>().await?;
+    Ok(document.resolve(&address.name))
+}
+
+/// Checks an address against a pubkey
+async fn check_address(
+    address: &Nip05Address,
+    pubkey: &PublicKey,
+    client: &reqwest::Client,
+    timeout: Duration,
+) -> Result<Nip05Verification> {
+    let resolved = resolve_address(address, client, timeout).await?;
+    let (status, relays) = match resolved {
+        Some((resolved, relays)) if resolved == *pubkey => (Nip05Status::Verified, relays),
+        _ => (Nip05Status::Failed, Vec::new()),
+    };
+    Ok(Nip05Verification {
+        nip05: address.to_string(),
+        pubkey: pubkey.to_hex(),
+        status,
+        relays,
+        checked_at: Timestamp::now().as_u64(),
+    })
+}
+
+/// Returns the last verification of an address for a pubkey, however old
+pub async fn cached_verification(
+    address: &Nip05Address,
+    pubkey: &PublicKey,
+    db: &Database,
+) -> Result<Option<Nip05Verification>> {
+    let row = sqlx::query_as::<_, (String, String, i64)>(
+        "SELECT status, relays, checked_at FROM nip05_verifications WHERE nip05 = ? AND pubkey = ?",
+    )
+    .bind(address.to_string())
+    .bind(pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(row.map(|(status, relays, checked_at)| Nip05Verification {
+        nip05: address.to_string(),
+        pubkey: pubkey.to_hex(),
+        status: status.into(),
+        relays: serde_json::from_str(&relays).unwrap_or_default(),
+        checked_at: checked_at as u64,
+    }))
+}
+
+async fn save_verification(verification: &Nip05Verification, db: &Database) -> Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO nip05_verifications (nip05, pubkey, status, relays, checked_at)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(&verification.nip05)
+    .bind(&verification.pubkey)
+    .bind(String::from(verification.status))
+    .bind(serde_json::to_string(&verification.relays).unwrap_or_default())
+    .bind(verification.checked_at as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// The status of a contact's address as far as we know, without going online. Stale results
+/// count as unchecked.
+pub async fn cached_status(pubkey: &PublicKey, metadata: &Metadata, db: &Database) -> Nip05Status {
+    let Some(address) = metadata
+        .nip05
+        .as_deref()
+        .and_then(|nip05| Nip05Address::parse(nip05).ok())
+    else {
+        return Nip05Status::Unchecked;
+    };
+    match cached_verification(&address, pubkey, db).await {
+        Ok(Some(verification)) if verification.is_fresh(Timestamp::now()) => verification.status,
+        _ => Nip05Status::Unchecked,
+    }
+}
+
+/// Verifies that an address belongs to a pubkey, from the cache unless the result is stale or
+/// `refresh` is set
+pub async fn verify(
+    pubkey: &PublicKey,
+    nip05: &str,
+    refresh: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<Nip05Verification> {
+    let address = Nip05Address::parse(nip05)?;
+    if !refresh {
+        if let Some(verification) = cached_verification(&address, pubkey, &wn.database).await? {
+            if verification.is_fresh(Timestamp::now()) {
+                return Ok(verification);
+            }
+        }
+    }
+
+    let verification = check_address(
+        &address,
+        pubkey,
+        &document_client(wn.nostr.http_client_builder()?)?,
+        wn.nostr.timeout().await?,
+    )
+    .await?;
+    save_verification(&verification, &wn.database).await?;
+
+    tracing::debug!(
+        target: "whitenoise::nip05::verify",
+        "{} is {:?} for {}",
+        address,
+        verification.status,
+        pubkey.to_hex()
+    );
+    Ok(verification)
+}
+
+/// Verifies the address in a contact's metadata, `Unchecked` if there is none or the domain
+/// couldn't be reached
+pub async fn verify_metadata(
+    pubkey: &PublicKey,
+    metadata: &Metadata,
+    wn: Arc<Whitenoise>,
+) -> Option<Nip05Verification> {
+    let nip05 = metadata.nip05.as_deref()?;
+    match verify(pubkey, nip05, false, wn).await {
+        Ok(verification) => Some(verification),
+        Err(e) => {
+            tracing::debug!(
+                target: "whitenoise::nip05::verify_metadata",
+                "Couldn't verify {}: {}",
+                nip05,
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Verifies the active account's own address
+pub async fn verify_own(refresh: bool, wn: Arc<Whitenoise>) -> Result<Option<Nip05Verification>> {
+    let account = Account::get_active(wn.clone()).await?;
+    match account.metadata.nip05.as_deref() {
+        Some(nip05) => Ok(Some(verify(&account.pubkey, nip05, refresh, wn).await?)),
+        None => Ok(None),
+    }
+}
+
+/// A contact found through their NIP-05 address
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Nip05Contact {
+    /// Hex encoded pubkey
+    pub pubkey: String,
+    pub contact: EnrichedContact,
+}
+
+/// Turns a `name@domain` address into a contact.
+///
+/// The relays listed in the NIP-05 document are used to find the contact's metadata and relay
+/// lists, and stand in for their relay list if they haven't published one.
+pub async fn lookup(nip05: &str, wn: Arc<Whitenoise>) -> Result<Nip05Contact> {
+    let address = Nip05Address::parse(nip05)?;
+    let (pubkey, relays) = resolve_address(
+        &address,
+        &document_client(wn.nostr.http_client_builder()?)?,
+        wn.nostr.timeout().await?,
+    )
+    .await?
+    .ok_or_else(|| Nip05Error::NotFound(address.to_string()))?;
+
+    let verification = Nip05Verification {
+        nip05: address.to_string(),
+        pubkey: pubkey.to_hex(),
+        status: Nip05Status::Verified,
+        relays: relays.clone(),
+        checked_at: Timestamp::now().as_u64(),
+    };
+    save_verification(&verification, &wn.database).await?;
+
+    let relay_urls: Vec<RelayUrl> = relays
+        .iter()
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .collect();
+    let metadata = wn
+        .nostr
+        .fetch_user_metadata_from(pubkey, &relay_urls)
+        .await?
+        .unwrap_or_default();
+    let mut nostr_relays = wn.nostr.fetch_user_relays(pubkey).await?;
+    if nostr_relays.is_empty() {
+        nostr_relays = relays;
+    }
+    let inbox_relays = wn.nostr.fetch_user_inbox_relays(pubkey).await?;
+    let key_package_relays = wn.nostr.fetch_user_key_package_relays(pubkey).await?;
+    let key_packages = wn.nostr.fetch_user_key_packages(pubkey).await?;
+
+    // The metadata may claim a different address than the one we looked up
+    let claimed = metadata
+        .nip05
+        .as_deref()
+        .and_then(|nip05| Nip05Address::parse(nip05).ok());
+    let nip05_status = if claimed.as_ref() == Some(&address) {
+        Nip05Status::Verified
+    } else {
+        cached_status(&pubkey, &metadata, &wn.database).await
+    };
+
+    Ok(Nip05Contact {
+        pubkey: pubkey.to_hex(),
+        contact: EnrichedContact {
+            metadata,
+            nip17: !inbox_relays.is_empty(),
+            nip104: !key_packages.is_empty(),
+            nip05_status,
+            nostr_relays,
+            inbox_relays,
+            key_package_relays,
+            annotation: contact_annotations::active_annotation(&pubkey, wn.clone()).await,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+    use mockito::{Matcher, Server};
+
+    #[test]
+    fn test_parse_address() {
+        let address = Nip05Address::parse("Bob@Example.com").unwrap();
+        assert_eq!(address.name, "bob");
+        assert_eq!(address.domain, "example.com");
+        assert_eq!(
+            address.document_url(),
+            format!(
+                "{}://example.com/.well-known/nostr.json?name=bob",
+                DOCUMENT_SCHEME
+            )
+        );
+
+        let root = Nip05Address::parse("example.com").unwrap();
+        assert_eq!(root.to_string(), "_@example.com");
+
+        assert!(Nip05Address::parse("bob smith@example.com").is_err());
+        assert!(Nip05Address::parse("bob@").is_err());
+        assert!(Nip05Address::parse("bob@example.com/path").is_err());
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let mut verification = Nip05Verification {
+            nip05: "bob@example.com".to_string(),
+            pubkey: Keys::generate().public_key().to_hex(),
+            status: Nip05Status::Verified,
+            relays: Vec::new(),
+            checked_at: 1_000_000,
+        };
+        let later = Timestamp::from(1_000_000 + 2 * 60 * 60);
+        assert!(verification.is_fresh(later));
+        verification.status = Nip05Status::Failed;
+        assert!(!verification.is_fresh(later));
+    }
+
+    #[tokio::test]
+    async fn test_check_address() {
+        let mut server = Server::new_async().await;
+        let domain = server.host_with_port();
+        let bob = Keys::generate().public_key();
+        let mallory = Keys::generate().public_key();
+
+        let _m = server
+            .mock("GET", "/.well-known/nostr.json")
+            .match_query(Matcher::UrlEncoded("name".into(), "bob".into()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                serde_json::json!({
+                    "names": { "bob": bob.to_hex() },
+                    "relays": { bob.to_hex(): ["wss://relay.example.com", "not a relay"] }
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let address = Nip05Address::parse(&format!("bob@{}", domain)).unwrap();
+        let client = document_client(reqwest::Client::builder()).unwrap();
+        let timeout = Duration::from_secs(5);
+
+        let verification = check_address(&address, &bob, &client, timeout)
+            .await
+            .unwrap();
+        assert_eq!(verification.status, Nip05Status::Verified);
+        assert_eq!(verification.relays, vec!["wss://relay.example.com"]);
+
+        let verification = check_address(&address, &mallory, &client, timeout)
+            .await
+            .unwrap();
+        assert_eq!(verification.status, Nip05Status::Failed);
+        assert!(verification.relays.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_name() {
+        let mut server = Server::new_async().await;
+        let _m = server
+            .mock("GET", "/.well-known/nostr.json")
+            .match_query(Matcher::UrlEncoded("name".into(), "alice".into()))
+            .with_status(404)
+            .create_async()
+            .await;
+
+        let address = Nip05Address::parse(&format!("alice@{}", server.host_with_port())).unwrap();
+        let client = document_client(reqwest::Client::builder()).unwrap();
+        let resolved = resolve_address(&address, &client, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert!(resolved.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_redirect_rejected() {
+        let mut server = Server::new_async().await;
+        let bob = Keys::generate().public_key();
+        let _redirect = server
+            .mock("GET", "/.well-known/nostr.json")
+            .match_query(Matcher::UrlEncoded("name".into(), "bob".into()))
+            .with_status(301)
+            .with_header("location", "/elsewhere.json")
+            .create_async()
+            .await;
+        let _target = server
+            .mock("GET", "/elsewhere.json")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(serde_json::json!({ "names": { "bob": bob.to_hex() } }).to_string())
+            .expect(0)
+            .create_async()
+            .await;
+
+        let address = Nip05Address::parse(&format!("bob@{}", server.host_with_port())).unwrap();
+        let client = document_client(reqwest::Client::builder()).unwrap();
+        let result = resolve_address(&address, &client, Duration::from_secs(5)).await;
+        assert!(matches!(result, Err(Nip05Error::Redirected(_))));
+    }
+
+    #[tokio::test]
+    async fn test_cached_status_ignores_stale_results() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let bob = Keys::generate().public_key();
+        let metadata = Metadata::new().nip05("bob@example.com");
+        let mut verification = Nip05Verification {
+            nip05: "bob@example.com".to_string(),
+            pubkey: bob.to_hex(),
+            status: Nip05Status::Verified,
+            relays: Vec::new(),
+            checked_at: Timestamp::now().as_u64(),
+        };
+        save_verification(&verification, &db).await.unwrap();
+        assert_eq!(
+            cached_status(&bob, &metadata, &db).await,
+            Nip05Status::Verified
+        );
+
+        verification.checked_at -= VERIFIED_TTL.as_secs() + 1;
+        save_verification(&verification, &db).await.unwrap();
+        assert_eq!(
+            cached_status(&bob, &metadata, &db).await,
+            Nip05Status::Unchecked
+        );
+    }
+}
@@ -0,0 +1,360 @@
+//! NIP-42 authentication for NostrManager
+//! Automatic authentication is turned off in the client so that we decide which relays get to
+//! learn who we are. When a relay sends an AUTH challenge we answer it with the active signer,
+//! unless the account opted out of authenticating to that relay. Subscriptions and events the
+//! relay rejected with `auth-required` are retried once the relay accepted our AUTH event, once
+//! per connection.
+
+use crate::database::Database;
+use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::runtime::wn;
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+/// Prefix relays use for messages rejected because the client isn't authenticated
+const AUTH_REQUIRED_PREFIX: &str = "auth-required:";
+
+/// Something a relay rejected with `auth-required`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum AuthRetry {
+    Subscription(SubscriptionId),
+    Event(EventId),
+}
+
+#[derive(Debug, Default)]
+struct RelayAuthState {
+    /// Our AUTH event the relay hasn't answered yet
+    pending: Option<EventId>,
+    /// Whether the relay accepted our AUTH on this connection
+    authenticated: bool,
+    /// What we already retried on this connection
+    retried: HashSet<AuthRetry>,
+    /// What to retry once the relay accepts our AUTH
+    waiting: Vec<AuthRetry>,
+}
+
+/// Keeps track of what to retry after authenticating to each relay, so that everything is
+/// retried once per connection and we don't loop forever
+#[derive(Debug, Default)]
+pub struct AuthRetries {
+    relays: Mutex<HashMap<RelayUrl, RelayAuthState>>,
+}
+
+impl AuthRetries {
+    /// Relays send a new challenge on every connection, so what happened on the previous one
+    /// doesn't count anymore. What's still waiting is retried after this AUTH.
+    fn challenged(&self, relay_url: &RelayUrl, auth_event_id: Option<EventId>) {
+        let mut relays = self.relays.lock().expect("Auth retries lock poisoned");
+        let state = relays.entry(relay_url.clone()).or_default();
+        *state = RelayAuthState {
+            pending: auth_event_id,
+            waiting: std::mem::take(&mut state.waiting),
+            ..Default::default()
+        };
+    }
+
+    /// Queues something the relay rejected with `auth-required`, unless it was already retried on
+    /// this connection or the relay rejected it after accepting our AUTH
+    fn auth_required(&self, relay_url: &RelayUrl, retry: AuthRetry) {
+        let mut relays = self.relays.lock().expect("Auth retries lock poisoned");
+        let state = relays.entry(relay_url.clone()).or_default();
+        if state.authenticated || state.retried.contains(&retry) || state.waiting.contains(&retry) {
+            return;
+        }
+        state.waiting.push(retry);
+    }
+
+    /// Handles the relay's answer to one of our events. Returns what to retry now if it was our
+    /// AUTH event and the relay accepted it.
+    fn answered(&self, relay_url: &RelayUrl, event_id: &EventId, accepted: bool) -> Vec<AuthRetry> {
+        let mut relays = self.relays.lock().expect("Auth retries lock poisoned");
+        let Some(state) = relays.get_mut(relay_url) else {
+            return Vec::new();
+        };
+        if state.pending.as_ref() != Some(event_id) {
+            return Vec::new();
+        }
+        state.pending = None;
+        if !accepted {
+            return Vec::new();
+        }
+
+        state.authenticated = true;
+        let waiting = std::mem::take(&mut state.waiting);
+        state.retried = waiting.iter().cloned().collect();
+        waiting
+    }
+
+    pub fn clear(&self) {
+        self.relays
+            .lock()
+            .expect("Auth retries lock poisoned")
+            .clear();
+    }
+}
+
+/// An account's choice about authenticating to a relay
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct RelayAuthSetting {
+    pub relay_url: String,
+    pub allow_auth: bool,
+    pub updated_at: i64,
+}
+
+/// Whether a relay rejected something because we aren't authenticated
+pub fn is_auth_required(message: &str) -> bool {
+    message.starts_with(AUTH_REQUIRED_PREFIX)
+}
+
+/// Whether an account is willing to authenticate to a relay. Defaults to `true`.
+pub async fn relay_auth_allowed(
+    account_pubkey: &PublicKey,
+    relay_url: &RelayUrl,
+    db: &Database,
+) -> std::result::Result<bool, sqlx::Error> {
+    let allow_auth = sqlx::query_scalar::<_, bool>(
+        "SELECT allow_auth FROM relay_auth_settings WHERE account_pubkey = ? AND relay_url = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(relay_url.to_string())
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(allow_auth.unwrap_or(true))
+}
+
+/// Saves whether an account is willing to authenticate to a relay
+pub async fn set_relay_auth_allowed(
+    account_pubkey: &PublicKey,
+    relay_url: &RelayUrl,
+    allow_auth: bool,
+    db: &Database,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO relay_auth_settings (account_pubkey, relay_url, allow_auth, updated_at)
+         VALUES (?, ?, ?, ?)",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(relay_url.to_string())
+    .bind(allow_auth)
+    .bind(Timestamp::now().as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Lists the relays an account made an explicit authentication choice for
+pub async fn relay_auth_settings(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> std::result::Result<Vec<RelayAuthSetting>, sqlx::Error> {
+    sqlx::query_as::<_, RelayAuthSetting>(
+        "SELECT relay_url, allow_auth, updated_at FROM relay_auth_settings
+         WHERE account_pubkey = ?
+         ORDER BY relay_url",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await
+}
+
+impl NostrManager {
+    /// Answers an AUTH challenge, if the active account allows authenticating to the relay.
+    pub(crate) async fn handle_auth_challenge(
+        &self,
+        relay_url: &RelayUrl,
+        challenge: &str,
+    ) -> Result<()> {
+        let signer = self.client.signer().await?;
+        let account_pubkey = signer.get_public_key().await?;
+
+        let allowed = relay_auth_allowed(&account_pubkey, relay_url, &wn().database)
+            .await
+            .map_err(|e| NostrManagerError::Auth(e.to_string()))?;
+        if !allowed {
+            tracing::debug!(
+                target: "whitenoise::nostr_manager::auth",
+                "Not authenticating to {}, disabled in settings",
+                relay_url
+            );
+            self.auth_retries.challenged(relay_url, None);
+            return Ok(());
+        }
+
+        let auth_event = self
+            .client
+            .sign_event_builder(EventBuilder::auth(challenge, relay_url.clone()))
+            .await?;
+        self.auth_retries.challenged(relay_url, Some(auth_event.id));
+        let relay = self.client.relay(relay_url).await?;
+        relay
+            .send_msg(ClientMessage::auth(auth_event))
+            .map_err(|e| NostrManagerError::Auth(e.to_string()))?;
+
+        tracing::debug!(
+            target: "whitenoise::nostr_manager::auth",
+            "Sent AUTH to {}",
+            relay_url
+        );
+        Ok(())
+    }
+
+    /// Remembers something the relay rejected with `auth-required`, to retry it once the relay
+    /// accepts our AUTH
+    pub(crate) fn queue_auth_retry(&self, relay_url: &RelayUrl, retry: AuthRetry) {
+        self.auth_retries.auth_required(relay_url, retry);
+    }
+
+    /// Handles the relay's answer to one of our events, retrying what it rejected with
+    /// `auth-required` if the event was our AUTH and the relay accepted it
+    pub(crate) async fn handle_event_answer(
+        &self,
+        relay_url: &RelayUrl,
+        event_id: &EventId,
+        accepted: bool,
+    ) -> Result<()> {
+        let retries = self.auth_retries.answered(relay_url, event_id, accepted);
+        if retries.is_empty() {
+            return Ok(());
+        }
+        tracing::debug!(
+            target: "whitenoise::nostr_manager::auth",
+            "Authenticated to {}",
+            relay_url
+        );
+        for retry in retries {
+            match retry {
+                AuthRetry::Subscription(subscription_id) => {
+                    self.retry_subscription(relay_url, &subscription_id).await?
+                }
+                AuthRetry::Event(event_id) => self.retry_event(relay_url, &event_id).await?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resubscribes to a subscription the relay closed with `auth-required`
+    async fn retry_subscription(
+        &self,
+        relay_url: &RelayUrl,
+        subscription_id: &SubscriptionId,
+    ) -> Result<()> {
+        let relay = self.client.relay(relay_url).await?;
+        let Some(filter) = relay.subscription(subscription_id).await else {
+            return Ok(());
+        };
+        relay
+            .subscribe_with_id(subscription_id.clone(), filter, SubscribeOptions::default())
+            .await
+            .map_err(|e| NostrManagerError::Auth(e.to_string()))?;
+
+        tracing::debug!(
+            target: "whitenoise::nostr_manager::auth",
+            "Retried subscription {} on {} after auth",
+            subscription_id,
+            relay_url
+        );
+        Ok(())
+    }
+
+    /// Republishes an event the relay rejected with `auth-required`
+    async fn retry_event(&self, relay_url: &RelayUrl, event_id: &EventId) -> Result<()> {
+        let Some(event) = self.client.database().event_by_id(event_id).await? else {
+            tracing::debug!(
+                target: "whitenoise::nostr_manager::auth",
+                "Can't retry event {}, it's not in the database",
+                event_id
+            );
+            return Ok(());
+        };
+        let relay = self.client.relay(relay_url).await?;
+        relay
+            .send_event(&event)
+            .await
+            .map_err(|e| NostrManagerError::Auth(e.to_string()))?;
+
+        tracing::debug!(
+            target: "whitenoise::nostr_manager::auth",
+            "Retried event {} on {} after auth",
+            event_id,
+            relay_url
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_auth_required() {
+        assert!(is_auth_required("auth-required: we only serve members"));
+        assert!(!is_auth_required("blocked: spam"));
+        assert!(!is_auth_required(""));
+    }
+
+    #[test]
+    fn test_auth_retries_after_auth_accepted() {
+        let retries = AuthRetries::default();
+        let relay_url = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let giftwraps = AuthRetry::Subscription(SubscriptionId::new("giftwraps"));
+        let auth_event_id = EventId::all_zeros();
+
+        // Nothing is retried before the relay accepts our AUTH
+        retries.auth_required(&relay_url, giftwraps.clone());
+        retries.challenged(&relay_url, Some(auth_event_id));
+        assert!(retries
+            .answered(&relay_url, &EventId::from_byte_array([1; 32]), true)
+            .is_empty());
+        assert_eq!(
+            retries.answered(&relay_url, &auth_event_id, true),
+            vec![giftwraps.clone()]
+        );
+
+        // Closed again after authenticating, retrying won't help
+        retries.auth_required(&relay_url, giftwraps.clone());
+        assert!(retries
+            .answered(&relay_url, &auth_event_id, true)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_auth_retries_on_every_connection() {
+        let retries = AuthRetries::default();
+        let relay_url = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let giftwraps = AuthRetry::Subscription(SubscriptionId::new("giftwraps"));
+
+        for auth_event_id in [
+            EventId::from_byte_array([1; 32]),
+            EventId::from_byte_array([2; 32]),
+        ] {
+            retries.challenged(&relay_url, Some(auth_event_id));
+            retries.auth_required(&relay_url, giftwraps.clone());
+            assert_eq!(
+                retries.answered(&relay_url, &auth_event_id, true),
+                vec![giftwraps.clone()]
+            );
+        }
+    }
+
+    #[test]
+    fn test_auth_rejected() {
+        let retries = AuthRetries::default();
+        let relay_url = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let event = AuthRetry::Event(EventId::from_byte_array([3; 32]));
+        let auth_event_id = EventId::from_byte_array([1; 32]);
+
+        retries.challenged(&relay_url, Some(auth_event_id));
+        retries.auth_required(&relay_url, event);
+        assert!(retries
+            .answered(&relay_url, &auth_event_id, false)
+            .is_empty());
+
+        retries.clear();
+        assert!(retries
+            .answered(&relay_url, &auth_event_id, true)
+            .is_empty());
+    }
+}
@@ -10,12 +10,19 @@ use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::{self, Receiver, Sender};
 
 use crate::accounts::{Account, AccountError};
+use crate::direct_messages;
+use crate::disappearing_messages;
+use crate::key_package_lifecycle;
 use crate::key_packages;
+use crate::message_search;
+use crate::mute_list;
 use crate::nostr_manager::NostrManagerError;
+use crate::read_markers::{self, READ_RECEIPT_KIND};
 use crate::relays::RelayType;
 use crate::secrets_store;
 use crate::whitenoise::Whitenoise;
 
+use std::collections::BTreeSet;
 use std::sync::Arc;
 
 #[derive(Error, Debug)]
@@ -42,6 +49,18 @@ pub enum EventProcessorError {
     NostrMlsError(#[from] nostr_mls::Error),
     #[error("Nostr MLS not initialized")]
     NostrMlsNotInitialized,
+    #[error("Timeout waiting for nostr_mls lock")]
+    NostrMlsLockTimeout,
+    #[error("Message search error: {0}")]
+    MessageSearchError(#[from] message_search::MessageSearchError),
+    #[error("Disappearing messages error: {0}")]
+    DisappearingMessagesError(#[from] disappearing_messages::DisappearingMessagesError),
+    #[error("Direct messages error: {0}")]
+    DirectMessagesError(#[from] direct_messages::DirectMessagesError),
+    #[error("Read markers error: {0}")]
+    ReadMarkersError(#[from] read_markers::ReadMarkersError),
+    #[error("Mute list error: {0}")]
+    MuteListError(#[from] mute_list::MuteListError),
 }
 
 pub type Result<T> = std::result::Result<T, EventProcessorError>;
@@ -206,11 +225,8 @@ impl EventProcessor {
                     Self::process_welcome(wn, active_account, event, unwrapped.rumor).await?;
                 }
                 Kind::PrivateDirectMessage => {
-                    tracing::debug!(
-                        target: "whitenoise::nostr_manager::event_processor",
-                        "Received private direct message: {:?}",
-                        unwrapped.rumor
-                    );
+                    Self::process_private_direct_message(wn, active_account, event, unwrapped)
+                        .await?;
                 }
                 _ => {
                     tracing::debug!(
@@ -231,6 +247,8 @@ impl EventProcessor {
         rumor_event: UnsignedEvent,
     ) -> Result<welcome_types::Welcome> {
         let welcome: welcome_types::Welcome;
+        let welcomer_muted =
+            mute_list::is_muted(&account.pubkey, &rumor_event.pubkey, &wn.database).await?;
         tracing::debug!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "Attempting to acquire nostr_mls lock");
         {
             let nostr_mls_guard = match tokio::time::timeout(
@@ -245,11 +263,7 @@ impl EventProcessor {
                 }
                 Err(_) => {
                     tracing::error!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "Timeout waiting for nostr_mls lock");
-                    return Err(EventProcessorError::NostrMlsError(
-                        nostr_mls::Error::KeyPackage(
-                            "Timeout waiting for nostr_mls lock".to_string(),
-                        ),
-                    ));
+                    return Err(EventProcessorError::NostrMlsLockTimeout);
                 }
             };
             let result = if let Some(nostr_mls) = nostr_mls_guard.as_ref() {
@@ -268,6 +282,16 @@ impl EventProcessor {
                 Err(EventProcessorError::NostrMlsNotInitialized)
             };
             welcome = result?;
+
+            // Welcomes from muted people are declined right away, they never show up. We don't
+            // join, so the key package they used stays where it is.
+            if welcomer_muted {
+                if let Some(nostr_mls) = nostr_mls_guard.as_ref() {
+                    nostr_mls.decline_welcome(&welcome)?;
+                }
+                tracing::debug!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "Declined welcome from muted pubkey {}", rumor_event.pubkey.to_hex());
+                return Ok(welcome);
+            }
         }
         tracing::debug!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "nostr_mls lock released");
 
@@ -280,13 +304,23 @@ impl EventProcessor {
             .and_then(|tag| tag.content());
 
         if let Some(key_package_event_id) = key_package_event_id {
+            let key_package_event_id = EventId::parse(key_package_event_id).unwrap();
             key_packages::delete_key_package_from_relays(
-                &EventId::parse(key_package_event_id).unwrap(),
+                &key_package_event_id,
                 &account.relays(RelayType::KeyPackage, wn.clone()).await?,
                 false, // For now we don't want to delete the key packages from MLS storage
                 wn.clone(),
             )
             .await?;
+            if let Err(e) = key_package_lifecycle::mark_deleted(
+                &account.pubkey,
+                &[key_package_event_id],
+                &wn.database,
+            )
+            .await
+            {
+                tracing::warn!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "Failed to mark used key package as deleted: {}", e);
+            }
             tracing::debug!(target: "whitenoise::nostr_manager::event_processor::process_welcome", "Deleted used key package from relays");
 
             key_packages::publish_key_package(wn.clone()).await?;
@@ -296,16 +330,32 @@ impl EventProcessor {
         Ok(welcome)
     }
 
-    // TODO: Implement private direct message processing, maybe...
-    #[allow(dead_code)]
     async fn process_private_direct_message(
-        _outer_event: Event,
-        inner_event: UnsignedEvent,
+        wn: Arc<Whitenoise>,
+        account: Account,
+        outer_event: Event,
+        unwrapped: UnwrappedGift,
     ) -> Result<()> {
+        // NIP-17: the seal must be signed by the author of the rumor
+        if unwrapped.sender != unwrapped.rumor.pubkey {
+            tracing::warn!(
+                target: "whitenoise::nostr_manager::event_processor",
+                "Dropping private direct message with mismatched seal and rumor authors"
+            );
+            return Ok(());
+        }
+
+        let message = direct_messages::save_message(
+            &account.pubkey,
+            &unwrapped.rumor,
+            Some(outer_event.id),
+            &wn.database,
+        )
+        .await?;
         tracing::debug!(
-            target: "whitenoise::event_processor",
-            "Received private direct message: {:?}",
-            inner_event
+            target: "whitenoise::nostr_manager::event_processor",
+            "Processed private direct message: {:?}",
+            message
         );
         Ok(())
     }
@@ -326,16 +376,30 @@ impl EventProcessor {
             }
             Err(_) => {
                 tracing::error!(target: "whitenoise::nostr_manager::event_processor", "Timeout waiting for nostr_mls lock");
-                return Err(EventProcessorError::NostrMlsError(
-                    nostr_mls::Error::KeyPackage("Timeout waiting for nostr_mls lock".to_string()),
-                ));
+                return Err(EventProcessorError::NostrMlsLockTimeout);
             }
         };
+        let mut inactive_group_ids: Vec<GroupId> = Vec::new();
+        let mut group_admins: BTreeSet<PublicKey> = BTreeSet::new();
         let result = if let Some(nostr_mls) = nostr_mls_guard.as_ref() {
             match nostr_mls.process_message(&event) {
                 Ok(message) => {
                     // TODO: Need to handle proposals and commits
                     tracing::debug!(target: "whitenoise::nostr_manager::event_processor", "Processed MLS message");
+                    if let Some(message) = &message {
+                        group_admins = nostr_mls
+                            .get_group(&message.mls_group_id)?
+                            .map(|group| group.admin_pubkeys)
+                            .unwrap_or_default();
+                    } else {
+                        // Commits can remove us from a group, find the groups we're no longer active in
+                        inactive_group_ids = nostr_mls
+                            .get_groups()?
+                            .into_iter()
+                            .filter(|group| group.state != group_types::GroupState::Active)
+                            .map(|group| group.mls_group_id)
+                            .collect();
+                    }
                     Ok(message)
                 }
                 Err(e) => {
@@ -348,7 +412,40 @@ impl EventProcessor {
             tracing::error!(target: "whitenoise::nostr_manager::event_processor", "Nostr MLS not initialized");
             Err(EventProcessorError::NostrMlsNotInitialized)
         };
+        drop(nostr_mls_guard);
         tracing::debug!(target: "whitenoise::nostr_manager::event_processor", "nostr_mls lock released");
+
+        if let Ok(message) = &result {
+            let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+            if let Some(message) = message {
+                if message.kind == Kind::Custom(READ_RECEIPT_KIND) {
+                    read_markers::record_read_receipt(&account_pubkey, message, &wn.database)
+                        .await?;
+                } else if !message.kind.is_ephemeral() {
+                    // The message was processed, a search index failure shouldn't turn that into an error
+                    if let Err(e) =
+                        message_search::index_message(&account_pubkey, message, &wn.database).await
+                    {
+                        tracing::warn!(target: "whitenoise::nostr_manager::event_processor", "Failed to index message {}: {}", message.id, e);
+                    }
+                    disappearing_messages::handle_message(
+                        &account_pubkey,
+                        message,
+                        &group_admins,
+                        &wn.database,
+                    )
+                    .await?;
+                }
+            }
+            for mls_group_id in inactive_group_ids.iter() {
+                if let Err(e) =
+                    message_search::purge_group(&account_pubkey, mls_group_id, &wn.database).await
+                {
+                    tracing::warn!(target: "whitenoise::nostr_manager::event_processor", "Failed to purge search index of group: {}", e);
+                }
+            }
+        }
+
         result
     }
 }
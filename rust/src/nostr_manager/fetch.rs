@@ -5,6 +5,7 @@
 
 use crate::nostr_manager::event_processor::ProcessableEvent;
 use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::relays::RelayType;
 use nostr_sdk::prelude::*;
 
 impl NostrManager {
@@ -36,56 +37,71 @@ impl NostrManager {
         }
     }
 
-    pub async fn fetch_user_relays(&self, pubkey: PublicKey) -> Result<Vec<String>> {
-        let filter = Filter::new().author(pubkey).kind(Kind::RelayList).limit(1);
-
-        let events = self
-            .client
-            .fetch_events(filter, self.timeout().await?)
-            .await
-            .map_err(NostrManagerError::from)?;
+    /// Fetches a user's metadata from the given relays as well as our own, e.g. the relays an
+    /// nprofile or NIP-05 document points to. The extra relays are only used for this lookup.
+    pub async fn fetch_user_metadata_from(
+        &self,
+        pubkey: PublicKey,
+        hint_relays: &[RelayUrl],
+    ) -> Result<Option<Metadata>> {
+        let mut relays = hint_relays.to_vec();
+        for relay in self.relays().await? {
+            if let Ok(relay) = RelayUrl::parse(&relay) {
+                if !relays.contains(&relay) {
+                    relays.push(relay);
+                }
+            }
+        }
+        let filter = Filter::new().author(pubkey).kind(Kind::Metadata).limit(1);
+        let events = self.fetch_events_from_relays(&relays, filter).await?;
+        Ok(events
+            .first()
+            .and_then(|event| Metadata::from_json(&event.content).ok()))
+    }
 
-        Ok(Self::relay_urls_from_events(events))
+    pub async fn fetch_user_relays(&self, pubkey: PublicKey) -> Result<Vec<String>> {
+        self.fetch_user_relay_list(pubkey, RelayType::Nostr).await
     }
 
     pub async fn fetch_user_inbox_relays(&self, pubkey: PublicKey) -> Result<Vec<String>> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::InboxRelays)
-            .limit(1);
-        let events = self
-            .client
-            .fetch_events(filter, self.timeout().await?)
-            .await
-            .map_err(NostrManagerError::from)?;
-
-        Ok(Self::relay_urls_from_events(events))
+        self.fetch_user_relay_list(pubkey, RelayType::Inbox).await
     }
 
     pub async fn fetch_user_key_package_relays(&self, pubkey: PublicKey) -> Result<Vec<String>> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::MlsKeyPackageRelays)
-            .limit(1);
-        let events = self
-            .client
-            .fetch_events(filter, self.timeout().await?)
+        self.fetch_user_relay_list(pubkey, RelayType::KeyPackage)
             .await
-            .map_err(NostrManagerError::from)?;
-
-        Ok(Self::relay_urls_from_events(events))
     }
 
+    /// Fetches key packages from the user's key package relays, or from our own relays if
+    /// the user doesn't publish a key package relay list.
     pub async fn fetch_user_key_packages(&self, pubkey: PublicKey) -> Result<Events> {
         let filter = Filter::new().author(pubkey).kind(Kind::MlsKeyPackage);
-        let events = self
-            .client
-            .fetch_events(filter, self.timeout().await?)
-            .await
-            .map_err(NostrManagerError::from)?;
+        let key_package_relays = self.relay_list_for(pubkey, RelayType::KeyPackage).await?;
+        let events = if key_package_relays.is_empty() {
+            self.client
+                .fetch_events(filter, self.timeout().await?)
+                .await
+                .map_err(NostrManagerError::from)?
+        } else {
+            self.fetch_events_from_relays(&key_package_relays, filter)
+                .await?
+        };
         Ok(events)
     }
 
+    async fn fetch_user_relay_list(
+        &self,
+        pubkey: PublicKey,
+        relay_type: RelayType,
+    ) -> Result<Vec<String>> {
+        Ok(self
+            .relay_list_for(pubkey, relay_type)
+            .await?
+            .iter()
+            .map(|url| url.to_string())
+            .collect())
+    }
+
     pub async fn fetch_contacts(&self) -> Result<Vec<Event>> {
         tracing::debug!(
             target: "whitenoise::nostr_client::fetch_contacts",
@@ -1,10 +1,17 @@
 use crate::accounts::Account;
 use crate::media::blossom::BlossomClient;
+use crate::nostr_manager::auth::AuthRetries;
 use crate::nostr_manager::event_processor::EventProcessor;
+use crate::nostr_manager::proxy::ProxyState;
+use crate::nostr_manager::relay_health::RelayHealthTracker;
+use crate::nostr_manager::router::RelayListCache;
+use crate::nostr_manager::sync::SyncState;
 use crate::relays::RelayType;
 use crate::types::NostrEncryptionMethod;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
@@ -14,13 +21,21 @@ use tokio::{spawn, sync::Mutex};
 #[cfg(any(target_os = "ios", target_os = "macos"))]
 use std::path::Path;
 
+pub mod auth;
 pub mod event_processor;
 pub mod fetch;
 pub mod parser;
+pub mod proxy;
 pub mod query;
+pub mod relay_health;
+pub mod relay_info;
+pub mod router;
 pub mod search;
+pub mod settings;
 pub mod subscriptions;
 pub mod sync;
+#[cfg(test)]
+pub(crate) mod test_relay;
 
 #[derive(Error, Debug)]
 pub enum NostrManagerError {
@@ -41,54 +56,92 @@ pub enum NostrManagerError {
     IoError(String),
     #[error("Account error: {0}")]
     AccountError(String),
+    #[error("Authentication error: {0}")]
+    Auth(String),
+    #[error("Relay information error: {0}")]
+    RelayInformation(String),
+    #[error("Proxy error: {0}")]
+    Proxy(String),
+    #[error("Invalid settings: {0}")]
+    InvalidSettings(String),
 }
 
-#[derive(Debug, Clone)]
+/// Settings of the NostrManager, stored globally or per account (see `settings.rs`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct NostrManagerSettings {
+    #[serde(rename = "timeout_secs", with = "settings::duration_secs")]
     pub timeout: Duration,
+    /// Relays we always connect to
     pub relays: Vec<String>,
-    pub blossom_server: String,
+    /// Relays used instead of `relays` in dev builds
+    pub dev_relays: Vec<String>,
+    /// Blossom servers, media is uploaded to the first one
+    pub blossom_servers: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
 pub struct NostrManager {
     pub client: Client,
-    pub blossom: BlossomClient,
+    blossom: Arc<std::sync::RwLock<BlossomClient>>,
+    proxy: Arc<std::sync::RwLock<ProxyState>>,
     pub settings: Arc<Mutex<NostrManagerSettings>>,
+    pub relay_health: Arc<RelayHealthTracker>,
+    auth_retries: Arc<AuthRetries>,
+    relay_lists: Arc<RelayListCache>,
+    temporary_relays: Arc<Mutex<HashMap<RelayUrl, usize>>>,
+    sync_state: Arc<SyncState>,
     event_processor: Arc<Mutex<EventProcessor>>,
 }
 
 impl Default for NostrManagerSettings {
     fn default() -> Self {
-        let mut relays = vec![];
-        if cfg!(dev) {
-            relays.push("ws://localhost:8080".to_string());
-            relays.push("ws://localhost:7777".to_string());
-            relays.push("wss://purplepag.es".to_string());
-            // relays.push("wss://nos.lol".to_string());
-        } else {
-            relays.push("wss://relay.damus.io".to_string());
-            relays.push("wss://purplepag.es".to_string());
-            relays.push("wss://relay.primal.net".to_string());
-            relays.push("wss://nos.lol".to_string());
-        }
-
         Self {
             timeout: Duration::from_secs(3),
-            relays,
-            blossom_server: if cfg!(dev) {
+            relays: vec![
+                "wss://relay.damus.io".to_string(),
+                "wss://purplepag.es".to_string(),
+                "wss://relay.primal.net".to_string(),
+                "wss://nos.lol".to_string(),
+            ],
+            dev_relays: vec![
+                "ws://localhost:8080".to_string(),
+                "ws://localhost:7777".to_string(),
+                "wss://purplepag.es".to_string(),
+            ],
+            blossom_servers: vec![if cfg!(dev) {
                 "http://localhost:3000".to_string()
             } else {
                 "https://blossom.primal.net".to_string()
-            },
+            }],
         }
     }
 }
+
+impl NostrManagerSettings {
+    /// The relays to connect to in this build
+    pub fn active_relays(&self) -> &[String] {
+        if cfg!(dev) {
+            &self.dev_relays
+        } else {
+            &self.relays
+        }
+    }
+
+    /// The Blossom server media is uploaded to
+    pub fn blossom_server(&self) -> String {
+        self.blossom_servers
+            .first()
+            .cloned()
+            .unwrap_or_else(|| Self::default().blossom_servers[0].clone())
+    }
+}
+
 pub type Result<T> = std::result::Result<T, NostrManagerError>;
 
 impl NostrManager {
-    pub async fn new(db_path: PathBuf) -> Result<Self> {
-        let opts = Options::default();
+    pub async fn new(db_path: PathBuf, settings: NostrManagerSettings) -> Result<Self> {
+        // We answer AUTH challenges ourselves, see `auth.rs`
+        let opts = Options::default().automatic_authentication(false);
 
         // Initialize the client with the appropriate database based on platform
         let client = {
@@ -108,24 +161,23 @@ impl NostrManager {
             }
         };
 
-        let settings = NostrManagerSettings::default();
-
-        let blossom = BlossomClient::new(&settings.blossom_server);
+        let blossom = BlossomClient::new(&settings.blossom_server());
 
-        // Add the default relays
-        for relay in &settings.relays {
-            client.add_relay(relay).await?;
-        }
-
-        // Connect to the default relays
-        client.connect().await;
+        // Default relays are connected in `connect_default_relays`, once the proxy settings are
+        // loaded
 
         let event_processor = Arc::new(Mutex::new(EventProcessor::new()));
 
         Ok(Self {
             client,
-            blossom,
+            blossom: Arc::new(std::sync::RwLock::new(blossom)),
+            proxy: Arc::new(std::sync::RwLock::new(ProxyState::Direct)),
             settings: Arc::new(Mutex::new(settings)),
+            relay_health: Arc::new(RelayHealthTracker::default()),
+            auth_retries: Arc::new(AuthRetries::default()),
+            relay_lists: Arc::new(RelayListCache::default()),
+            temporary_relays: Arc::new(Mutex::new(HashMap::new())),
+            sync_state: Arc::new(SyncState::default()),
             event_processor,
         })
     }
@@ -137,7 +189,16 @@ impl NostrManager {
 
     pub async fn relays(&self) -> Result<Vec<String>> {
         let guard = self.settings.lock().await;
-        Ok(guard.relays.clone())
+        Ok(guard.active_relays().to_vec())
+    }
+
+    /// The client for the current Blossom server
+    pub fn blossom(&self) -> BlossomClient {
+        self.blossom.read().expect("Blossom lock poisoned").clone()
+    }
+
+    pub(crate) fn set_blossom(&self, blossom: BlossomClient) {
+        *self.blossom.write().expect("Blossom lock poisoned") = blossom;
     }
 
     /// Extracts welcome events from a list of giftwrapped events.
@@ -210,13 +271,27 @@ impl NostrManager {
         );
         self.client.set_signer(keys.clone()).await;
 
+        // Relays are added below, make sure they go through the account's proxy
+        let proxy = proxy::load_proxy_settings(&account.pubkey, &wn.database)
+            .await
+            .map_err(|e| NostrManagerError::Proxy(e.to_string()))?;
+        self.apply_proxy_settings(&proxy).await?;
+
+        // Switch to the account's settings, the pool was just emptied so there's nothing to keep
+        let settings = settings::effective_settings(Some(&account.pubkey), &wn.database).await?;
+        *self.settings.lock().await = settings;
+        self.set_blossom(
+            BlossomClient::new(&self.settings.lock().await.blossom_server())
+                .with_proxy(self.proxy_state()),
+        );
+
         // Add the default relays
         tracing::debug!(
             target: "whitenoise::nostr_manager::set_nostr_identity",
             "Adding default relays"
         );
         for relay in self.relays().await? {
-            self.client.add_relay(relay).await?;
+            self.add_relay(relay).await?;
         }
 
         // Connect to the default relays
@@ -278,7 +353,7 @@ impl NostrManager {
 
             for relay in relays.iter() {
                 if !connected_relays.contains(relay) {
-                    self.client.add_relay(relay).await?;
+                    self.add_relay(relay).await?;
                     self.client.connect_relay(relay).await?;
                     tracing::debug!(
                         target: "whitenoise::nostr_manager::set_nostr_identity",
@@ -314,7 +389,7 @@ impl NostrManager {
 
             for relay in inbox_relays.iter() {
                 if !connected_relays.contains(relay) {
-                    self.client.add_read_relay(relay).await?;
+                    self.add_read_relay(relay).await?;
                     self.client.connect_relay(relay).await?;
                     tracing::debug!(
                         target: "whitenoise::nostr_manager::set_nostr_identity",
@@ -354,7 +429,7 @@ impl NostrManager {
 
             for relay in key_package_relays.iter() {
                 if !connected_relays.contains(relay) {
-                    self.client.add_relay(relay).await?;
+                    self.add_relay(relay).await?;
                     self.client.connect_relay(relay).await?;
                     tracing::debug!(
                         target: "whitenoise::nostr_manager::set_nostr_identity",
@@ -384,6 +459,13 @@ impl NostrManager {
         let new_processor = EventProcessor::new();
         *self.event_processor.lock().await = new_processor;
 
+        // Make sure expired messages get purged while the app is running
+        crate::disappearing_messages::start_sweeper();
+        // Keep retrying events that didn't reach all of their relays
+        crate::outbox::start_retry_worker();
+        // Replace our key package when it gets old and clean up the ones it replaced
+        crate::key_package_lifecycle::start_refresh_worker();
+
         // Spawn two tasks in parallel:
         // 1. Setup subscriptions to catch future events
         // 2. Fetch past events
@@ -474,6 +556,19 @@ impl NostrManager {
             }
         });
 
+        // Refresh what we know about the account's relays, without holding up the login
+        let account = account.clone();
+        spawn(async move {
+            let wn_state = crate::runtime::wn();
+            let mut relays = Vec::new();
+            for relay_type in [RelayType::Nostr, RelayType::Inbox, RelayType::KeyPackage] {
+                if let Ok(urls) = account.relays(relay_type, wn_state.clone()).await {
+                    relays.extend(urls);
+                }
+            }
+            wn_state.nostr.refresh_relay_information(&relays).await;
+        });
+
         Ok(())
     }
 
@@ -531,10 +626,9 @@ impl NostrManager {
 
     fn relay_urls_from_events(events: Events) -> Vec<String> {
         events
-            .into_iter()
-            .flat_map(|e| e.tags)
-            .filter(|tag| tag.kind() == TagKind::Relay)
-            .map_while(|tag| tag.content().map(|c| c.to_string()))
+            .iter()
+            .flat_map(router::relay_urls_from_event)
+            .map(|url| url.to_string())
             .collect()
     }
 
@@ -0,0 +1,278 @@
+//! SOCKS5 proxy support for NostrManager
+//! Accounts can route their relay connections and Blossom requests through a SOCKS5 proxy, e.g.
+//! a local Tor daemon. When the proxy can't be reached we either fall back to connecting
+//! directly or, if the account doesn't allow it, refuse to connect at all. The settings are
+//! stored in their own table rather than in the account settings.
+
+use crate::database::Database;
+use crate::media::blossom::BlossomClient;
+use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The address of a Tor daemon running with its default settings
+pub const DEFAULT_PROXY_ADDRESS: &str = "127.0.0.1:9050";
+
+/// An account's proxy settings
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct ProxySettings {
+    pub enabled: bool,
+    /// `host:port` of the SOCKS5 proxy
+    pub address: String,
+    /// Connect directly when the proxy can't be reached
+    pub allow_clearnet_fallback: bool,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            address: DEFAULT_PROXY_ADDRESS.to_string(),
+            allow_clearnet_fallback: false,
+        }
+    }
+}
+
+/// How we're currently connecting to relays and Blossom servers
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(tag = "mode", content = "address", rename_all = "snake_case")]
+pub enum ProxyState {
+    #[default]
+    Direct,
+    Proxy(SocketAddr),
+    /// The proxy is unreachable and clearnet fallback isn't allowed
+    Blocked,
+}
+
+/// Works out how to connect given the settings and whether the proxy could be reached
+fn proxy_state(settings: &ProxySettings, proxy: Option<SocketAddr>) -> ProxyState {
+    if !settings.enabled {
+        return ProxyState::Direct;
+    }
+    match proxy {
+        Some(address) => ProxyState::Proxy(address),
+        None if settings.allow_clearnet_fallback => ProxyState::Direct,
+        None => ProxyState::Blocked,
+    }
+}
+
+/// Returns the proxy address if it accepts connections
+async fn reachable_proxy(address: &str, timeout: Duration) -> Option<SocketAddr> {
+    let address: SocketAddr = address.parse().ok()?;
+    match tokio::time::timeout(timeout, tokio::net::TcpStream::connect(address)).await {
+        Ok(Ok(_)) => Some(address),
+        _ => None,
+    }
+}
+
+/// Builds an HTTP client that goes through the proxy, if there is one
+pub fn http_client(state: ProxyState) -> Result<reqwest::Client> {
+    http_client_builder(state)?
+        .build()
+        .map_err(|e| NostrManagerError::Proxy(e.to_string()))
+}
+
+/// An HTTP client builder that goes through the proxy, if there is one, for clients that need
+/// more options
+pub fn http_client_builder(state: ProxyState) -> Result<reqwest::ClientBuilder> {
+    match state {
+        ProxyState::Direct => Ok(reqwest::Client::builder()),
+        ProxyState::Proxy(address) => Ok(reqwest::Client::builder().proxy(
+            // socks5h so that DNS is resolved by the proxy too
+            reqwest::Proxy::all(format!("socks5h://{}", address))
+                .map_err(|e| NostrManagerError::Proxy(e.to_string()))?,
+        )),
+        ProxyState::Blocked => Err(proxy_unavailable()),
+    }
+}
+
+fn proxy_unavailable() -> NostrManagerError {
+    NostrManagerError::Proxy("Proxy unreachable and clearnet fallback is disabled".to_string())
+}
+
+/// Loads an account's proxy settings, the defaults if it never changed them
+pub async fn load_proxy_settings(
+    account_pubkey: &PublicKey,
+    db: &Database,
+) -> std::result::Result<ProxySettings, sqlx::Error> {
+    let settings = sqlx::query_as::<_, ProxySettings>(
+        "SELECT enabled, address, allow_clearnet_fallback FROM proxy_settings
+         WHERE account_pubkey = ?",
+    )
+    .bind(account_pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// Loads the proxy settings of the active account, the defaults without one
+pub async fn active_proxy_settings(
+    db: &Database,
+) -> std::result::Result<ProxySettings, sqlx::Error> {
+    let settings = sqlx::query_as::<_, ProxySettings>(
+        "SELECT p.enabled, p.address, p.allow_clearnet_fallback FROM proxy_settings p
+         JOIN accounts a ON a.pubkey = p.account_pubkey
+         WHERE a.active = TRUE",
+    )
+    .fetch_optional(&db.pool)
+    .await?;
+    Ok(settings.unwrap_or_default())
+}
+
+/// Stores an account's proxy settings
+pub async fn save_proxy_settings(
+    account_pubkey: &PublicKey,
+    settings: &ProxySettings,
+    db: &Database,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO proxy_settings
+         (account_pubkey, enabled, address, allow_clearnet_fallback, updated_at)
+         VALUES (?, ?, ?, ?, ?)",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(settings.enabled)
+    .bind(&settings.address)
+    .bind(settings.allow_clearnet_fallback)
+    .bind(Timestamp::now().as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+impl NostrManager {
+    /// Applies an account's proxy settings to relay connections and the Blossom client.
+    ///
+    /// Relays that are already in the pool keep their connection, this is meant to be called
+    /// before the pool is rebuilt in `set_nostr_identity`.
+    pub async fn apply_proxy_settings(&self, settings: &ProxySettings) -> Result<ProxyState> {
+        let proxy = if settings.enabled {
+            reachable_proxy(&settings.address, self.timeout().await?).await
+        } else {
+            None
+        };
+        let state = proxy_state(settings, proxy);
+        if settings.enabled && proxy.is_none() {
+            tracing::warn!(
+                target: "whitenoise::nostr_manager::proxy",
+                "Proxy {} unreachable, {}",
+                settings.address,
+                if state == ProxyState::Blocked {
+                    "not connecting"
+                } else {
+                    "connecting directly"
+                }
+            );
+        }
+
+        *self.proxy.write().expect("Proxy lock poisoned") = state;
+        let blossom = self.blossom();
+        self.set_blossom(BlossomClient::new(&blossom.url).with_proxy(state));
+        Ok(state)
+    }
+
+    pub fn proxy_state(&self) -> ProxyState {
+        *self.proxy.read().expect("Proxy lock poisoned")
+    }
+
+    /// An HTTP client that respects the proxy settings
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        http_client(self.proxy_state())
+    }
+
+    /// An HTTP client builder that respects the proxy settings
+    pub fn http_client_builder(&self) -> Result<reqwest::ClientBuilder> {
+        http_client_builder(self.proxy_state())
+    }
+
+    /// Options for a new relay connection, through the proxy if there is one
+    pub(crate) fn relay_options(&self) -> Result<RelayOptions> {
+        match self.proxy_state() {
+            ProxyState::Direct => Ok(RelayOptions::default()),
+            ProxyState::Proxy(address) => {
+                Ok(RelayOptions::default().connection_mode(ConnectionMode::Proxy(address)))
+            }
+            ProxyState::Blocked => Err(proxy_unavailable()),
+        }
+    }
+
+    /// Adds a relay to the pool, connecting through the proxy if there is one
+    pub async fn add_relay<U>(&self, url: U) -> Result<bool>
+    where
+        U: TryIntoUrl,
+        nostr_sdk::pool::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let opts = self.relay_options()?;
+        self.add_relay_with_opts(url, opts).await
+    }
+
+    /// Adds a relay we only read from (e.g. an inbox relay), through the proxy if there is one
+    pub async fn add_read_relay<U>(&self, url: U) -> Result<bool>
+    where
+        U: TryIntoUrl,
+        nostr_sdk::pool::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let opts = self.relay_options()?.write(false);
+        self.add_relay_with_opts(url, opts).await
+    }
+
+    async fn add_relay_with_opts<U>(&self, url: U, opts: RelayOptions) -> Result<bool>
+    where
+        U: TryIntoUrl,
+        nostr_sdk::pool::Error: From<<U as TryIntoUrl>::Err>,
+    {
+        let url = url
+            .try_into_url()
+            .map_err(|e| nostr_sdk::client::Error::from(nostr_sdk::pool::Error::from(e)))?;
+        if self.temporary_relays.lock().await.remove(&url).is_some() {
+            // The relay was only added for a single request, add it again as one of ours
+            self.client.remove_relay(url.clone()).await?;
+        }
+        Ok(self.client.add_relay_with_opts(url, opts).await?)
+    }
+
+    /// Applies the active account's proxy settings and connects to the default relays.
+    ///
+    /// Called once at startup, relays are only added after we know how to reach them.
+    pub async fn connect_default_relays(&self, db: &Database) -> Result<()> {
+        let proxy = active_proxy_settings(db)
+            .await
+            .map_err(|e| NostrManagerError::Proxy(e.to_string()))?;
+        self.apply_proxy_settings(&proxy).await?;
+        for relay in self.relays().await? {
+            self.add_relay(relay).await?;
+        }
+        self.client.connect().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_state() {
+        let address: SocketAddr = DEFAULT_PROXY_ADDRESS.parse().unwrap();
+        let mut settings = ProxySettings::default();
+        assert_eq!(proxy_state(&settings, None), ProxyState::Direct);
+
+        settings.enabled = true;
+        assert_eq!(
+            proxy_state(&settings, Some(address)),
+            ProxyState::Proxy(address)
+        );
+        assert_eq!(proxy_state(&settings, None), ProxyState::Blocked);
+
+        settings.allow_clearnet_fallback = true;
+        assert_eq!(proxy_state(&settings, None), ProxyState::Direct);
+    }
+
+    #[test]
+    fn test_blocked_http_client() {
+        assert!(http_client(ProxyState::Blocked).is_err());
+        assert!(http_client(ProxyState::Direct).is_ok());
+    }
+}
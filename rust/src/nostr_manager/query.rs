@@ -42,6 +42,21 @@ impl NostrManager {
         Ok(events)
     }
 
+    /// The relays we've seen an event on, sorted
+    pub async fn query_event_seen_on_relays(&self, event_id: &EventId) -> Result<Vec<String>> {
+        let mut relays: Vec<String> = self
+            .client
+            .database()
+            .event_seen_on_relays(event_id)
+            .await?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|url| url.to_string())
+            .collect();
+        relays.sort();
+        Ok(relays)
+    }
+
     pub async fn query_contact_list_pubkeys(&self) -> Result<Vec<PublicKey>> {
         let pubkey = self.client.signer().await?.get_public_key().await.unwrap();
 
@@ -0,0 +1,205 @@
+//! Relay health tracking for NostrManager
+//! This keeps track of what every relay in the pool has been telling us (notices, closed
+//! subscriptions, auth challenges and rejected events) so that the app can show which relays
+//! are working and why publishing to a relay fails.
+
+use crate::nostr_manager::NostrManager;
+use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// A message from a relay along with when we received it
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RelayNotice {
+    pub message: String,
+    pub received_at: u64,
+}
+
+/// A subscription the relay closed on us
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct ClosedSubscription {
+    pub subscription_id: String,
+    pub message: String,
+    pub received_at: u64,
+}
+
+/// An event the relay refused to store
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RejectedEvent {
+    pub event_id: String,
+    pub message: String,
+    pub received_at: u64,
+}
+
+/// What a relay has been telling us since the pool was started
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct RelayActivity {
+    pub last_notice: Option<RelayNotice>,
+    pub last_closed: Option<ClosedSubscription>,
+    pub last_auth_challenge_at: Option<u64>,
+    pub last_rejected_event: Option<RejectedEvent>,
+    pub accepted_events: u64,
+    pub rejected_events: u64,
+    pub last_message_at: Option<u64>,
+}
+
+/// The health of a single relay, as returned by the `relay_status` command
+#[derive(Debug, Clone, Serialize)]
+pub struct RelayHealth {
+    pub url: String,
+    pub status: String,
+    pub connected: bool,
+    pub latency_ms: Option<u64>,
+    pub connection_attempts: usize,
+    pub successful_connections: usize,
+    #[serde(flatten)]
+    pub activity: RelayActivity,
+}
+
+#[derive(Debug, Default)]
+pub struct RelayHealthTracker {
+    activity: Mutex<HashMap<String, RelayActivity>>,
+}
+
+impl RelayHealthTracker {
+    /// Records a message received from a relay
+    pub fn record(&self, relay_url: &RelayUrl, message: &RelayMessage, now: Timestamp) {
+        let now = now.as_u64();
+        let mut activity = self.activity.lock().expect("Relay health lock poisoned");
+        let activity = activity.entry(relay_url.to_string()).or_default();
+        activity.last_message_at = Some(now);
+
+        match message {
+            RelayMessage::Notice(message) => {
+                activity.last_notice = Some(RelayNotice {
+                    message: message.to_string(),
+                    received_at: now,
+                });
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } => {
+                activity.last_closed = Some(ClosedSubscription {
+                    subscription_id: subscription_id.to_string(),
+                    message: message.to_string(),
+                    received_at: now,
+                });
+            }
+            RelayMessage::Auth { .. } => {
+                activity.last_auth_challenge_at = Some(now);
+            }
+            RelayMessage::Ok {
+                event_id,
+                status,
+                message,
+            } => {
+                if *status {
+                    activity.accepted_events += 1;
+                } else {
+                    activity.rejected_events += 1;
+                    activity.last_rejected_event = Some(RejectedEvent {
+                        event_id: event_id.to_hex(),
+                        message: message.to_string(),
+                        received_at: now,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns what a relay has told us so far
+    pub fn activity(&self, relay_url: &RelayUrl) -> RelayActivity {
+        self.activity
+            .lock()
+            .expect("Relay health lock poisoned")
+            .get(&relay_url.to_string())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Forgets everything, e.g. when the relay pool shuts down
+    pub fn clear(&self) {
+        self.activity
+            .lock()
+            .expect("Relay health lock poisoned")
+            .clear();
+    }
+}
+
+impl NostrManager {
+    /// Returns the health of every relay in the pool
+    pub async fn relay_health(&self) -> Vec<RelayHealth> {
+        let mut health: Vec<RelayHealth> = self
+            .client
+            .relays()
+            .await
+            .into_iter()
+            .map(|(url, relay)| {
+                let stats = relay.stats();
+                RelayHealth {
+                    url: url.to_string(),
+                    status: relay.status().to_string(),
+                    connected: relay.status() == RelayStatus::Connected,
+                    latency_ms: stats.latency().map(|latency| latency.as_millis() as u64),
+                    connection_attempts: stats.attempts(),
+                    successful_connections: stats.success(),
+                    activity: self.relay_health.activity(&url),
+                }
+            })
+            .collect();
+        health.sort_by(|a, b| a.url.cmp(&b.url));
+        health
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_relay_messages() {
+        let tracker = RelayHealthTracker::default();
+        let relay_url = RelayUrl::parse("wss://relay.example.com").unwrap();
+        let event_id = EventId::all_zeros();
+        let now = Timestamp::from(1_000);
+
+        tracker.record(&relay_url, &RelayMessage::notice("slow down"), now);
+        tracker.record(&relay_url, &RelayMessage::auth("challenge"), now);
+        tracker.record(&relay_url, &RelayMessage::ok(event_id, true, ""), now);
+        tracker.record(
+            &relay_url,
+            &RelayMessage::ok(
+                event_id,
+                false,
+                "auth-required: we only accept events from members",
+            ),
+            now,
+        );
+        tracker.record(
+            &relay_url,
+            &RelayMessage::closed(
+                SubscriptionId::new("giftwraps"),
+                "auth-required: who are you?",
+            ),
+            now,
+        );
+
+        let activity = tracker.activity(&relay_url);
+        assert_eq!(activity.last_notice.unwrap().message, "slow down");
+        assert_eq!(activity.last_auth_challenge_at, Some(1_000));
+        assert_eq!(activity.accepted_events, 1);
+        assert_eq!(activity.rejected_events, 1);
+        assert!(activity
+            .last_rejected_event
+            .unwrap()
+            .message
+            .starts_with("auth-required"));
+        assert_eq!(activity.last_closed.unwrap().subscription_id, "giftwraps");
+
+        tracker.clear();
+        assert_eq!(tracker.activity(&relay_url), RelayActivity::default());
+    }
+}
@@ -0,0 +1,314 @@
+//! NIP-11 relay information for NostrManager
+//! Relays describe themselves (software, supported NIPs, limits) in a JSON document served over
+//! HTTP from the same address as the websocket. Documents are cached in the database for a day,
+//! and used to warn about relays that won't work well for what the user picked them for.
+
+use crate::database::Database;
+use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::relays::RelayType;
+use crate::runtime::wn;
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// How long a cached information document is used before fetching it again
+const RELAY_INFORMATION_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The parts of a NIP-11 relay information document we care about
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayInformation {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub software: Option<String>,
+    pub version: Option<String>,
+    #[serde(default)]
+    pub supported_nips: Vec<u16>,
+    pub limitation: Option<RelayLimitation>,
+}
+
+/// Limits a relay puts on its clients
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RelayLimitation {
+    pub max_message_length: Option<u64>,
+    pub max_subscriptions: Option<u64>,
+    pub max_content_length: Option<u64>,
+    #[serde(default)]
+    pub auth_required: bool,
+    #[serde(default)]
+    pub payment_required: bool,
+    #[serde(default)]
+    pub restricted_writes: bool,
+}
+
+impl RelayInformation {
+    pub fn supports_nip(&self, nip: u16) -> bool {
+        self.supported_nips.contains(&nip)
+    }
+
+    fn limitation(&self) -> RelayLimitation {
+        self.limitation.clone().unwrap_or_default()
+    }
+}
+
+/// Why a relay might not work well for what it was picked for
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RelayWarning {
+    /// We couldn't get the relay's information document
+    NoInformation { relay_url: String },
+    /// The relay doesn't list a NIP this kind of relay needs
+    MissingNip { relay_url: String, nip: u16 },
+    /// The relay only accepts events from paying users
+    PaymentRequired { relay_url: String },
+    /// The relay doesn't accept events from everyone
+    RestrictedWrites { relay_url: String },
+}
+
+/// NIPs a relay should support for each relay type
+pub fn required_nips(relay_type: RelayType) -> &'static [u16] {
+    match relay_type {
+        RelayType::Nostr => &[],
+        // Only the recipient should be able to read their gift wraps
+        RelayType::Inbox => &[42],
+        // Used key packages are deleted with deletion requests
+        RelayType::KeyPackage => &[9],
+    }
+}
+
+/// Returns what might go wrong with using a relay for a relay type
+pub fn relay_warnings(
+    relay_url: &str,
+    relay_type: RelayType,
+    info: Option<&RelayInformation>,
+) -> Vec<RelayWarning> {
+    let relay_url = relay_url.to_string();
+    let Some(info) = info else {
+        return vec![RelayWarning::NoInformation { relay_url }];
+    };
+
+    let mut warnings: Vec<RelayWarning> = required_nips(relay_type)
+        .iter()
+        .filter(|nip| !info.supports_nip(**nip))
+        .map(|nip| RelayWarning::MissingNip {
+            relay_url: relay_url.clone(),
+            nip: *nip,
+        })
+        .collect();
+
+    let limitation = info.limitation();
+    if limitation.payment_required {
+        warnings.push(RelayWarning::PaymentRequired {
+            relay_url: relay_url.clone(),
+        });
+    }
+    // Others have to be able to write to our inbox relays
+    if limitation.restricted_writes && relay_type == RelayType::Inbox {
+        warnings.push(RelayWarning::RestrictedWrites { relay_url });
+    }
+    warnings
+}
+
+/// Returns the HTTP(S) address the relay serves its information document from
+pub fn information_url(relay_url: &RelayUrl) -> String {
+    let url = relay_url.to_string();
+    if let Some(rest) = url.strip_prefix("wss://") {
+        format!("https://{}", rest)
+    } else if let Some(rest) = url.strip_prefix("ws://") {
+        format!("http://{}", rest)
+    } else {
+        url
+    }
+}
+
+/// Fetches the NIP-11 information document of a relay
+pub async fn fetch_relay_information(
+    client: &reqwest::Client,
+    relay_url: &RelayUrl,
+    timeout: Duration,
+) -> std::result::Result<RelayInformation, reqwest::Error> {
+    client
+        .get(information_url(relay_url))
+        .header("Accept", "application/nostr+json")
+        .timeout(timeout)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<RelayInformation>()
+        .await
+}
+
+/// Returns a cached information document, if we fetched it recently
+pub async fn cached_relay_information(
+    relay_url: &RelayUrl,
+    now: Timestamp,
+    db: &Database,
+) -> std::result::Result<Option<RelayInformation>, sqlx::Error> {
+    let row = sqlx::query_as::<_, (String, i64)>(
+        "SELECT document, fetched_at FROM relay_information WHERE relay_url = ?",
+    )
+    .bind(relay_url.to_string())
+    .fetch_optional(&db.pool)
+    .await?;
+
+    Ok(row
+        .filter(|(_, fetched_at)| {
+            (*fetched_at as u64) + RELAY_INFORMATION_TTL.as_secs() > now.as_u64()
+        })
+        .and_then(|(document, _)| serde_json::from_str(&document).ok()))
+}
+
+/// Caches an information document
+pub async fn save_relay_information(
+    relay_url: &RelayUrl,
+    info: &RelayInformation,
+    now: Timestamp,
+    db: &Database,
+) -> std::result::Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO relay_information (relay_url, document, fetched_at)
+         VALUES (?, ?, ?)",
+    )
+    .bind(relay_url.to_string())
+    .bind(serde_json::to_string(info).unwrap_or_default())
+    .bind(now.as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+impl NostrManager {
+    /// Returns the information document of a relay, from the cache unless it's stale or
+    /// `refresh` is set.
+    pub async fn relay_information(
+        &self,
+        relay_url: &RelayUrl,
+        refresh: bool,
+    ) -> Result<RelayInformation> {
+        let db = &wn().database;
+        if !refresh {
+            if let Some(info) = cached_relay_information(relay_url, Timestamp::now(), db)
+                .await
+                .map_err(|e| NostrManagerError::RelayInformation(e.to_string()))?
+            {
+                return Ok(info);
+            }
+        }
+
+        let info = fetch_relay_information(&self.http_client()?, relay_url, self.timeout().await?)
+            .await
+            .map_err(|e| NostrManagerError::RelayInformation(e.to_string()))?;
+        save_relay_information(relay_url, &info, Timestamp::now(), db)
+            .await
+            .map_err(|e| NostrManagerError::RelayInformation(e.to_string()))?;
+        Ok(info)
+    }
+
+    /// Fetches the information documents we don't have or that went stale, failures are logged
+    pub async fn refresh_relay_information(&self, relays: &[String]) {
+        let mut seen = Vec::new();
+        for url in relays {
+            let Ok(relay_url) = RelayUrl::parse(url) else {
+                continue;
+            };
+            if seen.contains(&relay_url) {
+                continue;
+            }
+            if let Err(e) = self.relay_information(&relay_url, false).await {
+                tracing::debug!(
+                    target: "whitenoise::nostr_manager::relay_info",
+                    "Failed to get information about {}: {}",
+                    relay_url,
+                    e
+                );
+            }
+            seen.push(relay_url);
+        }
+    }
+
+    /// Checks relays picked for a relay type, relays we can't get information about are
+    /// reported as such rather than failing the check
+    pub async fn check_relays(
+        &self,
+        relays: &[String],
+        relay_type: RelayType,
+    ) -> Vec<RelayWarning> {
+        let mut warnings = Vec::new();
+        for url in relays {
+            let info = match RelayUrl::parse(url) {
+                Ok(relay_url) => self.relay_information(&relay_url, false).await.ok(),
+                Err(_) => None,
+            };
+            warnings.extend(relay_warnings(url, relay_type, info.as_ref()));
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_information_url() {
+        let secure = RelayUrl::parse("wss://relay.example.com").unwrap();
+        assert!(information_url(&secure).starts_with("https://relay.example.com"));
+        let local = RelayUrl::parse("ws://localhost:8080").unwrap();
+        assert!(information_url(&local).starts_with("http://localhost:8080"));
+    }
+
+    #[test]
+    fn test_parse_relay_information() {
+        let info: RelayInformation = serde_json::from_str(
+            r#"{"name":"strfry","software":"git+https://github.com/hoytech/strfry.git","supported_nips":[1,2,4,9,11,40,70,77],"limitation":{"max_subscriptions":20}}"#,
+        )
+        .unwrap();
+        assert!(info.supports_nip(77));
+        assert!(!info.supports_nip(42));
+        assert_eq!(info.limitation.unwrap().max_subscriptions, Some(20));
+
+        let minimal: RelayInformation = serde_json::from_str("{}").unwrap();
+        assert!(minimal.supported_nips.is_empty());
+    }
+
+    #[test]
+    fn test_relay_warnings() {
+        let url = "wss://relay.example.com";
+        assert_eq!(
+            relay_warnings(url, RelayType::Inbox, None),
+            vec![RelayWarning::NoInformation {
+                relay_url: url.to_string()
+            }]
+        );
+
+        let info = RelayInformation {
+            supported_nips: vec![1, 9, 11],
+            limitation: Some(RelayLimitation {
+                payment_required: true,
+                restricted_writes: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            relay_warnings(url, RelayType::Inbox, Some(&info)),
+            vec![
+                RelayWarning::MissingNip {
+                    relay_url: url.to_string(),
+                    nip: 42
+                },
+                RelayWarning::PaymentRequired {
+                    relay_url: url.to_string()
+                },
+                RelayWarning::RestrictedWrites {
+                    relay_url: url.to_string()
+                },
+            ]
+        );
+        assert_eq!(
+            relay_warnings(url, RelayType::KeyPackage, Some(&info)),
+            vec![RelayWarning::PaymentRequired {
+                relay_url: url.to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,558 @@
+//! Outbox-model routing for NostrManager
+//! Instead of sending everything to whatever relays happen to be in the pool, events go where
+//! the people who need them will look:
+//! - Our metadata and relay lists go to our own write relays plus a few well-known indexers.
+//! - Gift wraps go to each recipient's inbox relays (NIP-17).
+//! - Key packages are looked up on the target's key package relays (MIP-00).
+//!
+//! Relay lists of other users are kept in a small in-memory cache so that looking someone up
+//! twice in a row doesn't hit the network twice.
+//!
+//! Relays that aren't ours are only added to the pool for the request that needs them, with just
+//! the READ or WRITE flag that request needs so our subscriptions never reach them, and removed
+//! again right after.
+
+use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::relays::RelayType;
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Relays that index metadata and relay lists of most of the network
+pub const INDEXER_RELAYS: &[&str] = &["wss://purplepag.es", "wss://user.kindpag.es"];
+
+/// How long a cached relay list is considered fresh
+const RELAY_LIST_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// Maximum number of relay lists kept in the cache
+const RELAY_LIST_CACHE_SIZE: usize = 512;
+
+/// What a relay is temporarily added to the pool for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TemporaryRelayUse {
+    /// Fetching events, the relay needs the READ flag
+    Read,
+    /// Publishing events, the relay needs the WRITE flag
+    Write,
+}
+
+impl TemporaryRelayUse {
+    fn flag(self) -> RelayServiceFlags {
+        match self {
+            Self::Read => RelayServiceFlags::READ,
+            Self::Write => RelayServiceFlags::WRITE,
+        }
+    }
+}
+
+/// Returns the kind of the relay list event for a relay type
+pub fn relay_list_kind(relay_type: RelayType) -> Kind {
+    match relay_type {
+        RelayType::Nostr => Kind::RelayList,
+        RelayType::Inbox => Kind::InboxRelays,
+        RelayType::KeyPackage => Kind::MlsKeyPackageRelays,
+    }
+}
+
+/// Returns the relay type of a relay list event kind
+pub fn relay_type_for_kind(kind: Kind) -> Option<RelayType> {
+    match kind {
+        Kind::RelayList => Some(RelayType::Nostr),
+        Kind::InboxRelays => Some(RelayType::Inbox),
+        Kind::MlsKeyPackageRelays => Some(RelayType::KeyPackage),
+        _ => None,
+    }
+}
+
+/// Extracts the relay URLs of a relay list event
+pub fn relay_urls_from_event(event: &Event) -> Vec<RelayUrl> {
+    event
+        .tags
+        .iter()
+        // NIP-65 lists use `r` tags, inbox and key package relay lists use `relay` tags
+        .filter(|tag| {
+            tag.kind() == TagKind::Relay
+                || tag.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::R))
+        })
+        .filter_map(|tag| tag.content().and_then(|url| RelayUrl::parse(url).ok()))
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct CachedRelayList {
+    relays: Vec<RelayUrl>,
+    created_at: Timestamp,
+    fetched_at: Timestamp,
+}
+
+/// A small cache of other users' relay lists
+#[derive(Debug, Default)]
+pub struct RelayListCache {
+    entries: Mutex<HashMap<(PublicKey, Kind), CachedRelayList>>,
+}
+
+impl RelayListCache {
+    /// Returns the cached relays of a user, if we fetched them recently
+    fn get(&self, pubkey: &PublicKey, kind: Kind, now: Timestamp) -> Option<Vec<RelayUrl>> {
+        let entries = self.entries.lock().expect("Relay list cache lock poisoned");
+        entries
+            .get(&(*pubkey, kind))
+            .filter(|entry| entry.fetched_at + RELAY_LIST_TTL.as_secs() > now)
+            .map(|entry| entry.relays.clone())
+    }
+
+    /// Stores a relay list, ignoring lists older than the one we already have
+    fn insert(
+        &self,
+        pubkey: PublicKey,
+        kind: Kind,
+        relays: Vec<RelayUrl>,
+        created_at: Timestamp,
+        now: Timestamp,
+    ) {
+        let mut entries = self.entries.lock().expect("Relay list cache lock poisoned");
+        if let Some(existing) = entries.get_mut(&(pubkey, kind)) {
+            // Refresh the fetch time either way, the list is still current
+            existing.fetched_at = now;
+            if existing.created_at > created_at {
+                return;
+            }
+        }
+
+        if entries.len() >= RELAY_LIST_CACHE_SIZE && !entries.contains_key(&(pubkey, kind)) {
+            if let Some(oldest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.fetched_at)
+                .map(|(key, _)| *key)
+            {
+                entries.remove(&oldest);
+            }
+        }
+
+        entries.insert(
+            (pubkey, kind),
+            CachedRelayList {
+                relays,
+                created_at,
+                fetched_at: now,
+            },
+        );
+    }
+
+    /// Updates the cache with a relay list event we received
+    pub fn update_from_event(&self, event: &Event) {
+        if relay_type_for_kind(event.kind).is_some() {
+            self.insert(
+                event.pubkey,
+                event.kind,
+                relay_urls_from_event(event),
+                event.created_at,
+                Timestamp::now(),
+            );
+        }
+    }
+
+    pub fn clear(&self) {
+        self.entries
+            .lock()
+            .expect("Relay list cache lock poisoned")
+            .clear();
+    }
+}
+
+impl NostrManager {
+    /// Looks up one of a user's relay lists, using the cache when possible.
+    ///
+    /// Relay lists are fetched from the indexers and our default relays.
+    pub async fn relay_list_for(
+        &self,
+        pubkey: PublicKey,
+        relay_type: RelayType,
+    ) -> Result<Vec<RelayUrl>> {
+        let kind = relay_list_kind(relay_type);
+        if let Some(relays) = self.relay_lists.get(&pubkey, kind, Timestamp::now()) {
+            return Ok(relays);
+        }
+
+        let mut lookup_relays = self.default_relay_urls().await?;
+        lookup_relays.extend(indexer_relay_urls());
+
+        let filter = Filter::new().author(pubkey).kind(kind).limit(1);
+        let events = self
+            .fetch_events_from_relays(&lookup_relays, filter)
+            .await?;
+
+        // Not finding a list may just mean the indexers were slow, so we only cache what we found
+        let Some(event) = events.first() else {
+            return Ok(Vec::new());
+        };
+        let relays = relay_urls_from_event(event);
+        self.relay_lists.insert(
+            pubkey,
+            kind,
+            relays.clone(),
+            event.created_at,
+            Timestamp::now(),
+        );
+        Ok(relays)
+    }
+
+    /// Where to publish our own metadata and relay lists: our write relays plus the indexers
+    pub fn own_outbox_relays(&self, write_relays: &[String]) -> Vec<RelayUrl> {
+        let mut relays: Vec<RelayUrl> = write_relays
+            .iter()
+            .filter_map(|url| RelayUrl::parse(url).ok())
+            .collect();
+        for indexer in indexer_relay_urls() {
+            if !relays.contains(&indexer) {
+                relays.push(indexer);
+            }
+        }
+        relays
+    }
+
+    /// Signs and publishes an event to our write relays plus the indexers
+    pub async fn publish_to_outbox(
+        &self,
+        builder: EventBuilder,
+        write_relays: &[String],
+    ) -> Result<Output<EventId>> {
+        let event = self.client.sign_event_builder(builder).await?;
+        self.publish_event_to_outbox(&event, write_relays).await
+    }
+
+    /// Publishes an already signed event to our write relays plus the indexers
+    pub async fn publish_event_to_outbox(
+        &self,
+        event: &Event,
+        write_relays: &[String],
+    ) -> Result<Output<EventId>> {
+        let mut relays = self.own_outbox_relays(write_relays);
+        if relays.is_empty() {
+            relays = self.default_relay_urls().await?;
+        }
+        self.send_event_to_relays(&relays, event).await
+    }
+
+    /// Fetches our own events from our write relays, the indexers and our default relays
+    pub async fn fetch_own_events(
+        &self,
+        filter: Filter,
+        write_relays: &[String],
+    ) -> Result<Events> {
+        let relays = self.own_lookup_relays(write_relays).await?;
+        self.fetch_events_from_relays(&relays, filter).await
+    }
+
+    /// Like `fetch_own_events`, but also tells whether at least one relay confirmed with EOSE
+    /// that it sent everything it has. Finding nothing only means there is nothing then, rather
+    /// than that every relay timed out or couldn't be reached.
+    pub async fn fetch_own_events_confirmed(
+        &self,
+        filter: Filter,
+        write_relays: &[String],
+    ) -> Result<(Vec<Event>, bool)> {
+        let relays = self.own_lookup_relays(write_relays).await?;
+        let timeout = self.timeout().await?;
+        let temporary = self
+            .acquire_temporary_relays(&relays, TemporaryRelayUse::Read)
+            .await?;
+
+        let mut fetches = tokio::task::JoinSet::new();
+        for url in relays.iter() {
+            let relay = match self.client.relay(url).await {
+                Ok(relay) => relay,
+                Err(e) => {
+                    tracing::debug!(
+                        target: "whitenoise::nostr_manager::router",
+                        "Relay {} not in the pool: {}",
+                        url,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let filter = filter.clone();
+            fetches.spawn(async move {
+                let result = relay
+                    .fetch_events(filter, timeout, ReqExitPolicy::ExitOnEOSE)
+                    .await;
+                (relay.url().clone(), result)
+            });
+        }
+
+        let mut events = Vec::new();
+        let mut confirmed = false;
+        while let Some(joined) = fetches.join_next().await {
+            match joined {
+                Ok((_, Ok(fetched))) => {
+                    confirmed = true;
+                    events.extend(fetched);
+                }
+                Ok((url, Err(e))) => {
+                    tracing::debug!(
+                        target: "whitenoise::nostr_manager::router",
+                        "No EOSE from {}: {}",
+                        url,
+                        e
+                    );
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_manager::router",
+                        "Fetch task failed: {}",
+                        e
+                    );
+                }
+            }
+        }
+        self.release_temporary_relays(temporary).await;
+        Ok((events, confirmed))
+    }
+
+    /// Where to look for our own events: our write relays, the indexers and our default relays
+    async fn own_lookup_relays(&self, write_relays: &[String]) -> Result<Vec<RelayUrl>> {
+        let mut relays = self.own_outbox_relays(write_relays);
+        for relay in self.default_relay_urls().await? {
+            if !relays.contains(&relay) {
+                relays.push(relay);
+            }
+        }
+        Ok(relays)
+    }
+
+    /// Sends an event to exactly these relays, adding the ones that aren't in the pool just for
+    /// this request
+    pub async fn send_event_to_relays(
+        &self,
+        relays: &[RelayUrl],
+        event: &Event,
+    ) -> Result<Output<EventId>> {
+        let temporary = self
+            .acquire_temporary_relays(relays, TemporaryRelayUse::Write)
+            .await?;
+        let result = self.client.send_event_to(relays.to_vec(), event).await;
+        self.release_temporary_relays(temporary).await;
+        Ok(result?)
+    }
+
+    /// Fetches events from exactly these relays, adding the ones that aren't in the pool just for
+    /// this request
+    pub async fn fetch_events_from_relays(
+        &self,
+        relays: &[RelayUrl],
+        filter: Filter,
+    ) -> Result<Events> {
+        let timeout = self.timeout().await?;
+        let temporary = self
+            .acquire_temporary_relays(relays, TemporaryRelayUse::Read)
+            .await?;
+        let result = self
+            .client
+            .fetch_events_from(relays.to_vec(), filter, timeout)
+            .await;
+        self.release_temporary_relays(temporary).await;
+        Ok(result?)
+    }
+
+    /// Adds the relays that aren't in the pool yet with only the flag `relay_use` needs, and
+    /// waits until they're connected. Relays other requests added temporarily are shared.
+    ///
+    /// Returns the relays to hand to `release_temporary_relays` once the request is done.
+    pub(crate) async fn acquire_temporary_relays(
+        &self,
+        relays: &[RelayUrl],
+        relay_use: TemporaryRelayUse,
+    ) -> Result<Vec<RelayUrl>> {
+        let mut acquired = Vec::new();
+        let result = {
+            let mut users = self.temporary_relays.lock().await;
+            self.add_temporary_relays(relays, relay_use, &mut users, &mut acquired)
+                .await
+        };
+        if let Err(e) = result {
+            self.release_temporary_relays(acquired).await;
+            return Err(e);
+        }
+        if let Err(e) = self.wait_for_temporary_relays(&acquired).await {
+            self.release_temporary_relays(acquired).await;
+            return Err(e);
+        }
+        Ok(acquired)
+    }
+
+    async fn add_temporary_relays(
+        &self,
+        relays: &[RelayUrl],
+        relay_use: TemporaryRelayUse,
+        users: &mut HashMap<RelayUrl, usize>,
+        acquired: &mut Vec<RelayUrl>,
+    ) -> Result<()> {
+        for relay in relays {
+            if let Some(count) = users.get_mut(relay) {
+                // Another request may have added it for something else
+                self.client
+                    .relay(relay)
+                    .await?
+                    .flags()
+                    .add(relay_use.flag());
+                *count += 1;
+                acquired.push(relay.clone());
+                continue;
+            }
+            let opts = self
+                .relay_options()?
+                .read(relay_use == TemporaryRelayUse::Read)
+                .write(relay_use == TemporaryRelayUse::Write);
+            if self.client.add_relay_with_opts(relay.clone(), opts).await? {
+                users.insert(relay.clone(), 1);
+                acquired.push(relay.clone());
+                self.client
+                    .connect_relay(relay.clone())
+                    .await
+                    .map_err(NostrManagerError::from)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until temporary relays are connected, at most for the request timeout. Relays that
+    /// don't connect in time are left to fail the request.
+    async fn wait_for_temporary_relays(&self, relays: &[RelayUrl]) -> Result<()> {
+        let timeout = self.timeout().await?;
+        let mut waits = tokio::task::JoinSet::new();
+        for url in relays {
+            let relay = self.client.relay(url).await?;
+            waits.spawn(async move { relay.wait_for_connection(timeout).await });
+        }
+        while waits.join_next().await.is_some() {}
+        Ok(())
+    }
+
+    /// Removes temporary relays from the pool once no request uses them anymore
+    pub(crate) async fn release_temporary_relays(&self, relays: Vec<RelayUrl>) {
+        let mut users = self.temporary_relays.lock().await;
+        for relay in relays {
+            let Some(count) = users.get_mut(&relay) else {
+                // It became one of our relays in the meantime
+                continue;
+            };
+            *count -= 1;
+            if *count > 0 {
+                continue;
+            }
+            users.remove(&relay);
+            if let Err(e) = self.client.remove_relay(relay.clone()).await {
+                tracing::warn!(
+                    target: "whitenoise::nostr_manager::router",
+                    "Failed to remove temporary relay {}: {}",
+                    relay,
+                    e
+                );
+            }
+        }
+    }
+
+    async fn default_relay_urls(&self) -> Result<Vec<RelayUrl>> {
+        Ok(self
+            .relays()
+            .await?
+            .iter()
+            .filter_map(|url| RelayUrl::parse(url).ok())
+            .collect())
+    }
+}
+
+fn indexer_relay_urls() -> Vec<RelayUrl> {
+    // Indexers aren't reachable from the local dev setup
+    if cfg!(dev) {
+        return Vec::new();
+    }
+    INDEXER_RELAYS
+        .iter()
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr_manager::test_relay::MockRelay;
+    use crate::nostr_manager::NostrManagerSettings;
+
+    fn relay_list_event(keys: &Keys, relays: &[&str], created_at: u64) -> Event {
+        EventBuilder::new(Kind::MlsKeyPackageRelays, "")
+            .tags(
+                relays
+                    .iter()
+                    .map(|url| Tag::custom(TagKind::Relay, [url.to_string()])),
+            )
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_relay_urls_from_event() {
+        let keys = Keys::generate();
+        let event = relay_list_event(&keys, &["wss://relay.one", "not a url"], 1);
+        assert_eq!(
+            relay_urls_from_event(&event),
+            vec![RelayUrl::parse("wss://relay.one").unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_relay_list_cache() {
+        let cache = RelayListCache::default();
+        let keys = Keys::generate();
+        let kind = Kind::MlsKeyPackageRelays;
+        let now = Timestamp::now();
+
+        let newer = relay_list_event(&keys, &["wss://relay.two"], 200);
+        let older = relay_list_event(&keys, &["wss://relay.one"], 100);
+        cache.update_from_event(&newer);
+        cache.update_from_event(&older);
+        assert_eq!(
+            cache.get(&keys.public_key(), kind, now),
+            Some(vec![RelayUrl::parse("wss://relay.two").unwrap()])
+        );
+
+        // Entries go stale after a while
+        let later = now + RELAY_LIST_TTL.as_secs() + 1;
+        assert_eq!(cache.get(&keys.public_key(), kind, later), None);
+
+        cache.clear();
+        assert_eq!(cache.get(&keys.public_key(), kind, now), None);
+    }
+
+    #[tokio::test]
+    async fn test_temporary_relays_reach_relays_outside_the_pool() {
+        let relay = MockRelay::run().await;
+        let dir = tempfile::TempDir::new().unwrap();
+        let nostr = NostrManager::new(dir.path().to_path_buf(), NostrManagerSettings::default())
+            .await
+            .unwrap();
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note("hello")
+            .sign_with_keys(&keys)
+            .unwrap();
+
+        let output = nostr
+            .send_event_to_relays(&[relay.url()], &event)
+            .await
+            .unwrap();
+        assert!(output.success.contains(&relay.url()));
+        assert_eq!(relay.events().await, vec![event.clone()]);
+        // Only added for the request
+        assert!(nostr.client.relay(relay.url()).await.is_err());
+
+        let events = nostr
+            .fetch_events_from_relays(&[relay.url()], Filter::new().author(keys.public_key()))
+            .await
+            .unwrap();
+        assert_eq!(events.into_iter().collect::<Vec<_>>(), vec![event]);
+        assert!(nostr.client.relay(relay.url()).await.is_err());
+    }
+}
@@ -1,5 +1,9 @@
+use crate::accounts::Account;
+use crate::mute_list;
+use crate::nip05;
+use crate::nostr_manager::router::relay_urls_from_event;
 use crate::nostr_manager::NostrManager;
-use crate::nostr_manager::NostrManagerError;
+use crate::nostr_manager::{NostrManagerError, Result};
 use crate::types::EnrichedContact;
 use crate::whitenoise::Whitenoise;
 use nostr_sdk::prelude::*;
@@ -7,29 +11,30 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 impl NostrManager {
+    /// Searches relays that support NIP-50 for metadata events, merged with the matching
+    /// events we have locally
+    pub async fn search_metadata_events(&self, query: String) -> Result<Vec<Event>> {
+        let filter = Filter::new().kind(Kind::Metadata).search(query);
+        let stored_events = self.client.database().query(filter.clone()).await?;
+        let fetched_events = self
+            .client
+            .fetch_events(filter, self.timeout().await?)
+            .await?;
+        Ok(stored_events.merge(fetched_events).into_iter().collect())
+    }
+
     pub async fn search_users(
         &self,
         query: String,
         wn: Arc<Whitenoise>,
     ) -> Result<HashMap<String, EnrichedContact>> {
-        let filter = Filter::new().kind(Kind::Metadata).search(query);
-
-        let stored_events = wn
-            .nostr
-            .client
-            .database()
-            .query(filter.clone())
-            .await
-            .map_err(NostrManagerError::from)?;
-
-        let fetched_events = wn
-            .nostr
-            .client
-            .fetch_events(filter.clone(), wn.nostr.timeout().await.unwrap())
-            .await
-            .map_err(NostrManagerError::from)?;
-
-        let users = stored_events.merge(fetched_events);
+        let mut users = wn.nostr.search_metadata_events(query).await?;
+        // Muted people don't show up in search results
+        if let Ok(account_pubkey) = Account::get_active_pubkey(wn.clone()).await {
+            if let Ok(muted) = mute_list::muted_pubkey_set(&account_pubkey, &wn.database).await {
+                users.retain(|user| !muted.contains(&user.pubkey));
+            }
+        }
 
         let pubkeys: Vec<PublicKey> = users
             .iter()
@@ -41,29 +46,48 @@ impl NostrManager {
             .client
             .fetch_events(
                 Filter::new().authors(pubkeys).kinds(vec![
+                    Kind::RelayList,
                     Kind::MlsKeyPackageRelays,
                     Kind::InboxRelays,
                     Kind::MlsKeyPackage,
                 ]),
-                wn.nostr.timeout().await.unwrap(),
+                wn.nostr.timeout().await?,
             )
             .await
             .map_err(NostrManagerError::from)?;
 
         let mut enriched_contacts = HashMap::new();
 
+        let relays_of = |pubkey: PublicKey, kind: Kind| -> Vec<String> {
+            enriching_events
+                .iter()
+                .filter(|event| event.kind == kind && event.pubkey == pubkey)
+                .max_by_key(|event| event.created_at)
+                .map(|event| {
+                    relay_urls_from_event(event)
+                        .iter()
+                        .map(|url| url.to_string())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
         for user in users {
+            let metadata = Metadata::from_json(&user.content).unwrap_or_default();
+            let nip05_status = nip05::cached_status(&user.pubkey, &metadata, &wn.database).await;
             let enriched_contact = EnrichedContact {
-                metadata: Metadata::from_json(&user.content).unwrap_or_default(),
+                metadata,
+                nip05_status,
                 nip17: enriching_events
                     .iter()
                     .any(|event| event.kind == Kind::InboxRelays && event.pubkey == user.pubkey),
                 nip104: enriching_events
                     .iter()
                     .any(|event| event.kind == Kind::MlsKeyPackage && event.pubkey == user.pubkey),
-                nostr_relays: Vec::new(), // For now, we don't care about these since we're only searching in the context of finding a person to start a conversation with. We'll fetch all their data later.
-                inbox_relays: Vec::new(), // For now, we don't care about these
-                key_package_relays: Vec::new(), // For now, we don't care about these
+                nostr_relays: relays_of(user.pubkey, Kind::RelayList),
+                inbox_relays: relays_of(user.pubkey, Kind::InboxRelays),
+                key_package_relays: relays_of(user.pubkey, Kind::MlsKeyPackageRelays),
+                annotation: None,
             };
             enriched_contacts.insert(user.pubkey.to_hex(), enriched_contact);
         }
@@ -0,0 +1,340 @@
+//! Persisted settings for NostrManager
+//! Settings can be stored globally and overridden per account. The settings in effect are the
+//! active account's if it has any, then the global ones, then the defaults. Changes are applied
+//! to the running client and Blossom client right away.
+
+use crate::accounts::Account;
+use crate::database::{Database, DatabaseError};
+use crate::media::blossom::BlossomClient;
+use crate::nostr_manager::{NostrManager, NostrManagerError, NostrManagerSettings, Result};
+use crate::relays::{normalize_relay_urls, RelayType};
+use crate::whitenoise::Whitenoise;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Bounds for the timeout of relay requests
+const MIN_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// (De)serializes a duration as whole seconds
+pub(crate) mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_secs())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_secs(u64::deserialize(deserializer)?))
+    }
+}
+
+/// Who settings apply to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SettingsScope {
+    Global,
+    Account(PublicKey),
+}
+
+impl SettingsScope {
+    fn key(&self) -> String {
+        match self {
+            Self::Global => "global".to_string(),
+            Self::Account(pubkey) => pubkey.to_hex(),
+        }
+    }
+}
+
+impl NostrManagerSettings {
+    /// Checks the settings and normalizes the relay URLs
+    pub fn validated(mut self) -> Result<Self> {
+        if self.timeout < MIN_TIMEOUT || self.timeout > MAX_TIMEOUT {
+            return Err(NostrManagerError::InvalidSettings(format!(
+                "Timeout must be between {} and {} seconds",
+                MIN_TIMEOUT.as_secs(),
+                MAX_TIMEOUT.as_secs()
+            )));
+        }
+
+        self.relays = normalize_relay_urls(&self.relays).map_err(invalid_relay)?;
+        self.dev_relays = normalize_relay_urls(&self.dev_relays).map_err(invalid_relay)?;
+        if self.active_relays().is_empty() {
+            return Err(NostrManagerError::InvalidSettings(
+                "At least one relay is required".to_string(),
+            ));
+        }
+
+        if self.blossom_servers.is_empty() {
+            return Err(NostrManagerError::InvalidSettings(
+                "At least one Blossom server is required".to_string(),
+            ));
+        }
+        for server in self.blossom_servers.iter_mut() {
+            let url = Url::parse(server.trim()).map_err(|_| {
+                NostrManagerError::InvalidSettings(format!("Invalid Blossom server: {}", server))
+            })?;
+            if url.scheme() != "https" && url.scheme() != "http" {
+                return Err(NostrManagerError::InvalidSettings(format!(
+                    "Invalid Blossom server: {}",
+                    server
+                )));
+            }
+            *server = url.as_str().trim_end_matches('/').to_string();
+        }
+        Ok(self)
+    }
+}
+
+fn invalid_relay(url: String) -> NostrManagerError {
+    NostrManagerError::InvalidSettings(format!("Invalid relay URL: {}", url))
+}
+
+/// Loads the settings stored for a scope
+pub async fn load_settings(
+    scope: SettingsScope,
+    db: &Database,
+) -> Result<Option<NostrManagerSettings>> {
+    let settings =
+        sqlx::query_scalar::<_, String>("SELECT settings FROM nostr_settings WHERE scope = ?")
+            .bind(scope.key())
+            .fetch_optional(&db.pool)
+            .await
+            .map_err(DatabaseError::from)?;
+    settings
+        .map(|settings| {
+            serde_json::from_str(&settings).map_err(|e| {
+                NostrManagerError::InvalidSettings(format!(
+                    "Stored settings for {} are corrupt: {}",
+                    scope.key(),
+                    e
+                ))
+            })
+        })
+        .transpose()
+}
+
+/// Stores the settings for a scope
+pub async fn save_settings(
+    scope: SettingsScope,
+    settings: &NostrManagerSettings,
+    db: &Database,
+) -> Result<()> {
+    let json = serde_json::to_string(settings)
+        .map_err(|e| NostrManagerError::InvalidSettings(e.to_string()))?;
+    sqlx::query(
+        "INSERT OR REPLACE INTO nostr_settings (scope, settings, updated_at) VALUES (?, ?, ?)",
+    )
+    .bind(scope.key())
+    .bind(json)
+    .bind(Timestamp::now().as_u64() as i64)
+    .execute(&db.pool)
+    .await
+    .map_err(DatabaseError::from)?;
+    Ok(())
+}
+
+/// Forgets the settings of a scope, so that the next scope up applies
+pub async fn delete_settings(scope: SettingsScope, db: &Database) -> Result<()> {
+    sqlx::query("DELETE FROM nostr_settings WHERE scope = ?")
+        .bind(scope.key())
+        .execute(&db.pool)
+        .await
+        .map_err(DatabaseError::from)?;
+    Ok(())
+}
+
+/// Returns the settings in effect for an account, or globally without one
+pub async fn effective_settings(
+    account_pubkey: Option<&PublicKey>,
+    db: &Database,
+) -> Result<NostrManagerSettings> {
+    if let Some(pubkey) = account_pubkey {
+        if let Some(settings) = load_settings(SettingsScope::Account(*pubkey), db).await? {
+            return Ok(settings);
+        }
+    }
+    Ok(load_settings(SettingsScope::Global, db)
+        .await?
+        .unwrap_or_default())
+}
+
+impl NostrManager {
+    /// Switches to new settings.
+    ///
+    /// The Blossom client is replaced, new default relays are connected and default relays that
+    /// were dropped are disconnected, unless they're in `keep` (e.g. the account's own relays).
+    /// If a new relay can't be added the current settings stay in effect.
+    pub async fn apply_settings(
+        &self,
+        settings: NostrManagerSettings,
+        keep: &[String],
+    ) -> Result<()> {
+        let settings = settings.validated()?;
+
+        let mut added = Vec::new();
+        for relay in settings.active_relays() {
+            match self.add_relay(relay.as_str()).await {
+                Ok(true) => added.push(relay.clone()),
+                Ok(false) => {}
+                Err(e) => {
+                    for relay in added {
+                        let _ = self.client.remove_relay(relay.as_str()).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        for relay in added.iter() {
+            self.client.connect_relay(relay.as_str()).await?;
+        }
+
+        let previous = {
+            let mut guard = self.settings.lock().await;
+            std::mem::replace(&mut *guard, settings.clone())
+        };
+        self.set_blossom(
+            BlossomClient::new(&settings.blossom_server()).with_proxy(self.proxy_state()),
+        );
+
+        for relay in previous.active_relays() {
+            if !settings.active_relays().contains(relay) && !keep.contains(relay) {
+                if let Err(e) = self.client.remove_relay(relay.as_str()).await {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_manager::settings",
+                        "Failed to remove relay {}: {}",
+                        relay,
+                        e
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the settings in effect for the active account, e.g. after they were edited
+pub async fn reload_settings(wn: Arc<Whitenoise>) -> Result<NostrManagerSettings> {
+    let account = Account::get_active(wn.clone()).await.ok();
+    let settings = effective_settings(account.as_ref().map(|a| &a.pubkey), &wn.database).await?;
+
+    let mut keep = Vec::new();
+    if let Some(account) = account {
+        for relay_type in [RelayType::Nostr, RelayType::Inbox, RelayType::KeyPackage] {
+            keep.extend(
+                account
+                    .relays(relay_type, wn.clone())
+                    .await
+                    .map_err(|e| NostrManagerError::AccountError(e.to_string()))?,
+            );
+        }
+    }
+
+    wn.nostr.apply_settings(settings.clone(), &keep).await?;
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::setup_test_db;
+
+    #[test]
+    fn test_settings_serialization() {
+        let settings = NostrManagerSettings::default();
+        let json = serde_json::to_value(&settings).unwrap();
+        assert_eq!(json["timeout_secs"], 3);
+        let parsed: NostrManagerSettings = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, settings);
+    }
+
+    #[test]
+    fn test_validated() {
+        let settings = NostrManagerSettings {
+            relays: vec!["wss://nos.lol".to_string(), " wss://nos.lol ".to_string()],
+            dev_relays: vec!["ws://localhost:8080".to_string()],
+            blossom_servers: vec!["https://blossom.example.com/".to_string()],
+            ..Default::default()
+        }
+        .validated()
+        .unwrap();
+        assert_eq!(settings.relays.len(), 1);
+        assert_eq!(
+            settings.blossom_servers,
+            vec!["https://blossom.example.com"]
+        );
+
+        let too_slow = NostrManagerSettings {
+            timeout: Duration::from_secs(600),
+            ..Default::default()
+        };
+        assert!(too_slow.validated().is_err());
+
+        let no_blossom = NostrManagerSettings {
+            blossom_servers: vec!["ftp://files.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(no_blossom.validated().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_effective_settings() {
+        let (db, _temp_dir) = setup_test_db().await;
+        let pubkey = Keys::generate().public_key();
+        assert_eq!(
+            effective_settings(Some(&pubkey), &db).await.unwrap(),
+            NostrManagerSettings::default()
+        );
+
+        let global = NostrManagerSettings {
+            timeout: Duration::from_secs(5),
+            ..Default::default()
+        };
+        save_settings(SettingsScope::Global, &global, &db)
+            .await
+            .unwrap();
+        assert_eq!(
+            effective_settings(Some(&pubkey), &db).await.unwrap(),
+            global
+        );
+
+        let account = NostrManagerSettings {
+            timeout: Duration::from_secs(10),
+            ..Default::default()
+        };
+        save_settings(SettingsScope::Account(pubkey), &account, &db)
+            .await
+            .unwrap();
+        assert_eq!(
+            effective_settings(Some(&pubkey), &db).await.unwrap(),
+            account
+        );
+        assert_eq!(effective_settings(None, &db).await.unwrap(), global);
+
+        delete_settings(SettingsScope::Account(pubkey), &db)
+            .await
+            .unwrap();
+        assert_eq!(
+            effective_settings(Some(&pubkey), &db).await.unwrap(),
+            global
+        );
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_settings() {
+        let (db, _temp_dir) = setup_test_db().await;
+        sqlx::query("INSERT INTO nostr_settings (scope, settings, updated_at) VALUES (?, ?, ?)")
+            .bind(SettingsScope::Global.key())
+            .bind("not json")
+            .bind(0i64)
+            .execute(&db.pool)
+            .await
+            .unwrap();
+        assert!(matches!(
+            load_settings(SettingsScope::Global, &db).await,
+            Err(NostrManagerError::InvalidSettings(_))
+        ));
+        assert!(effective_settings(None, &db).await.is_err());
+    }
+}
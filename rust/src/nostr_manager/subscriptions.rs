@@ -1,8 +1,14 @@
 //! Subscription functions for NostrManager
 //! This mostly handles subscribing and processing events as they come in while the user is active.
 
+use crate::contact_annotations;
+use crate::contacts;
+use crate::mute_list;
+use crate::nostr_manager::auth::{self, AuthRetry};
 use crate::nostr_manager::event_processor::ProcessableEvent;
 use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::runtime::wn;
+use crate::typing_indicators::{self, TYPING_INDICATOR_KIND};
 use nostr_sdk::prelude::*;
 
 const MLS_MESSAGES_SUB: &str = "mls_messages";
@@ -62,13 +68,6 @@ impl NostrManager {
     }
 
     async fn subscribe_giftwraps(&self, pubkey: PublicKey) -> Result<Output<SubscriptionId>> {
-        // This is a hack to get the client to do the initial authenticate on relays that require it.
-        // https://github.com/rust-nostr/nostr/issues/509
-        let null_filter = Filter::new().kind(Kind::GiftWrap).pubkey(pubkey).limit(0);
-        self.client
-            .fetch_events(null_filter, self.timeout().await?)
-            .await?;
-
         let giftwrap_filter = Filter::new()
             .kind(Kind::GiftWrap)
             .pubkey(pubkey)
@@ -80,7 +79,7 @@ impl NostrManager {
     pub async fn subscribe_mls_group_messages(&self, group_ids: Vec<String>) -> Result<Output<()>> {
         let sub_id = SubscriptionId::new(MLS_MESSAGES_SUB);
         let mls_message_filter = Filter::new()
-            .kind(Kind::MlsGroupMessage)
+            .kinds([Kind::MlsGroupMessage, Kind::Custom(TYPING_INDICATOR_KIND)])
             .custom_tags(SingleLetterTag::lowercase(Alphabet::H), group_ids)
             .since(Timestamp::now());
 
@@ -115,7 +114,7 @@ impl NostrManager {
                         Ok(false)
                     }
                     RelayPoolNotification::Message { relay_url, message } => {
-                        self.handle_message(relay_url, message)?;
+                        self.handle_message(relay_url, message).await?;
                         Ok(false)
                     }
                     RelayPoolNotification::Shutdown => {
@@ -160,30 +159,136 @@ impl NostrManager {
                     .await
                     .map_err(|e| NostrManagerError::FailedToQueueEvent(e.to_string()))?;
             }
+            Kind::Custom(TYPING_INDICATOR_KIND) => {
+                if let Err(e) = typing_indicators::receive_indicator(&event, wn()).await {
+                    tracing::debug!(
+                        target: "whitenoise::nostr_client::subscriptions::handle_event",
+                        "Ignoring typing indicator: {}",
+                        e
+                    );
+                }
+            }
+            Kind::RelayList | Kind::InboxRelays | Kind::MlsKeyPackageRelays => {
+                self.relay_lists.update_from_event(&event);
+            }
+            Kind::Metadata => {
+                if let Err(e) = contacts::update_metadata(&event, &wn().database).await {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_client::subscriptions::handle_event",
+                        "Failed to update contact directory: {}",
+                        e
+                    );
+                }
+            }
+            Kind::MuteList => {
+                if let Err(e) = mute_list::apply_mute_list(&event, wn()).await {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_client::subscriptions::handle_event",
+                        "Failed to apply mute list: {}",
+                        e
+                    );
+                }
+            }
+            Kind::ApplicationSpecificData => {
+                if let Err(e) = contact_annotations::apply_annotations_event(&event, wn()).await {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_client::subscriptions::handle_event",
+                        "Failed to apply contact annotations: {}",
+                        e
+                    );
+                }
+            }
+            Kind::ContactList => {
+                if let Err(e) = contacts::apply_contact_list(&event, &wn().database).await {
+                    tracing::warn!(
+                        target: "whitenoise::nostr_client::subscriptions::handle_event",
+                        "Failed to update contact directory: {}",
+                        e
+                    );
+                }
+            }
             _ => {}
         }
         Ok(())
     }
 
     // Handle other types of notifications
-    fn handle_message(&self, relay_url: RelayUrl, message: RelayMessage) -> Result<()> {
-        let variant_name = match message {
-            RelayMessage::Event { .. } => "Event",
-            RelayMessage::Ok { .. } => "Ok",
-            RelayMessage::Notice { .. } => "Notice",
-            RelayMessage::Closed { .. } => "Closed",
-            RelayMessage::EndOfStoredEvents(_) => "EndOfStoredEvents",
-            RelayMessage::Auth { .. } => "Auth",
-            RelayMessage::Count { .. } => "Count",
-            RelayMessage::NegMsg { .. } => "NegMsg",
-            RelayMessage::NegErr { .. } => "NegErr",
+    async fn handle_message(&self, relay_url: RelayUrl, message: RelayMessage) -> Result<()> {
+        match &message {
+            RelayMessage::Notice(notice) => {
+                tracing::info!(
+                    target: "whitenoise::nostr_client::handle_notifications",
+                    "Notice from {}: {}",
+                    relay_url,
+                    notice
+                );
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } => {
+                tracing::warn!(
+                    target: "whitenoise::nostr_client::handle_notifications",
+                    "{} closed subscription {}: {}",
+                    relay_url,
+                    subscription_id,
+                    message
+                );
+            }
+            RelayMessage::Ok {
+                event_id,
+                status: false,
+                message,
+            } => {
+                tracing::warn!(
+                    target: "whitenoise::nostr_client::handle_notifications",
+                    "{} rejected event {}: {}",
+                    relay_url,
+                    event_id,
+                    message
+                );
+            }
+            _ => {}
+        }
+        self.relay_health
+            .record(&relay_url, &message, Timestamp::now());
+
+        // Auth failures shouldn't take down the notification loop
+        let auth_result = match &message {
+            RelayMessage::Auth { challenge } => {
+                self.handle_auth_challenge(&relay_url, challenge).await
+            }
+            RelayMessage::Closed {
+                subscription_id,
+                message,
+            } if auth::is_auth_required(message) => {
+                self.queue_auth_retry(
+                    &relay_url,
+                    AuthRetry::Subscription(SubscriptionId::clone(subscription_id)),
+                );
+                Ok(())
+            }
+            RelayMessage::Ok {
+                event_id,
+                status,
+                message,
+            } => {
+                if !status && auth::is_auth_required(message) {
+                    self.queue_auth_retry(&relay_url, AuthRetry::Event(*event_id));
+                }
+                self.handle_event_answer(&relay_url, event_id, *status)
+                    .await
+            }
+            _ => Ok(()),
         };
-        tracing::debug!(
-            target: "whitenoise::nostr_client::handle_notifications",
-            "Received message from {}: {}",
-            relay_url,
-            variant_name
-        );
+        if let Err(e) = auth_result {
+            tracing::error!(
+                target: "whitenoise::nostr_client::handle_notifications",
+                "Error handling authentication with {}: {}",
+                relay_url,
+                e
+            );
+        }
         Ok(())
     }
 
@@ -192,6 +297,10 @@ impl NostrManager {
             target: "whitenoise::nostr_client::handle_notifications",
             "Relay pool shutdown"
         );
+        self.relay_health.clear();
+        self.auth_retries.clear();
+        self.relay_lists.clear();
+        self.sync_state.clear();
         Ok(())
     }
 }
@@ -1,356 +1,412 @@
-//! Negentropy syncing functions for NostrManager
-//! Negentropy is a fast/efficient way to fetch only the events that we don't have.
-//! It's currently only suppoted by strfry relays so this is not used as extensively as it will be in the future.
+//! Syncing functions for NostrManager
+//! Negentropy (NIP-77) is a fast/efficient way to fetch only the events that we don't have, but
+//! not every relay supports it. Each relay is probed once (its NIP-11 document, then the first
+//! negentropy attempt) and relays that don't support it are synced with plain `since` based REQs.
+//! Results are kept per relay and per category so partial failures are visible.
 
-#![allow(unused)]
-
-use crate::nostr_manager::{NostrManager, NostrManagerError, Result};
+use crate::contact_annotations;
+use crate::nostr_manager::{NostrManager, Result};
 use nostr_sdk::prelude::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// The NIP number of negentropy syncing
+const NEGENTROPY_NIP: u16 = 77;
+
+/// What is being synced
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncCategory {
+    Metadata,
+    Contacts,
+    ContactsMetadata,
+    MuteList,
+    ContactAnnotations,
+    Relays,
+    InboxRelays,
+    KeyPackageRelays,
+    KeyPackages,
+    GiftWraps,
+    GroupMessages,
+}
+
+/// How a relay was synced
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncMethod {
+    Negentropy,
+    Req,
+}
+
+/// The outcome of syncing one category with one relay
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct RelaySyncResult {
+    pub relay_url: String,
+    pub category: SyncCategory,
+    pub method: SyncMethod,
+    /// Number of events received from the relay
+    pub received: usize,
+    pub error: Option<String>,
+}
+
+/// The outcome of a full sync
+#[derive(Debug, Clone, Default, Serialize, PartialEq, Eq)]
+pub struct SyncReport {
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub results: Vec<RelaySyncResult>,
+}
+
+impl SyncReport {
+    /// Results that failed, with both negentropy and REQ
+    pub fn failures(&self) -> Vec<&RelaySyncResult> {
+        self.results.iter().filter(|r| r.error.is_some()).collect()
+    }
+
+    /// Whether every category was synced with every relay
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|r| r.error.is_none())
+    }
+}
+
+/// What we learned about relays while syncing
+#[derive(Debug, Default)]
+pub struct SyncState {
+    /// Whether a relay supports negentropy, relays we haven't probed yet are missing
+    negentropy: Mutex<HashMap<RelayUrl, bool>>,
+    last_report: Mutex<Option<SyncReport>>,
+}
+
+impl SyncState {
+    fn negentropy_support(&self, relay_url: &RelayUrl) -> Option<bool> {
+        self.negentropy
+            .lock()
+            .expect("Sync state lock poisoned")
+            .get(relay_url)
+            .copied()
+    }
+
+    fn set_negentropy_support(&self, relay_url: &RelayUrl, supported: bool) {
+        self.negentropy
+            .lock()
+            .expect("Sync state lock poisoned")
+            .insert(relay_url.clone(), supported);
+    }
+
+    pub fn last_report(&self) -> Option<SyncReport> {
+        self.last_report
+            .lock()
+            .expect("Sync state lock poisoned")
+            .clone()
+    }
+
+    fn set_last_report(&self, report: SyncReport) {
+        *self.last_report.lock().expect("Sync state lock poisoned") = Some(report);
+    }
+
+    pub fn clear(&self) {
+        self.negentropy
+            .lock()
+            .expect("Sync state lock poisoned")
+            .clear();
+        *self.last_report.lock().expect("Sync state lock poisoned") = None;
+    }
+}
+
+/// Whether a negentropy error means the relay doesn't speak negentropy at all, as opposed to
+/// a timeout or a dropped connection
+fn is_negentropy_unsupported(error: &str) -> bool {
+    let error = error.to_lowercase();
+    error.contains("neg-err")
+        || error.contains("negentropy")
+        || error.contains("unknown cmd")
+        || error.contains("unknown message type")
+}
 
 impl NostrManager {
+    /// Syncs everything we need for a user with every relay in the pool.
+    ///
+    /// Failures with individual relays don't fail the sync, they're reported in the returned
+    /// [`SyncReport`], which is also kept around for the `get_sync_report` command.
     pub async fn sync_for_user(
         &self,
         pubkey: PublicKey,
         last_synced: Timestamp,
         group_ids: Vec<String>,
-    ) -> Result<()> {
-        self.sync_user_metadata(pubkey, last_synced).await?;
-        self.sync_contacts(last_synced).await?;
-        self.sync_contacts_metadata(last_synced).await?;
-        self.sync_user_relays(pubkey, last_synced).await?;
-        self.sync_user_inbox_relays(pubkey, last_synced).await?;
-        self.sync_user_key_package_relays(pubkey, last_synced)
-            .await?;
-        self.sync_user_key_packages(pubkey, last_synced).await?;
-        self.sync_user_giftwrapped_events(pubkey, last_synced)
-            .await?;
-        self.sync_group_messages(last_synced, group_ids).await?;
-        Ok(())
-    }
+    ) -> Result<SyncReport> {
+        let started_at = Timestamp::now();
+        let until = Timestamp::now();
+        let relays: Vec<RelayUrl> = self.client.relays().await.into_keys().collect();
 
-    pub async fn sync_user_metadata(
-        &self,
-        pubkey: PublicKey,
-        last_synced: Timestamp,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .kind(Kind::Metadata)
-            .author(pubkey)
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_metadata",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_metadata",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_metadata",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
-    }
+        let mut filters = vec![
+            (
+                SyncCategory::Metadata,
+                Filter::new().kind(Kind::Metadata).author(pubkey),
+            ),
+            (
+                SyncCategory::Contacts,
+                Filter::new().kind(Kind::ContactList).author(pubkey),
+            ),
+            (
+                SyncCategory::MuteList,
+                Filter::new().kind(Kind::MuteList).author(pubkey),
+            ),
+            (
+                SyncCategory::ContactAnnotations,
+                Filter::new()
+                    .kind(Kind::ApplicationSpecificData)
+                    .author(pubkey)
+                    .identifier(contact_annotations::APP_DATA_IDENTIFIER),
+            ),
+            (
+                SyncCategory::Relays,
+                Filter::new().kind(Kind::RelayList).author(pubkey),
+            ),
+            (
+                SyncCategory::InboxRelays,
+                Filter::new().kind(Kind::InboxRelays).author(pubkey),
+            ),
+            (
+                SyncCategory::KeyPackageRelays,
+                Filter::new().kind(Kind::MlsKeyPackageRelays).author(pubkey),
+            ),
+            (
+                SyncCategory::KeyPackages,
+                Filter::new().kind(Kind::MlsKeyPackage).author(pubkey),
+            ),
+            (
+                SyncCategory::GiftWraps,
+                Filter::new().kind(Kind::GiftWrap).pubkey(pubkey),
+            ),
+        ];
+        if !group_ids.is_empty() {
+            filters.push((
+                SyncCategory::GroupMessages,
+                Filter::new()
+                    .kind(Kind::MlsGroupMessage)
+                    .custom_tags(SingleLetterTag::lowercase(Alphabet::H), group_ids),
+            ));
+        }
+        let filters: Vec<(SyncCategory, Filter)> = filters
+            .into_iter()
+            .map(|(category, filter)| (category, filter.since(last_synced).until(until)))
+            .collect();
 
-    async fn sync_user_relays(&self, pubkey: PublicKey, last_synced: Timestamp) -> Result<()> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::RelayList)
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_relays",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_relays",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_relays",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+        let mut results = self.sync_with_relays(&relays, filters).await;
+
+        // Contacts metadata depends on the contact list we just synced
+        let contacts_pubkeys = self.query_contact_list_pubkeys().await?;
+        if !contacts_pubkeys.is_empty() {
+            let filter = Filter::new()
+                .kind(Kind::Metadata)
+                .authors(contacts_pubkeys)
+                .since(last_synced)
+                .until(until);
+            results.extend(
+                self.sync_with_relays(&relays, vec![(SyncCategory::ContactsMetadata, filter)])
+                    .await,
+            );
+        }
+
+        let report = SyncReport {
+            started_at: started_at.as_u64(),
+            finished_at: Timestamp::now().as_u64(),
+            results,
+        };
+        for failure in report.failures() {
+            tracing::warn!(
+                target: "whitenoise::nostr_manager::sync",
+                "Failed to sync {:?} with {}: {}",
+                failure.category,
+                failure.relay_url,
+                failure.error.as_deref().unwrap_or_default()
+            );
+        }
+        self.sync_state.set_last_report(report.clone());
+        Ok(report)
     }
 
-    async fn sync_user_inbox_relays(
+    /// Syncs the filters with every relay, each relay in its own task
+    async fn sync_with_relays(
         &self,
-        pubkey: PublicKey,
-        last_synced: Timestamp,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::InboxRelays)
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_inbox_relays",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_inbox_relays",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_inbox_relays",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+        relays: &[RelayUrl],
+        filters: Vec<(SyncCategory, Filter)>,
+    ) -> Vec<RelaySyncResult> {
+        let handles: Vec<_> = relays
+            .iter()
+            .map(|relay_url| {
+                let manager = self.clone();
+                let relay_url = relay_url.clone();
+                let filters = filters.clone();
+                tokio::spawn(async move {
+                    let mut results = Vec::with_capacity(filters.len());
+                    for (category, filter) in filters {
+                        results.push(manager.sync_category(&relay_url, category, filter).await);
+                    }
+                    results
+                })
+            })
+            .collect();
+
+        let mut results = Vec::new();
+        for handle in handles {
+            match handle.await {
+                Ok(relay_results) => results.extend(relay_results),
+                Err(e) => tracing::error!(
+                    target: "whitenoise::nostr_manager::sync",
+                    "Sync task failed: {}",
+                    e
+                ),
+            }
+        }
+        results
     }
 
-    async fn sync_user_key_package_relays(
+    /// Syncs one category with one relay, with negentropy if the relay supports it and with a
+    /// REQ otherwise
+    async fn sync_category(
         &self,
-        pubkey: PublicKey,
-        last_synced: Timestamp,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::MlsKeyPackageRelays)
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_package_relays",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_package_relays",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_package_relays",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+        relay_url: &RelayUrl,
+        category: SyncCategory,
+        filter: Filter,
+    ) -> RelaySyncResult {
+        if self.supports_negentropy(relay_url).await != Some(false) {
+            let error = match self
+                .client
+                .sync_with([relay_url.clone()], filter.clone(), &SyncOptions::default())
+                .await
+            {
+                Ok(output) if output.success.contains(relay_url) => {
+                    self.sync_state.set_negentropy_support(relay_url, true);
+                    return RelaySyncResult {
+                        relay_url: relay_url.to_string(),
+                        category,
+                        method: SyncMethod::Negentropy,
+                        received: output.val.received.len(),
+                        error: None,
+                    };
+                }
+                Ok(output) => output
+                    .failed
+                    .get(relay_url)
+                    .cloned()
+                    .unwrap_or_else(|| "Relay didn't respond".to_string()),
+                Err(e) => e.to_string(),
+            };
+
+            if is_negentropy_unsupported(&error) {
+                self.sync_state.set_negentropy_support(relay_url, false);
+            }
+            tracing::debug!(
+                target: "whitenoise::nostr_manager::sync",
+                "Negentropy sync of {:?} with {} failed, falling back to REQ: {}",
+                category,
+                relay_url,
+                error
+            );
+        }
+
+        let result = match self.timeout().await {
+            Ok(timeout) => self
+                .client
+                .fetch_events_from([relay_url.clone()], filter, timeout)
+                .await
+                .map(|events| events.len())
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        RelaySyncResult {
+            relay_url: relay_url.to_string(),
+            category,
+            method: SyncMethod::Req,
+            received: result.as_ref().copied().unwrap_or_default(),
+            error: result.err(),
+        }
     }
 
-    async fn sync_user_key_packages(
-        &self,
-        pubkey: PublicKey,
-        last_synced: Timestamp,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .author(pubkey)
-            .kind(Kind::MlsKeyPackage)
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_packages",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_packages",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_key_packages",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+    /// Whether a relay supports negentropy, `None` if we don't know yet.
+    ///
+    /// Relays that advertise NIP-77 in their NIP-11 document are assumed to support it, for the
+    /// others we find out on the first attempt.
+    async fn supports_negentropy(&self, relay_url: &RelayUrl) -> Option<bool> {
+        if let Some(supported) = self.sync_state.negentropy_support(relay_url) {
+            return Some(supported);
+        }
+
+        match self.relay_information(relay_url, false).await {
+            Ok(info) if info.supports_nip(NEGENTROPY_NIP) => {
+                self.sync_state.set_negentropy_support(relay_url, true);
+                Some(true)
+            }
+            _ => None,
+        }
     }
 
-    async fn sync_contacts(&self, last_synced: Timestamp) -> Result<()> {
-        let pubkey = self
-            .client
-            .signer()
-            .await
-            .map_err(NostrManagerError::Client)?
-            .get_public_key()
-            .await
-            .map_err(NostrManagerError::Signer)?;
-
-        let filter = Filter::new()
-            .kind(Kind::ContactList)
-            .author(pubkey)
-            .since(last_synced)
-            .until(Timestamp::now());
-
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+    /// Returns the report of the last sync, if there was one
+    pub fn last_sync_report(&self) -> Option<SyncReport> {
+        self.sync_state.last_report()
     }
+}
 
-    async fn sync_contacts_metadata(&self, last_synced: Timestamp) -> Result<()> {
-        let contacts_pubkeys = self.query_contact_list_pubkeys().await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let filter = Filter::new()
-            .kind(Kind::Metadata)
-            .authors(contacts_pubkeys)
-            .since(last_synced)
-            .until(Timestamp::now());
-
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts_metadata",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts_metadata",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_contacts_metadata",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+    #[test]
+    fn test_is_negentropy_unsupported() {
+        assert!(is_negentropy_unsupported(
+            "NEG-ERR: negentropy disabled on this relay"
+        ));
+        assert!(is_negentropy_unsupported("negentropy not supported"));
+        assert!(is_negentropy_unsupported("ERROR: bad msg: unknown cmd"));
+        assert!(!is_negentropy_unsupported("timeout"));
+        assert!(!is_negentropy_unsupported("relay not connected"));
     }
 
-    async fn sync_group_messages(
-        &self,
-        last_synced: Timestamp,
-        group_ids: Vec<String>,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .kind(Kind::MlsGroupMessage)
-            .custom_tags(SingleLetterTag::lowercase(Alphabet::H), group_ids)
-            .since(last_synced)
-            .until(Timestamp::now());
-
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_group_messages",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_group_messages",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_group_messages",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+    #[test]
+    fn test_sync_report_failures() {
+        let ok = RelaySyncResult {
+            relay_url: "wss://relay.one".to_string(),
+            category: SyncCategory::Metadata,
+            method: SyncMethod::Negentropy,
+            received: 1,
+            error: None,
+        };
+        let failed = RelaySyncResult {
+            relay_url: "wss://relay.two".to_string(),
+            category: SyncCategory::GiftWraps,
+            method: SyncMethod::Req,
+            received: 0,
+            error: Some("timeout".to_string()),
+        };
+
+        let report = SyncReport {
+            started_at: 0,
+            finished_at: 1,
+            results: vec![ok.clone()],
+        };
+        assert!(report.is_complete());
+
+        let report = SyncReport {
+            started_at: 0,
+            finished_at: 1,
+            results: vec![ok, failed.clone()],
+        };
+        assert!(!report.is_complete());
+        assert_eq!(report.failures(), vec![&failed]);
     }
 
-    async fn sync_user_giftwrapped_events(
-        &self,
-        pubkey: PublicKey,
-        last_synced: Timestamp,
-    ) -> Result<()> {
-        let filter = Filter::new()
-            .kind(Kind::GiftWrap)
-            .pubkeys(vec![pubkey])
-            .since(last_synced)
-            .until(Timestamp::now());
-        let output = self
-            .client
-            .sync(filter, &nostr_sdk::SyncOptions::default())
-            .await?;
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_giftwrapped_events",
-            "SUCCESS: {:?}",
-            output.success
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_giftwrapped_events",
-            "FAILED: {:?}",
-            output.failed
-        );
-        tracing::debug!(
-            target: "whitenoise::nostr_client::sync_user_giftwrapped_events",
-            "VALUES: local: {:?}, remote: {:?}, sent: {:?}, received: {:?}, send_failed: {:?}",
-            output.val.local.len(),
-            output.val.remote.len(),
-            output.val.sent.len(),
-            output.val.received.len(),
-            output.val.send_failures.len()
-        );
-        Ok(())
+    #[test]
+    fn test_negentropy_support_is_remembered() {
+        let state = SyncState::default();
+        let relay_url = RelayUrl::parse("wss://relay.example.com").unwrap();
+        assert_eq!(state.negentropy_support(&relay_url), None);
+
+        state.set_negentropy_support(&relay_url, false);
+        assert_eq!(state.negentropy_support(&relay_url), Some(false));
+
+        state.clear();
+        assert_eq!(state.negentropy_support(&relay_url), None);
     }
 }
@@ -0,0 +1,104 @@
+//! A minimal relay for tests
+//! It keeps the events it receives and answers REQs with the ones that match, so tests can
+//! check what actually reached a relay.
+
+use futures_util::{SinkExt, StreamExt};
+use nostr_sdk::prelude::*;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// A relay listening on a local port until it's dropped
+pub(crate) struct MockRelay {
+    url: RelayUrl,
+    events: Arc<Mutex<Vec<Event>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockRelay {
+    pub(crate) async fn run() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = RelayUrl::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let task = tokio::spawn({
+            let events = events.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, events.clone()));
+                }
+            }
+        });
+        Self { url, events, task }
+    }
+
+    pub(crate) fn url(&self) -> RelayUrl {
+        self.url.clone()
+    }
+
+    /// The events the relay received, oldest first
+    pub(crate) async fn events(&self) -> Vec<Event> {
+        self.events.lock().await.clone()
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(stream: TcpStream, events: Arc<Mutex<Vec<Event>>>) {
+    let Ok(mut ws) = tokio_tungstenite::accept_async(stream).await else {
+        return;
+    };
+    while let Some(Ok(message)) = ws.next().await {
+        let Ok(Value::Array(message)) =
+            serde_json::from_str::<Value>(message.to_text().unwrap_or(""))
+        else {
+            continue;
+        };
+        for reply in handle(&message, &events).await {
+            if ws.send(Message::text(reply.to_string())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn handle(message: &[Value], events: &Mutex<Vec<Event>>) -> Vec<Value> {
+    match message.first().and_then(Value::as_str) {
+        Some("EVENT") => {
+            let Some(event) = message
+                .get(1)
+                .and_then(|event| Event::from_json(event.to_string()).ok())
+            else {
+                return Vec::new();
+            };
+            let id = event.id.to_hex();
+            events.lock().await.push(event);
+            vec![json!(["OK", id, true, ""])]
+        }
+        Some("REQ") => {
+            let Some(subscription_id) = message.get(1).and_then(Value::as_str) else {
+                return Vec::new();
+            };
+            let filters: Vec<Filter> = message[2..]
+                .iter()
+                .filter_map(|filter| Filter::from_json(filter.to_string()).ok())
+                .collect();
+            let mut replies: Vec<Value> = events
+                .lock()
+                .await
+                .iter()
+                .filter(|event| filters.iter().any(|filter| filter.match_event(event)))
+                .map(|event| json!(["EVENT", subscription_id, event]))
+                .collect();
+            replies.push(json!(["EOSE", subscription_id]));
+            replies
+        }
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,530 @@
+//! Durable outbox for outgoing events
+//!
+//! Events are persisted before they're published so that nothing gets lost when relays are
+//! unreachable. Every attempt records which relays accepted the event and why the others
+//! rejected it, and a background worker retries undelivered events with exponential backoff
+//! once at least one of their relays is connected again.
+
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::accounts::Account;
+use crate::database::Database;
+use crate::whitenoise::Whitenoise;
+
+/// After this many attempts we stop retrying an event
+pub const MAX_DELIVERY_ATTEMPTS: u32 = 12;
+
+const BASE_RETRY_DELAY_SECS: u64 = 5;
+const MAX_RETRY_DELAY_SECS: u64 = 10 * 60;
+
+/// How often the retry worker looks for events that are due
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+static RETRY_WORKER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+pub enum OutboxError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error("Event error: {0}")]
+    EventError(#[from] nostr_sdk::event::Error),
+    #[error("Invalid event id: {0}")]
+    InvalidEventId(String),
+    #[error("Outbox event not found: {0}")]
+    NotFound(String),
+}
+
+pub type Result<T> = std::result::Result<T, OutboxError>;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum DeliveryState {
+    /// No relay has accepted the event yet, we'll keep trying
+    Queued,
+    /// Some, but not all, relays accepted the event
+    PartiallySent,
+    /// Every relay accepted the event
+    Sent,
+    /// We gave up without any relay accepting the event
+    Failed,
+}
+
+impl From<String> for DeliveryState {
+    fn from(s: String) -> Self {
+        match s.to_lowercase().as_str() {
+            "queued" => Self::Queued,
+            "partially_sent" => Self::PartiallySent,
+            "sent" => Self::Sent,
+            "failed" => Self::Failed,
+            _ => panic!("Invalid delivery state: {}", s),
+        }
+    }
+}
+
+impl From<DeliveryState> for String {
+    fn from(state: DeliveryState) -> Self {
+        match state {
+            DeliveryState::Queued => "queued".to_string(),
+            DeliveryState::PartiallySent => "partially_sent".to_string(),
+            DeliveryState::Sent => "sent".to_string(),
+            DeliveryState::Failed => "failed".to_string(),
+        }
+    }
+}
+
+/// A row in the outbox_events table
+#[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
+struct OutboxRow {
+    event_id: String,
+    message_id: Option<String>,
+    account_pubkey: String,
+    event: String,             // JSON string
+    relays: String,            // JSON string
+    successful_relays: String, // JSON string
+    failed_relays: String,     // JSON string
+    state: String,
+    attempts: i64,
+    next_attempt_at: i64,
+    created_at: i64,
+    updated_at: i64,
+}
+
+/// Delivery status of an outgoing event
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryStatus {
+    pub event_id: String,
+    pub message_id: Option<String>,
+    pub state: DeliveryState,
+    /// Every relay the event should be published to
+    pub relays: Vec<String>,
+    /// The relays that accepted the event
+    pub successful_relays: Vec<String>,
+    /// The relays that rejected the event, with the last error they returned
+    pub failed_relays: HashMap<String, String>,
+    pub attempts: u32,
+    /// When the next attempt is scheduled, `None` once we're done with the event
+    pub next_attempt_at: Option<Timestamp>,
+    pub created_at: Timestamp,
+    pub updated_at: Timestamp,
+}
+
+impl TryFrom<OutboxRow> for DeliveryStatus {
+    type Error = OutboxError;
+
+    fn try_from(row: OutboxRow) -> Result<Self> {
+        let state = DeliveryState::from(row.state);
+        let attempts = row.attempts as u32;
+        Ok(Self {
+            event_id: row.event_id,
+            message_id: row.message_id,
+            state,
+            relays: serde_json::from_str(&row.relays)?,
+            successful_relays: serde_json::from_str(&row.successful_relays)?,
+            failed_relays: serde_json::from_str(&row.failed_relays)?,
+            attempts,
+            next_attempt_at: is_pending(state, attempts)
+                .then(|| Timestamp::from(row.next_attempt_at as u64)),
+            created_at: Timestamp::from(row.created_at as u64),
+            updated_at: Timestamp::from(row.updated_at as u64),
+        })
+    }
+}
+
+/// Persists a signed event so that it can be (re)published to the given relays
+pub async fn enqueue(
+    account_pubkey: &PublicKey,
+    message_id: Option<EventId>,
+    event: &Event,
+    relays: &[RelayUrl],
+    db: &Database,
+) -> Result<()> {
+    let now = Timestamp::now().as_u64() as i64;
+    let relays: Vec<String> = relays.iter().map(|r| r.to_string()).collect();
+
+    sqlx::query(
+        "INSERT OR IGNORE INTO outbox_events (
+            event_id, message_id, account_pubkey, event, relays, successful_relays,
+            failed_relays, state, attempts, next_attempt_at, created_at, updated_at
+         ) VALUES (?, ?, ?, ?, ?, '[]', '{}', ?, 0, ?, ?, ?)",
+    )
+    .bind(event.id.to_hex())
+    .bind(message_id.map(|id| id.to_hex()))
+    .bind(account_pubkey.to_hex())
+    .bind(event.as_json())
+    .bind(serde_json::to_string(&relays)?)
+    .bind(String::from(DeliveryState::Queued))
+    .bind(now)
+    .bind(now)
+    .bind(now)
+    .execute(&db.pool)
+    .await?;
+
+    tracing::debug!(
+        target: "whitenoise::outbox::enqueue",
+        "Queued event {} for {} relays",
+        event.id.to_hex(),
+        relays.len()
+    );
+    Ok(())
+}
+
+/// Publishes a queued event to every relay that hasn't accepted it yet and records the results
+pub async fn deliver(
+    account_pubkey: &PublicKey,
+    event_id: &EventId,
+    wn: Arc<Whitenoise>,
+) -> Result<DeliveryStatus> {
+    let row = fetch_row(account_pubkey, event_id, &wn.database)
+        .await?
+        .ok_or_else(|| OutboxError::NotFound(event_id.to_hex()))?;
+    let event = Event::from_json(&row.event)?;
+    let mut status = DeliveryStatus::try_from(row)?;
+
+    let pending_relays: Vec<RelayUrl> = status
+        .relays
+        .iter()
+        .filter(|url| !status.successful_relays.contains(url))
+        .filter_map(|url| RelayUrl::parse(url).ok())
+        .collect();
+
+    if !pending_relays.is_empty() {
+        match wn
+            .nostr
+            .client
+            .send_event_to(pending_relays.clone(), &event)
+            .await
+        {
+            Ok(output) => {
+                for url in output.success.iter() {
+                    let url = url.to_string();
+                    status.failed_relays.remove(&url);
+                    if !status.successful_relays.contains(&url) {
+                        status.successful_relays.push(url);
+                    }
+                }
+                for (url, error) in output.failed.iter() {
+                    status
+                        .failed_relays
+                        .insert(url.to_string(), error.to_string());
+                }
+            }
+            Err(e) => {
+                for url in pending_relays.iter() {
+                    status.failed_relays.insert(url.to_string(), e.to_string());
+                }
+            }
+        }
+    }
+
+    status.attempts += 1;
+    status.state = next_state(
+        status.relays.len(),
+        status.successful_relays.len(),
+        status.attempts,
+    );
+    let now = Timestamp::now();
+    status.next_attempt_at =
+        is_pending(status.state, status.attempts).then(|| now + retry_delay(status.attempts));
+    status.updated_at = now;
+
+    sqlx::query(
+        "UPDATE outbox_events
+         SET successful_relays = ?, failed_relays = ?, state = ?, attempts = ?,
+             next_attempt_at = ?, updated_at = ?
+         WHERE event_id = ? AND account_pubkey = ?",
+    )
+    .bind(serde_json::to_string(&status.successful_relays)?)
+    .bind(serde_json::to_string(&status.failed_relays)?)
+    .bind(String::from(status.state))
+    .bind(status.attempts as i64)
+    .bind(status.next_attempt_at.unwrap_or(now).as_u64() as i64)
+    .bind(now.as_u64() as i64)
+    .bind(event_id.to_hex())
+    .bind(account_pubkey.to_hex())
+    .execute(&wn.database.pool)
+    .await?;
+
+    tracing::debug!(
+        target: "whitenoise::outbox::deliver",
+        "Event {} sent to {}/{} relays ({:?})",
+        event_id.to_hex(),
+        status.successful_relays.len(),
+        status.relays.len(),
+        status.state
+    );
+    Ok(status)
+}
+
+/// Returns the delivery status of the event that carried a message
+pub async fn delivery_status_for_message(
+    account_pubkey: &PublicKey,
+    message_id: &EventId,
+    db: &Database,
+) -> Result<Option<DeliveryStatus>> {
+    let row = sqlx::query_as::<_, OutboxRow>(
+        "SELECT * FROM outbox_events WHERE message_id = ? AND account_pubkey = ?
+         ORDER BY created_at DESC LIMIT 1",
+    )
+    .bind(message_id.to_hex())
+    .bind(account_pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?;
+
+    row.map(DeliveryStatus::try_from).transpose()
+}
+
+/// Retries every due event of an account whose relays are reachable again.
+///
+/// Returns the number of events that were retried.
+pub async fn retry_pending(account_pubkey: &PublicKey, wn: Arc<Whitenoise>) -> Result<usize> {
+    let rows = sqlx::query_as::<_, OutboxRow>(
+        "SELECT * FROM outbox_events
+         WHERE account_pubkey = ? AND state IN (?, ?) AND attempts < ? AND next_attempt_at <= ?
+         ORDER BY created_at ASC",
+    )
+    .bind(account_pubkey.to_hex())
+    .bind(String::from(DeliveryState::Queued))
+    .bind(String::from(DeliveryState::PartiallySent))
+    .bind(MAX_DELIVERY_ATTEMPTS as i64)
+    .bind(Timestamp::now().as_u64() as i64)
+    .fetch_all(&wn.database.pool)
+    .await?;
+
+    if rows.is_empty() {
+        return Ok(0);
+    }
+
+    let connected_relays: BTreeSet<String> = wn
+        .nostr
+        .client
+        .relays()
+        .await
+        .into_iter()
+        .filter(|(_, relay)| relay.status() == RelayStatus::Connected)
+        .map(|(url, _)| url.to_string())
+        .collect();
+
+    let mut retried = 0;
+    for row in rows {
+        let event_id = row.event_id.clone();
+        let attempts = row.attempts as u32;
+        match retry_row(account_pubkey, row, &connected_relays, wn.clone()).await {
+            Ok(true) => retried += 1,
+            Ok(false) => {}
+            Err(e) => {
+                // One broken event mustn't hold back the others
+                tracing::warn!(
+                    target: "whitenoise::outbox::retry_pending",
+                    "Failed to retry event {}: {}",
+                    event_id,
+                    e
+                );
+                if let Err(e) =
+                    record_failed_attempt(account_pubkey, &event_id, attempts, &wn.database).await
+                {
+                    tracing::error!(
+                        target: "whitenoise::outbox::retry_pending",
+                        "Failed to record failed attempt for event {}: {}",
+                        event_id,
+                        e
+                    );
+                }
+            }
+        }
+    }
+    Ok(retried)
+}
+
+/// Retries a pending event if one of its remaining relays is connected.
+///
+/// Returns whether it was retried.
+async fn retry_row(
+    account_pubkey: &PublicKey,
+    row: OutboxRow,
+    connected_relays: &BTreeSet<String>,
+    wn: Arc<Whitenoise>,
+) -> Result<bool> {
+    let relays: Vec<String> = serde_json::from_str(&row.relays)?;
+    let successful_relays: Vec<String> = serde_json::from_str(&row.successful_relays)?;
+    // Don't burn attempts while we're offline
+    let reachable = relays
+        .iter()
+        .any(|url| !successful_relays.contains(url) && connected_relays.contains(url));
+    if !reachable {
+        return Ok(false);
+    }
+
+    let event_id = EventId::from_hex(&row.event_id)
+        .map_err(|_| OutboxError::InvalidEventId(row.event_id.clone()))?;
+    deliver(account_pubkey, &event_id, wn).await?;
+    Ok(true)
+}
+
+/// Counts an attempt that failed before reaching any relay, so that the event backs off and is
+/// eventually given up on instead of failing on every pass
+async fn record_failed_attempt(
+    account_pubkey: &PublicKey,
+    event_id: &str,
+    previous_attempts: u32,
+    db: &Database,
+) -> Result<()> {
+    let attempts = previous_attempts + 1;
+    let now = Timestamp::now();
+    // Give up once we're out of attempts, otherwise keep the current state
+    let state = (attempts >= MAX_DELIVERY_ATTEMPTS).then(|| String::from(DeliveryState::Failed));
+    sqlx::query(
+        "UPDATE outbox_events
+         SET state = COALESCE(?, state), attempts = ?, next_attempt_at = ?, updated_at = ?
+         WHERE event_id = ? AND account_pubkey = ?",
+    )
+    .bind(state)
+    .bind(attempts as i64)
+    .bind((now + retry_delay(attempts)).as_u64() as i64)
+    .bind(now.as_u64() as i64)
+    .bind(event_id)
+    .bind(account_pubkey.to_hex())
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Starts the background task that retries undelivered events of the active account.
+///
+/// Calling this more than once is a no-op.
+pub fn start_retry_worker() {
+    if RETRY_WORKER_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        tracing::debug!(
+            target: "whitenoise::outbox::retry_worker",
+            "Starting outbox retry worker"
+        );
+        let mut interval = tokio::time::interval(RETRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            let wn = crate::runtime::wn();
+            let Ok(account_pubkey) = Account::get_active_pubkey(wn.clone()).await else {
+                continue;
+            };
+            match retry_pending(&account_pubkey, wn).await {
+                Ok(0) => {}
+                Ok(count) => {
+                    tracing::debug!(
+                        target: "whitenoise::outbox::retry_worker",
+                        "Retried {} queued events",
+                        count
+                    );
+                }
+                Err(e) => {
+                    tracing::error!(
+                        target: "whitenoise::outbox::retry_worker",
+                        "Error retrying queued events: {}",
+                        e
+                    );
+                }
+            }
+        }
+    });
+}
+
+async fn fetch_row(
+    account_pubkey: &PublicKey,
+    event_id: &EventId,
+    db: &Database,
+) -> Result<Option<OutboxRow>> {
+    Ok(sqlx::query_as::<_, OutboxRow>(
+        "SELECT * FROM outbox_events WHERE event_id = ? AND account_pubkey = ?",
+    )
+    .bind(event_id.to_hex())
+    .bind(account_pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?)
+}
+
+/// Works out the state of an event after a delivery attempt
+fn next_state(total_relays: usize, successful_relays: usize, attempts: u32) -> DeliveryState {
+    if successful_relays >= total_relays {
+        DeliveryState::Sent
+    } else if successful_relays > 0 {
+        DeliveryState::PartiallySent
+    } else if attempts >= MAX_DELIVERY_ATTEMPTS {
+        DeliveryState::Failed
+    } else {
+        DeliveryState::Queued
+    }
+}
+
+/// Whether an event in this state will be retried
+fn is_pending(state: DeliveryState, attempts: u32) -> bool {
+    matches!(state, DeliveryState::Queued | DeliveryState::PartiallySent)
+        && attempts < MAX_DELIVERY_ATTEMPTS
+}
+
+/// Exponential backoff (in seconds) before the next attempt, capped at ten minutes
+fn retry_delay(attempts: u32) -> u64 {
+    BASE_RETRY_DELAY_SECS
+        .saturating_mul(2u64.saturating_pow(attempts.saturating_sub(1)))
+        .min(MAX_RETRY_DELAY_SECS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), 5);
+        assert_eq!(retry_delay(2), 10);
+        assert_eq!(retry_delay(3), 20);
+        assert_eq!(retry_delay(8), MAX_RETRY_DELAY_SECS);
+        assert_eq!(retry_delay(64), MAX_RETRY_DELAY_SECS);
+    }
+
+    #[test]
+    fn test_next_state() {
+        assert_eq!(next_state(3, 3, 1), DeliveryState::Sent);
+        assert_eq!(next_state(3, 1, 1), DeliveryState::PartiallySent);
+        assert_eq!(next_state(3, 0, 1), DeliveryState::Queued);
+        assert_eq!(
+            next_state(3, 0, MAX_DELIVERY_ATTEMPTS),
+            DeliveryState::Failed
+        );
+        assert_eq!(
+            next_state(3, 2, MAX_DELIVERY_ATTEMPTS),
+            DeliveryState::PartiallySent
+        );
+    }
+
+    #[test]
+    fn test_is_pending() {
+        assert!(is_pending(DeliveryState::Queued, 1));
+        assert!(is_pending(DeliveryState::PartiallySent, 1));
+        assert!(!is_pending(
+            DeliveryState::PartiallySent,
+            MAX_DELIVERY_ATTEMPTS
+        ));
+        assert!(!is_pending(DeliveryState::Sent, 1));
+        assert!(!is_pending(DeliveryState::Failed, 1));
+    }
+
+    #[test]
+    fn test_delivery_state_round_trip() {
+        for state in [
+            DeliveryState::Queued,
+            DeliveryState::PartiallySent,
+            DeliveryState::Sent,
+            DeliveryState::Failed,
+        ] {
+            assert_eq!(DeliveryState::from(String::from(state)), state);
+        }
+    }
+}
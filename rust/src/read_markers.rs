@@ -0,0 +1,248 @@
+//! Read markers, unread counts and read receipts
+//!
+//! Every account keeps a marker per group that records the newest message it has read. Unread
+//! counts are derived from the group's messages and that marker, ignoring our own messages,
+//! reactions and control messages.
+//!
+//! Read receipts are optional. When turned on in the account settings, marking a group as read
+//! sends an ephemeral inner event of [`READ_RECEIPT_KIND`] that references the last read message.
+//! Receipts from other members are stored so the UI can show who has seen what.
+
+use nostr_mls::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::database::Database;
+use crate::disappearing_messages::EXPIRATION_SETTING_KIND;
+
+/// Inner event kind of a read receipt. It's in the ephemeral range since receipts are
+/// signals rather than part of the conversation.
+pub const READ_RECEIPT_KIND: u16 = 24451;
+
+#[derive(Error, Debug)]
+pub enum ReadMarkersError {
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+}
+
+pub type Result<T> = std::result::Result<T, ReadMarkersError>;
+
+/// How far into a group an account has read
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReadMarker {
+    /// Hex encoded event id of the last read message
+    pub last_read_message_id: Option<String>,
+    /// Unix timestamp of the last read message
+    pub last_read_at: i64,
+    pub updated_at: i64,
+}
+
+/// The last message another member of a group told us they've read
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReadReceipt {
+    /// Hex encoded pubkey of the member
+    pub member_pubkey: String,
+    /// Hex encoded event id of the last message they've read
+    pub last_read_message_id: String,
+    /// Unix timestamp of the receipt
+    pub read_at: i64,
+}
+
+/// Returns the read marker of a group, if the account ever read it
+pub async fn read_marker(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    db: &Database,
+) -> Result<Option<ReadMarker>> {
+    Ok(sqlx::query_as::<_, ReadMarker>(
+        "SELECT last_read_message_id, last_read_at, updated_at FROM group_read_markers
+         WHERE mls_group_id = ? AND account_pubkey = ?",
+    )
+    .bind(mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .fetch_optional(&db.pool)
+    .await?)
+}
+
+/// Moves the read marker of a group forward to the given message.
+///
+/// Markers never move backwards, so marking an older message as read is a no-op.
+pub async fn mark_read(
+    account_pubkey: &PublicKey,
+    message: &message_types::Message,
+    db: &Database,
+) -> Result<()> {
+    sqlx::query(
+        "INSERT INTO group_read_markers (mls_group_id, account_pubkey, last_read_message_id, last_read_at, updated_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(mls_group_id, account_pubkey) DO UPDATE SET
+            last_read_message_id = excluded.last_read_message_id,
+            last_read_at = excluded.last_read_at,
+            updated_at = excluded.updated_at
+         WHERE excluded.last_read_at >= group_read_markers.last_read_at",
+    )
+    .bind(message.mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .bind(message.id.to_hex())
+    .bind(message.created_at.as_u64() as i64)
+    .bind(Timestamp::now().as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Whether a message counts towards the unread count of an account
+pub fn is_countable(account_pubkey: &PublicKey, message: &message_types::Message) -> bool {
+    message.pubkey != *account_pubkey
+        && message.kind != Kind::Reaction
+        && message.kind != Kind::EventDeletion
+        && message.kind != Kind::Custom(EXPIRATION_SETTING_KIND)
+        && !message.kind.is_ephemeral()
+}
+
+/// Counts the messages of a group that arrived after the read marker
+pub fn unread_count(
+    account_pubkey: &PublicKey,
+    messages: &[message_types::Message],
+    marker: Option<&ReadMarker>,
+) -> u64 {
+    let last_read_at = marker.map(|m| m.last_read_at).unwrap_or(0);
+    let last_read_message_id = marker.and_then(|m| m.last_read_message_id.as_deref());
+
+    messages
+        .iter()
+        .filter(|message| is_countable(account_pubkey, message))
+        .filter(|message| {
+            let created_at = message.created_at.as_u64() as i64;
+            // Messages sharing the marker's timestamp are only unread if they aren't the marker
+            created_at > last_read_at
+                || (created_at == last_read_at
+                    && last_read_message_id.is_some_and(|id| id != message.id.to_hex()))
+        })
+        .count() as u64
+}
+
+/// Builds the tags of a read receipt for the given message
+pub fn read_receipt_tags(message_id: &EventId) -> Vec<Tag> {
+    vec![Tag::event(*message_id)]
+}
+
+/// Stores a read receipt received from another group member
+pub async fn record_read_receipt(
+    account_pubkey: &PublicKey,
+    receipt: &message_types::Message,
+    db: &Database,
+) -> Result<()> {
+    let Some(last_read_message_id) = receipt
+        .tags
+        .iter()
+        .find(|tag| tag.kind() == TagKind::e())
+        .and_then(|tag| tag.content())
+    else {
+        tracing::warn!(
+            target: "whitenoise::read_markers::record_read_receipt",
+            "Ignoring read receipt without a message reference"
+        );
+        return Ok(());
+    };
+
+    sqlx::query(
+        "INSERT INTO group_read_receipts (mls_group_id, account_pubkey, member_pubkey, last_read_message_id, read_at)
+         VALUES (?, ?, ?, ?, ?)
+         ON CONFLICT(mls_group_id, account_pubkey, member_pubkey) DO UPDATE SET
+            last_read_message_id = excluded.last_read_message_id,
+            read_at = excluded.read_at
+         WHERE excluded.read_at >= group_read_receipts.read_at",
+    )
+    .bind(receipt.mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .bind(receipt.pubkey.to_hex())
+    .bind(last_read_message_id)
+    .bind(receipt.created_at.as_u64() as i64)
+    .execute(&db.pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns the latest read receipt of every member of a group
+pub async fn read_receipts(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    db: &Database,
+) -> Result<Vec<ReadReceipt>> {
+    Ok(sqlx::query_as::<_, ReadReceipt>(
+        "SELECT member_pubkey, last_read_message_id, read_at FROM group_read_receipts
+         WHERE mls_group_id = ? AND account_pubkey = ?
+         ORDER BY read_at DESC",
+    )
+    .bind(mls_group_id.as_slice())
+    .bind(account_pubkey.to_hex())
+    .fetch_all(&db.pool)
+    .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_message(
+        kind: Kind,
+        author: PublicKey,
+        created_at: u64,
+    ) -> message_types::Message {
+        let mut event = UnsignedEvent::new(
+            author,
+            Timestamp::from(created_at),
+            kind,
+            Tags::new(),
+            "hello",
+        );
+        event.ensure_id();
+        message_types::Message {
+            id: event.id.unwrap(),
+            pubkey: author,
+            kind,
+            mls_group_id: GroupId::from_slice(&[1, 2, 3]),
+            created_at: event.created_at,
+            content: event.content.clone(),
+            tags: Tags::new(),
+            event,
+            wrapper_event_id: EventId::from_hex(
+                "1234567890abcdef1234567890abcdef1234567890abcdef1234567890abcdef",
+            )
+            .unwrap(),
+            state: message_types::MessageState::Created,
+        }
+    }
+
+    #[test]
+    fn test_unread_count() {
+        let account = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let messages = vec![
+            create_test_message(Kind::Custom(9), other, 100),
+            create_test_message(Kind::Custom(9), other, 200),
+            create_test_message(Kind::Custom(9), account, 300),
+            create_test_message(Kind::Reaction, other, 300),
+            create_test_message(Kind::Custom(READ_RECEIPT_KIND), other, 300),
+            create_test_message(Kind::Custom(EXPIRATION_SETTING_KIND), other, 300),
+            create_test_message(Kind::Custom(9), other, 300),
+        ];
+
+        assert_eq!(unread_count(&account, &messages, None), 3);
+
+        let marker = ReadMarker {
+            last_read_message_id: Some(messages[1].id.to_hex()),
+            last_read_at: 200,
+            updated_at: 400,
+        };
+        assert_eq!(unread_count(&account, &messages, Some(&marker)), 1);
+
+        let marker = ReadMarker {
+            last_read_message_id: Some(messages[6].id.to_hex()),
+            last_read_at: 300,
+            updated_at: 400,
+        };
+        assert_eq!(unread_count(&account, &messages, Some(&marker)), 0);
+    }
+}
@@ -43,3 +43,45 @@ impl From<RelayType> for String {
         }
     }
 }
+
+/// Normalizes a relay URL (scheme and host case, surrounding whitespace), `None` if it's invalid
+pub fn normalize_relay_url(url: &str) -> Option<String> {
+    RelayUrl::parse(url.trim()).ok().map(|url| url.to_string())
+}
+
+/// Normalizes and deduplicates relay URLs, keeping their order.
+///
+/// Fails with the first invalid URL.
+pub fn normalize_relay_urls(urls: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::with_capacity(urls.len());
+    for url in urls {
+        let url = normalize_relay_url(url).ok_or_else(|| url.clone())?;
+        if !normalized.contains(&url) {
+            normalized.push(url);
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_relay_urls() {
+        let urls = vec![
+            "wss://relay.damus.io".to_string(),
+            " wss://Relay.Damus.io ".to_string(),
+            "wss://nos.lol".to_string(),
+        ];
+        let normalized = normalize_relay_urls(&urls).unwrap();
+        assert_eq!(normalized.len(), 2);
+        assert_eq!(normalized[1], normalize_relay_url("wss://nos.lol").unwrap());
+
+        let invalid = vec!["wss://nos.lol".to_string(), "not a relay".to_string()];
+        assert_eq!(
+            normalize_relay_urls(&invalid),
+            Err("not a relay".to_string())
+        );
+    }
+}
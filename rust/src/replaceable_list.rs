@@ -0,0 +1,227 @@
+//! Editing our replaceable lists (contact list, mute list)
+//!
+//! These lists are replaceable events shared with every other client of the account, so an edit
+//! is made on top of the latest version we can find, locally or on our relays. Publishing a list
+//! built from a stale copy would silently drop what another client added: right before
+//! publishing we look for the latest list again and redo the edit on top of it if it changed.
+
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+
+use crate::accounts::Account;
+use crate::nostr_manager::NostrManagerError;
+use crate::whitenoise::Whitenoise;
+
+/// How many times we redo an edit because a newer list showed up while we were editing
+const MAX_EDIT_ATTEMPTS: usize = 3;
+
+/// A kind of replaceable list and how to read and write its entries
+#[async_trait::async_trait]
+pub(crate) trait ReplaceableList {
+    const KIND: Kind;
+
+    /// The entries of the list, `Default` when there's no list yet
+    type Tags: Default + Send + Sync;
+    type Error: From<NostrManagerError>
+        + From<nostr_sdk::client::Error>
+        + From<DatabaseError>
+        + Send;
+
+    /// Reads the entries of a published list
+    async fn read(event: &Event, wn: Arc<Whitenoise>) -> Result<Self::Tags, Self::Error>;
+
+    /// Builds the list to publish, `base` is the list it replaces
+    async fn build(
+        tags: &Self::Tags,
+        base: Option<&Event>,
+        account: &Account,
+        wn: Arc<Whitenoise>,
+    ) -> Result<EventBuilder, Self::Error>;
+
+    fn conflict() -> Self::Error;
+
+    fn unavailable() -> Self::Error;
+
+    fn not_published(reasons: Vec<String>) -> Self::Error;
+}
+
+/// The outcome of editing a list
+pub(crate) enum Edited<T> {
+    /// The list already had the edit, nothing was published. Comes with the latest list, if any.
+    Unchanged(T, Option<Event>),
+    /// The edited list was published
+    Published(T, Event),
+}
+
+/// Applies an edit on top of the latest version of a list and publishes the result. `edit`
+/// returns `None` when there's nothing to change.
+pub(crate) async fn edit_list<L: ReplaceableList>(
+    account: &Account,
+    write_relays: &[String],
+    edit: impl Fn(&L::Tags) -> Option<L::Tags> + Send + Sync,
+    wn: Arc<Whitenoise>,
+) -> Result<Edited<L::Tags>, L::Error> {
+    for _ in 0..MAX_EDIT_ATTEMPTS {
+        let base = find_latest_list::<L>(account, write_relays, wn.clone()).await?;
+        let base_tags = match &base {
+            Some(event) => L::read(event, wn.clone()).await?,
+            None => L::Tags::default(),
+        };
+
+        let Some(tags) = edit(&base_tags) else {
+            return Ok(Edited::Unchanged(base_tags, base));
+        };
+
+        // Someone published a newer list while we were editing, start over from that one
+        let latest = find_latest_list::<L>(account, write_relays, wn.clone()).await?;
+        if latest.as_ref().map(|e| e.id) != base.as_ref().map(|e| e.id) {
+            tracing::debug!(
+                target: "whitenoise::replaceable_list::edit_list",
+                "List of kind {} changed while editing, retrying",
+                L::KIND
+            );
+            continue;
+        }
+
+        let created_at = next_created_at(base.as_ref().map(|e| e.created_at), Timestamp::now());
+        let builder = L::build(&tags, base.as_ref(), account, wn.clone())
+            .await?
+            .custom_created_at(created_at);
+        let event = wn.nostr.client.sign_event_builder(builder).await?;
+
+        let output = wn
+            .nostr
+            .publish_event_to_outbox(&event, write_relays)
+            .await?;
+        if output.success.is_empty() {
+            return Err(L::not_published(output.failed.values().cloned().collect()));
+        }
+        wn.nostr.client.database().save_event(&event).await?;
+        return Ok(Edited::Published(tags, event));
+    }
+
+    Err(L::conflict())
+}
+
+/// Finds our latest list, in the local database or on our relays.
+///
+/// `None` means we have no list at all, which we only trust if a relay confirmed it. Otherwise
+/// editing "nothing" and publishing the result would wipe the whole list.
+async fn find_latest_list<L: ReplaceableList>(
+    account: &Account,
+    write_relays: &[String],
+    wn: Arc<Whitenoise>,
+) -> Result<Option<Event>, L::Error> {
+    let filter = Filter::new().author(account.pubkey).kind(L::KIND).limit(1);
+
+    let (fetched, confirmed) = wn
+        .nostr
+        .fetch_own_events_confirmed(filter.clone(), write_relays)
+        .await?;
+    let stored = wn.nostr.client.database().query(filter).await?;
+
+    match latest_event(fetched.into_iter().chain(stored)) {
+        Some(event) => Ok(Some(event)),
+        None if confirmed => Ok(None),
+        None => Err(L::unavailable()),
+    }
+}
+
+/// The latest of several versions of a replaceable event. On equal timestamps the lowest id
+/// wins, as NIP-01 says.
+pub(crate) fn latest_event(events: impl IntoIterator<Item = Event>) -> Option<Event> {
+    events
+        .into_iter()
+        .max_by(|a, b| a.created_at.cmp(&b.created_at).then(b.id.cmp(&a.id)))
+}
+
+/// The timestamp of an edited list, which has to be newer than the list it replaces even if
+/// our clock is behind
+pub(crate) fn next_created_at(base: Option<Timestamp>, now: Timestamp) -> Timestamp {
+    match base {
+        Some(base) if base >= now => base + 1,
+        _ => now,
+    }
+}
+
+fn is_p_tag(tag: &Tag, pubkey: &PublicKey) -> bool {
+    tag.kind() == TagKind::p() && tag.content() == Some(pubkey.to_hex().as_str())
+}
+
+/// Adds a `p` tag, `None` if the tags already have one for the pubkey
+pub(crate) fn add_p_tag(tags: &[Tag], pubkey: &PublicKey) -> Option<Vec<Tag>> {
+    if tags.iter().any(|tag| is_p_tag(tag, pubkey)) {
+        return None;
+    }
+    let mut tags = tags.to_vec();
+    tags.push(Tag::public_key(*pubkey));
+    Some(tags)
+}
+
+/// Removes every `p` tag of a pubkey, keeping every other tag. `None` if there's none.
+pub(crate) fn remove_p_tag(tags: &[Tag], pubkey: &PublicKey) -> Option<Vec<Tag>> {
+    if !tags.iter().any(|tag| is_p_tag(tag, pubkey)) {
+        return None;
+    }
+    Some(
+        tags.iter()
+            .filter(|tag| !is_p_tag(tag, pubkey))
+            .cloned()
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn contact_list(keys: &Keys, tags: Vec<Tag>, created_at: u64) -> Event {
+        EventBuilder::new(Kind::ContactList, "")
+            .tags(tags)
+            .custom_created_at(Timestamp::from(created_at))
+            .sign_with_keys(keys)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_p_tag_helpers_keep_other_tags() {
+        let alice = Keys::generate().public_key();
+        let bob = Keys::generate().public_key();
+        let tags = vec![
+            Tag::parse(["p", &alice.to_hex(), "wss://relay.example.com", "alice"]).unwrap(),
+            Tag::parse(["word", "spoilers"]).unwrap(),
+        ];
+
+        assert!(add_p_tag(&tags, &alice).is_none());
+        let added = add_p_tag(&tags, &bob).unwrap();
+        assert_eq!(added[..2], tags[..]);
+        assert_eq!(added[2], Tag::public_key(bob));
+
+        assert!(remove_p_tag(&tags, &bob).is_none());
+        let removed = remove_p_tag(&added, &alice).unwrap();
+        assert_eq!(removed, vec![tags[1].clone(), Tag::public_key(bob)]);
+    }
+
+    #[test]
+    fn test_latest_event() {
+        let keys = Keys::generate();
+        let older = contact_list(&keys, vec![], 100);
+        let newer = contact_list(&keys, vec![Tag::public_key(keys.public_key())], 200);
+        assert_eq!(
+            latest_event(vec![older.clone(), newer.clone()]).unwrap().id,
+            newer.id
+        );
+        assert!(latest_event(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_next_created_at() {
+        let now = Timestamp::from(1_000);
+        assert_eq!(next_created_at(None, now), now);
+        assert_eq!(next_created_at(Some(Timestamp::from(900)), now), now);
+        assert_eq!(
+            next_created_at(Some(Timestamp::from(1_000)), now),
+            Timestamp::from(1_001)
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use crate::contact_annotations::ContactAnnotation;
+use crate::nip05::Nip05Status;
 use nostr_sdk::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -10,12 +12,18 @@ pub struct EnrichedContact {
     pub nip17: bool,
     /// Whether the contact supports NIP-104.
     pub nip104: bool,
+    /// Whether the contact's NIP-05 address is verified. NIP-05
+    #[serde(default)]
+    pub nip05_status: Nip05Status,
     /// The relays for the user. NIP-65
     pub nostr_relays: Vec<String>,
     /// The relays for the contact's inbox. NIP-17
     pub inbox_relays: Vec<String>,
     /// The relays for the contact's key package. NIP-104
     pub key_package_relays: Vec<String>,
+    /// Our private nickname, note and pin for the contact
+    #[serde(default)]
+    pub annotation: Option<ContactAnnotation>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
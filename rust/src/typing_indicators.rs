@@ -0,0 +1,277 @@
+//! Typing indicators
+//!
+//! Typing indicators are ephemeral events of [`TYPING_INDICATOR_KIND`] published next to the
+//! group's MLS messages, not through MLS, so they never end up in MLS storage. The outer event is
+//! signed with a throwaway key and tagged with the group's Nostr ID like an MLS message. Its content
+//! is our signed indicator, NIP-44 encrypted with the group's current exporter secret so only
+//! members can read it. Received indicators are only kept in memory until they expire a few
+//! seconds later.
+
+use nostr_mls::prelude::*;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+use crate::accounts::{Account, AccountError};
+use crate::whitenoise::Whitenoise;
+
+/// Kind of a typing indicator, both the outer ephemeral event and our signed inner event
+pub const TYPING_INDICATOR_KIND: u16 = 24452;
+
+#[derive(Error, Debug)]
+pub enum TypingIndicatorError {
+    #[error("Account error: {0}")]
+    AccountError(#[from] AccountError),
+    #[error("Nostr MLS error: {0}")]
+    NostrMlsError(#[from] nostr_mls::Error),
+    #[error("Nostr MLS not initialized")]
+    NostrMlsNotInitialized,
+    #[error("Timeout waiting for nostr_mls lock")]
+    NostrMlsLockTimeout,
+    #[error("Nostr client error: {0}")]
+    NostrClientError(#[from] nostr_sdk::client::Error),
+    #[error("Signer error: {0}")]
+    SignerError(#[from] nostr_sdk::SignerError),
+    #[error("Key error: {0}")]
+    KeyError(#[from] nostr_sdk::key::Error),
+    #[error("Encryption error: {0}")]
+    EncryptionError(#[from] nostr_sdk::nips::nip44::Error),
+    #[error("Event error: {0}")]
+    EventError(#[from] nostr_sdk::event::Error),
+    #[error("Event builder error: {0}")]
+    EventBuilderError(#[from] nostr_sdk::event::builder::Error),
+    #[error("Invalid typing indicator: {0}")]
+    InvalidIndicator(String),
+}
+
+pub type Result<T> = std::result::Result<T, TypingIndicatorError>;
+
+/// How long a typing indicator is shown for unless it's refreshed or stopped
+pub const TYPING_INDICATOR_TTL_SECS: u64 = 6;
+
+/// Content of an indicator sent when a member stops typing
+const STOPPED_CONTENT: &str = "stopped";
+
+static TYPING_STATE: Lazy<Mutex<TypingState>> = Lazy::new(|| Mutex::new(TypingState::default()));
+
+/// Content of a typing indicator for the given state
+pub fn indicator_content(is_typing: bool) -> String {
+    if is_typing {
+        String::new()
+    } else {
+        STOPPED_CONTENT.to_string()
+    }
+}
+
+/// Sends a typing indicator to a group. It's sent once and isn't retried.
+pub async fn send_indicator(
+    group: &group_types::Group,
+    is_typing: bool,
+    wn: Arc<Whitenoise>,
+) -> Result<()> {
+    let signer = wn.nostr.client.signer().await?;
+    let inner = UnsignedEvent::new(
+        signer.get_public_key().await?,
+        Timestamp::now(),
+        Kind::Custom(TYPING_INDICATOR_KIND),
+        Tags::new(),
+        indicator_content(is_typing),
+    );
+    let inner = signer.sign_event(inner).await?;
+
+    let (secret, relays) = {
+        let nostr_mls_guard = tokio::time::timeout(Duration::from_secs(5), wn.nostr_mls.lock())
+            .await
+            .map_err(|_| TypingIndicatorError::NostrMlsLockTimeout)?;
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(TypingIndicatorError::NostrMlsNotInitialized)?;
+        (
+            nostr_mls.exporter_secret(&group.mls_group_id)?,
+            nostr_mls.get_relays(&group.mls_group_id)?,
+        )
+    };
+
+    let group_keys = Keys::new(SecretKey::from_slice(&secret.secret)?);
+    let content = nip44::encrypt(
+        group_keys.secret_key(),
+        &group_keys.public_key(),
+        inner.as_json(),
+        nip44::Version::default(),
+    )?;
+    let event = EventBuilder::new(Kind::Custom(TYPING_INDICATOR_KIND), content)
+        .tag(Tag::custom(
+            TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::H)),
+            [hex::encode(group.nostr_group_id)],
+        ))
+        .sign_with_keys(&Keys::generate())?;
+
+    wn.nostr.client.send_event_to(relays, &event).await?;
+    Ok(())
+}
+
+/// Handles a typing indicator event published to one of our groups
+pub async fn receive_indicator(event: &Event, wn: Arc<Whitenoise>) -> Result<()> {
+    let nostr_group_id = event
+        .tags
+        .find(TagKind::SingleLetter(SingleLetterTag::lowercase(
+            Alphabet::H,
+        )))
+        .and_then(|tag| tag.content())
+        .ok_or_else(|| TypingIndicatorError::InvalidIndicator("missing h tag".to_string()))?;
+    let account_pubkey = Account::get_active_pubkey(wn.clone()).await?;
+
+    let (mls_group_id, secret, members) = {
+        let nostr_mls_guard = tokio::time::timeout(Duration::from_secs(5), wn.nostr_mls.lock())
+            .await
+            .map_err(|_| TypingIndicatorError::NostrMlsLockTimeout)?;
+        let nostr_mls = nostr_mls_guard
+            .as_ref()
+            .ok_or(TypingIndicatorError::NostrMlsNotInitialized)?;
+        let group = nostr_mls
+            .get_groups()?
+            .into_iter()
+            .find(|group| hex::encode(group.nostr_group_id) == nostr_group_id)
+            .ok_or_else(|| TypingIndicatorError::InvalidIndicator("unknown group".to_string()))?;
+        (
+            group.mls_group_id.clone(),
+            nostr_mls.exporter_secret(&group.mls_group_id)?,
+            nostr_mls.get_members(&group.mls_group_id)?,
+        )
+    };
+
+    let group_keys = Keys::new(SecretKey::from_slice(&secret.secret)?);
+    let inner = Event::from_json(nip44::decrypt(
+        group_keys.secret_key(),
+        &group_keys.public_key(),
+        &event.content,
+    )?)?;
+    inner.verify()?;
+    if inner.kind != Kind::Custom(TYPING_INDICATOR_KIND) || !members.contains(&inner.pubkey) {
+        return Err(TypingIndicatorError::InvalidIndicator(
+            "not a typing indicator from a group member".to_string(),
+        ));
+    }
+
+    handle_indicator(
+        &account_pubkey,
+        &mls_group_id,
+        &inner.pubkey,
+        inner.created_at,
+        &inner.content,
+    );
+    Ok(())
+}
+
+/// Records a typing indicator received from another group member
+fn handle_indicator(
+    account_pubkey: &PublicKey,
+    mls_group_id: &GroupId,
+    member: &PublicKey,
+    created_at: Timestamp,
+    content: &str,
+) {
+    if member == account_pubkey {
+        return;
+    }
+    let mut state = TYPING_STATE.lock().expect("Typing state lock poisoned");
+    state.handle(
+        account_pubkey,
+        mls_group_id,
+        member,
+        created_at,
+        content,
+        Timestamp::now(),
+    );
+}
+
+/// Returns the members that are currently typing in a group
+pub fn typing_members(account_pubkey: &PublicKey, mls_group_id: &GroupId) -> Vec<PublicKey> {
+    let mut state = TYPING_STATE.lock().expect("Typing state lock poisoned");
+    state.typing_members(account_pubkey, mls_group_id, Timestamp::now())
+}
+
+type TypingKey = (PublicKey, Vec<u8>, PublicKey);
+
+/// Members typing in each group, keyed by account, group and member
+#[derive(Debug, Default)]
+struct TypingState {
+    expirations: HashMap<TypingKey, Timestamp>,
+}
+
+impl TypingState {
+    fn handle(
+        &mut self,
+        account_pubkey: &PublicKey,
+        mls_group_id: &GroupId,
+        member: &PublicKey,
+        created_at: Timestamp,
+        content: &str,
+        now: Timestamp,
+    ) {
+        let key = (*account_pubkey, mls_group_id.as_slice().to_vec(), *member);
+        if content.trim() == STOPPED_CONTENT {
+            self.expirations.remove(&key);
+            return;
+        }
+
+        // Don't trust clocks in the future, and ignore indicators that arrive late
+        let sent_at = created_at.min(now);
+        let expires_at = sent_at + TYPING_INDICATOR_TTL_SECS;
+        if expires_at > now {
+            self.expirations.insert(key, expires_at);
+        }
+    }
+
+    fn typing_members(
+        &mut self,
+        account_pubkey: &PublicKey,
+        mls_group_id: &GroupId,
+        now: Timestamp,
+    ) -> Vec<PublicKey> {
+        self.expirations.retain(|_, expires_at| *expires_at > now);
+        self.expirations
+            .keys()
+            .filter(|(account, group_id, _)| {
+                account == account_pubkey && group_id.as_slice() == mls_group_id.as_slice()
+            })
+            .map(|(_, _, member)| *member)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_typing_state() {
+        let mut state = TypingState::default();
+        let account = Keys::generate().public_key();
+        let member = Keys::generate().public_key();
+        let group = GroupId::from_slice(&[1, 2, 3]);
+        let other_group = GroupId::from_slice(&[4, 5, 6]);
+        let now = Timestamp::from(1_000);
+
+        let typing = indicator_content(true);
+        state.handle(&account, &group, &member, now, &typing, now);
+        assert_eq!(state.typing_members(&account, &group, now), vec![member]);
+        assert!(state.typing_members(&account, &other_group, now).is_empty());
+
+        // Indicators expire on their own
+        let later = now + TYPING_INDICATOR_TTL_SECS;
+        assert!(state.typing_members(&account, &group, later).is_empty());
+
+        // Indicators that arrive after they'd have expired are ignored
+        state.handle(&account, &group, &member, now, &typing, later);
+        assert!(state.typing_members(&account, &group, later).is_empty());
+
+        // Stopping removes the indicator right away
+        state.handle(&account, &group, &member, now, &typing, now);
+        let stopped = indicator_content(false);
+        state.handle(&account, &group, &member, now, &stopped, now);
+        assert!(state.typing_members(&account, &group, now).is_empty());
+    }
+}
@@ -1,5 +1,5 @@
 use crate::database::Database;
-use crate::nostr_manager::NostrManager;
+use crate::nostr_manager::{settings, NostrManager};
 use nostr_mls::NostrMls;
 use nostr_mls_sqlite_storage::NostrMlsSqliteStorage;
 use std::path::PathBuf;
@@ -27,15 +27,34 @@ impl Whitenoise {
         // FIXME(justin): manual change while removing tauri ...
         let logs_dir = data_dir.join("logs");
 
+        let database = Database::new(data_dir.join("whitenoise.sqlite"))
+            .await
+            .expect("Failed to create database");
+        let settings = settings::effective_settings(None, &database)
+            .await
+            .unwrap_or_else(|e| {
+                tracing::error!(
+                    target: "whitenoise::whitenoise::new",
+                    "Failed to load Nostr settings, using the defaults: {}",
+                    e
+                );
+                Default::default()
+            });
+
+        let nostr = NostrManager::new(data_dir.clone(), settings)
+            .await
+            .expect("Failed to create Nostr manager");
+        if let Err(e) = nostr.connect_default_relays(&database).await {
+            tracing::error!(
+                target: "whitenoise::whitenoise::new",
+                "Failed to connect to default relays: {}",
+                e
+            );
+        }
+
         Self {
-            database: Arc::new(
-                Database::new(data_dir.join("whitenoise.sqlite"))
-                    .await
-                    .expect("Failed to create database"),
-            ),
-            nostr: NostrManager::new(data_dir.clone())
-                .await
-                .expect("Failed to create Nostr manager"),
+            database: Arc::new(database),
+            nostr,
             nostr_mls: Arc::new(Mutex::new(None)),
             data_dir,
             logs_dir,
@@ -58,6 +77,15 @@ impl Whitenoise {
         // Remove database (accounts and media) data
         self.database.delete_all_data().await?;
 
+        // Remove cached profile images
+        if let Err(e) = crate::media::delete_profile_image_dir(&self.data_dir) {
+            tracing::error!(
+                target: "whitenoise::delete_all_data",
+                "Failed to remove profile image cache: {:?}",
+                e
+            );
+        }
+
         // Remove MLS related data
         {
             let mut nostr_mls = self.nostr_mls.lock().await;